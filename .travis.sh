@@ -15,6 +15,11 @@ then
 fi
 
 cargo build --verbose
+if [ "$TRAVIS_RUST_VERSION" = stable ]
+then
+    rustup component add clippy
+    cargo clippy --all-targets -- -D warnings
+fi
 cargo test --verbose
 
 # Test original cargo build
@@ -1,11 +1,24 @@
 use clap::App;
+use clap::AppSettings;
 use clap::Arg;
 use clap::ArgGroup;
 use clap::ArgMatches;
 use clap::SubCommand;
 use dinghy_lib::BuildArgs;
+use dinghy_lib::RunArgs;
+use dinghy_lib::compiler::BuildOptions;
+use dinghy_lib::compiler::CheckCommand;
 use dinghy_lib::compiler::CompileMode;
+use dinghy_lib::config::Configuration;
+use dinghy_lib::lock::LockWait;
+use dinghy_lib::errors::*;
+use dinghy_lib::ndk;
+use dinghy_lib::network::NetworkProfile;
+use dinghy_lib::region::Region;
+use std::collections::HashMap;
 use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Duration;
 
 pub struct CargoDinghyCli {}
 
@@ -21,6 +34,34 @@ impl CargoDinghyCli {
                 .quiet()
                 .overlay()
                 .platform()
+                .record()
+                .simulator()
+
+                .subcommand(SubCommand::with_name("agent-devices")
+                    .setting(AppSettings::Hidden)
+                    .about("List the devices of this host for remote agent clients"))
+
+                .subcommand(SubCommand::with_name("agent-run")
+                    .setting(AppSettings::Hidden)
+                    .about("Run a bundle synced by a remote agent client on a device of this host")
+                    .arg(Arg::with_name("BUNDLE")
+                        .long("bundle")
+                        .takes_value(true)
+                        .required(true)
+                        .help("directory of the bundle"))
+                    .arg(Arg::with_name("LIBS")
+                        .long("libs")
+                        .takes_value(true)
+                        .required(true)
+                        .help("directory of the dynamic libraries of the bundle"))
+                    .arg(Arg::with_name("MODE")
+                        .long("mode")
+                        .takes_value(true)
+                        .possible_values(&["bench", "build", "test"])
+                        .default_value("build")
+                        .help("how the bundle was built"))
+                    .envs()
+                    .additional_args())
 
                 .subcommand(SubCommand::with_name("all-devices")
                     .about("List all devices that can be used with Dinghy"))
@@ -28,36 +69,85 @@ impl CargoDinghyCli {
                 .subcommand(SubCommand::with_name("all-platforms")
                     .about("List all platforms known to dinghy"))
 
-                .subcommand(SubCommand::with_name("bench")
-                    .about("Run the benchmarks")
-                    .lib()
+                .subcommand(SubCommand::with_name("android-gradle")
+                    .about("Build the cdylib crates for the ABIs of a Gradle project and copy them to its jniLibs")
+                    .package()
+                    .job()
+                    .message_format()
+                    .debug_or_release()
+                    .features()
+                    .all_features()
+                    .no_default_features()
+                    .verbose()
+                    .bearded()
+                    .arg(Arg::with_name("PROJECT")
+                        .long("project")
+                        .takes_value(true)
+                        .value_name("DIR")
+                        .default_value(".")
+                        .help("root of the Gradle project"))
+                    .arg(Arg::with_name("MODULE")
+                        .long("module")
+                        .takes_value(true)
+                        .default_value("app")
+                        .help("module of the Gradle project receiving the libraries"))
+                    .arg(Arg::with_name("CONNECTED_TEST")
+                        .long("connected-test")
+                        .help("then run ./gradlew connectedAndroidTest, on the device selected with -d if any")))
+
+                .subcommand(SubCommand::with_name("archive")
+                    .about("Package the executables as they are installed on the device (e.g. signed IPAs)")
+                    .package()
+                    .job()
+                    .message_format()
+                    .install_targets()
                     .bin()
                     .example()
+                    .examples()
                     .test()
                     .bench()
-                    .package()
-                    .all()
-                    .exclude()
-                    .job()
+                    .debug_or_release()
                     .features()
-                    .no_default_features()
                     .all_features()
-                    .common_remote()
+                    .no_default_features()
                     .target()
                     .verbose()
-                    .additional_args()
                     .strip()
                     .bearded())
 
+                .subcommand(SubCommand::with_name("avd")
+                    .about("Manage the Android emulators")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(SubCommand::with_name("list")
+                        .about("List the Android virtual devices, and the emulators running them"))
+                    .subcommand(SubCommand::with_name("boot")
+                        .about("Boot an Android virtual device, and leave it running")
+                        .arg(Arg::with_name("NAME")
+                            .required(true)
+                            .help("name of the virtual device"))
+                        .arg(Arg::with_name("WINDOW")
+                            .long("window")
+                            .help("show the emulator window instead of running it headless")))
+                    .subcommand(SubCommand::with_name("kill")
+                        .about("Shut down an emulator")
+                        .arg(Arg::with_name("NAME")
+                            .required(true)
+                            .help("name of the virtual device, or serial number of the emulator"))))
+
+                .subcommand(CargoDinghyCli::bench_command())
+
                 .subcommand(SubCommand::with_name("build")
                     .about("Compile the current project")
                     .package()
                     .all()
                     .exclude()
                     .job()
+                    .message_format()
+                    .install_targets()
                     .lib()
                     .bin()
                     .example()
+                    .examples()
                     .test()
                     .bench()
                     .debug_or_release()
@@ -68,89 +158,462 @@ impl CargoDinghyCli {
                     .verbose()
                     .additional_args()
                     .strip()
+                    .dry_run()
                     .bearded())
 
+                .subcommand(CargoDinghyCli::check_command("check")
+                    .about("Check the current project for the platform, without building it"))
+
                 .subcommand(SubCommand::with_name("clean")
                     .about("Remove artifacts that cargo has generated in the past"))
 
+                .subcommand(CargoDinghyCli::check_command("clippy")
+                    .about("Lint the current project for the platform with clippy")
+                    .arg(Arg::with_name("ARGS")
+                        .multiple(true)
+                        .help("clippy arguments, after --, e.g. -- -D warnings")))
+
                 .subcommand(SubCommand::with_name("devices")
                     .about("List devices that can be used with Dinghy for the selected platform"))
 
-                .subcommand(SubCommand::with_name("lldbproxy")
-                    .about("Debug through lldb"))
+                .subcommand(SubCommand::with_name("doctor")
+                    .about("Check the environment dinghy needs, with how to fix what is missing"))
 
-                .subcommand(SubCommand::with_name("run")
-                    .about("Build and execute src/main.rs")
-                    .bin()
-                    .example()
+                .subcommand(SubCommand::with_name("fs")
+                    .about("Browse the data of an app on the device (iOS container, Android data of a debuggable package)")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(SubCommand::with_name("ls")
+                        .about("List a directory of the app data")
+                        .arg(Arg::with_name("APP")
+                            .required(true)
+                            .help("bundle identifier (iOS) or package (Android) of the app"))
+                        .arg(Arg::with_name("PATH")
+                            .help("directory, relative to the app data (default: its root)")))
+                    .subcommand(SubCommand::with_name("pull")
+                        .about("Copy a file or a directory of the app data to the host")
+                        .arg(Arg::with_name("APP")
+                            .required(true)
+                            .help("bundle identifier (iOS) or package (Android) of the app"))
+                        .arg(Arg::with_name("PATH")
+                            .required(true)
+                            .help("file or directory, relative to the app data"))
+                        .arg(Arg::with_name("DEST")
+                            .help("host directory receiving it (default: the current one)")))
+                    .subcommand(SubCommand::with_name("push")
+                        .about("Copy a file of the host to the app data")
+                        .arg(Arg::with_name("APP")
+                            .required(true)
+                            .help("bundle identifier (iOS) or package (Android) of the app"))
+                        .arg(Arg::with_name("LOCAL")
+                            .required(true)
+                            .help("file to copy"))
+                        .arg(Arg::with_name("PATH")
+                            .required(true)
+                            .help("destination, relative to the app data"))))
+
+                .subcommand(SubCommand::with_name("gc")
+                    .about("Remove from the devices the bundles and apps no run used for a while")
+                    .arg(Arg::with_name("OLDER_THAN")
+                        .long("older-than")
+                        .takes_value(true)
+                        .value_name("DAYS")
+                        .help("remove what wasn't installed or used for this many days (default: 7)"))
+                    .arg(Arg::with_name("DRY_RUN")
+                        .long("dry-run")
+                        .help("only list what would be removed")))
+
+                .subcommand(SubCommand::with_name("ios-xcode")
+                    .about("Build the staticlib crates for iOS as an xcframework, and run the XCTest suites linking it")
                     .package()
                     .job()
+                    .message_format()
                     .debug_or_release()
                     .features()
                     .all_features()
                     .no_default_features()
-                    .target()
                     .verbose()
-                    .common_remote()
-                    .additional_args()
-                    .strip()
-                    .bearded())
+                    .bearded()
+                    .arg(Arg::with_name("PLATFORMS")
+                        .long("platforms")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("iOS platform to build for, repeatable (default: ios-aarch64 and ios-x86_64)"))
+                    .arg(Arg::with_name("OUTPUT")
+                        .long("output")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("DIR")
+                        .help("directory receiving the xcframework"))
+                    .arg(Arg::with_name("UNIVERSAL")
+                        .long("universal")
+                        .help("make a universal library (and its headers) instead of an xcframework"))
+                    .arg(Arg::with_name("XCODE_TEST")
+                        .long("xcode-test")
+                        .help("then run xcodebuild test on the [ios.xcode] project, on the device selected with -d if any")))
 
-                .subcommand(SubCommand::with_name("test")
-                    .about("Run the tests")
-                    .lib()
+                .subcommand(SubCommand::with_name("jnilibs")
+                    .about("Build the cdylib crates and lay them out for Android apps (jniLibs/<abi>/, or an APK)")
+                    .package()
+                    .job()
+                    .message_format()
+                    .debug_or_release()
+                    .features()
+                    .all_features()
+                    .no_default_features()
+                    .verbose()
+                    .bearded()
+                    .arg(Arg::with_name("PLATFORMS")
+                        .long("platforms")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Android platform to build for, repeatable for several ABIs (default: the selected platform)"))
+                    .arg(Arg::with_name("APK")
+                        .long("apk")
+                        .takes_value(true)
+                        .value_name("TEMPLATE")
+                        .help("Add the libraries to a copy of this APK, written to the output path"))
+                    .arg(Arg::with_name("OUTPUT")
+                        .long("output")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("PATH")
+                        .help("jniLibs directory to fill, or APK to write with --apk")))
+
+                .subcommand(SubCommand::with_name("kill")
+                    .about("Kill processes of the device, e.g. test runs left stuck by a previous invocation")
+                    .arg(Arg::with_name("TARGET")
+                        .required(true)
+                        .value_name("PID|NAME")
+                        .help("pid of the process to kill, or name of the processes to kill")))
+
+                .subcommand(SubCommand::with_name("lldbproxy")
+                    .about("Debug through lldb"))
+
+                .subcommand(SubCommand::with_name("log")
+                    .about("Stream the console of the device (Android, iOS devices and simulators)")
+                    .arg(Arg::with_name("DEVICE")
+                        .value_name("DEVICE")
+                        .help("device to stream the console of, instead of the one of -d"))
+                    .arg(Arg::with_name("PROCESS")
+                        .long("process")
+                        .short("p")
+                        .takes_value(true)
+                        .value_name("NAME")
+                        .help("only show the messages of the process NAME"))
+                    .arg(Arg::with_name("JSON")
+                        .long("json")
+                        .help("Print the messages as JSON, one object per line"))
+                    .arg(Arg::with_name("NO_CLEAR")
+                        .long("no-clear")
+                        .help("Keep the messages logged before the start (Android clears them otherwise)")))
+
+                .subcommand(SubCommand::with_name("platforms")
+                    .about("List the platforms, whether they can be used on this host, and the tools they need")
+                    .arg(Arg::with_name("JSON")
+                        .long("json")
+                        .help("Print the listing as JSON")))
+
+                .subcommand(SubCommand::with_name("profile")
+                    .about("Build and execute src/main.rs under the device profiler")
                     .bin()
                     .example()
-                    .test()
-                    .bench()
-                    .all()
                     .package()
-                    .exclude()
                     .job()
+                    .message_format()
+                    .install_targets()
+                    .debug_or_release()
                     .features()
                     .all_features()
                     .no_default_features()
-                    .debug_or_release()
                     .target()
                     .verbose()
-                    .common_remote()
+                    .envs()
                     .additional_args()
-                    .strip()
                     .bearded())
+
+                .subcommand(SubCommand::with_name("ps")
+                    .about("List the processes running on the device")
+                    .arg(Arg::with_name("JSON")
+                        .long("json")
+                        .help("Print the listing as JSON")))
+
+                .subcommand(CargoDinghyCli::run_command())
+
+                .subcommand(SubCommand::with_name("reboot")
+                    .about("Reboot the device")
+                    .arg(Arg::with_name("DEVICE")
+                        .value_name("DEVICE")
+                        .help("device to reboot, instead of the one of -d")))
+
+                .subcommand(SubCommand::with_name("screenshot")
+                    .about("Take a screenshot of the device")
+                    .arg(Arg::with_name("OUTPUT")
+                        .required(true)
+                        .value_name("PATH")
+                        .help("file receiving the screenshot (PNG, TIFF on old iOS versions)")))
+
+                .subcommand(SubCommand::with_name("serve")
+                    .about("Serve the devices of this host to remote agent clients")
+                    .arg(Arg::with_name("LISTEN")
+                        .long("listen")
+                        .takes_value(true)
+                        .help("address to listen on, e.g. 0.0.0.0:7420")))
+
+                .subcommand(SubCommand::with_name("setup")
+                    .about("Install what dinghy needs for a family of devices")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(SubCommand::with_name("android")
+                        .about("Download an Android NDK into ~/.dinghy/ndk, and declare toolchains using it in ~/.dinghy.toml")
+                        .arg(Arg::with_name("NDK_VERSION")
+                            .long("ndk-version")
+                            .takes_value(true)
+                            .default_value(ndk::NDK_VERSION)
+                            .help("NDK release, e.g. r26d"))
+                        .arg(Arg::with_name("API")
+                            .long("api")
+                            .takes_value(true)
+                            .help("Android API level the toolchains target, the oldest supported by default"))))
+
+                .subcommand(CargoDinghyCli::test_command())
+
+                .subcommand(SubCommand::with_name("watch")
+                    .about("Build and run again on the device each time the sources change")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(CargoDinghyCli::bench_command())
+                    .subcommand(CargoDinghyCli::run_command())
+                    .subcommand(CargoDinghyCli::test_command()))
         }.get_matches_from(args)
     }
 
+    /// `check` and `clippy`: the package and target selection of `build`.
+    fn check_command<'a, 'b>(name: &str) -> App<'a, 'b> {
+        SubCommand::with_name(name)
+            .package()
+            .all()
+            .exclude()
+            .job()
+            .message_format()
+            .install_targets()
+            .lib()
+            .bin()
+            .example()
+            .examples()
+            .test()
+            .bench()
+            .debug_or_release()
+            .features()
+            .all_features()
+            .no_default_features()
+            .target()
+            .verbose()
+    }
+
+    fn bench_command<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("bench")
+            .about("Run the benchmarks")
+            .baseline()
+            .repeat()
+            .lib()
+            .bin()
+            .example()
+            .examples()
+            .test()
+            .bench()
+            .package()
+            .all()
+            .exclude()
+            .job()
+            .message_format()
+            .install_targets()
+            .features()
+            .no_default_features()
+            .all_features()
+            .common_remote()
+            .color()
+            .target()
+            .verbose()
+            .additional_args()
+            .strip()
+            .dry_run()
+            .bearded()
+    }
+
+    fn run_command<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("run")
+            .about("Build and execute src/main.rs")
+            .bin()
+            .example()
+            .examples()
+            .package()
+            .job()
+            .message_format()
+            .install_targets()
+            .debug_or_release()
+            .features()
+            .all_features()
+            .no_default_features()
+            .target()
+            .verbose()
+            .common_remote()
+            .additional_args()
+            .strip()
+            .dry_run()
+            .bearded()
+    }
+
+    fn test_command<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("test")
+            .about("Run the tests")
+            .lib()
+            .bin()
+            .example()
+            .examples()
+            .test()
+            .bench()
+            .all()
+            .package()
+            .exclude()
+            .job()
+            .message_format()
+            .install_targets()
+            .features()
+            .all_features()
+            .no_default_features()
+            .debug_or_release()
+            .target()
+            .verbose()
+            .common_remote()
+            .color()
+            .additional_args()
+            .strip()
+            .dry_run()
+            .bearded()
+    }
+
     pub fn build_args_from(matches: &ArgMatches) -> BuildArgs {
+        let (subcommand, sub_args) = CargoDinghyCli::command(matches);
         BuildArgs {
-            compile_mode: match matches.subcommand() {
-                ("bench", Some(_)) => CompileMode::Bench,
-                ("test", Some(_)) => CompileMode::Test,
+            check: match subcommand {
+                "check" => Some(CheckCommand::Check),
+                "clippy" => Some(CheckCommand::Clippy(sub_args.map(|it| arg_as_string_vec(it, "ARGS")).unwrap_or(vec![]))),
+                _ => None,
+            },
+            compile_mode: match subcommand {
+                "bench" => CompileMode::Bench,
+                "test" => CompileMode::Test,
                 _ => CompileMode::Build,
             },
+            coverage: sub_args.map(|it| it.is_present("COVERAGE")).unwrap_or(false),
+            dry_run: sub_args.map(|it| it.is_present("DRY_RUN")).unwrap_or(false),
             forced_overlays: arg_as_string_vec(matches, "OVERLAY"),
             verbose: matches.occurrences_of("VERBOSE") > 0,
         }
     }
+
+    /// The command run and its arguments, the one watched for `watch`.
+    pub fn command<'a, 'b>(matches: &'b ArgMatches<'a>) -> (&'b str, Option<&'b ArgMatches<'a>>) {
+        match matches.subcommand() {
+            ("watch", Some(watch_args)) => watch_args.subcommand(),
+            command => command,
+        }
+    }
+
+    /// Whether the compiler messages are printed as JSON on the standard
+    /// output, as asked on the command line or in the configuration.
+    pub fn json_messages(matches: &ArgMatches, conf: &Configuration) -> bool {
+        let args = CargoDinghyCli::command(matches).1.unwrap_or(matches);
+        BuildOptions::message_format(args, conf).as_ref().map(|it| it.as_str()) == Some("json")
+    }
+
+    pub fn lock_wait_from(matches: &ArgMatches) -> LockWait {
+        if matches.is_present("NO_WAIT") {
+            LockWait::NoWait
+        } else {
+            value_t!(matches, "LOCK_TIMEOUT", u64).ok()
+                .map(|it| LockWait::For(Duration::from_secs(it)))
+                .unwrap_or(LockWait::Forever)
+        }
+    }
+
+    pub fn run_args_from(matches: &ArgMatches, conf: &Configuration) -> Result<RunArgs> {
+        let mut args = conf.run.args.clone().unwrap_or(vec![]);
+        args.extend(arg_as_string_vec(matches, "ARGS"));
+        let mut envs = conf.run.env.clone().unwrap_or(HashMap::new()).into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>();
+        envs.sort();
+        let region = Region::new(matches.value_of("LOCALE").or(conf.run.locale.as_ref().map(|it| it.as_str())),
+                                 matches.value_of("TIMEZONE").or(conf.run.timezone.as_ref().map(|it| it.as_str())))?;
+        envs.extend(region.envs());
+        envs.extend(arg_as_string_vec(matches, "ENVS"));
+        // Test binaries see no terminal on the devices, tell libtest instead.
+        if let Some(color) = matches.value_of("COLOR") {
+            if !args.iter().any(|it| it.starts_with("--color")) {
+                let color = match color {
+                    "auto" if ::isatty::stdout_isatty() => "always",
+                    "auto" => "never",
+                    color => color,
+                };
+                args.push(format!("--color={}", color));
+            }
+        }
+        Ok(RunArgs {
+            args,
+            compress: matches.is_present("COMPRESS") || conf.run.compress.unwrap_or(false),
+            coverage: matches.is_present("COVERAGE"),
+            device_region: matches.is_present("DEVICE_REGION") || conf.run.device_region.unwrap_or(false),
+            envs,
+            fresh: matches.is_present("FRESH") || conf.run.fresh.unwrap_or(false),
+            keep_awake: matches.is_present("KEEP_AWAKE") || conf.run.keep_awake.unwrap_or(false),
+            lldb_commands: conf.run.lldb_commands.clone().unwrap_or(vec![]).into_iter()
+                .chain(arg_as_string_vec(matches, "LLDB_COMMAND"))
+                .collect(),
+            lldb_helpers: conf.run.lldb_helpers.clone().unwrap_or(vec![]).into_iter().map(PathBuf::from).collect(),
+            network_profile: match matches.value_of("NETWORK_PROFILE").or(conf.run.network_profile.as_ref().map(|it| it.as_str())) {
+                Some(name) => Some(NetworkProfile::named(name, conf)?),
+                None => None,
+            },
+            record: matches.is_present("RECORD_SCREEN") || conf.run.record.unwrap_or(false),
+            region,
+            screenshot_on_failure: matches.is_present("SCREENSHOT_ON_FAILURE") || conf.run.screenshot_on_failure.unwrap_or(false),
+            timeout: match matches.value_of("TIMEOUT") {
+                Some(_) => Some(value_t!(matches, "TIMEOUT", u64)?),
+                None => conf.run.timeout,
+            }.map(Duration::from_secs),
+        })
+    }
 }
 
 pub trait CargoDinghyCliExt {
     fn additional_args(self) -> Self;
     fn all(self) -> Self;
     fn all_features(self) -> Self;
+    fn baseline(self) -> Self;
     fn bin(self) -> Self;
     fn bench(self) -> Self;
+    fn color(self) -> Self;
     fn common_remote(self) -> Self;
     fn device(self) -> Self;
+    fn dry_run(self) -> Self;
+    fn envs(self) -> Self;
     fn example(self) -> Self;
+    fn examples(self) -> Self;
     fn exclude(self) -> Self;
     fn exe(self) -> Self;
     fn features(self) -> Self;
+    fn install_targets(self) -> Self;
     fn job(self) -> Self;
     fn lib(self) -> Self;
+    fn message_format(self) -> Self;
     fn no_default_features(self) -> Self;
     fn overlay(self) -> Self;
     fn package(self) -> Self;
     fn platform(self) -> Self;
+    fn record(self) -> Self;
+    fn repeat(self) -> Self;
+    fn simulator(self) -> Self;
     fn debug_or_release(self) -> Self;
     fn strip(self) -> Self;
     fn target(self) -> Self;
@@ -186,6 +649,29 @@ impl<'a, 'b> CargoDinghyCliExt for App<'a, 'b> {
             .help("only the specified benchmark target"))
     }
 
+    fn color(self) -> Self {
+        self.arg(Arg::with_name("COLOR")
+            .long("color")
+            .takes_value(true)
+            .possible_values(&["auto", "always", "never"])
+            .default_value("auto")
+            .help("Coloring of the test output: auto colors it when dinghy writes to a terminal"))
+    }
+
+    fn baseline(self) -> Self {
+        self.arg(Arg::with_name("BASELINE")
+            .long("baseline")
+            .takes_value(true)
+            .value_name("NAME")
+            .help("Record the medians of the benchmarks of the device under the current commit, and compare them to the ones of the commit NAME points to"))
+            .arg(Arg::with_name("FAIL_IF_REGRESSION")
+                .long("fail-if-regression")
+                .takes_value(true)
+                .value_name("PERCENT")
+                .requires("BASELINE")
+                .help("Fail if a median is more than PERCENT slower than in the baseline"))
+    }
+
     fn bin(self) -> Self {
         self.arg(Arg::with_name("BIN")
             .long("bin")
@@ -194,20 +680,111 @@ impl<'a, 'b> CargoDinghyCliExt for App<'a, 'b> {
     }
 
     fn common_remote(self) -> Self {
-        self
+        self.envs()
             .arg(Arg::with_name("CLEANUP")
                 .long("cleanup")
                 .takes_value(false)
                 .help("cleanup device after complete"))
+            .arg(Arg::with_name("COMPRESS")
+                .long("compress")
+                .takes_value(false)
+                .help("send the bundles compressed, for slow links to the device"))
+            .arg(Arg::with_name("COVERAGE")
+                .long("coverage")
+                .takes_value(false)
+                .help("build with coverage instrumentation and collect the coverage data of the run"))
             .arg(Arg::with_name("DEBUGGER")
                 .long("debugger")
                 .takes_value(false)
                 .help("just start debugger"))
-            .arg(Arg::with_name("ENVS")
-                .long("env")
+            .arg(Arg::with_name("DEVICE_REGION")
+                .long("device-region")
+                .help("Also set the locale and timezone of the device itself during the run (Android only)"))
+            .arg(Arg::with_name("FORWARD")
+                .long("forward")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("LOCAL:REMOTE")
+                .help("Forward a host port to a device port for the duration of the run"))
+            .arg(Arg::with_name("FRESH")
+                .long("fresh")
+                .help("Remove the app and its data from the device before installing it again, to start from a known state"))
+            .arg(Arg::with_name("GROUP")
+                .long("group")
+                .takes_value(true)
+                .value_name("NAME")
+                .help("Run on the connected devices of a group of the configuration at once, like --all-devices"))
+            .arg(Arg::with_name("JSON")
+                .long("json")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Write the parsed test results as JSON to the given file"))
+            .arg(Arg::with_name("JUNIT")
+                .long("junit")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Write the parsed test results as a JUnit XML report to the given file"))
+            .arg(Arg::with_name("KEEP_AWAKE")
+                .long("keep-awake")
+                .help("Keep the device awake, screen on, during the run"))
+            .arg(Arg::with_name("LLDB_COMMAND")
+                .long("lldb-command")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("COMMAND")
+                .help("Run an lldb command before launching the app (iOS only), after the ones of the configuration"))
+            .arg(Arg::with_name("LOCALE")
+                .long("locale")
+                .takes_value(true)
+                .value_name("LOCALE")
+                .help("Locale of the run (e.g. fr_FR), setting LANG and LC_ALL"))
+            .arg(Arg::with_name("LOCK_TIMEOUT")
+                .long("lock-timeout")
+                .takes_value(true)
+                .help("Give up if the device is still used by another dinghy run after the given number of seconds"))
+            .arg(Arg::with_name("NETWORK_PROFILE")
+                .long("network-profile")
+                .takes_value(true)
+                .value_name("PROFILE")
+                .help("Simulate the latency, loss and bandwidth of a network profile during the run (edge, 3g, lte, very-bad, loss, or one of the configuration)"))
+            .arg(Arg::with_name("NO_WAIT")
+                .long("no-wait")
+                .conflicts_with("LOCK_TIMEOUT")
+                .help("Give up right away if the device is used by another dinghy run"))
+            .arg(Arg::with_name("RECORD_SCREEN")
+                .long("record-screen")
+                .help("Record the screen of the device during the run, kept with its artifacts (Android and iOS simulator)"))
+            .arg(Arg::with_name("REVERSE")
+                .long("reverse")
                 .takes_value(true)
                 .multiple(true)
-                .help("Space-separated list of env variables to set e.g. RUST_TRACE=trace"))
+                .number_of_values(1)
+                .value_name("LOCAL:REMOTE")
+                .help("Make a host port reachable from the device for the duration of the run (Android, ssh, host and iOS simulator)"))
+            .arg(Arg::with_name("RETRIES")
+                .long("retries")
+                .takes_value(true)
+                .value_name("COUNT")
+                .help("Run the failed tests again, alone, up to COUNT times, reporting the ones passing then as flaky"))
+            .arg(Arg::with_name("SCREENSHOT_ON_FAILURE")
+                .long("screenshot-on-failure")
+                .help("Take a screenshot of the device when a runnable fails, kept with its artifacts"))
+            .arg(Arg::with_name("SHARD")
+                .long("shard")
+                .takes_value(true)
+                .value_name("INDEX/COUNT")
+                .help("Only run the runnables of one shard of the build (e.g. 2/4), or with --all-devices or --group `auto` to split them across the devices"))
+            .arg(Arg::with_name("TIMEOUT")
+                .long("timeout")
+                .takes_value(true)
+                .help("Kill the remote process if it runs for longer than the given number of seconds"))
+            .arg(Arg::with_name("TIMEZONE")
+                .long("timezone")
+                .takes_value(true)
+                .value_name("TZ")
+                .help("Timezone of the run (e.g. Europe/Paris), setting TZ"))
     }
 
     fn device(self) -> Self {
@@ -216,6 +793,24 @@ impl<'a, 'b> CargoDinghyCliExt for App<'a, 'b> {
             .long("device")
             .takes_value(true)
             .help("device hint"))
+            .arg(Arg::with_name("ALL_DEVICES")
+                .long("all-devices")
+                .conflicts_with_all(&["DEVICE", "SIM", "AVD"])
+                .help("Run on every device at once (bench, run and test only), building each platform concurrently"))
+    }
+
+    fn dry_run(self) -> Self {
+        self.arg(Arg::with_name("DRY_RUN")
+            .long("dry-run")
+            .help("Print what would be compiled, bundled and run on which device, without compiling nor touching any device"))
+    }
+
+    fn envs(self) -> Self {
+        self.arg(Arg::with_name("ENVS")
+            .long("env")
+            .takes_value(true)
+            .multiple(true)
+            .help("Space-separated list of env variables to set e.g. RUST_TRACE=trace"))
     }
 
     fn example(self) -> Self {
@@ -225,6 +820,12 @@ impl<'a, 'b> CargoDinghyCliExt for App<'a, 'b> {
             .help("only the specified example"))
     }
 
+    fn examples(self) -> Self {
+        self.arg(Arg::with_name("EXAMPLES")
+            .long("examples")
+            .help("all examples"))
+    }
+
     fn exclude(self) -> Self {
         self.arg(Arg::with_name("EXCLUDE")
             .long("exclude")
@@ -248,6 +849,12 @@ impl<'a, 'b> CargoDinghyCliExt for App<'a, 'b> {
             .help("Space-separated list of features to also build"))
     }
 
+    fn install_targets(self) -> Self {
+        self.arg(Arg::with_name("INSTALL_TARGETS")
+            .long("install-targets")
+            .help("Install the missing standard library of the target with rustup, without asking"))
+    }
+
     fn job(self) -> Self {
         self.arg(Arg::with_name("JOBS")
             .long("jobs")
@@ -262,6 +869,14 @@ impl<'a, 'b> CargoDinghyCliExt for App<'a, 'b> {
             .help("only the library"))
     }
 
+    fn message_format(self) -> Self {
+        self.arg(Arg::with_name("MESSAGE_FORMAT")
+            .long("message-format")
+            .takes_value(true)
+            .possible_values(&["human", "json"])
+            .help("Format of the compiler messages"))
+    }
+
     fn no_default_features(self) -> Self {
         self.arg(Arg::with_name("NO_DEFAULT_FEATURES")
             .long("no-default-features")
@@ -272,7 +887,7 @@ impl<'a, 'b> CargoDinghyCliExt for App<'a, 'b> {
         self.arg(Arg::with_name("STRIP")
             .long("strip")
             .takes_value(false)
-            .help("strip the final executable (will have '-stripped' extension)"))
+            .help("strip the deployed executables (the unstripped ones are kept on the host, for symbolication)"))
     }
 
     fn package(self) -> Self {
@@ -302,6 +917,55 @@ impl<'a, 'b> CargoDinghyCliExt for App<'a, 'b> {
             .help("Use a specific platform (build only)"))
     }
 
+    fn record(self) -> Self {
+        self.arg(Arg::with_name("RECORD")
+            .long("record")
+            .takes_value(true)
+            .value_name("TRACE")
+            .help("Record the operations made on the devices to the given trace file"))
+            .arg(Arg::with_name("REPLAY")
+                .long("replay")
+                .takes_value(true)
+                .value_name("TRACE")
+                .conflicts_with("RECORD")
+                .help("Use the devices recorded in the given trace file instead of the connected ones"))
+    }
+
+    fn repeat(self) -> Self {
+        self.arg(Arg::with_name("REPEAT")
+            .long("repeat")
+            .takes_value(true)
+            .value_name("COUNT")
+            .help("Run each benchmark executable COUNT times, and report the variance of the measures"))
+            .arg(Arg::with_name("COOL_DOWN")
+                .long("cool-down")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Pause before each repetition of --repeat"))
+    }
+
+    fn simulator(self) -> Self {
+        self.arg(Arg::with_name("SIM")
+            .long("sim")
+            .takes_value(true)
+            .value_name("DEVICE TYPE")
+            .help("Run on an iOS simulator of this device type (e.g. \"iPhone 8\"), booted if needed"))
+            .arg(Arg::with_name("SIM_OS")
+                .long("sim-os")
+                .takes_value(true)
+                .value_name("VERSION")
+                .requires("SIM")
+                .help("iOS version of the simulator, the most recent one by default"))
+            .arg(Arg::with_name("AVD")
+                .long("avd")
+                .takes_value(true)
+                .conflicts_with("SIM")
+                .help("Run on an emulator of this Android virtual device, booted headless if needed"))
+            .arg(Arg::with_name("KEEP_ALIVE")
+                .long("keep-alive")
+                .help("Leave the simulator or emulator booted by --sim or --avd running afterwards"))
+    }
+
     fn debug_or_release(self) -> Self {
         self
             .arg(Arg::with_name("RELEASE")
@@ -3,31 +3,88 @@ extern crate cargo;
 extern crate clap;
 extern crate dinghy_lib;
 extern crate error_chain;
-extern crate itertools;
+extern crate isatty;
 #[macro_use]
 extern crate log;
-extern crate pretty_env_logger;
 
 use clap::ArgMatches;
 use cli::CargoDinghyCli;
+use dinghy_lib::artifacts;
+use dinghy_lib::baseline;
 use dinghy_lib::Build;
+use dinghy_lib::cleanup::install_interrupt_handler;
+use dinghy_lib::compiler;
 use dinghy_lib::compiler::Compiler;
+use dinghy_lib::compiler::CompileMode;
+use dinghy_lib::config::Configuration;
 use dinghy_lib::config::dinghy_config;
+use dinghy_lib::console::ConsoleOptions;
 use dinghy_lib::Device;
+use dinghy_lib::device::agent;
+use dinghy_lib::device::avd;
+use dinghy_lib::device::simctl;
 use dinghy_lib::Dinghy;
+use dinghy_lib::doctor;
 use dinghy_lib::errors::*;
+use dinghy_lib::forward;
+use dinghy_lib::gradle;
+use dinghy_lib::gradle::GradleProject;
+use dinghy_lib::installs;
+use dinghy_lib::jnilibs;
+use dinghy_lib::launcher;
+use dinghy_lib::lock;
+use dinghy_lib::lock::LockWait;
+use dinghy_lib::logging;
+use dinghy_lib::ndk;
+use dinghy_lib::output;
 use dinghy_lib::Platform;
+use dinghy_lib::processes;
+use dinghy_lib::processes::KillTarget;
 use dinghy_lib::project::Project;
-use dinghy_lib::utils::arg_as_string_vec;
+use dinghy_lib::repetition;
+use dinghy_lib::repetition::BenchStats;
+use dinghy_lib::report;
+use dinghy_lib::report::TestOutcome;
+use dinghy_lib::report::TestReport;
+use dinghy_lib::RunArgs;
+use dinghy_lib::runner;
+use dinghy_lib::runner::DeviceQuery;
+use dinghy_lib::runner::RunOutcome;
+use dinghy_lib::runner::Runner;
+use dinghy_lib::serve;
+use dinghy_lib::setup;
+use dinghy_lib::shard::Shard;
+use dinghy_lib::timings;
+use dinghy_lib::trace;
+use dinghy_lib::watch::SourceWatcher;
+use dinghy_lib::xcframework;
 use error_chain::ChainedError;
-use itertools::Itertools;
 use std::env;
 use std::env::current_dir;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::thread;
 use std::time;
 use ErrorKind;
 
+/// `println!` for what dinghy shows, on stderr when the standard output is
+/// reserved for the compiler messages.
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        if ::dinghy_lib::output::stdout_reserved() { eprintln!($($arg)*) } else { println!($($arg)*) }
+    };
+}
+
+/// `print!`, the same way as `outln!`.
+macro_rules! out {
+    ($($arg:tt)*) => {
+        if ::dinghy_lib::output::stdout_reserved() { eprint!($($arg)*) } else { print!($($arg)*) }
+    };
+}
+
 mod cli;
 
 fn main() {
@@ -45,86 +102,782 @@ fn main() {
         };
         env::set_var("RUST_LOG", format!("cargo_dinghy={},dinghy={}", dinghy_verbosity, dinghy_verbosity));
     };
-    pretty_env_logger::init();
+    // No log file when not in a project, there is no target directory for it.
+    let log_dir = compiler::base_target_dir().ok().map(|it| it.join("dinghy").join("logs"));
+    let log_file = logging::init(&env::var("RUST_LOG").unwrap_or_default(), log_dir.as_ref().map(|it| it.as_path()))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e.display_chain());
+            None
+        });
 
-    if let Err(e) = run_command(&matches) {
+    let conf = dinghy_config(current_dir().unwrap()).map(Arc::new);
+    // The standard output only carries the compiler messages when they are
+    // consumed as JSON.
+    let json_messages = match conf {
+        Ok(ref conf) => CargoDinghyCli::json_messages(&matches, conf),
+        Err(_) => CargoDinghyCli::json_messages(&matches, &Configuration::default()),
+    };
+    if json_messages {
+        output::reserve_stdout();
+    }
+    let result = conf.and_then(|conf| run_command(&matches, conf, json_messages));
+    show_timings(json_messages);
+    if let Err(e) = result {
         error!("{}", e.display_chain());
-        println!("{}", e.display_chain());
+        if let Some(log_file) = log_file {
+            error!("The debug log of this run is in {}", log_file.display());
+        }
+        if !json_messages {
+            println!("{}", e.display_chain());
+        }
         match e.kind() {
+            &ErrorKind::RunFailed(_) => std::process::exit(2),
             &ErrorKind::PackagesCannotBeCompiledForPlatform(_) => std::process::exit(3),
+            &ErrorKind::RunTimeout(_, _) => std::process::exit(4),
+            &ErrorKind::DeviceBusy(_) => std::process::exit(5),
             _ => std::process::exit(1),
         };
     }
 }
 
-fn run_command(args: &ArgMatches) -> Result<()> {
-    let conf = Arc::new(dinghy_config(current_dir().unwrap())?);
-    let compiler = Arc::new(Compiler::from_args(args.subcommand().1.unwrap_or(args)));
-    let dinghy = Dinghy::probe(&conf, &compiler)?;
+fn run_command(args: &ArgMatches, conf: Arc<Configuration>, json_messages: bool) -> Result<()> {
+    install_interrupt_handler()?;
+    let compiler = Arc::new(Compiler::from_args(CargoDinghyCli::command(args).1.unwrap_or(args), &conf));
+    if let Some(trace) = args.value_of("RECORD") {
+        trace::start_recording(trace)?;
+    }
+    match args.subcommand() {
+        ("avd", Some(sub_args)) => return manage_avds(sub_args),
+        ("doctor", Some(_)) => return doctor(&conf, &compiler),
+        ("platforms", Some(sub_args)) => return show_platforms(&conf, &compiler, sub_args),
+        ("setup", Some(sub_args)) => return setup(sub_args),
+        _ => {}
+    }
+    // Booted before looking for devices, and shut down once done.
+    let simulator = match args.value_of("SIM") {
+        Some(sim) => Some(simctl::boot(sim, args.value_of("SIM_OS"), args.is_present("KEEP_ALIVE"))?),
+        None => None,
+    };
+    let emulator = match args.value_of("AVD") {
+        Some(avd) => Some(avd::boot(avd, false, args.is_present("KEEP_ALIVE"))?),
+        None => None,
+    };
+    let dinghy = match args.value_of("REPLAY") {
+        Some(trace) => Dinghy::replay(&conf, &compiler, Path::new(trace))?,
+        None => Dinghy::probe(&conf, &compiler)?,
+    };
     let project = Project::new(&conf);
     match args.subcommand() {
+        ("agent-devices", Some(_)) => return show_agent_devices(&dinghy),
+        ("agent-run", Some(sub_args)) => return agent_run(&dinghy, &conf, args, sub_args),
         ("all-devices", Some(_)) => return show_all_devices(&dinghy),
+        ("serve", Some(sub_args)) => return serve(&conf, sub_args),
         ("all-platforms", Some(_)) => return show_all_platforms(&dinghy),
         _ => {}
     };
 
-    let (platform, device) = select_platform_and_device_from_cli(&args, &dinghy)?;
+    if args.is_present("ALL_DEVICES") {
+        match args.subcommand() {
+            ("bench", Some(sub_args)) | ("run", Some(sub_args)) | ("test", Some(sub_args)) =>
+                return run_on_all_devices(&dinghy, &project, dinghy.devices(), args, sub_args),
+            (sub, _) => return Err(format!("--all-devices can't be used with '{}'", sub).into()),
+        }
+    }
+    match args.subcommand() {
+        ("bench", Some(sub_args)) | ("run", Some(sub_args)) | ("test", Some(sub_args)) if sub_args.is_present("GROUP") => {
+            if args.is_present("DEVICE") || args.is_present("SIM") || args.is_present("AVD") {
+                return Err("--group can't be used with -d, --sim or --avd".into());
+            }
+            let devices = group_devices(&dinghy, &conf, sub_args.value_of("GROUP").unwrap())?;
+            return run_on_all_devices(&dinghy, &project, devices, args, sub_args);
+        }
+        ("gc", Some(sub_args)) => return gc(&dinghy, args, sub_args),
+        _ => {}
+    }
+
+    let (platform, device) = select_platform_and_device_from_cli(
+        &args, &dinghy, simulator.as_ref().map(|it| it.udid()).or(emulator.as_ref().map(|it| it.serial())))?;
     info!("Targeting platform '{}' and device '{}'",
           platform.id(), device.as_ref().map(|it| it.id()).unwrap_or("<none>"));
+    let project = match device {
+        Some(ref device) => project.for_device(&platform.id(), device.id()),
+        None => project,
+    };
 
     match args.subcommand() {
-        ("bench", Some(sub_args)) => prepare_and_run(device, project, platform, args, sub_args),
+        ("android-gradle", Some(sub_args)) => android_gradle(&dinghy, device, &project, args, sub_args),
+        ("archive", Some(sub_args)) => archive(device, project, platform, args, sub_args),
+        ("bench", Some(sub_args)) => prepare_and_run(device, &project, platform, args, sub_args),
         ("build", Some(sub_args)) => build(&platform, &project, args, sub_args).and(Ok(())),
+        ("check", Some(sub_args)) => build(&platform, &project, args, sub_args).and(Ok(())),
         ("clean", Some(_)) => compiler.clean(None),
+        ("clippy", Some(sub_args)) => build(&platform, &project, args, sub_args).and(Ok(())),
         ("devices", Some(_)) => show_all_devices_for_platform(&dinghy, platform),
+        ("fs", Some(sub_args)) => app_files(device, sub_args),
+        ("ios-xcode", Some(sub_args)) => ios_xcode(&dinghy, device, &project, args, sub_args),
+        ("jnilibs", Some(sub_args)) => jnilibs(&dinghy, platform, &project, args, sub_args),
+        ("kill", Some(sub_args)) => kill(device, sub_args),
         ("lldbproxy", Some(_)) => run_lldb(device),
-        ("run", Some(sub_args)) => prepare_and_run(device, project, platform, args, sub_args),
-        ("test", Some(sub_args)) => prepare_and_run(device, project, platform, args, sub_args),
+        ("log", Some(sub_args)) => stream_console(device, sub_args),
+        ("profile", Some(sub_args)) => profile(device, project, platform, args, sub_args),
+        ("ps", Some(sub_args)) => show_processes(device, sub_args),
+        ("reboot", Some(_)) => reboot(device),
+        ("run", Some(sub_args)) => prepare_and_run(device, &project, platform, args, sub_args),
+        ("screenshot", Some(sub_args)) => screenshot(device, sub_args),
+        ("test", Some(sub_args)) => prepare_and_run(device, &project, platform, args, sub_args),
+        ("watch", Some(sub_args)) => watch(device, &project, platform, args, sub_args, json_messages),
         (sub, _) => Err(format!("Unknown dinghy command '{}'", sub))?,
     }
 }
 
+fn android_gradle(dinghy: &Dinghy,
+                  device: Option<Arc<Box<Device>>>,
+                  project: &Project,
+                  args: &ArgMatches,
+                  sub_args: &ArgMatches) -> Result<()> {
+    let gradle = GradleProject::new(sub_args.value_of("PROJECT").unwrap_or("."),
+                                    sub_args.value_of("MODULE").unwrap_or("app"));
+    let abis = gradle.abi_filters()?;
+    for (abi, platform) in gradle::platforms_for(dinghy, &abis)? {
+        let build = build(&platform, project, args, sub_args)?;
+        if build.cdylibs.is_empty() {
+            warn!("No cdylib built for {}", platform.id());
+        }
+        for lib in jnilibs::copy_to_layout(&build, abi, &gradle.jni_libs_dir())? {
+            outln!("Copied {}", lib.display());
+        }
+    }
+    if sub_args.is_present("CONNECTED_TEST") {
+        // Without -d or --avd, gradle runs the tests on every device connected.
+        let serial = match device {
+            Some(ref device) if args.is_present("DEVICE") || args.is_present("AVD") => Some(device.id()),
+            _ => None,
+        };
+        gradle.connected_android_test(serial)?;
+    }
+    Ok(())
+}
+
+fn archive(
+    device: Option<Arc<Box<Device>>>,
+    project: Project,
+    platform: Arc<Box<Platform>>,
+    args: &ArgMatches,
+    sub_args: &ArgMatches,
+) -> Result<()> {
+    let build = build(&platform, &project, args, sub_args)?;
+    let device = device.ok_or("No device found")?;
+    for archive in device.archive_app(&project, &build)? {
+        outln!("Archived {}", archive.display());
+    }
+    Ok(())
+}
+
 fn build(platform: &Arc<Box<Platform>>,
          project: &Project,
          args: &ArgMatches,
          sub_args: &ArgMatches) -> Result<Build> {
     let build_args = CargoDinghyCli::build_args_from(args);
-    let build = platform.build(&project, &build_args)?;
+    let mut build = platform.build(&project, &build_args)?;
+    let strip = sub_args.is_present("STRIP") || project.conf.build.strip.unwrap_or(false);
 
-    if sub_args.is_present("STRIP") {
-        platform.strip(&build)?;
+    if build.build_args.dry_run {
+        show_build_plan(platform, &build, strip);
+        return Ok(build);
+    }
+    if strip {
+        platform.strip(&mut build)?;
     }
     Ok(build)
 }
 
 fn prepare_and_run(
     device: Option<Arc<Box<Device>>>,
-    project: Project,
+    project: &Project,
     platform: Arc<Box<Platform>>,
     args: &ArgMatches,
     sub_args: &ArgMatches,
 ) -> Result<()> {
-    let build = build(&platform.clone(), &project, args, sub_args)?;
-    let device = device.ok_or("No device found")?;
-    let args = arg_as_string_vec(sub_args, "ARGS");
-    let envs = arg_as_string_vec(sub_args, "ENVS");
+    let mut build = build(&platform.clone(), project, args, sub_args)?;
+    match sub_args.value_of("SHARD") {
+        Some("auto") => Err("--shard auto splits the runnables across devices, it needs --all-devices or --group")?,
+        Some(shard) => Shard::parse(shard)?.select(&mut build.runnables),
+        None => {}
+    }
+    let runner = runner_for(device.ok_or("No device found")?, project, sub_args)?;
+    let run_args = CargoDinghyCli::run_args_from(sub_args, &project.conf)?;
+    if build.build_args.dry_run {
+        for line in runner.dry_run(project, &build, &run_args)? {
+            outln!("{}", line);
+        }
+        return Ok(());
+    }
 
-    let args = args.iter().map(|s| &s[..]).collect::<Vec<_>>();
-    let envs = envs.iter().map(|s| &s[..]).collect::<Vec<_>>();
-    let build_bundles = if sub_args.is_present("DEBUGGER") {
-        vec![device.debug_app(&project, &build, &*args, &*envs)?]
+    let outcome = if sub_args.is_present("DEBUGGER") {
+        runner.debug(project, &build, &run_args)?
     } else {
-        device.run_app(&project, &build, &*args, &*envs)?
+        runner.run(project, &build, &run_args)?
+    };
+    if let Some(path) = sub_args.value_of("JSON") {
+        report::write_json(path, &outcome.reports)?;
+    }
+    if let Some(path) = sub_args.value_of("JUNIT") {
+        report::write_junit(path, &outcome.reports)?;
+    }
+    conclude_run(project, &build, &runner, &run_args, &outcome, sub_args)
+}
+
+/// The runner of `device`, as set up by the options of the run.
+fn runner_for(device: Arc<Box<Device>>, project: &Project, sub_args: &ArgMatches) -> Result<Runner> {
+    let port_pairs = |name| sub_args.values_of(name)
+        .map(|specs| specs.map(forward::parse_port_pair).collect::<Result<Vec<_>>>())
+        .unwrap_or(Ok(vec![]));
+    let (forwards, reverses) = (port_pairs("FORWARD")?, port_pairs("REVERSE")?);
+    let retries = match sub_args.value_of("RETRIES") {
+        Some(retries) => retries.parse::<usize>().map_err(|_| format!("Invalid retry count '{}'", retries))?,
+        None => 0,
+    };
+    let bench_conf = &project.conf.bench;
+    let repetitions = match sub_args.value_of("REPEAT") {
+        Some(count) => count.parse::<usize>().map_err(|_| format!("Invalid repetition count '{}'", count))?,
+        None => bench_conf.repeat.unwrap_or(1),
+    };
+    let cool_down = match sub_args.value_of("COOL_DOWN") {
+        Some(seconds) => seconds.parse::<u64>().map_err(|_| format!("Invalid cool-down '{}'", seconds))?,
+        None => bench_conf.cool_down.unwrap_or(0),
+    };
+    let mut runner = Runner::new(device)
+        .lock_wait(CargoDinghyCli::lock_wait_from(sub_args))
+        .repeat(repetitions, time::Duration::from_secs(cool_down))
+        .retries(retries);
+    for (local, remote) in forwards {
+        runner = runner.forward(local, remote);
+    }
+    for (local, remote) in reverses {
+        runner = runner.reverse(local, remote);
+    }
+    Ok(runner)
+}
+
+/// Show the reports of a run, compare it to the baseline and clean up after
+/// it. Fails if the run failed, or regressed.
+fn conclude_run(project: &Project,
+                build: &Build,
+                runner: &Runner,
+                run_args: &RunArgs,
+                outcome: &RunOutcome,
+                sub_args: &ArgMatches) -> Result<()> {
+    show_reports(&outcome.reports);
+    show_bench_stats(&outcome.bench_stats, project.conf.bench.max_variation.unwrap_or(repetition::DEFAULT_MAX_VARIATION));
+    show_skipped_doctests(build);
+    if run_args.coverage {
+        match outcome.profdata {
+            Some(ref profdata) => outln!("Coverage data merged in {}", profdata.display()),
+            None => outln!("Coverage data collected in {}",
+                             build.artifacts(runner.device().id()).coverage_dir().display()),
+        }
+    }
+    let regressed = match sub_args.value_of("BASELINE") {
+        Some(baseline) if !outcome.reports.is_empty() => compare_to_baseline(project, build, runner.device().id(), baseline, sub_args, &outcome.reports)?,
+        _ => false,
     };
 
     if sub_args.is_present("CLEANUP") {
-        for build_bundle in build_bundles {
-            device.clean_app(&build_bundle)?;
+        runner.clean(outcome)?;
+    }
+
+    if !outcome.is_success() {
+        Err(ErrorKind::RunFailed("Test".to_string()))?
+    }
+    if regressed {
+        Err(ErrorKind::RunFailed(format!("The benchmarks of {} against {}",
+                                         runner.device().id(), sub_args.value_of("BASELINE").unwrap_or(""))))?
+    }
+    Ok(())
+}
+
+/// `--baseline`: records the benchmarks of the run and compares them to the
+/// baseline, the difference being shown and kept with the reports. Returns
+/// whether a benchmark regressed beyond `--fail-if-regression`.
+fn compare_to_baseline(project: &Project,
+                       build: &Build,
+                       device_id: &str,
+                       name: &str,
+                       sub_args: &ArgMatches,
+                       reports: &[TestReport]) -> Result<bool> {
+    let threshold = match sub_args.value_of("FAIL_IF_REGRESSION") {
+        Some(percent) => Some(percent.trim_right_matches('%').parse::<f64>()
+            .map_err(|_| format!("Invalid regression threshold '{}'", percent))?),
+        None => None,
+    };
+    let comparison = match baseline::compare_and_record(project, device_id, name, baseline::medians(reports), threshold)? {
+        Some(comparison) => comparison,
+        None => {
+            outln!("No baseline {} recorded for {} yet, nothing to compare", name, device_id);
+            return Ok(false);
+        }
+    };
+    outln!();
+    out!("{}", comparison);
+    let path = artifacts::ensure_dir(build.artifacts(device_id).reports_dir())?.join("bench-diff.json");
+    fs::File::create(&path)
+        .and_then(|mut file| file.write_all(comparison.to_json().pretty(2).as_bytes()))
+        .chain_err(|| format!("Couldn't write the benchmark diff to {}", path.display()))?;
+    outln!("Benchmark diff written to {}", path.display());
+    Ok(!comparison.regressions().is_empty())
+}
+
+/// `--all-devices` and `--group`: the command run on `devices` at once. The
+/// runnables are built once per platform, then run on all the devices
+/// concurrently, each from a thread of its own, their output being shown as
+/// it comes with the id of the device as a prefix. With `--shard auto`, each
+/// device runs a shard of the runnables. The reports of the devices are
+/// merged.
+fn run_on_all_devices(dinghy: &Dinghy,
+                      project: &Project,
+                      devices: Vec<Arc<Box<Device>>>,
+                      args: &ArgMatches,
+                      sub_args: &ArgMatches) -> Result<()> {
+    if sub_args.is_present("DEBUGGER") {
+        Err("--debugger can't be used with --all-devices or --group")?
+    }
+    let platform = match args.value_of("PLATFORM") {
+        Some(name) => Some(dinghy.platform_by_name(name).ok_or(format!("No '{}' platform found", name))?),
+        None => None,
+    };
+    let mut targets = vec![];
+    for device in devices {
+        if let Some(failure) = device.failure() {
+            warn!("Skipping {}, it is unavailable: {}", device.id(), failure);
+            continue;
+        }
+        let compatible = match platform {
+            Some(ref platform) if platform.is_compatible_with(&**device) => Some(platform.clone()),
+            Some(_) => None,
+            None => dinghy.platforms().iter().find(|it| it.is_compatible_with(&**device)).cloned(),
+        };
+        match compatible {
+            Some(compatible) => targets.push((device, compatible)),
+            None => info!("Skipping {}, no platform runs on it", device.id()),
+        }
+    }
+    if targets.is_empty() {
+        Err("No device found")?
+    }
+
+    // One build at a time, as a build exports the environment of its platform.
+    let mut builds: Vec<(String, Build)> = vec![];
+    for &(_, ref platform) in &targets {
+        if !builds.iter().any(|&(ref id, _)| *id == platform.id()) {
+            info!("Building for platform {}", platform.id());
+            builds.push((platform.id(), build(platform, project, args, sub_args)?));
+        }
+    }
+
+    let mut runs = vec![];
+    for (ix, (device, platform)) in targets.iter().cloned().enumerate() {
+        let mut build = builds.iter()
+            .find(|&&(ref id, _)| *id == platform.id())
+            .map(|&(_, ref build)| build.clone())
+            .ok_or(format!("No build for platform {}", platform.id()))?;
+        match sub_args.value_of("SHARD") {
+            Some("auto") => Shard::parse(&format!("{}/{}", ix + 1, targets.len()))?.select(&mut build.runnables),
+            Some(shard) => Shard::parse(shard)?.select(&mut build.runnables),
+            None => {}
+        }
+        let project = project.for_device(&platform.id(), device.id());
+        let run_args = CargoDinghyCli::run_args_from(sub_args, &project.conf)?;
+        let runner = runner_for(device, &project, sub_args)?;
+        if build.build_args.dry_run {
+            outln!("==== {} ({}) ====", runner.device().id(), platform.id());
+            for line in runner.dry_run(&project, &build, &run_args)? {
+                outln!("{}", line);
+            }
+            continue;
+        }
+        info!("Running on {} with platform {}", runner.device().id(), platform.id());
+        if runs.is_empty() {
+            output::prefix_lines();
+        }
+        runs.push((platform.id(), thread::spawn(move || {
+            let outcome = {
+                let _span = logging::span(runner.device().id().to_string());
+                runner.run(&project, &build, &run_args)
+            };
+            (project, build, runner, run_args, outcome)
+        })));
+    }
+    if runs.is_empty() {
+        return Ok(());
+    }
+
+    let mut failures = 0;
+    let mut run_failures = 0;
+    let mut results = vec![];
+    let mut reports = vec![];
+    for (platform_id, run) in runs {
+        let (project, build, runner, run_args, outcome) = run.join().map_err(|_| "A device run panicked")?;
+        let device_id = runner.device().id().to_string();
+        outln!();
+        outln!("==== {} ({}) ====", device_id, platform_id);
+        let concluded = outcome.and_then(|outcome| {
+            reports.extend(outcome.reports.iter().cloned());
+            conclude_run(&project, &build, &runner, &run_args, &outcome, sub_args)
+        });
+        if let Err(ref e) = concluded {
+            failures += 1;
+            match *e.kind() {
+                ErrorKind::RunFailed(_) => run_failures += 1,
+                _ => outln!("{}", e.display_chain()),
+            }
+        }
+        results.push((device_id, platform_id, concluded.is_ok()));
+    }
+    outln!();
+    outln!("{:<40} {:<24} {}", "device", "platform", "result");
+    for (device_id, platform_id, success) in results {
+        outln!("{:<40} {:<24} {}", device_id, platform_id, if success { "ok" } else { "FAILED" });
+    }
+    if let Some(path) = sub_args.value_of("JSON") {
+        report::write_json(path, &reports)?;
+    }
+    if let Some(path) = sub_args.value_of("JUNIT") {
+        report::write_junit(path, &reports)?;
+    }
+    // Runnables failing on every failed device is a failed run, anything
+    // else is an error of dinghy.
+    if failures > 0 && run_failures == failures {
+        Err(ErrorKind::RunFailed(format!("The run on {} of {} devices", failures, targets.len())))?
+    } else if failures > 0 {
+        Err(format!("Failed on {} of {} devices 🐛", failures, targets.len()))?
+    }
+    Ok(())
+}
+
+/// The devices of the group `name` of the configuration: the devices matching
+/// any of its hints, warning about the hints no device matches.
+fn group_devices(dinghy: &Dinghy, conf: &Configuration, name: &str) -> Result<Vec<Arc<Box<Device>>>> {
+    let hints = conf.groups.get(name).ok_or(format!("No device group '{}' in the configuration", name))?;
+    let mut devices: Vec<Arc<Box<Device>>> = vec![];
+    for hint in hints {
+        let query = DeviceQuery::new().device(hint);
+        let matching = dinghy.devices().into_iter().filter(|it| query.matches(&***it)).collect::<Vec<_>>();
+        if matching.is_empty() {
+            warn!("No device found for `{}' of group '{}', skipping it", hint, name);
+        }
+        for device in matching {
+            if !devices.iter().any(|it| it.id() == device.id()) {
+                devices.push(device);
+            }
+        }
+    }
+    Ok(devices)
+}
+
+/// `gc`: remove the installs no run used for a while from the devices, or
+/// from the one of `-d`.
+fn gc(dinghy: &Dinghy, args: &ArgMatches, sub_args: &ArgMatches) -> Result<()> {
+    let days = match sub_args.value_of("OLDER_THAN") {
+        Some(days) => days.parse::<u64>().map_err(|_| format!("Invalid number of days '{}'", days))?,
+        None => 7,
+    };
+    let max_age = time::Duration::from_secs(days * 24 * 3600);
+    let query = DeviceQuery {
+        platform: None,
+        device: args.value_of("DEVICE").map(|it| it.to_string()),
+    };
+    for device in dinghy.devices().into_iter().filter(|it| query.matches(&***it)) {
+        let stale = installs::stale(device.id(), max_age)?;
+        if stale.is_empty() {
+            continue;
+        }
+        let _lock = match lock::lock_for_run(&**device, LockWait::NoWait) {
+            Ok(lock) => lock,
+            Err(_) => {
+                warn!("{} is used by another dinghy run, skipping it", device.id());
+                continue;
+            }
+        };
+        for location in stale {
+            if sub_args.is_present("DRY_RUN") {
+                println!("Would remove {} from {}", location, device.id());
+                continue;
+            }
+            match device.uninstall(&location) {
+                Ok(()) => {
+                    installs::forget(device.id(), &location)?;
+                    println!("Removed {} from {}", location, device.id());
+                }
+                Err(e) => warn!("{}", e),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `watch`: the command run again each time the sources change, until
+/// interrupted. Unchanged bundles and files are not sent to the device again.
+fn watch(device: Option<Arc<Box<Device>>>,
+         project: &Project,
+         platform: Arc<Box<Platform>>,
+         args: &ArgMatches,
+         watch_args: &ArgMatches,
+         json_messages: bool) -> Result<()> {
+    let sub_args = match watch_args.subcommand() {
+        (_, Some(sub_args)) => sub_args,
+        _ => return Err("watch requires a bench, run or test command".into()),
+    };
+    if sub_args.is_present("GROUP") {
+        return Err("watch can't be used with --group".into());
+    }
+    let root = project.project_dir()?.canonicalize()?;
+    let ignored = ::std::iter::once(root.join("target"))
+        .chain(env::var_os("CARGO_TARGET_DIR").and_then(|it| PathBuf::from(it).canonicalize().ok()))
+        .collect();
+    let watcher = SourceWatcher::new(&root, ignored)?;
+    loop {
+        if let Err(e) = prepare_and_run(device.clone(), project, platform.clone(), args, sub_args) {
+            error!("{}", e.display_chain());
+        }
+        show_timings(json_messages);
+        outln!();
+        outln!("Watching {} for changes...", root.display());
+        let changed = watcher.wait_for_change()?;
+        info!("{} changed, running again", changed[0].display());
+    }
+}
+
+fn agent_run(dinghy: &Dinghy, conf: &Configuration, args: &ArgMatches, sub_args: &ArgMatches) -> Result<()> {
+    let device_id = args.value_of("DEVICE").ok_or("agent-run requires a device id")?;
+    let device = dinghy.devices()
+        .into_iter()
+        .find(|it| it.id() == device_id)
+        .ok_or(format!("No device {} on this agent", device_id))?;
+    let compile_mode = match sub_args.value_of("MODE") {
+        Some("bench") => CompileMode::Bench,
+        Some("test") => CompileMode::Test,
+        _ => CompileMode::Build,
+    };
+    let run_args = CargoDinghyCli::run_args_from(sub_args, conf)?;
+    // Runs from several hosts queue up on the agent.
+    let _lock = lock::lock_for_run(&**device, LockWait::Forever)?;
+    let reports = agent::run_shipped_bundle(&**device,
+                                            Path::new(sub_args.value_of("BUNDLE").ok_or("agent-run requires a bundle")?),
+                                            Path::new(sub_args.value_of("LIBS").ok_or("agent-run requires a library directory")?),
+                                            compile_mode,
+                                            &run_args)?;
+    if reports.iter().any(|it| !it.is_success()) {
+        Err(ErrorKind::RunFailed("Test".to_string()))?
+    }
+    Ok(())
+}
+
+fn ios_xcode(dinghy: &Dinghy,
+             device: Option<Arc<Box<Device>>>,
+             project: &Project,
+             args: &ArgMatches,
+             sub_args: &ArgMatches) -> Result<()> {
+    let names = sub_args.values_of("PLATFORMS").map(|it| it.collect()).unwrap_or(vec!["ios-aarch64", "ios-x86_64"]);
+    let output = PathBuf::from(sub_args.value_of("OUTPUT").ok_or("ios-xcode requires an output directory")?);
+    let mut libs = vec![];
+    let mut target_path = None;
+    for name in names {
+        let platform = dinghy.platform_by_name(name).ok_or(format!("No '{}' platform found", name))?;
+        let rustc_triple = match platform.rustc_triple() {
+            Some(triple) if triple.contains("-apple-ios") => triple.to_string(),
+            _ => Err(format!("'{}' is not an iOS platform", name))?,
+        };
+        let build = build(&platform, project, args, sub_args)?;
+        if build.staticlibs.is_empty() {
+            warn!("No staticlib built for {}", platform.id());
+        }
+        target_path.get_or_insert(build.target_path.clone());
+        libs.extend(build.staticlibs.iter().map(|lib| (rustc_triple.clone(), lib.clone())));
+    }
+    let file_name = libs.first().and_then(|&(_, ref lib)| lib.file_name()).ok_or("No staticlib built")?.to_owned();
+    if libs.iter().any(|&(_, ref lib)| lib.file_name() != Some(&file_name)) {
+        Err("Several staticlib crates were built, select one with -p")?
+    }
+    let lib_name = file_name.to_string_lossy();
+    let name = if lib_name.starts_with("lib") { &lib_name[3..] } else { &lib_name[..] };
+    let name = name.trim_right_matches(".a").to_string();
+
+    let scratch = target_path.ok_or("No build made")?.join("dinghy").join("xcframework");
+    artifacts::reset_dir(&scratch)?;
+    let headers = match project.conf.ios.cbindgen {
+        Some(ref cbindgen) => {
+            let headers = scratch.join("headers");
+            xcframework::generate_header(Path::new(cbindgen), &name, &headers)?;
+            Some(headers)
+        }
+        None => None,
+    };
+    artifacts::ensure_dir(&output)?;
+    if sub_args.is_present("UNIVERSAL") {
+        let universal = output.join(&file_name);
+        xcframework::lipo(&libs.iter().map(|&(_, ref lib)| lib.clone()).collect::<Vec<_>>(), &universal)?;
+        if let Some(ref headers) = headers {
+            for entry in fs::read_dir(headers)? {
+                let entry = entry?;
+                fs::copy(entry.path(), artifacts::ensure_dir(output.join("include"))?.join(entry.file_name()))?;
+            }
+        }
+        outln!("Universal library saved in {}", universal.display());
+    } else {
+        let framework = output.join(format!("{}.xcframework", name));
+        xcframework::create_xcframework(&libs, headers.as_ref().map(|it| it.as_path()), &scratch, &framework)?;
+        outln!("XCFramework saved in {}", framework.display());
+    }
+
+    if sub_args.is_present("XCODE_TEST") {
+        let xcode = project.conf.ios.xcode.as_ref().ok_or("No Xcode project, set one in the [ios.xcode] configuration")?;
+        let destination = match device {
+            Some(ref device) if args.is_present("DEVICE") || args.is_present("SIM") => format!("id={}", device.id()),
+            _ => xcode.destination.clone()
+                .ok_or("No destination for the tests, select a device with -d or set `destination` in [ios.xcode]")?,
+        };
+        xcframework::xcodebuild_test(xcode, &destination)?;
+    }
+    Ok(())
+}
+
+fn jnilibs(dinghy: &Dinghy,
+           platform: Arc<Box<Platform>>,
+           project: &Project,
+           args: &ArgMatches,
+           sub_args: &ArgMatches) -> Result<()> {
+    let platforms = match sub_args.values_of("PLATFORMS") {
+        Some(names) => names
+            .map(|name| dinghy.platform_by_name(name).ok_or(format!("No '{}' platform found", name).into()))
+            .collect::<Result<Vec<_>>>()?,
+        None => vec![platform],
+    };
+    let output = PathBuf::from(sub_args.value_of("OUTPUT").ok_or("jnilibs requires an output path")?);
+    let template = sub_args.value_of("APK");
+    let mut staging = None;
+    for platform in platforms {
+        let abi = jnilibs::android_abi(&**platform)?;
+        let build = build(&platform, project, args, sub_args)?;
+        if template.is_some() && staging.is_none() {
+            let dir = build.target_path.join("dinghy").join("apk");
+            artifacts::reset_dir(&dir)?;
+            staging = Some(dir);
+        }
+        let layout_dir = match staging {
+            // The APK layout is the jniLibs one, under `lib`.
+            Some(ref staging) => staging.join("lib"),
+            None => output.clone(),
+        };
+        let libs = jnilibs::copy_to_layout(&build, abi, &layout_dir)?;
+        if build.cdylibs.is_empty() {
+            warn!("No cdylib built for {}", platform.id());
+        }
+        for lib in libs {
+            outln!("Copied {}", lib.display());
         }
     }
+    if let (Some(template), Some(staging)) = (template, staging) {
+        jnilibs::package_apk(Path::new(template), &staging, &output)?;
+        outln!("Packaged {}, sign it again (e.g. with apksigner) before installing it", output.display());
+    }
     Ok(())
 }
 
+fn profile(
+    device: Option<Arc<Box<Device>>>,
+    project: Project,
+    platform: Arc<Box<Platform>>,
+    args: &ArgMatches,
+    sub_args: &ArgMatches,
+) -> Result<()> {
+    let build = build(&platform.clone(), &project, args, sub_args)?;
+    let device = device.ok_or("No device found")?;
+    let run_args = CargoDinghyCli::run_args_from(sub_args, &project.conf)?;
+    let _lock = lock::lock_for_run(&**device, CargoDinghyCli::lock_wait_from(sub_args))?;
+    let (_, trace) = device.profile_app(&project, &build, &run_args)?;
+    outln!("Profiling trace saved in {}", trace.display());
+    Ok(())
+}
+
+fn show_build_plan(platform: &Arc<Box<Platform>>, build: &Build, strip: bool) {
+    outln!("Dry run: nothing is compiled nor sent to any device.");
+    outln!("Would build for platform '{}' ({:?}) in {}",
+             platform.id(), build.build_args.compile_mode, build.target_path.display());
+    for runnable in &build.runnables {
+        outln!("  * {} from {}", runnable.exe.display(), runnable.source.display());
+    }
+    if strip {
+        outln!("  and strip them");
+    }
+    show_skipped_doctests(build);
+}
+
+fn show_reports(reports: &[TestReport]) {
+    if !reports.iter().any(|it| it.summary.is_some() || !it.tests.is_empty()) {
+        return;
+    }
+    outln!();
+    outln!("{:<40} {:>8} {:>8} {:>8} {:>8}  {}", "runnable", "passed", "failed", "ignored", "time", "result");
+    for report in reports {
+        let (passed, failed, ignored) = match report.summary {
+            Some(ref summary) => (summary.passed, summary.failed, summary.ignored),
+            None => (report.count(TestOutcome::Ok), report.count(TestOutcome::Failed), report.count(TestOutcome::Ignored)),
+        };
+        let flaky = report.count(TestOutcome::Flaky);
+        let exit = match (report.exit_code, report.signal) {
+            // Passed on retry.
+            _ if report.exit_success => String::new(),
+            (_, Some(signal)) => format!(" (signal {})", signal),
+            (Some(code), None) if code != 0 => format!(" (exit code {})", code),
+            _ => String::new(),
+        };
+        outln!("{:<40} {:>8} {:>8} {:>8} {:>8}  {}{}{}",
+                 report.runnable, passed, failed, ignored,
+                 report.duration.map(|it| format!("{:.1}s", timings::secs(it))).unwrap_or(String::new()),
+                 if report.is_success() { "ok" } else { "FAILED" },
+                 exit,
+                 if flaky > 0 { format!(" ({} flaky)", flaky) } else { String::new() });
+    }
+}
+
+fn show_bench_stats(bench_stats: &[BenchStats], max_variation: f64) {
+    if bench_stats.is_empty() {
+        return;
+    }
+    outln!();
+    outln!("{:<40} {:>8} {:>14} {:>24} {:>9}", "benchmark", "runs", "mean (ns)", "95% interval (ns)", "variation");
+    for stats in bench_stats {
+        let (low, high) = stats.interval();
+        outln!("{:<40} {:>8} {:>14.1} {:>24} {:>8.1}%{}",
+                 stats.name, stats.samples.len(), stats.mean(), format!("{:.1} - {:.1}", low, high), stats.variation(),
+                 if stats.is_unstable(max_variation) { "  UNSTABLE" } else { "" });
+    }
+}
+
+/// Where the time of the command went, as a JSON message along the compiler
+/// ones when they are consumed as JSON.
+fn show_timings(json_messages: bool) {
+    let mut timings = timings::take();
+    timings.compiler_cache = launcher::stats();
+    if timings.is_empty() {
+        return;
+    }
+    if json_messages {
+        println!("{}", timings.to_json().dump());
+    } else {
+        println!();
+        println!("Timings: {}", timings);
+    }
+}
+
+fn show_skipped_doctests(build: &Build) {
+    if !build.doctests.is_empty() {
+        outln!();
+        outln!("Doctests skipped for {}: they only run on the host, use `cargo test --doc` to run them",
+                 build.doctests.join(", "));
+    }
+}
+
 fn run_lldb(device: Option<Arc<Box<Device>>>) -> Result<()> {
     let device = device.ok_or("No device found")?;
     let lldb = device.start_remote_lldb()?;
@@ -134,6 +887,121 @@ fn run_lldb(device: Option<Arc<Box<Device>>>) -> Result<()> {
     }
 }
 
+fn app_files(device: Option<Arc<Box<Device>>>, sub_args: &ArgMatches) -> Result<()> {
+    let device = device.ok_or("No device found")?;
+    match sub_args.subcommand() {
+        ("ls", Some(ls_args)) => {
+            for entry in device.list_app_files(ls_args.value_of("APP").unwrap(), ls_args.value_of("PATH").unwrap_or(""))? {
+                println!("{}", entry);
+            }
+        }
+        ("pull", Some(pull_args)) => {
+            let dest = Path::new(pull_args.value_of("DEST").unwrap_or("."));
+            device.pull_app_files(pull_args.value_of("APP").unwrap(), pull_args.value_of("PATH").unwrap(), dest)?;
+        }
+        ("push", Some(push_args)) => {
+            device.push_app_file(push_args.value_of("APP").unwrap(),
+                                 Path::new(push_args.value_of("LOCAL").unwrap()),
+                                 push_args.value_of("PATH").unwrap())?;
+        }
+        (sub, _) => Err(format!("Unknown fs command '{}'", sub))?,
+    }
+    Ok(())
+}
+
+fn reboot(device: Option<Arc<Box<Device>>>) -> Result<()> {
+    let device = device.ok_or("No device found")?;
+    let _lock = lock::lock_for_run(&**device, LockWait::Forever)?;
+    device.reboot()?;
+    info!("{} rebooted", device.id());
+    Ok(())
+}
+
+fn kill(device: Option<Arc<Box<Device>>>, sub_args: &ArgMatches) -> Result<()> {
+    let device = device.ok_or("No device found")?;
+    let target = KillTarget::parse(sub_args.value_of("TARGET").unwrap());
+    let killed = device.kill(&target)?;
+    if killed.is_empty() {
+        Err(format!("No process {} on {}", target, device.id()))?
+    }
+    for process in &killed {
+        info!("Killed {} ({}) on {}", process.name, process.pid, device.id());
+    }
+    Ok(())
+}
+
+fn show_processes(device: Option<Arc<Box<Device>>>, sub_args: &ArgMatches) -> Result<()> {
+    let device = device.ok_or("No device found")?;
+    let running = device.processes()?;
+    if sub_args.is_present("JSON") {
+        println!("{}", processes::to_json(&running).pretty(2));
+        return Ok(());
+    }
+    println!("{:>7} {:<12} {}", "PID", "USER", "NAME");
+    for process in &running {
+        println!("{}", process);
+    }
+    Ok(())
+}
+
+fn screenshot(device: Option<Arc<Box<Device>>>, sub_args: &ArgMatches) -> Result<()> {
+    let device = device.ok_or("No device found")?;
+    let path = Path::new(sub_args.value_of("OUTPUT").unwrap());
+    device.screenshot(path)?;
+    info!("Screenshot of {} saved to {}", device.id(), path.display());
+    Ok(())
+}
+
+fn serve(conf: &Configuration, sub_args: &ArgMatches) -> Result<()> {
+    let listen = sub_args.value_of("LISTEN")
+        .map(|it| it.to_string())
+        .or(conf.serve.listen.clone())
+        .ok_or("No address to listen on, use --listen or set `listen` in the [serve] configuration")?;
+    let token = env::var("DINGHY_SERVE_TOKEN").ok()
+        .or(conf.serve.token.clone())
+        .ok_or("No token, set DINGHY_SERVE_TOKEN or `token` in the [serve] configuration")?;
+    let work_dir = conf.serve.path.clone().unwrap_or("/tmp/dinghy-serve".to_string());
+    serve::serve(&listen, &token, Path::new(&work_dir))
+}
+
+fn stream_console(device: Option<Arc<Box<Device>>>, sub_args: &ArgMatches) -> Result<()> {
+    let device = device.ok_or("No device found")?;
+    device.stream_console(&ConsoleOptions {
+        process: sub_args.value_of("PROCESS").map(|it| it.to_string()),
+        clear: !sub_args.is_present("NO_CLEAR"),
+        json: sub_args.is_present("JSON"),
+        colored: !sub_args.is_present("JSON") && isatty::stdout_isatty(),
+    })
+}
+
+fn manage_avds(sub_args: &ArgMatches) -> Result<()> {
+    match sub_args.subcommand() {
+        ("boot", Some(boot_args)) => {
+            let name = boot_args.value_of("NAME").ok_or("avd boot requires a virtual device name")?;
+            let emulator = avd::boot(name, boot_args.is_present("WINDOW"), true)?;
+            println!("{} running as {}", name, emulator.serial());
+        }
+        ("kill", Some(kill_args)) => avd::kill(kill_args.value_of("NAME").ok_or("avd kill requires a virtual device name")?)?,
+        _ => {
+            let running = avd::running()?;
+            for name in avd::list()? {
+                match running.iter().find(|&&(_, ref avd)| *avd == name) {
+                    Some(&(ref serial, _)) => println!("{} (running as {})", name, serial),
+                    None => println!("{}", name),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn show_agent_devices(dinghy: &Dinghy) -> Result<()> {
+    for line in agent::describe_devices(dinghy) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
 fn show_all_devices(dinghy: &Dinghy) -> Result<()> {
     println!("List of available devices for all platforms:");
     show_devices(&dinghy, None)
@@ -146,6 +1014,73 @@ fn show_all_platforms(dinghy: &Dinghy) -> Result<()> {
     Ok(())
 }
 
+fn doctor(conf: &Arc<Configuration>, compiler: &Arc<Compiler>) -> Result<()> {
+    let checks = doctor::checks(conf, compiler)?;
+    for check in &checks {
+        println!("[{}] {}: {}", if check.passed() { " ok " } else { "FAIL" }, check.name, check.detail);
+        if let Some(ref fix) = check.fix {
+            println!("       fix: {}", fix);
+        }
+    }
+    let failed = checks.iter().filter(|it| !it.passed()).count();
+    if failed > 0 {
+        Err(format!("{} of {} checks failed", failed, checks.len()))?
+    }
+    println!("All {} checks passed", checks.len());
+    Ok(())
+}
+
+fn setup(sub_args: &ArgMatches) -> Result<()> {
+    match sub_args.subcommand() {
+        ("android", Some(android_args)) => {
+            let version = android_args.value_of("NDK_VERSION").unwrap_or(ndk::NDK_VERSION);
+            let api = match android_args.value_of("API") {
+                Some(api) => api.parse().map_err(|_| format!("Invalid Android API level {}", api))?,
+                None => ndk::MIN_API,
+            };
+            let ndk_dir = ndk::install(version)?;
+            let entries = ndk::toolchain_entries(&ndk::prebuilt_dir(&ndk_dir)?, api);
+            let config = env::home_dir().ok_or("No home directory")?.join(".dinghy.toml");
+            for triple in ndk::configure(&config, &entries)? {
+                println!("Added the {} toolchain to {}", triple, config.display());
+            }
+            println!("NDK {} installed in {}", version, ndk_dir.display());
+            Ok(())
+        }
+        _ => Err("Unknown setup".into()),
+    }
+}
+
+fn show_platforms(conf: &Arc<Configuration>, compiler: &Arc<Compiler>, sub_args: &ArgMatches) -> Result<()> {
+    let tools = setup::host_tools();
+    let platforms = setup::platforms(conf, compiler)?;
+    if sub_args.is_present("JSON") {
+        println!("{}", setup::to_json(&tools, &platforms).pretty(2));
+        return Ok(());
+    }
+    println!("Host tools:");
+    for tool in &tools {
+        let path = tool.path.as_ref().map(|it| it.display().to_string()).unwrap_or("not found".to_string());
+        println!("  {:16} {} ({})", tool.name, path, tool.purpose);
+    }
+    println!("Platforms:");
+    for platform in &platforms {
+        let triple = platform.rustc_triple.as_ref().map(|it| it.as_str()).unwrap_or("host");
+        if let Some(ref unavailable) = platform.unavailable {
+            println!("  {:16} {:32} unavailable: {}", platform.id, triple, unavailable);
+            continue;
+        }
+        let target = match platform.target_installed {
+            Some(true) => "target installed".to_string(),
+            Some(false) => format!("target missing (rustup target add {})", triple),
+            None => "target unknown".to_string(),
+        };
+        let devices = if platform.devices.is_empty() { "none".to_string() } else { platform.devices.join(", ") };
+        println!("  {:16} {:32} {}, devices: {}", platform.id, triple, target, devices);
+    }
+    Ok(())
+}
+
 fn show_all_devices_for_platform(dinghy: &Dinghy, platform: Arc<Box<Platform>>) -> Result<()> {
     println!("List of available devices for platform '{}':", platform.id());
     show_devices(&dinghy, Some(platform))
@@ -160,43 +1095,30 @@ fn show_devices(dinghy: &Dinghy, platform: Option<Arc<Box<Platform>>>) -> Result
         error!("No matching device found");
         println!("No matching device found");
     } else {
-        for device in devices { println!("{}", device); }
+        let ids = devices.iter().map(|it| it.id()).collect::<Vec<_>>();
+        for device in &devices {
+            // Devices with the same name are told apart by their id.
+            if devices.iter().filter(|it| it.name() == device.name()).count() > 1 {
+                println!("{} (-d {})", device, runner::unique_id_prefix(device.id(), &ids));
+            } else {
+                println!("{}", device);
+            }
+        }
     }
     Ok(())
 }
 
 fn select_platform_and_device_from_cli(matches: &ArgMatches,
-                                       dinghy: &Dinghy) -> Result<(Arc<Box<Platform>>, Option<Arc<Box<Device>>>)> {
-    if let Some(platform_name) = matches.value_of("PLATFORM") {
-        let platform = dinghy
-            .platform_by_name(platform_name)
-            .ok_or(format!("No '{}' platform found", platform_name))?;
-
-        let device = dinghy.devices()
-            .into_iter()
-            .filter(|device| matches.value_of("DEVICE")
-                .map(|filter| format!("{}", device).to_lowercase().contains(&filter.to_lowercase()))
-                .unwrap_or(true))
-            .filter(|it| platform.is_compatible_with(&**it.as_ref()))
-            .next();
-
-        Ok((platform, device))
-    } else if let Some(device_filter) = matches.value_of("DEVICE") {
-        let devices = dinghy.devices()
-            .into_iter()
-            .filter(move |it| format!("{}", it).to_lowercase().contains(&device_filter.to_lowercase()))
-            .collect_vec();
-        if devices.len() == 0 {
-            Err(format!("No devices found for name hint `{}'", device_filter))?;
-        }
-        devices.into_iter().filter_map(|d| {
-            let pf = dinghy.platforms().iter().find(|pf| pf.is_compatible_with(&**d)).cloned();
-            debug!("Looking for platform for {}: found {:?}", d.id(), pf.as_ref().map(|p| p.id()));
-            pf.map(|it| (it,Some(d)))
-        })
-        .next()
-        .ok_or(format!("No device and platform combination found for device hint `{}'", device_filter).into())
-    } else {
-        Ok((dinghy.host_platform(), Some(dinghy.host_device())))
-    }
+                                       dinghy: &Dinghy,
+                                       booted: Option<&str>) -> Result<(Arc<Box<Platform>>, Option<Arc<Box<Device>>>)> {
+    // `cargo dinghy reboot <device>` and `log <device>` name the device after
+    // the command.
+    let command_device = match matches.subcommand() {
+        ("log", Some(sub_args)) | ("reboot", Some(sub_args)) => sub_args.value_of("DEVICE"),
+        _ => None,
+    };
+    DeviceQuery {
+        platform: matches.value_of("PLATFORM").map(|it| it.to_string()),
+        device: matches.value_of("DEVICE").or(command_device).or(booted).map(|it| it.to_string()),
+    }.select(dinghy)
 }
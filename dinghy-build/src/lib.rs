@@ -20,6 +20,7 @@ extern crate log;
 
 pub mod build;
 pub mod build_env;
+pub mod triple;
 pub mod utils;
 
 use build::is_cross_compiling;
@@ -30,6 +31,7 @@ use std::ffi::OsStr;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
+use triple::Triple;
 use utils::path_to_str;
 use utils::path_between;
 
@@ -65,7 +67,7 @@ pub trait CommandExt {
 
 impl CommandExt for Command {
     fn arg_for_macos<S: AsRef<OsStr>>(&mut self, arg: S) -> Result<&mut Command> {
-        if env::var("TARGET").map(|target| target.contains("-apple-darwin")).unwrap_or(false) {
+        if Triple::target().map(|target| target.is_apple() && target.os == "darwin").unwrap_or(false) {
             self.arg(arg.as_ref());
         }
         Ok(self)
@@ -0,0 +1,211 @@
+//! Target triples, assembled in one place.
+//!
+//! Rust target triples don't follow a single pattern (`aarch64-apple-ios`,
+//! `armv7-linux-androideabi`, `x86_64-unknown-linux-gnu`), and the cfg values
+//! given to build.rs scripts (`CARGO_CFG_TARGET_ARCH`, ...) lose part of the
+//! information (`arm` for all the 32 bits ARM flavours). `Triple` rebuilds the
+//! full triple from either side.
+
+use std::env;
+use std::fmt;
+use Result;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Triple {
+    pub arch: String,
+    /// `None` for the triples without a vendor part (Android).
+    pub vendor: Option<String>,
+    pub os: String,
+    pub env: Option<String>,
+}
+
+impl Triple {
+    /// Split a triple into its parts.
+    pub fn parse(triple: &str) -> Option<Triple> {
+        let parts: Vec<&str> = triple.split('-').collect();
+        match parts.len() {
+            3 if parts[1] == "linux" => Some(Triple::new(parts[0], None, parts[1], Some(parts[2]))),
+            3 => Some(Triple::new(parts[0], Some(parts[1]), parts[2], None)),
+            4 => Some(Triple::new(parts[0], Some(parts[1]), parts[2], Some(parts[3]))),
+            _ => None,
+        }
+    }
+
+    /// Rebuild a triple from the `target_arch`, `target_vendor`, `target_os`
+    /// and `target_env` cfg values.
+    pub fn from_cfg(arch: &str, vendor: &str, os: &str, target_env: &str) -> Triple {
+        match os {
+            "ios" => Triple::ios(if arch == "arm" { "armv7" } else { arch }),
+            "android" => Triple::android(match arch {
+                "arm" => "armv7",
+                "x86" => "i686",
+                arch => arch,
+            }),
+            _ => Triple::new(arch,
+                             Some(vendor),
+                             os,
+                             if target_env.is_empty() { None } else { Some(target_env) }),
+        }
+    }
+
+    /// The triple of an iOS device or simulator of the given CPU architecture.
+    pub fn ios(arch: &str) -> Triple {
+        Triple::new(arch, Some("apple"), "ios", None)
+    }
+
+    /// The triple of an iOS simulator running on a host of the given CPU
+    /// architecture: the Intel ones don't tell the simulator apart.
+    pub fn ios_simulator(arch: &str) -> Triple {
+        Triple::new(arch, Some("apple"), "ios", if arch == "x86_64" { None } else { Some("sim") })
+    }
+
+    /// The triple of an Apple Watch of the given CPU architecture.
+    pub fn watchos(arch: &str) -> Triple {
+        Triple::new(arch, Some("apple"), "watchos", None)
+    }
+
+    /// The triple of an Android device of the given CPU architecture.
+    pub fn android(arch: &str) -> Triple {
+        let env = if arch.starts_with("arm") || arch.starts_with("thumb") { "androideabi" } else { "android" };
+        Triple::new(arch, None, "linux", Some(env))
+    }
+
+    /// The triple running the binaries of an Android ABI (as listed by the
+    /// `ro.product.cpu.abilist` property).
+    pub fn android_abi(abi: &str) -> Option<Triple> {
+        Some(Triple::android(match abi {
+            "arm64-v8a" => "aarch64",
+            "armeabi-v7a" => "armv7",
+            "armeabi" => "arm",
+            "x86" => "i686",
+            "x86_64" => "x86_64",
+            _ => return None,
+        }))
+    }
+
+    /// The triple being built for, in a build.rs script.
+    pub fn target() -> Result<Triple> {
+        if let Ok(triple) = env::var("TARGET") {
+            return Triple::parse(&triple).ok_or(format!("Unrecognized target triple {}", triple).into());
+        }
+        Ok(Triple::from_cfg(&env::var("CARGO_CFG_TARGET_ARCH")?,
+                            &env::var("CARGO_CFG_TARGET_VENDOR")?,
+                            &env::var("CARGO_CFG_TARGET_OS")?,
+                            &env::var("CARGO_CFG_TARGET_ENV").unwrap_or(String::new())))
+    }
+
+    /// The Android ABI of the triple, as named in `jniLibs` and APKs.
+    pub fn to_android_abi(&self) -> Option<&'static str> {
+        if !self.is_android() {
+            return None;
+        }
+        match self.arch.as_str() {
+            "aarch64" => Some("arm64-v8a"),
+            "armv7" | "thumbv7neon" => Some("armeabi-v7a"),
+            "arm" => Some("armeabi"),
+            "i686" => Some("x86"),
+            "x86_64" => Some("x86_64"),
+            _ => None,
+        }
+    }
+
+    /// The architecture of the qemu-user emulator (`qemu-<arch>`) running
+    /// the Linux executables of this triple.
+    pub fn to_qemu_arch(&self) -> Option<&'static str> {
+        if self.os != "linux" || self.is_android() {
+            return None;
+        }
+        match self.arch.as_str() {
+            "aarch64" => Some("aarch64"),
+            "arm" | "armv5te" | "armv7" | "thumbv7neon" => Some("arm"),
+            "i586" | "i686" => Some("i386"),
+            "mips" => Some("mips"),
+            "mipsel" => Some("mipsel"),
+            "mips64" => Some("mips64"),
+            "mips64el" => Some("mips64el"),
+            "powerpc" => Some("ppc"),
+            "powerpc64" => Some("ppc64"),
+            "powerpc64le" => Some("ppc64le"),
+            "riscv64gc" => Some("riscv64"),
+            "s390x" => Some("s390x"),
+            "x86_64" => Some("x86_64"),
+            _ => None,
+        }
+    }
+
+    pub fn is_android(&self) -> bool {
+        self.env.as_ref().map(|it| it.starts_with("android")).unwrap_or(false)
+    }
+
+    pub fn is_apple(&self) -> bool {
+        self.vendor.as_ref().map(|it| it == "apple").unwrap_or(false)
+    }
+
+    pub fn is_ios(&self) -> bool {
+        self.is_apple() && self.os == "ios"
+    }
+
+    /// Whether the triple is one of the Apple devices and simulators built
+    /// with the SDKs of Xcode (iOS, tvOS, watchOS, visionOS), unlike macOS
+    /// and Mac Catalyst (`-macabi`).
+    pub fn is_apple_mobile(&self) -> bool {
+        self.is_apple()
+            && ["ios", "tvos", "watchos", "visionos"].contains(&self.os.as_str())
+            && self.env.as_ref().map(|it| it != "macabi").unwrap_or(true)
+    }
+
+    /// Whether the triple builds for an Apple simulator rather than devices:
+    /// `-sim` triples, and the Intel ones.
+    pub fn is_simulator(&self) -> bool {
+        self.is_apple_mobile()
+            && (self.env.as_ref().map(|it| it == "sim").unwrap_or(false) || self.arch == "x86_64" || self.arch == "i386")
+    }
+
+    fn new(arch: &str, vendor: Option<&str>, os: &str, env: Option<&str>) -> Triple {
+        Triple {
+            arch: arch.to_string(),
+            vendor: vendor.map(|it| it.to_string()),
+            os: os.to_string(),
+            env: env.map(|it| it.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Triple {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.arch)?;
+        if let Some(ref vendor) = self.vendor {
+            write!(f, "-{}", vendor)?;
+        }
+        write!(f, "-{}", self.os)?;
+        if let Some(ref env) = self.env {
+            write!(f, "-{}", env)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assemble_triples() {
+        for triple in &["aarch64-apple-ios", "armv7-linux-androideabi", "x86_64-unknown-linux-gnu", "x86_64-apple-darwin"] {
+            assert_eq!(Triple::parse(triple).unwrap().to_string(), *triple);
+        }
+        assert_eq!(Triple::from_cfg("arm", "unknown", "android", "").to_string(), "armv7-linux-androideabi");
+        assert_eq!(Triple::from_cfg("aarch64", "apple", "ios", "").to_string(), "aarch64-apple-ios");
+        assert_eq!(Triple::watchos("arm64_32").to_string(), "arm64_32-apple-watchos");
+        assert_eq!(Triple::android_abi("x86").unwrap().to_string(), "i686-linux-android");
+        assert_eq!(Triple::parse("armv7-linux-androideabi").unwrap().to_android_abi(), Some("armeabi-v7a"));
+        assert!(Triple::parse("aarch64-linux-android").unwrap().is_android());
+        assert_eq!(Triple::parse("armv7-unknown-linux-gnueabihf").unwrap().to_qemu_arch(), Some("arm"));
+        assert_eq!(Triple::parse("aarch64-linux-android").unwrap().to_qemu_arch(), None);
+        assert!(Triple::parse("aarch64-apple-tvos").unwrap().is_apple_mobile());
+        assert!(!Triple::parse("aarch64-apple-darwin").unwrap().is_apple_mobile());
+        assert!(Triple::parse("aarch64-apple-ios-sim").unwrap().is_simulator());
+        assert_eq!(Triple::ios_simulator("aarch64").to_string(), "aarch64-apple-ios-sim");
+        assert!(!Triple::parse("aarch64-apple-ios-macabi").unwrap().is_apple_mobile());
+    }
+}
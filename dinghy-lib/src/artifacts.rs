@@ -0,0 +1,128 @@
+//! Host-side staging area of everything dinghy produces or collects for a
+//! device.
+//!
+//! Each device gets its own directory under `<target>/dinghy/<device id>/`,
+//! kept after the run so that deployment issues can be investigated:
+//!
+//! * `archives`: standalone packages of `cargo dinghy archive` (IPAs...),
+//! * `bundles/<runnable>` and `bundles/overlay`: what is sent to the device,
+//! * `coverage`: coverage data pulled back from the device,
+//! * `criterion`: criterion data of the benchmarks pulled back from the device,
+//! * `deltas`: what was sent by incremental iOS installs,
+//! * `profiles`: traces of `cargo dinghy profile`,
+//! * `recordings`: screen recording of the last run,
+//! * `reports`: test reports of the last run,
+//! * `screenshots`: screenshots of the device after failed runs,
+//! * `tmp`: scratch files (e.g. lldb scripts), reset on each use.
+//!
+//! Crash logs pulled back after failed runs are kept apart, in
+//! `target/dinghy/<device id>/crashes`, whatever the platform and profile of
+//! the build, as is the output of the runnables while they run, in
+//! `target/dinghy/<device id>/output`, until the runner moves it to `reports`.
+
+use errors::*;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug)]
+pub struct ArtifactStore {
+    root: PathBuf,
+}
+
+impl ArtifactStore {
+    pub fn new<P: AsRef<Path>>(target_path: P, device_id: &str) -> ArtifactStore {
+        ArtifactStore {
+            root: target_path.as_ref().join("dinghy").join(sanitize(device_id)),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Packages of the runnables made to be handed over, e.g. to QA or to a
+    /// device farm.
+    pub fn archives_dir(&self) -> PathBuf {
+        self.root.join("archives")
+    }
+
+    /// Root of the bundles, mirrored as is in the work directory of remote
+    /// devices.
+    pub fn bundles_dir(&self) -> PathBuf {
+        self.root.join("bundles")
+    }
+
+    pub fn coverage_dir(&self) -> PathBuf {
+        self.root.join("coverage")
+    }
+
+    /// Criterion data of the last benchmark run, before its merge into the
+    /// data of the host.
+    pub fn criterion_dir(&self) -> PathBuf {
+        self.root.join("criterion")
+    }
+
+    /// Bookkeeping of the incremental app installs on iOS devices.
+    pub fn deltas_dir(&self) -> PathBuf {
+        self.root.join("deltas")
+    }
+
+    pub fn profiles_dir(&self) -> PathBuf {
+        self.root.join("profiles")
+    }
+
+    pub fn recordings_dir(&self) -> PathBuf {
+        self.root.join("recordings")
+    }
+
+    pub fn reports_dir(&self) -> PathBuf {
+        self.root.join("reports")
+    }
+
+    pub fn screenshots_dir(&self) -> PathBuf {
+        self.root.join("screenshots")
+    }
+
+    /// An empty scratch directory, whose content is kept until its next use.
+    pub fn scratch_dir(&self, name: &str) -> Result<PathBuf> {
+        let dir = self.root.join("tmp").join(name);
+        reset_dir(&dir)?;
+        Ok(dir)
+    }
+}
+
+/// Where the crash logs of `device_id` are kept.
+pub fn crashes_dir(device_id: &str) -> Result<PathBuf> {
+    Ok(ArtifactStore::new(::compiler::base_target_dir()?, device_id).root().join("crashes"))
+}
+
+/// Where the output of the runnables of `device_id` is written as it comes.
+pub fn output_dir(device_id: &str) -> Result<PathBuf> {
+    Ok(ArtifactStore::new(::compiler::base_target_dir()?, device_id).root().join("output"))
+}
+
+/// Create `dir` if needed, and return it.
+pub fn ensure_dir<P: AsRef<Path>>(dir: P) -> Result<PathBuf> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir).chain_err(|| format!("Couldn't create {}", dir.display()))?;
+    Ok(dir.to_path_buf())
+}
+
+/// Empty `dir`, creating it if needed.
+pub fn reset_dir<P: AsRef<Path>>(dir: P) -> Result<()> {
+    let dir = dir.as_ref();
+    if dir.exists() {
+        fs::remove_dir_all(dir).chain_err(|| format!("Couldn't remove {}", dir.display()))?;
+    }
+    ensure_dir(dir)?;
+    Ok(())
+}
+
+/// Device ids are used as directory names, but ssh ids or simulator names may
+/// contain separators.
+pub fn sanitize(device_id: &str) -> String {
+    device_id.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
+        .collect()
+}
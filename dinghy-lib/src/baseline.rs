@@ -0,0 +1,319 @@
+//! Benchmark baselines of the devices, and regression gating against them
+//! (`cargo dinghy bench --baseline <name> --fail-if-regression <percent>`).
+//!
+//! Each benchmark run with a baseline records the median time of the
+//! benchmarks of the device under `target/dinghy-baselines/<device id>/`, in a
+//! file named after the commit checked out. The baseline is the record of the
+//! commit its name points to (a branch, a tag, a hash), or the record of that
+//! name when git doesn't know it. The medians are the ones of libtest
+//! (`ns/iter`) and criterion (the middle of its estimates), by runnable and
+//! benchmark name. Runs of a checkout with uncommitted changes are not
+//! recorded, their measures not being the ones of the commit.
+
+use artifacts;
+use errors::*;
+use json;
+use json::JsonValue;
+use project::Project;
+use report::TestOutcome;
+use report::TestReport;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// The medians of a run of the benchmarks of a device, in nanoseconds.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Record {
+    pub device: String,
+    pub commit: String,
+    pub medians: BTreeMap<String, f64>,
+}
+
+impl Record {
+    fn to_json(&self) -> JsonValue {
+        let mut record = JsonValue::new_object();
+        record["device"] = self.device.as_str().into();
+        record["commit"] = self.commit.as_str().into();
+        let mut medians = JsonValue::new_object();
+        for (name, median) in &self.medians {
+            medians[name.as_str()] = (*median).into();
+        }
+        record["medians"] = medians;
+        record
+    }
+
+    fn from_json(json: &JsonValue) -> Record {
+        Record {
+            device: json["device"].as_str().unwrap_or("").to_string(),
+            commit: json["commit"].as_str().unwrap_or("").to_string(),
+            medians: json["medians"].entries()
+                .filter_map(|(name, median)| median.as_f64().map(|it| (name.to_string(), it)))
+                .collect(),
+        }
+    }
+}
+
+/// A benchmark of both the baseline and the run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    pub name: String,
+    pub baseline: f64,
+    pub current: f64,
+}
+
+impl Change {
+    /// How much slower the run is than the baseline, in percent.
+    pub fn percent(&self) -> f64 {
+        if self.baseline == 0.0 { 0.0 } else { (self.current - self.baseline) / self.baseline * 100.0 }
+    }
+}
+
+/// The difference between a run and the baseline of the device.
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    pub device: String,
+    pub baseline: String,
+    pub baseline_commit: String,
+    pub changes: Vec<Change>,
+    /// Benchmarks of the run only.
+    pub added: Vec<String>,
+    /// Benchmarks of the baseline only.
+    pub removed: Vec<String>,
+    /// Slowdown over which a benchmark regressed, in percent.
+    pub threshold: Option<f64>,
+}
+
+impl Comparison {
+    pub fn new(baseline: &str, record: &Record, current: &Record, threshold: Option<f64>) -> Comparison {
+        let changes = current.medians.iter()
+            .filter_map(|(name, &median)| record.medians.get(name).map(|&baseline| Change {
+                name: name.clone(),
+                baseline,
+                current: median,
+            }))
+            .collect();
+        Comparison {
+            device: current.device.clone(),
+            baseline: baseline.to_string(),
+            baseline_commit: record.commit.clone(),
+            changes,
+            added: current.medians.keys().filter(|it| !record.medians.contains_key(*it)).cloned().collect(),
+            removed: record.medians.keys().filter(|it| !current.medians.contains_key(*it)).cloned().collect(),
+            threshold,
+        }
+    }
+
+    pub fn regressions(&self) -> Vec<&Change> {
+        match self.threshold {
+            Some(threshold) => self.changes.iter().filter(|it| it.percent() > threshold).collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut comparison = JsonValue::new_object();
+        comparison["device"] = self.device.as_str().into();
+        comparison["baseline"] = self.baseline.as_str().into();
+        comparison["baseline_commit"] = self.baseline_commit.as_str().into();
+        if let Some(threshold) = self.threshold {
+            comparison["threshold"] = threshold.into();
+        }
+        let mut changes = JsonValue::new_array();
+        for change in &self.changes {
+            let mut json_change = JsonValue::new_object();
+            json_change["name"] = change.name.as_str().into();
+            json_change["baseline_ns"] = change.baseline.into();
+            json_change["current_ns"] = change.current.into();
+            json_change["change_percent"] = change.percent().into();
+            json_change["regression"] = self.threshold.map(|it| change.percent() > it).unwrap_or(false).into();
+            let _ = changes.push(json_change);
+        }
+        comparison["changes"] = changes;
+        comparison["added"] = self.added.iter().map(|it| it.as_str()).collect::<Vec<_>>().into();
+        comparison["removed"] = self.removed.iter().map(|it| it.as_str()).collect::<Vec<_>>().into();
+        comparison["regressions"] = self.regressions().len().into();
+        comparison
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(fmt, "Benchmarks of {} against baseline {} ({})",
+                 self.device, self.baseline, short_commit(&self.baseline_commit))?;
+        writeln!(fmt, "{:<48} {:>12} {:>12} {:>9}", "benchmark", "baseline", "current", "change")?;
+        for change in &self.changes {
+            let regressed = self.threshold.map(|it| change.percent() > it).unwrap_or(false);
+            writeln!(fmt, "{:<48} {:>12} {:>12} {:>+8.1}%{}",
+                     change.name, format_ns(change.baseline), format_ns(change.current), change.percent(),
+                     if regressed { "  REGRESSION" } else { "" })?;
+        }
+        for name in &self.added {
+            writeln!(fmt, "{:<48} {:>12} {:>12} {:>9}", name, "-", "new", "")?;
+        }
+        for name in &self.removed {
+            writeln!(fmt, "{:<48} {:>12} {:>12} {:>9}", name, "removed", "-", "")?;
+        }
+        Ok(())
+    }
+}
+
+/// Directory of the records of `device_id`.
+pub fn device_dir(project: &Project, device_id: &str) -> Result<PathBuf> {
+    Ok(project.target_dir(None)?.join("dinghy-baselines").join(artifacts::sanitize(device_id)))
+}
+
+/// The medians of the benchmarks of `reports`, by runnable and benchmark
+/// name: `<runnable>: <benchmark>`, two bench executables having benchmarks
+/// of the same name at times.
+pub fn medians(reports: &[TestReport]) -> BTreeMap<String, f64> {
+    reports.iter()
+        .flat_map(|report| report.tests.iter().map(move |test| (runnable_name(&report.runnable), test)))
+        .filter(|&(_, test)| test.outcome == TestOutcome::Bench)
+        .filter_map(|(runnable, test)| test.details.as_ref()
+            .and_then(|it| median_ns(it))
+            .map(|it| (format!("{}: {}", runnable, test.name), it)))
+        .collect()
+}
+
+/// The id of a runnable without the hash cargo suffixes the executables of
+/// tests and benches with, which changes from a build to the next.
+fn runnable_name(runnable_id: &str) -> &str {
+    match runnable_id.rfind('-') {
+        Some(index) if index + 1 < runnable_id.len() && runnable_id[index + 1..].chars().all(|c| c.is_digit(16)) =>
+            &runnable_id[..index],
+        _ => runnable_id,
+    }
+}
+
+/// Records the medians of the run of `device_id`, under the commit checked
+/// out, and compares them to `baseline`. Returns `None` when the baseline
+/// has no record for the device yet.
+pub fn compare_and_record(project: &Project, device_id: &str, baseline: &str,
+                          medians: BTreeMap<String, f64>, threshold: Option<f64>) -> Result<Option<Comparison>> {
+    let project_dir = project.project_dir()?;
+    let dir = artifacts::ensure_dir(device_dir(project, device_id)?)?;
+    let baseline_key = git_commit(&project_dir, baseline).unwrap_or(baseline.to_string());
+    let record = load(&dir.join(format!("{}.json", artifacts::sanitize(&baseline_key))))?;
+    let current = Record {
+        device: device_id.to_string(),
+        commit: git_commit(&project_dir, "HEAD").unwrap_or(String::new()),
+        medians,
+    };
+    if current.commit.is_empty() {
+        warn!("{} is not a git checkout, the benchmarks of {} are not recorded", project_dir.display(), device_id);
+    } else if git_dirty(&project_dir) {
+        warn!("{} has uncommitted changes, the benchmarks of {} are not recorded under {}",
+              project_dir.display(), device_id, short_commit(&current.commit));
+    } else if current.medians.is_empty() {
+        warn!("No benchmark measure in the output of the run on {}, nothing to record", device_id);
+    } else {
+        let path = dir.join(format!("{}.json", current.commit));
+        fs::File::create(&path)
+            .and_then(|mut file| file.write_all(current.to_json().pretty(2).as_bytes()))
+            .chain_err(|| format!("Couldn't record the benchmarks of {} in {}", device_id, path.display()))?;
+        debug!("Benchmarks of {} recorded in {}", device_id, path.display());
+    }
+    Ok(record.map(|record| Comparison::new(baseline, &record, &current, threshold)))
+}
+
+fn load(path: &Path) -> Result<Option<Record>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut content = String::new();
+    fs::File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut content))
+        .chain_err(|| format!("Couldn't read the baseline {}", path.display()))?;
+    Ok(Some(Record::from_json(&json::parse(&content)?)))
+}
+
+/// The commit `revision` points to in the repository of `dir`, if any.
+fn git_commit(dir: &Path, revision: &str) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C").arg(dir)
+        .args(&["rev-parse", "--verify", "--quiet"])
+        .arg(format!("{}^{{commit}}", revision))
+        .output().ok()?;
+    let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() && !commit.is_empty() { Some(commit) } else { None }
+}
+
+/// Whether the checkout of `dir` has changes git doesn't ignore, including
+/// untracked files. A checkout git can't tell about counts as dirty.
+fn git_dirty(dir: &Path) -> bool {
+    match Command::new("git").arg("-C").arg(dir).args(&["status", "--porcelain"]).output() {
+        Ok(ref output) if output.status.success() => !output.stdout.iter().all(|it| it.is_ascii_whitespace()),
+        _ => true,
+    }
+}
+
+fn short_commit(commit: &str) -> &str {
+    if commit.len() > 10 && commit.is_char_boundary(10) { &commit[..10] } else { commit }
+}
+
+/// The median of the details of a benchmark, in nanoseconds: libtest gives
+/// `1,234 ns/iter (+/- 56)`, criterion its estimates `26.0 us 26.2 us 26.5 us`.
+pub fn median_ns(details: &str) -> Option<f64> {
+    let tokens = details.split_whitespace().collect::<Vec<_>>();
+    if tokens.len() >= 2 && tokens[1] == "ns/iter" {
+        return tokens[0].replace(',', "").parse().ok();
+    }
+    if tokens.len() == 6 {
+        return Some(tokens[2].parse::<f64>().ok()? * unit_ns(tokens[3])?);
+    }
+    None
+}
+
+fn unit_ns(unit: &str) -> Option<f64> {
+    match unit {
+        "ps" => Some(1e-3),
+        "ns" => Some(1.0),
+        "us" | "µs" => Some(1e3),
+        "ms" => Some(1e6),
+        "s" => Some(1e9),
+        _ => None,
+    }
+}
+
+fn format_ns(ns: f64) -> String {
+    if ns >= 1e9 {
+        format!("{:.2} s", ns / 1e9)
+    } else if ns >= 1e6 {
+        format!("{:.2} ms", ns / 1e6)
+    } else if ns >= 1e3 {
+        format!("{:.2} us", ns / 1e3)
+    } else {
+        format!("{:.2} ns", ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regressions() {
+        assert_eq!(median_ns("1,234 ns/iter (+/- 56)"), Some(1234.0));
+        assert_eq!(median_ns("26.029 us 26.251 us 26.505 us"), Some(26251.0));
+        assert_eq!(median_ns("a lot"), None);
+        assert_eq!(runnable_name("parsing-1e8a3f1a2b3c4d5e"), "parsing");
+        assert_eq!(runnable_name("my-bench"), "my-bench");
+        let record = |commit: &str, medians: &[(&str, f64)]| Record {
+            device: "pixel".to_string(),
+            commit: commit.to_string(),
+            medians: medians.iter().map(|&(name, median)| (name.to_string(), median)).collect(),
+        };
+        let baseline = record("abc", &[("fib", 100.0), ("parse", 2000.0), ("old", 5.0)]);
+        let current = record("def", &[("fib", 104.0), ("parse", 2400.0), ("new", 7.0)]);
+        assert_eq!(Record::from_json(&baseline.to_json()), baseline);
+        let comparison = Comparison::new("main", &baseline, &current, Some(5.0));
+        assert_eq!(comparison.regressions().iter().map(|it| it.name.as_str()).collect::<Vec<_>>(), vec!["parse"]);
+        assert_eq!((comparison.added.clone(), comparison.removed.clone()), (vec!["new".to_string()], vec!["old".to_string()]));
+        assert_eq!(comparison.to_json()["regressions"].as_usize(), Some(1));
+    }
+}
@@ -0,0 +1,181 @@
+//! Tests of the crates only producing a `cdylib` (FFI layers, plugins...),
+//! which can't be run by themselves.
+//!
+//! A crate opts in by exporting a `dinghy_test_main(argc, argv)` entry point
+//! (`dinghy_test::cdylib_tests!` writes it). Dinghy builds a small shim
+//! executable for the target, which loads the library with `dlopen` and calls
+//! the entry point with its arguments: each library gets a runnable, named
+//! after it, running that shim.
+
+use errors::*;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use utils::copy_and_sync_file;
+use Runnable;
+
+/// The symbol the shim calls, `extern "C" fn(c_int, *const *const c_char) -> c_int`.
+pub const ENTRY_POINT: &str = "dinghy_test_main";
+
+const SHIM_MANIFEST: &str = r#"[package]
+name = "dinghy-cdylib-shim"
+version = "0.1.0"
+publish = false
+
+[workspace]
+"#;
+
+const SHIM_SOURCE: &str = r#"use std::env;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::os::raw::c_int;
+use std::os::raw::c_void;
+use std::path::PathBuf;
+use std::process;
+
+#[cfg_attr(any(target_os = "linux", target_os = "android"), link(name = "dl"))]
+extern "C" {
+    fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlerror() -> *const c_char;
+}
+
+const RTLD_NOW: c_int = 2;
+
+fn last_error() -> String {
+    let error = unsafe { dlerror() };
+    if error.is_null() { String::new() } else { unsafe { CStr::from_ptr(error) }.to_string_lossy().into_owned() }
+}
+
+// The library is named after the shim: next to it, in the parent directory
+// (host builds), or in the library path of the device.
+fn candidates() -> Vec<PathBuf> {
+    let exe = env::current_exe().expect("Current exe path not accessible");
+    let name = format!("{}.{}", exe.file_name().unwrap().to_string_lossy(),
+                       if cfg!(any(target_os = "macos", target_os = "ios")) { "dylib" } else { "so" });
+    let mut candidates = env::var_os("DINGHY_CDYLIB").into_iter().map(PathBuf::from).collect::<Vec<_>>();
+    if let Some(dir) = exe.parent() {
+        candidates.push(dir.join(&name));
+        candidates.extend(dir.parent().map(|it| it.join(&name)));
+    }
+    candidates.push(PathBuf::from(name));
+    candidates
+}
+
+fn main() {
+    let mut errors = vec![];
+    for library in candidates() {
+        if library.is_absolute() && !library.exists() {
+            continue;
+        }
+        let path = CString::new(library.to_string_lossy().into_owned()).unwrap();
+        let handle = unsafe { dlopen(path.as_ptr(), RTLD_NOW) };
+        if handle.is_null() {
+            errors.push(format!("{}: {}", library.display(), last_error()));
+            continue;
+        }
+        let symbol = CString::new("dinghy_test_main").unwrap();
+        let entry = unsafe { dlsym(handle, symbol.as_ptr()) };
+        if entry.is_null() {
+            eprintln!("{} doesn't export dinghy_test_main: {}", library.display(), last_error());
+            process::exit(101);
+        }
+        let entry: extern "C" fn(c_int, *const *const c_char) -> c_int = unsafe { std::mem::transmute(entry) };
+        let args = env::args().map(|it| CString::new(it).unwrap()).collect::<Vec<_>>();
+        let argv = args.iter().map(|it| it.as_ptr()).collect::<Vec<_>>();
+        process::exit(entry(argv.len() as c_int, argv.as_ptr()));
+    }
+    eprintln!("Couldn't load the library under test:\n{}", errors.join("\n"));
+    process::exit(101);
+}
+"#;
+
+/// Whether the library at `path` exports the entry point.
+pub fn has_entry_point(path: &Path) -> bool {
+    let mut content = vec![];
+    fs::File::open(path).and_then(|mut it| it.read_to_end(&mut content)).is_ok() && contains_entry_point(&content)
+}
+
+/// The name of the entry point is in the dynamic symbol table of the
+/// libraries exporting it.
+fn contains_entry_point(content: &[u8]) -> bool {
+    content.windows(ENTRY_POINT.len()).any(|it| it == ENTRY_POINT.as_bytes())
+}
+
+/// The runnables of the `cdylibs`, which export the entry point, built for
+/// `rustc_triple`, each with the root of its package. The shim is built in
+/// `shim_dir`, and copied next to the libraries, in a `dinghy-cdylib`
+/// directory.
+pub fn test_runnables(cdylibs: &[(PathBuf, PathBuf)], shim_dir: &Path, rustc_triple: Option<&str>) -> Result<Vec<Runnable>> {
+    if cdylibs.is_empty() {
+        return Ok(vec![]);
+    }
+    let shim = build_shim(shim_dir, rustc_triple)?;
+    let mut runnables = vec![];
+    for &(ref cdylib, ref source) in cdylibs {
+        let id = cdylib.file_stem().ok_or(format!("Invalid library {}", cdylib.display()))?.to_string_lossy().to_string();
+        let dir = cdylib.parent().ok_or(format!("Invalid library {}", cdylib.display()))?.join("dinghy-cdylib");
+        fs::create_dir_all(&dir)?;
+        let exe = dir.join(&id);
+        copy_and_sync_file(&shim, &exe)?;
+        debug!("Testing {} through {}", cdylib.display(), exe.display());
+        runnables.push(Runnable {
+            id,
+            exe,
+            source: source.clone(),
+            stripped_exe: None,
+        });
+    }
+    Ok(runnables)
+}
+
+fn build_shim(shim_dir: &Path, rustc_triple: Option<&str>) -> Result<PathBuf> {
+    let src_dir = shim_dir.join("src");
+    fs::create_dir_all(&src_dir)?;
+    write_if_changed(&shim_dir.join("Cargo.toml"), SHIM_MANIFEST)?;
+    write_if_changed(&src_dir.join("main.rs"), SHIM_SOURCE)?;
+
+    let target_dir = shim_dir.join("target");
+    let mut command = Command::new("cargo");
+    command.arg("build")
+        .arg("--manifest-path").arg(shim_dir.join("Cargo.toml"))
+        .arg("--target-dir").arg(&target_dir);
+    if let Some(rustc_triple) = rustc_triple {
+        command.arg("--target").arg(rustc_triple);
+    }
+    debug!("Building the cdylib test shim: {:?}", command);
+    if !command.status().chain_err(|| "Couldn't run cargo")?.success() {
+        bail!("Couldn't build the cdylib test shim for {}", rustc_triple.unwrap_or("host"))
+    }
+    let target_dir = match rustc_triple {
+        Some(rustc_triple) => target_dir.join(rustc_triple),
+        None => target_dir,
+    };
+    Ok(target_dir.join("debug").join("dinghy-cdylib-shim"))
+}
+
+/// Writes `content` to `path` unless it is already there, for cargo not to
+/// rebuild the shim each time.
+fn write_if_changed(path: &Path, content: &str) -> Result<()> {
+    let mut current = String::new();
+    if fs::File::open(path).and_then(|mut it| it.read_to_string(&mut current)).is_ok() && current == content {
+        return Ok(());
+    }
+    fs::File::create(path)?.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_point() {
+        assert!(contains_entry_point(b"\x7fELF\0\0__cxa_finalize\0dinghy_test_main\0"));
+        assert!(!contains_entry_point(b"\x7fELF\0\0__cxa_finalize\0dinghy_test\0"));
+    }
+}
@@ -0,0 +1,81 @@
+//! Teardown of remote resources when dinghy is interrupted.
+//!
+//! Devices register what needs to be undone (remote processes, debug sessions,
+//! ...) for as long as the returned guard lives. On Ctrl-C, all the registered
+//! cleanups are run before exiting, unless interrupts are forwarded to an
+//! interactive child (e.g. a debugger).
+
+use ctrlc;
+use errors::*;
+use std::collections::BTreeMap;
+use std::process;
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref CLEANUPS: Mutex<Cleanups> = Mutex::new(Cleanups::default());
+}
+
+#[derive(Default)]
+struct Cleanups {
+    next_id: usize,
+    actions: BTreeMap<usize, Box<Fn() + Send>>,
+    forwarding: usize,
+}
+
+/// Unregisters its cleanup action when dropped.
+pub struct CleanupGuard {
+    id: usize,
+}
+
+impl Drop for CleanupGuard {
+    fn drop(&mut self) {
+        if let Ok(mut cleanups) = CLEANUPS.lock() {
+            cleanups.actions.remove(&self.id);
+        }
+    }
+}
+
+/// Stops forwarding interrupts when dropped.
+pub struct ForwardInterruptsGuard;
+
+impl Drop for ForwardInterruptsGuard {
+    fn drop(&mut self) {
+        if let Ok(mut cleanups) = CLEANUPS.lock() {
+            cleanups.forwarding -= 1;
+        }
+    }
+}
+
+/// Ignore Ctrl-C while the guard is alive, leaving it to the foreground child
+/// process, which receives it as well.
+pub fn forward_interrupts() -> ForwardInterruptsGuard {
+    let mut cleanups = CLEANUPS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    cleanups.forwarding += 1;
+    ForwardInterruptsGuard
+}
+
+/// Register an action to run if dinghy is interrupted while the guard is alive.
+pub fn on_interrupt<F: Fn() + Send + 'static>(action: F) -> CleanupGuard {
+    let mut cleanups = CLEANUPS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let id = cleanups.next_id;
+    cleanups.next_id += 1;
+    cleanups.actions.insert(id, Box::new(action));
+    CleanupGuard { id }
+}
+
+/// Install the Ctrl-C handler running the registered cleanups.
+pub fn install_interrupt_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if let Ok(cleanups) = CLEANUPS.lock() {
+            if cleanups.forwarding > 0 {
+                return;
+            }
+            warn!("Interrupted, cleaning up");
+            for action in cleanups.actions.values().rev() {
+                action();
+            }
+        }
+        process::exit(130);
+    }).map_err(|e| format!("Couldn't install interrupt handler ({:?})", e))?;
+    Ok(())
+}
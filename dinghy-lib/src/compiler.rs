@@ -1,7 +1,10 @@
 extern crate cargo;
 
 use Build;
+use timings;
+use timings::Phase;
 use BuildArgs;
+use cargo::core::PackageIdSpec;
 use cargo::core::Workspace;
 use cargo::ops as CargoOps;
 use cargo::ops::CleanOptions;
@@ -15,9 +18,13 @@ use cargo::ops::TestOptions;
 use cargo::util::config::Config as CompileConfig;
 use cargo::util::important_paths::find_root_manifest_for_wd;
 use clap::ArgMatches;
+use config::Configuration;
+use dinghy_build::build_env::set_env;
 use dinghy_build::build_env::target_env_from_triple;
+use dinghy_build::triple::Triple;
 use ErrorKind;
 use itertools::Itertools;
+use logging;
 use Result;
 use ResultExt;
 use Runnable;
@@ -38,25 +45,142 @@ use utils::copy_and_sync_file;
 use utils::is_library;
 use walkdir::WalkDir;
 
+/// Directory of the dinghy builds, below the target directory of cargo.
+static ISOLATED_TARGET_DIR: &str = "dinghy-build";
+
 pub struct Compiler {
-    build_command: Box<Fn(Option<&str>, &BuildArgs) -> Result<Build>>,
-    clean_command: Box<Fn(Option<&str>) -> Result<()>>,
-    run_command: Box<Fn(Option<&str>, &BuildArgs, &[&str]) -> Result<()>>,
+    build_command: Box<Fn(Option<&str>, &BuildArgs) -> Result<Build> + Send + Sync>,
+    clean_command: Box<Fn(Option<&str>) -> Result<()> + Send + Sync>,
+    examples_command: Box<Fn(Option<&str>, &BuildArgs) -> Result<Build> + Send + Sync>,
+    run_command: Box<Fn(Option<&str>, &BuildArgs, &[&str]) -> Result<()> + Send + Sync>,
+}
+
+/// What cargo compiles, and how: the package and target selection, features
+/// and profile of `cargo build`.
+#[derive(Clone, Debug, Default)]
+pub struct BuildOptions {
+    pub all: bool,
+    pub all_examples: bool,
+    pub all_features: bool,
+    /// Time the rustc invocations through a wrapper script.
+    pub bearded: bool,
+    pub benches: Vec<String>,
+    pub bins: Vec<String>,
+    pub examples: Vec<String>,
+    pub excludes: Vec<String>,
+    pub features: Vec<String>,
+    /// Install the missing rustup targets.
+    pub install_targets: bool,
+    pub jobs: Option<u32>,
+    pub lib_only: bool,
+    pub message_format: Option<String>,
+    pub no_default_features: bool,
+    pub packages: Vec<String>,
+    pub release: bool,
+    pub tests: Vec<String>,
+    pub verbosity: u32,
+}
+
+/// The cargo commands only checking the code, run in place of a build.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CheckCommand {
+    Check,
+    /// With the arguments of clippy itself (e.g. `-D warnings`).
+    Clippy(Vec<String>),
+}
+
+impl CheckCommand {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            CheckCommand::Check => "check",
+            CheckCommand::Clippy(_) => "clippy",
+        }
+    }
+}
+
+impl BuildOptions {
+    /// The options of a cargo-like command line, completed by the `[build]`
+    /// configuration.
+    pub fn from_args(matches: &ArgMatches, conf: &Configuration) -> BuildOptions {
+        BuildOptions {
+            all: matches.is_present("ALL"),
+            all_examples: matches.is_present("EXAMPLES"),
+            all_features: matches.is_present("ALL_FEATURES"),
+            bearded: matches.is_present("BEARDED"),
+            benches: arg_as_string_vec(matches, "BENCH"),
+            bins: arg_as_string_vec(matches, "BIN"),
+            examples: arg_as_string_vec(matches, "EXAMPLE"),
+            excludes: arg_as_string_vec(matches, "EXCLUDE"),
+            features: matches
+                .value_of("FEATURES")
+                .unwrap_or("")
+                .split(" ")
+                .map(|s| s.into())
+                .collect(),
+            install_targets: matches.is_present("INSTALL_TARGETS") || conf.build.install_targets.unwrap_or(false),
+            jobs: matches
+                .value_of("JOBS")
+                .map(|v| v.parse::<u32>().unwrap())
+                .or(conf.build.jobs),
+            lib_only: matches.is_present("LIB"),
+            message_format: BuildOptions::message_format(matches, conf),
+            no_default_features: matches.is_present("NO_DEFAULT_FEATURES"),
+            packages: arg_as_string_vec(matches, "SPEC"),
+            release: matches.is_present("RELEASE"),
+            tests: arg_as_string_vec(matches, "TEST"),
+            verbosity: matches.occurrences_of("VERBOSE") as u32,
+        }
+    }
+
+    /// The same options, selecting the examples only: the ones named, else
+    /// all of them.
+    pub fn examples_only(&self) -> BuildOptions {
+        BuildOptions {
+            all_examples: self.all_examples || self.examples.is_empty(),
+            benches: vec![],
+            bins: vec![],
+            lib_only: false,
+            tests: vec![],
+            ..self.clone()
+        }
+    }
+
+    /// `--message-format`, or the one of the `[build]` configuration.
+    pub fn message_format(matches: &ArgMatches, conf: &Configuration) -> Option<String> {
+        matches.value_of("MESSAGE_FORMAT")
+            .map(|it| it.to_string())
+            .or(conf.build.message_format.clone())
+    }
 }
 
 impl Compiler {
-    pub fn from_args(matches: &ArgMatches) -> Self {
+    pub fn new(options: BuildOptions) -> Self {
         Compiler {
-            build_command: create_build_command(matches),
-            clean_command: create_clean_command(matches),
-            run_command: create_run_command(matches),
+            build_command: create_build_command(options.clone()),
+            clean_command: create_clean_command(options.clone()),
+            examples_command: create_build_command(options.examples_only()),
+            run_command: create_run_command(options),
         }
     }
 
+    pub fn from_args(matches: &ArgMatches, conf: &Configuration) -> Self {
+        Compiler::new(BuildOptions::from_args(matches, conf))
+    }
+
     pub fn build(&self, rustc_triple: Option<&str>, build_args: &BuildArgs) -> Result<Build> {
+        let _span = logging::span(format!("build {}", rustc_triple.unwrap_or("host")));
+        let _timer = timings::start(Phase::Compile);
         (self.build_command)(rustc_triple, build_args)
     }
 
+    /// Build the examples for `rustc_triple`, as `build` does, whatever the
+    /// other targets selected: each example is a runnable of the build.
+    pub fn compile_examples(&self, rustc_triple: Option<&str>, build_args: &BuildArgs) -> Result<Build> {
+        let _span = logging::span(format!("build examples {}", rustc_triple.unwrap_or("host")));
+        let _timer = timings::start(Phase::Compile);
+        (self.examples_command)(rustc_triple, build_args)
+    }
+
     pub fn clean(&self, rustc_triple: Option<&str>) -> Result<()> {
         (self.clean_command)(rustc_triple)
     }
@@ -82,32 +206,21 @@ impl ProjectMetadata {
     }
 }
 
-fn create_build_command(matches: &ArgMatches) -> Box<Fn(Option<&str>, &BuildArgs) -> Result<Build>> {
-    let all = matches.is_present("ALL");
-    let all_features = matches.is_present("ALL_FEATURES");
-    let benches = arg_as_string_vec(matches, "BENCH");
-    let bins = arg_as_string_vec(matches, "BIN");
-    let features: Vec<String> = matches
-        .value_of("FEATURES")
-        .unwrap_or("")
-        .split(" ")
-        .map(|s| s.into())
-        .collect();
-    let examples = arg_as_string_vec(matches, "EXAMPLE");
-    let excludes = arg_as_string_vec(matches, "EXCLUDE");
-    let jobs = matches
-        .value_of("JOBS")
-        .map(|v| v.parse::<u32>().unwrap());
-    let lib_only = matches.is_present("LIB");
-    let no_default_features = matches.is_present("NO_DEFAULT_FEATURES");
-    let packages = arg_as_string_vec(matches, "SPEC");
-    let release = matches.is_present("RELEASE");
-    let verbosity = matches.occurrences_of("VERBOSE") as u32;
-    let tests = arg_as_string_vec(matches, "TEST");
-    let bearded = matches.is_present("BEARDED");
+fn create_build_command(options: BuildOptions) -> Box<Fn(Option<&str>, &BuildArgs) -> Result<Build> + Send + Sync> {
+    let check_options = options.clone();
+    let BuildOptions {
+        all, all_examples, all_features, bearded, benches, bins, examples, excludes, features, install_targets,
+        jobs, lib_only, message_format, no_default_features, packages, release, tests, verbosity,
+    } = options;
 
     Box::new(move |rustc_triple: Option<&str>, build_args: &BuildArgs| {
         let release = build_args.compile_mode == CompileMode::Bench || release;
+        if let (Some(rustc_triple), false) = (rustc_triple, build_args.dry_run) {
+            ::rustup::ensure_target(rustc_triple, install_targets)?;
+        }
+        if build_args.coverage { ::coverage::setup_instrumentation(); }
+        ::crossbuild::separate_host_and_target(rustc_triple);
+        isolate_target_dir()?;
         let mut config = CompileConfig::default()?;
         config.configure(verbosity,
                          None,
@@ -144,6 +257,10 @@ fn create_build_command(matches: &ArgMatches) -> Box<Fn(Option<&str>, &BuildArgs
             (packages.clone(), excludes.clone())
         };
 
+        if let Some(ref check) = build_args.check {
+            return run_check(check, &check_options, &packages, &excludes, &workspace, build_args, rustc_triple);
+        }
+
         let compile_options = CompileOptions {
             config: &config,
             jobs,
@@ -161,31 +278,84 @@ fn create_build_command(matches: &ArgMatches) -> Box<Fn(Option<&str>, &BuildArgs
                 lib_only,
                 &bins, false,
                 &tests, false,
-                &examples, false,
+                &examples, all_examples,
                 &benches, false,
                 false, // all_targets
             ),
             release,
             mode: build_args.compile_mode,
-            message_format: MessageFormat::Human,
+            message_format: message_format_from(message_format.as_ref())?,
             target_rustdoc_args: None,
             target_rustc_args: None,
         };
 
+        if build_args.dry_run {
+            return plan_build(&workspace, &compile_options, build_args, rustc_triple);
+        }
         if bearded { setup_dinghy_wrapper(&workspace, rustc_triple)?; }
-        let compilation = CargoOps::compile(&workspace, &compile_options)?;
-        let build = to_build(compilation, &config, build_args, rustc_triple)?;
+        let compilation = CargoOps::compile(&workspace, &compile_options)
+            .map_err(|e| ::crossbuild::explain_build_failure(e.into(), rustc_triple))?;
+        let mut build = to_build(compilation, &config, build_args, rustc_triple)?;
+        build.cdylibs = find_libraries(&workspace, &compile_options, build_args, &build.target_path, rustc_triple, LibraryKind::Cdylib)?
+            .into_iter()
+            .filter(|it| it.is_file())
+            .collect();
+        build.staticlibs = find_libraries(&workspace, &compile_options, build_args, &build.target_path, rustc_triple, LibraryKind::Staticlib)?
+            .into_iter()
+            .filter(|it| it.is_file())
+            .collect();
         copy_dependencies_to_target(&build)?;
+        // Apple mobile apps can't load libraries from outside their signed
+        // bundle, and the filters selecting other targets than the libraries
+        // leave the cdylibs out.
+        let apple_mobile = rustc_triple.and_then(|it| Triple::parse(it)).map(|it| it.is_apple_mobile()).unwrap_or(false);
+        let libs_selected = lib_only
+            || (bins.is_empty() && tests.is_empty() && examples.is_empty() && !all_examples && benches.is_empty());
+        if build_args.compile_mode == CompileMode::Test && libs_selected && !apple_mobile {
+            // cdylib-only crates are tested through the shim of `::cdylib`,
+            // their libraries being built on the side.
+            let lib_args = BuildArgs { compile_mode: CompileMode::Build, ..build_args.clone() };
+            let lib_options = CompileOptions {
+                config: &config,
+                jobs,
+                target: rustc_triple,
+                features: &*features,
+                all_features,
+                no_default_features,
+                spec: CompilePackages::from_flags(
+                    workspace.is_virtual(),
+                    all,
+                    &excludes,
+                    &packages,
+                )?,
+                filter: CompileFilter::new(true, &[], false, &[], false, &[], false, &[], false, false),
+                release,
+                mode: CompileMode::Build,
+                message_format: message_format_from(message_format.as_ref())?,
+                target_rustdoc_args: None,
+                target_rustc_args: None,
+            };
+            let cdylibs = find_package_libraries(&workspace, &lib_options, &lib_args, &build.target_path, rustc_triple, LibraryKind::Cdylib)?;
+            if !cdylibs.is_empty() {
+                CargoOps::compile(&workspace, &lib_options)
+                    .map_err(|e| ::crossbuild::explain_build_failure(e.into(), rustc_triple))?;
+                let tested = cdylibs.into_iter().filter(|&(ref it, _)| ::cdylib::has_entry_point(it)).collect::<Vec<_>>();
+                let shim_dir = workspace.target_dir().into_path_unlocked().join("dinghy-cdylib-shim");
+                build.runnables.extend(::cdylib::test_runnables(&tested, &shim_dir, rustc_triple)?);
+                // Already in the target directory, they are only added for
+                // the bundles.
+                build.dynamic_libraries.extend(tested.into_iter().map(|(it, _)| it));
+            }
+        }
         Ok(build)
     })
 }
 
-fn create_clean_command(matches: &ArgMatches) -> Box<Fn(Option<&str>) -> Result<()>> {
-    let packages = arg_as_string_vec(matches, "SPEC");
-    let release = matches.is_present("RELEASE");
-    let verbosity = matches.occurrences_of("VERBOSE") as u32;
+fn create_clean_command(options: BuildOptions) -> Box<Fn(Option<&str>) -> Result<()> + Send + Sync> {
+    let BuildOptions { packages, release, verbosity, .. } = options;
 
     Box::new(move |rustc_triple: Option<&str>| {
+        isolate_target_dir()?;
         let mut config = CompileConfig::default()?;
         config.configure(verbosity,
                          None,
@@ -208,32 +378,16 @@ fn create_clean_command(matches: &ArgMatches) -> Box<Fn(Option<&str>) -> Result<
     })
 }
 
-fn create_run_command(matches: &ArgMatches) -> Box<Fn(Option<&str>, &BuildArgs, &[&str]) -> Result<()>> {
-    let all = matches.is_present("ALL");
-    let all_features = matches.is_present("ALL_FEATURES");
-    let benches = arg_as_string_vec(matches, "BENCH");
-    let bins = arg_as_string_vec(matches, "BIN");
-    let features: Vec<String> = matches
-        .value_of("FEATURES")
-        .unwrap_or("")
-        .split(" ")
-        .map(|s| s.into())
-        .collect();
-    let examples = arg_as_string_vec(matches, "EXAMPLE");
-    let excludes = arg_as_string_vec(matches, "EXCLUDE");
-    let jobs = matches
-        .value_of("JOBS")
-        .map(|v| v.parse::<u32>().unwrap());
-    let lib_only = matches.is_present("LIB");
-    let no_default_features = matches.is_present("NO_DEFAULT_FEATURES");
-    let packages = arg_as_string_vec(matches, "SPEC");
-    let release = matches.is_present("RELEASE");
-    let verbosity = matches.occurrences_of("VERBOSE") as u32;
-    let tests = arg_as_string_vec(matches, "TEST");
-    let bearded = matches.is_present("BEARDED");
+fn create_run_command(options: BuildOptions) -> Box<Fn(Option<&str>, &BuildArgs, &[&str]) -> Result<()> + Send + Sync> {
+    let BuildOptions {
+        all, all_examples, all_features, bearded, benches, bins, examples, excludes, features,
+        jobs, lib_only, message_format, no_default_features, packages, release, tests, verbosity, ..
+    } = options;
 
     Box::new(move |rustc_triple: Option<&str>, build_args: &BuildArgs, args: &[&str]| {
         let release = build_args.compile_mode == CompileMode::Bench || release;
+        ::crossbuild::separate_host_and_target(rustc_triple);
+        isolate_target_dir()?;
         let mut config = CompileConfig::default()?;
         config.configure(verbosity,
                          None,
@@ -268,13 +422,13 @@ fn create_run_command(matches: &ArgMatches) -> Box<Fn(Option<&str>, &BuildArgs,
                 lib_only,
                 &bins, false,
                 &tests, false,
-                &examples, false,
+                &examples, all_examples,
                 &benches, false,
                 false, // all_targets
             ),
             release,
             mode: build_args.compile_mode,
-            message_format: MessageFormat::Human,
+            message_format: message_format_from(message_format.as_ref())?,
             target_rustdoc_args: None,
             target_rustc_args: None,
         };
@@ -317,6 +471,138 @@ fn create_run_command(matches: &ArgMatches) -> Box<Fn(Option<&str>, &BuildArgs,
     })
 }
 
+/// Run `cargo check` or `cargo clippy` for `rustc_triple`, with the packages
+/// and targets of the build. It inherits the environment the platform set up
+/// for the build (linker, C compilers, sysroot...), so that the build scripts
+/// of the dependencies work as they do when building for the device.
+fn run_check(check: &CheckCommand,
+             options: &BuildOptions,
+             packages: &[String],
+             excludes: &[String],
+             workspace: &Workspace,
+             build_args: &BuildArgs,
+             rustc_triple: Option<&str>) -> Result<Build> {
+    let mut command = Command::new("cargo");
+    command.arg(check.as_str());
+    if let Some(rustc_triple) = rustc_triple {
+        command.arg("--target").arg(rustc_triple);
+    }
+    if options.all || (workspace.is_virtual() && packages.is_empty()) {
+        command.arg("--all");
+    }
+    for package in packages {
+        command.arg("-p").arg(package);
+    }
+    for exclude in excludes {
+        command.arg("--exclude").arg(exclude);
+    }
+    if options.lib_only {
+        command.arg("--lib");
+    }
+    for &(flag, names) in &[("--bin", &options.bins), ("--example", &options.examples),
+                            ("--test", &options.tests), ("--bench", &options.benches)] {
+        for name in names {
+            command.arg(flag).arg(name);
+        }
+    }
+    if options.all_examples {
+        command.arg("--examples");
+    }
+    let features = options.features.iter().filter(|it| !it.is_empty()).join(" ");
+    if !features.is_empty() {
+        command.arg("--features").arg(features);
+    }
+    if options.all_features {
+        command.arg("--all-features");
+    }
+    if options.no_default_features {
+        command.arg("--no-default-features");
+    }
+    if options.release {
+        command.arg("--release");
+    }
+    if let Some(jobs) = options.jobs {
+        command.arg("--jobs").arg(jobs.to_string());
+    }
+    if let Some(ref message_format) = options.message_format {
+        command.arg("--message-format").arg(message_format);
+    }
+    for _ in 0..options.verbosity {
+        command.arg("--verbose");
+    }
+    if let CheckCommand::Clippy(ref clippy_args) = *check {
+        if !clippy_args.is_empty() {
+            command.arg("--").args(clippy_args);
+        }
+    }
+    debug!("Running {:?}", command);
+    let status = command.status()
+        .chain_err(|| format!("Couldn't run cargo {}", check.as_str()))?;
+    if !status.success() {
+        bail!("cargo {} failed for {}", check.as_str(), rustc_triple.unwrap_or("host"))
+    }
+    let mut target_dir = workspace.target_dir();
+    if let Some(rustc_triple) = rustc_triple {
+        target_dir = target_dir.join(rustc_triple);
+    }
+    Ok(Build {
+        build_args: build_args.clone(),
+        cdylibs: vec![],
+        doctests: vec![],
+        dynamic_libraries: vec![],
+        runnables: vec![],
+        rustc_triple: rustc_triple.map(|it| it.to_string()),
+        staticlibs: vec![],
+        target_path: target_dir.join(if options.release { "release" } else { "debug" }).into_path_unlocked(),
+    })
+}
+
+/// Have cargo build in a target directory of its own. Dinghy builds run with
+/// other environment variables (linkers, flags, overlays...) than plain cargo
+/// builds, so sharing the directory would make each of them rebuild what the
+/// other just built.
+fn isolate_target_dir() -> Result<()> {
+    if env::var_os("CARGO_TARGET_DIR").map(|it| Path::new(&it).ends_with(ISOLATED_TARGET_DIR)).unwrap_or(false) {
+        return Ok(());
+    }
+    set_env("CARGO_TARGET_DIR", base_target_dir()?.join(ISOLATED_TARGET_DIR));
+    Ok(())
+}
+
+/// The target directory cargo would use, before its isolation.
+pub fn base_target_dir() -> Result<PathBuf> {
+    match env::var_os("CARGO_TARGET_DIR") {
+        Some(ref target_dir) if Path::new(target_dir).ends_with(ISOLATED_TARGET_DIR) =>
+            Ok(Path::new(target_dir).parent().ok_or("Invalid CARGO_TARGET_DIR")?.to_path_buf()),
+        Some(target_dir) => Ok(PathBuf::from(target_dir)),
+        None => Ok(find_root_manifest_for_wd(None, &current_dir()?)?
+            .parent()
+            .ok_or("Couldn't find the project directory")?
+            .join("target")),
+    }
+}
+
+/// The features of `crate_name` in the dependencies of the project, as
+/// resolved for a build of its members with their default features.
+pub fn resolved_features(crate_name: &str) -> Result<HashSet<String>> {
+    let config = CompileConfig::default()?;
+    let workspace = Workspace::new(&find_root_manifest_for_wd(None, &current_dir()?)?, &config)?;
+    let specs = workspace.members().map(|it| PackageIdSpec::from_package_id(it.package_id())).collect::<Vec<_>>();
+    let (_, resolve) = CargoOps::resolve_ws_precisely(&workspace, None, &[], false, false, &specs)?;
+    Ok(resolve.iter()
+        .filter(|it| it.name() == crate_name)
+        .flat_map(|it| resolve.features(it).iter().cloned())
+        .collect())
+}
+
+fn message_format_from(name: Option<&String>) -> Result<MessageFormat> {
+    match name.map(|it| it.as_str()) {
+        None | Some("human") => Ok(MessageFormat::Human),
+        Some("json") => Ok(MessageFormat::Json),
+        Some(other) => bail!("Unknown message format '{}', expected 'human' or 'json'", other),
+    }
+}
+
 fn setup_dinghy_wrapper(workspace: &Workspace, rustc_triple: Option<&str>) -> Result<()> {
     let mut target_dir = workspace.target_dir();
     target_dir.push(rustc_triple.unwrap_or("host"));
@@ -356,10 +642,18 @@ fn to_build(compilation: Compilation,
             config: &CompileConfig,
             build_args: &BuildArgs,
             rustc_triple: Option<&str>) -> Result<Build> {
+    // Doctests are only run by `cargo test` on the host, they are not part of
+    // the test executables sent to devices.
+    let doctests = match (build_args.compile_mode, rustc_triple) {
+        (CompileMode::Test, Some(_)) => compilation.to_doc_test.iter().map(|it| it.name().to_string()).collect(),
+        _ => vec![],
+    };
     match build_args.compile_mode {
         CompileMode::Build => {
             Ok(Build {
                 build_args: build_args.clone(),
+                cdylibs: vec![],
+                doctests,
                 dynamic_libraries: find_dynamic_libraries(&compilation,
                                                           config,
                                                           build_args,
@@ -375,9 +669,12 @@ fn to_build(compilation: Compilation,
                                 .ok_or(format!("Invalid executable file '{}'", &exe_path.display()))?
                                 .to_string(),
                             source: PathBuf::from("."),
+                            stripped_exe: None,
                         })
                     })
                     .collect::<Result<Vec<_>>>()?,
+                rustc_triple: rustc_triple.map(|it| it.to_string()),
+                staticlibs: vec![],
                 target_path: compilation.root_output.clone(),
             })
         }
@@ -385,6 +682,8 @@ fn to_build(compilation: Compilation,
         _ => {
             Ok(Build {
                 build_args: build_args.clone(),
+                cdylibs: vec![],
+                doctests,
                 dynamic_libraries: find_dynamic_libraries(&compilation,
                                                           config,
                                                           build_args,
@@ -400,15 +699,134 @@ fn to_build(compilation: Compilation,
                                 .ok_or(format!("Invalid executable file '{}'", &exe_path.display()))?
                                 .to_string(),
                             source: pkg.root().to_path_buf(),
+                            stripped_exe: None,
                         })
                     })
                     .collect::<Result<Vec<_>>>()?,
+                rustc_triple: rustc_triple.map(|it| it.to_string()),
+                staticlibs: vec![],
                 target_path: compilation.root_output.clone(),
             })
         }
     }
 }
 
+// The build as cargo would make it, without compiling anything. Runnables are
+// named after their targets (actual test executables get a hash suffix), and
+// dynamic libraries are unknown as they are found in the build outputs.
+fn plan_build(workspace: &Workspace,
+              compile_options: &CompileOptions,
+              build_args: &BuildArgs,
+              rustc_triple: Option<&str>) -> Result<Build> {
+    let specs = compile_options.spec.into_package_id_specs(workspace)?;
+    let mut target_dir = workspace.target_dir();
+    if let Some(rustc_triple) = rustc_triple {
+        target_dir = target_dir.join(rustc_triple);
+    }
+    let target_path = target_dir.join(if compile_options.release { "release" } else { "debug" })
+        .into_path_unlocked();
+
+    let mut runnables = vec![];
+    let mut doctests = vec![];
+    for package in workspace.members().filter(|it| specs.iter().any(|spec| spec.matches(it.package_id()))) {
+        let lib_doctested = package.targets().iter().any(|it| it.is_lib() && it.doctested());
+        if build_args.compile_mode == CompileMode::Test && rustc_triple.is_some() && lib_doctested {
+            doctests.push(package.name().to_string());
+        }
+        for target in package.targets() {
+            let selected = match compile_options.filter {
+                CompileFilter::Default { .. } => match build_args.compile_mode {
+                    CompileMode::Build => target.is_bin(),
+                    CompileMode::Bench => target.benched(),
+                    _ => target.tested(),
+                },
+                ref filter => filter.matches(target),
+            };
+            if !selected {
+                continue;
+            }
+            let (exe, source) = match build_args.compile_mode {
+                CompileMode::Build if target.is_bin() => (target_path.join(target.name()), PathBuf::from(".")),
+                CompileMode::Build if target.is_example() => (target_path.join("examples").join(target.name()), PathBuf::from(".")),
+                CompileMode::Build => continue,
+                _ => (target_path.join("deps").join(target.crate_name()), package.root().to_path_buf()),
+            };
+            runnables.push(Runnable {
+                id: exe.file_name()
+                    .and_then(|it| it.to_str())
+                    .ok_or(format!("Invalid executable file '{}'", &exe.display()))?
+                    .to_string(),
+                exe,
+                source,
+                stripped_exe: None,
+            });
+        }
+    }
+
+    Ok(Build {
+        build_args: build_args.clone(),
+        cdylibs: find_libraries(workspace, compile_options, build_args, &target_path, rustc_triple, LibraryKind::Cdylib)?,
+        doctests,
+        dynamic_libraries: vec![],
+        runnables,
+        rustc_triple: rustc_triple.map(|it| it.to_string()),
+        staticlibs: find_libraries(workspace, compile_options, build_args, &target_path, rustc_triple, LibraryKind::Staticlib)?,
+        target_path,
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LibraryKind {
+    Cdylib,
+    Staticlib,
+}
+
+// The C libraries (`crate-type = ["cdylib"]` or `["staticlib"]`) of the
+// selected packages, where cargo puts them. Only plain builds make them.
+fn find_libraries(workspace: &Workspace,
+                  compile_options: &CompileOptions,
+                  build_args: &BuildArgs,
+                  target_path: &Path,
+                  rustc_triple: Option<&str>,
+                  kind: LibraryKind) -> Result<Vec<PathBuf>> {
+    Ok(find_package_libraries(workspace, compile_options, build_args, target_path, rustc_triple, kind)?
+        .into_iter()
+        .map(|(library, _)| library)
+        .collect())
+}
+
+// The same, with the root directory of the package of each library.
+fn find_package_libraries(workspace: &Workspace,
+                          compile_options: &CompileOptions,
+                          build_args: &BuildArgs,
+                          target_path: &Path,
+                          rustc_triple: Option<&str>,
+                          kind: LibraryKind) -> Result<Vec<(PathBuf, PathBuf)>> {
+    if build_args.compile_mode != CompileMode::Build {
+        return Ok(vec![]);
+    }
+    let apple = match rustc_triple {
+        Some(triple) => triple.contains("-apple-"),
+        None => cfg!(target_os = "macos"),
+    };
+    let extension = match kind {
+        LibraryKind::Staticlib => "a",
+        LibraryKind::Cdylib if apple => "dylib",
+        LibraryKind::Cdylib => "so",
+    };
+    let specs = compile_options.spec.into_package_id_specs(workspace)?;
+    Ok(workspace.members()
+        .filter(|it| specs.iter().any(|spec| spec.matches(it.package_id())))
+        .flat_map(|package| package.targets().iter().map(move |target| (package, target)))
+        .filter(|&(_, target)| match kind {
+            LibraryKind::Cdylib => target.is_cdylib(),
+            LibraryKind::Staticlib => target.is_staticlib(),
+        })
+        .map(|(package, target)| (target_path.join(format!("lib{}.{}", target.crate_name(), extension)),
+                                  package.root().to_path_buf()))
+        .collect())
+}
+
 fn exclude_by_target_triple(rustc_triple: Option<&str>, project_metadata_list: &[ProjectMetadata], excludes: &[String]) -> Vec<String> {
     let mut all_excludes: Vec<String> = excludes.to_vec();
     all_excludes.extend(project_metadata_list.iter()
@@ -1,3 +1,4 @@
+use dinghy_build::triple::Triple;
 use itertools::Itertools;
 use serde::de::{self, Deserialize};
 use std::io::Read;
@@ -17,6 +18,18 @@ pub struct TestData {
     pub copy_git_ignored: bool,
 }
 
+impl TestData {
+    fn new(id: String, file: &path::Path, source: TestDataConfiguration) -> TestData {
+        TestData {
+            id,
+            base: file.to_path_buf(),
+            target: source.target.unwrap_or(source.source.clone()),
+            source: source.source,
+            copy_git_ignored: source.copy_git_ignored,
+        }
+    }
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub struct TestDataConfiguration {
     pub copy_git_ignored: bool,
@@ -78,24 +91,175 @@ impl<'de> de::Deserialize<'de> for TestDataConfiguration {
     }
 }
 
+/// A remote host running dinghy with devices attached, reached through ssh,
+/// or at `address` when it runs `cargo dinghy serve`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AgentConfiguration {
+    pub address: Option<String>,
+    pub token: Option<String>,
+    pub hostname: Option<String>,
+    pub username: Option<String>,
+    pub port: Option<u16>,
+    /// Work directory on the agent, `/tmp` by default.
+    pub path: Option<String>,
+    /// Command running dinghy on the agent, `cargo dinghy` by default.
+    pub dinghy: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct AndroidConfiguration {
+    pub apk_runner: Option<ApkRunnerConfiguration>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ApkRunnerConfiguration {
+    pub instrumentation: String,
+    pub path: String,
+}
+
+impl ApkRunnerConfiguration {
+    pub fn package(&self) -> &str {
+        self.instrumentation.split("/").next().unwrap_or(&self.instrumentation)
+    }
+}
+
+/// Settings of the iOS signing and of `cargo dinghy ios-xcode`, in `[ios]`.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct IosConfiguration {
+    /// cbindgen configuration generating the C header of the libraries.
+    pub cbindgen: Option<String>,
+    /// Keychain the signing identities are looked for in, instead of the
+    /// default ones.
+    pub keychain: Option<String>,
+    /// Password unlocking `keychain`. Better left out of the configuration,
+    /// in the `keychain_password_env` variable.
+    pub keychain_password: Option<String>,
+    /// Environment variable holding the password of `keychain`,
+    /// `DINGHY_KEYCHAIN_PASSWORD` by default.
+    pub keychain_password_env: Option<String>,
+    /// Name or SHA-1 of the identity signing the apps, the first development
+    /// identity with a profile for the device by default.
+    pub signing_identity: Option<String>,
+    pub xcode: Option<XcodeConfiguration>,
+}
+
+/// The Xcode project running the XCTest suites linking the libraries.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct XcodeConfiguration {
+    /// `.xcodeproj` or `.xcworkspace`.
+    pub project: String,
+    pub scheme: String,
+    /// `xcodebuild` destination used when no device is selected, e.g.
+    /// `platform=iOS Simulator,name=iPhone 8`.
+    pub destination: Option<String>,
+}
+
+/// The runtime of the WebAssembly (WASI) executables, in `[wasi]`.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct WasiConfiguration {
+    /// wasmtime or wasmer, by name or path. The first of them found in the
+    /// `PATH` by default.
+    pub runtime: Option<String>,
+    /// Arguments of the runtime, before the executable.
+    pub runtime_args: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BundleResource {
+    pub id: String,
+    pub base: path::PathBuf,
+    pub source: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct BundleConfiguration {
+    pub entitlements: Option<String>,
+    /// Template of the bundle identifier of the apps, with `{base}`, `{crate}`
+    /// and `{job}` placeholders.
+    pub identifier: Option<String>,
+    pub info_plist: Option<collections::BTreeMap<String, String>>,
+    pub resources: Option<collections::BTreeMap<String, String>>,
+}
+
+/// Settings of one device, in `[devices.<device id>]`.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct DeviceConfiguration {
+    /// Run settings of the device, over the project and platform ones.
+    pub run: Option<RunOverlayConfiguration>,
+    /// Triple of the binaries the device runs, instead of the one matching
+    /// its detected CPU (iOS devices only).
+    pub rustc_triple: Option<String>,
+    /// Directory the runnables are installed in and run from. It has to allow
+    /// running executables (Android, ssh and Docker devices only).
+    pub work_dir: Option<String>,
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Configuration {
+    pub agents: collections::BTreeMap<String, AgentConfiguration>,
+    pub android: AndroidConfiguration,
+    pub bench: BenchConfiguration,
+    pub build: BuildConfiguration,
+    pub bundle_entitlements: Vec<path::PathBuf>,
+    pub bundle_identifier: Option<String>,
+    pub bundle_info_plist: collections::BTreeMap<String, String>,
+    pub bundle_resources: Vec<BundleResource>,
+    pub devices: collections::BTreeMap<String, DeviceConfiguration>,
+    pub docker_devices: collections::BTreeMap<String, DockerDeviceConfiguration>,
+    /// Device groups, by name: the `-d` hints of their devices.
+    pub groups: collections::BTreeMap<String, Vec<String>>,
+    pub ios: IosConfiguration,
+    pub network_profiles: collections::BTreeMap<String, NetworkProfileConfiguration>,
+    /// Run settings of the devices, by device id.
+    pub device_runs: collections::BTreeMap<String, RunOverlay>,
     pub platforms: collections::BTreeMap<String, PlatformConfiguration>,
+    /// Run settings of the platforms, by platform id.
+    pub platform_runs: collections::BTreeMap<String, RunOverlay>,
+    pub run: RunConfiguration,
+    pub serve: ServeConfiguration,
     pub ssh_devices: collections::BTreeMap<String, SshDeviceConfiguration>,
+    /// Variables exported when building for a triple, for the build scripts
+    /// of the `-sys` crates (e.g. `OPENSSL_DIR`, `PKG_CONFIG_PATH`).
+    pub target_env: collections::BTreeMap<String, collections::BTreeMap<String, String>>,
     pub test_data: Vec<TestData>,
+    pub toolchains: collections::BTreeMap<String, ToolchainConfiguration>,
+    /// Triples to use instead of the ones computed for the devices.
+    pub triples: collections::BTreeMap<String, String>,
+    pub wasi: WasiConfiguration,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 struct ConfigurationFileContent {
+    pub agents: Option<collections::BTreeMap<String, AgentConfiguration>>,
+    pub android: Option<AndroidConfiguration>,
+    pub bench: Option<BenchConfiguration>,
+    pub build: Option<BuildConfiguration>,
+    pub bundle: Option<BundleConfiguration>,
+    pub devices: Option<collections::BTreeMap<String, DeviceConfiguration>>,
+    pub docker_devices: Option<collections::BTreeMap<String, DockerDeviceConfiguration>>,
+    pub groups: Option<collections::BTreeMap<String, Vec<String>>>,
+    pub ios: Option<IosConfiguration>,
+    pub network_profiles: Option<collections::BTreeMap<String, NetworkProfileConfiguration>>,
     pub platforms: Option<collections::BTreeMap<String, PlatformConfiguration>>,
+    pub run: Option<RunConfiguration>,
+    pub serve: Option<ServeConfiguration>,
     pub ssh_devices: Option<collections::BTreeMap<String, SshDeviceConfiguration>>,
+    pub target_env: Option<collections::BTreeMap<String, collections::BTreeMap<String, String>>>,
     pub test_data: Option<collections::BTreeMap<String, TestDataConfiguration>>,
+    pub toolchains: Option<collections::BTreeMap<String, ToolchainConfiguration>>,
+    pub triples: Option<collections::BTreeMap<String, String>>,
+    pub wasi: Option<WasiConfiguration>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct PlatformConfiguration {
     pub env: Option<collections::HashMap<String, String>>,
+    /// Other Apple targets to build along `rustc_triple`, merged into
+    /// universal binaries (iOS platforms only).
+    pub fat_rustc_triples: Option<Vec<String>>,
     pub overlays: Option<collections::HashMap<String, OverlayConfiguration>>,
+    /// Run settings of the platform, over the project ones.
+    pub run: Option<RunOverlayConfiguration>,
     pub rustc_triple: Option<String>,
     pub sysroot: Option<String>,
     pub toolchain: Option<String>,
@@ -105,7 +269,9 @@ impl PlatformConfiguration {
     pub fn empty() -> Self {
         PlatformConfiguration {
             env: None,
+            fat_rustc_triples: None,
             overlays: None,
+            run: None,
             rustc_triple: None,
             sysroot: None,
             toolchain: None,
@@ -121,12 +287,186 @@ impl PlatformConfiguration {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct BenchConfiguration {
+    /// Pause between the repetitions of a benchmark, in seconds.
+    pub cool_down: Option<u64>,
+    /// Variation of the measures of a benchmark across its repetitions over
+    /// which it is unstable, in percent.
+    pub max_variation: Option<f64>,
+    /// Battery level under which benchmarks are unreliable, in percent.
+    pub min_battery: Option<u8>,
+    /// Thermal state over which benchmarks are unreliable (`none`, `light`,
+    /// `moderate`...).
+    pub max_thermal: Option<String>,
+    /// Pin the CPU governors to `performance` and stop the thermal daemons of
+    /// rooted Android devices during benchmarks.
+    pub pin_cpu: Option<bool>,
+    /// How many times to run each benchmark executable.
+    pub repeat: Option<usize>,
+    /// Refuse to run benchmarks on an unfit device instead of warning.
+    pub strict: Option<bool>,
+    /// How long to wait for an unfit device to cool down or charge, in
+    /// seconds.
+    pub wait: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct BuildConfiguration {
+    /// Command wrapping the C compilers and linkers (`sccache`, `ccache`, or
+    /// `auto` to use whichever is in the `PATH`).
+    pub compiler_launcher: Option<String>,
+    /// Install the missing standard libraries with rustup without asking.
+    pub install_targets: Option<bool>,
+    /// Number of parallel jobs, unless `--jobs` is given.
+    pub jobs: Option<u32>,
+    /// Format of the compiler messages (`human` or `json`), unless
+    /// `--message-format` is given.
+    pub message_format: Option<String>,
+    /// Deploy stripped executables, as with `--strip`.
+    pub strip: Option<bool>,
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct OverlayConfiguration {
     pub path: String,
     pub scope: Option<String>,
 }
 
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RunConfiguration {
+    /// Commands run on the host after the run on a device.
+    pub after_run: Option<Vec<String>>,
+    /// Arguments given to the runnables, before the command line ones.
+    pub args: Option<Vec<String>>,
+    /// Commands run on the host before installing on a device.
+    pub before_install: Option<Vec<String>>,
+    /// Send the bundles compressed, as with `--compress`.
+    pub compress: Option<bool>,
+    /// Also set the locale and timezone of the device, as with
+    /// `--device-region`.
+    pub device_region: Option<bool>,
+    /// Commands run on the device before the run.
+    pub device_setup: Option<Vec<String>>,
+    /// Commands run on the device after the run.
+    pub device_teardown: Option<Vec<String>>,
+    /// Environment of the runnables, under the command line one.
+    pub env: Option<collections::HashMap<String, String>>,
+    /// Reinstall the app with clean data on each run, as with `--fresh`.
+    pub fresh: Option<bool>,
+    /// Keep the screen of the device on during runs.
+    pub keep_awake: Option<bool>,
+    /// lldb commands run before launching the app (iOS only), e.g. to set
+    /// breakpoints.
+    pub lldb_commands: Option<Vec<String>>,
+    /// Python modules imported in lldb after the dinghy helpers (iOS only),
+    /// relative to the configuration file.
+    pub lldb_helpers: Option<Vec<String>>,
+    /// Locale of the runs, as with `--locale`.
+    pub locale: Option<String>,
+    /// Network profile of the runs, as with `--network-profile`.
+    pub network_profile: Option<String>,
+    /// Record the screen of the device during runs, as with `--record-screen`.
+    pub record: Option<bool>,
+    /// Take a screenshot of the device after failed runs, as with
+    /// `--screenshot-on-failure`.
+    pub screenshot_on_failure: Option<bool>,
+    /// Maximum duration of a run, in seconds.
+    pub timeout: Option<u64>,
+    /// Timezone of the runs, as with `--timezone`.
+    pub timezone: Option<String>,
+}
+
+/// A simulated network condition (`[network_profiles.<name>]`).
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct NetworkProfileConfiguration {
+    /// Added latency, in milliseconds.
+    pub delay: Option<u32>,
+    /// Condition profile of the iOS condition inducer.
+    pub ios_condition: Option<String>,
+    /// Packets dropped, in percents.
+    pub loss: Option<f32>,
+    /// Bandwidth, in kbit/s.
+    pub rate: Option<u32>,
+}
+
+/// Run settings of a platform (`[platforms.<id>.run]`) or of a device
+/// (`[devices.<id>.run]`).
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RunOverlayConfiguration {
+    /// Appended to the project hooks.
+    pub after_run: Option<Vec<String>>,
+    /// Appended to the project arguments.
+    pub args: Option<Vec<String>>,
+    /// Appended to the project hooks.
+    pub before_install: Option<Vec<String>>,
+    /// Appended to the project device hooks.
+    pub device_setup: Option<Vec<String>>,
+    /// Appended to the project device hooks.
+    pub device_teardown: Option<Vec<String>>,
+    /// Replacing the project variables of the same names.
+    pub env: Option<collections::HashMap<String, String>>,
+    /// Replacing the project test data of the same ids.
+    pub test_data: Option<collections::BTreeMap<String, TestDataConfiguration>>,
+}
+
+/// Run settings of a platform or device, as merged from the configuration
+/// files.
+#[derive(Clone, Debug, Default)]
+pub struct RunOverlay {
+    pub after_run: Vec<String>,
+    pub args: Vec<String>,
+    pub before_install: Vec<String>,
+    pub device_setup: Vec<String>,
+    pub device_teardown: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub test_data: Vec<TestData>,
+}
+
+impl RunOverlay {
+    fn merge(&mut self, file: &path::Path, conf: RunOverlayConfiguration) {
+        self.after_run.extend(conf.after_run.unwrap_or(vec![]));
+        self.args.extend(conf.args.unwrap_or(vec![]));
+        self.before_install.extend(conf.before_install.unwrap_or(vec![]));
+        self.device_setup.extend(conf.device_setup.unwrap_or(vec![]));
+        self.device_teardown.extend(conf.device_teardown.unwrap_or(vec![]));
+        self.env.extend(conf.env.unwrap_or(collections::HashMap::new()));
+        for (id, source) in conf.test_data.unwrap_or(collections::BTreeMap::new()) {
+            self.test_data.push(TestData::new(id, file, source));
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ServeConfiguration {
+    /// Address to listen on, unless `--listen` is given.
+    pub listen: Option<String>,
+    /// Token clients have to present, unless `DINGHY_SERVE_TOKEN` is set.
+    pub token: Option<String>,
+    /// Work directory of the clients files, `/tmp/dinghy-serve` by default.
+    pub path: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ToolchainConfiguration {
+    pub ar: Option<String>,
+    pub cc: Option<String>,
+    pub cxx: Option<String>,
+    pub env: Option<collections::HashMap<String, String>>,
+    pub linker: Option<String>,
+    pub sysroot: Option<String>,
+}
+
+impl ToolchainConfiguration {
+    pub fn env(&self) -> Vec<(String, String)> {
+        self.env.as_ref()
+            .map(|it| it.iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect_vec())
+            .unwrap_or(vec![])
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct SshDeviceConfiguration {
     pub hostname: String,
@@ -138,22 +478,258 @@ pub struct SshDeviceConfiguration {
     pub platform: Option<String>,
 }
 
+/// A Docker image standing for a device, its executables being run in
+/// containers of it.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DockerDeviceConfiguration {
+    pub image: String,
+    /// Options of `docker create`, e.g. `["--platform", "linux/arm64"]`.
+    pub args: Option<Vec<String>>,
+    pub platform: Option<String>,
+}
+
 impl Configuration {
+    /// The triple to run on a device of the given triple, as overridden in
+    /// the `[triples]` section (e.g. to use `thumbv7neon-linux-androideabi` on
+    /// ARMv7 Android devices).
+    pub fn rustc_triple_for(&self, triple: &Triple) -> String {
+        let triple = triple.to_string();
+        self.triples.get(&triple).cloned().unwrap_or(triple)
+    }
+
+    /// The settings of the device `id`, empty if it has none.
+    pub fn device(&self, id: &str) -> DeviceConfiguration {
+        self.devices.get(id).cloned().unwrap_or(DeviceConfiguration::default())
+    }
+
+    /// The configuration of runs on `device_id` with the platform
+    /// `platform_id`: the run settings of the platform then of the device
+    /// merged over the project ones.
+    pub fn for_device(&self, platform_id: &str, device_id: &str) -> Configuration {
+        let mut conf = self.clone();
+        for overlay in self.platform_runs.get(platform_id).into_iter().chain(self.device_runs.get(device_id)) {
+            conf.run.after_run.get_or_insert(vec![]).extend(overlay.after_run.iter().cloned());
+            conf.run.args.get_or_insert(vec![]).extend(overlay.args.iter().cloned());
+            conf.run.before_install.get_or_insert(vec![]).extend(overlay.before_install.iter().cloned());
+            conf.run.device_setup.get_or_insert(vec![]).extend(overlay.device_setup.iter().cloned());
+            conf.run.device_teardown.get_or_insert(vec![]).extend(overlay.device_teardown.iter().cloned());
+            conf.run.env.get_or_insert(collections::HashMap::new()).extend(overlay.env.iter().cloned());
+            for test_data in &overlay.test_data {
+                conf.test_data.retain(|it| it.id != test_data.id);
+                conf.test_data.push(test_data.clone());
+            }
+        }
+        conf
+    }
+
     pub fn merge(&mut self, file: &path::Path) -> Result<()> {
         let other = read_config_file(&file)?;
         if let Some(pfs) = other.platforms {
+            for (id, pf) in &pfs {
+                if let Some(ref run) = pf.run {
+                    self.platform_runs.entry(id.clone()).or_insert(RunOverlay::default()).merge(file, run.clone());
+                }
+            }
             self.platforms.extend(pfs)
         }
+        self.agents
+            .extend(other.agents.unwrap_or(collections::BTreeMap::new()));
+        self.docker_devices
+            .extend(other.docker_devices.unwrap_or(collections::BTreeMap::new()));
+        self.groups
+            .extend(other.groups.unwrap_or(collections::BTreeMap::new()));
+        self.network_profiles
+            .extend(other.network_profiles.unwrap_or(collections::BTreeMap::new()));
         self.ssh_devices
             .extend(other.ssh_devices.unwrap_or(collections::BTreeMap::new()));
+        for (triple, env) in other.target_env.unwrap_or(collections::BTreeMap::new()) {
+            self.target_env.entry(triple).or_insert(collections::BTreeMap::new()).extend(env);
+        }
+        self.toolchains
+            .extend(other.toolchains.unwrap_or(collections::BTreeMap::new()));
+        self.triples
+            .extend(other.triples.unwrap_or(collections::BTreeMap::new()));
         for (id, source) in other.test_data.unwrap_or(collections::BTreeMap::new()) { // TODO Remove key
-            self.test_data.push(TestData {
-                id: id.to_string(),
-                base: file.to_path_buf(),
-                source: source.source.clone(),
-                target: source.target.unwrap_or(source.source.clone()),
-                copy_git_ignored: source.copy_git_ignored,
-            })
+            self.test_data.push(TestData::new(id, file, source))
+        }
+        for (id, device) in other.devices.unwrap_or(collections::BTreeMap::new()) {
+            if let Some(run) = device.run {
+                self.device_runs.entry(id.clone()).or_insert(RunOverlay::default()).merge(file, run);
+            }
+            let merged = self.devices.entry(id).or_insert(DeviceConfiguration::default());
+            if device.rustc_triple.is_some() {
+                merged.rustc_triple = device.rustc_triple;
+            }
+            if device.work_dir.is_some() {
+                merged.work_dir = device.work_dir;
+            }
+        }
+        if let Some(bench) = other.bench {
+            if bench.cool_down.is_some() {
+                self.bench.cool_down = bench.cool_down;
+            }
+            if bench.max_variation.is_some() {
+                self.bench.max_variation = bench.max_variation;
+            }
+            if bench.min_battery.is_some() {
+                self.bench.min_battery = bench.min_battery;
+            }
+            if bench.max_thermal.is_some() {
+                self.bench.max_thermal = bench.max_thermal;
+            }
+            if bench.pin_cpu.is_some() {
+                self.bench.pin_cpu = bench.pin_cpu;
+            }
+            if bench.repeat.is_some() {
+                self.bench.repeat = bench.repeat;
+            }
+            if bench.strict.is_some() {
+                self.bench.strict = bench.strict;
+            }
+            if bench.wait.is_some() {
+                self.bench.wait = bench.wait;
+            }
+        }
+        if let Some(build) = other.build {
+            if build.install_targets.is_some() {
+                self.build.install_targets = build.install_targets;
+            }
+            if build.jobs.is_some() {
+                self.build.jobs = build.jobs;
+            }
+            if build.message_format.is_some() {
+                self.build.message_format = build.message_format;
+            }
+            if build.strip.is_some() {
+                self.build.strip = build.strip;
+            }
+        }
+        if let Some(run) = other.run {
+            if run.after_run.is_some() {
+                self.run.after_run = run.after_run;
+            }
+            if run.args.is_some() {
+                self.run.args = run.args;
+            }
+            if run.before_install.is_some() {
+                self.run.before_install = run.before_install;
+            }
+            if run.device_setup.is_some() {
+                self.run.device_setup = run.device_setup;
+            }
+            if run.device_teardown.is_some() {
+                self.run.device_teardown = run.device_teardown;
+            }
+            if run.compress.is_some() {
+                self.run.compress = run.compress;
+            }
+            if let Some(env) = run.env {
+                self.run.env.get_or_insert(collections::HashMap::new()).extend(env);
+            }
+            if run.device_region.is_some() {
+                self.run.device_region = run.device_region;
+            }
+            if run.fresh.is_some() {
+                self.run.fresh = run.fresh;
+            }
+            if run.keep_awake.is_some() {
+                self.run.keep_awake = run.keep_awake;
+            }
+            if run.locale.is_some() {
+                self.run.locale = run.locale;
+            }
+            if run.network_profile.is_some() {
+                self.run.network_profile = run.network_profile;
+            }
+            if run.record.is_some() {
+                self.run.record = run.record;
+            }
+            if run.screenshot_on_failure.is_some() {
+                self.run.screenshot_on_failure = run.screenshot_on_failure;
+            }
+            if run.lldb_commands.is_some() {
+                self.run.lldb_commands = run.lldb_commands;
+            }
+            if let Some(helpers) = run.lldb_helpers {
+                let base = file.parent().unwrap_or(path::Path::new("/"));
+                self.run.lldb_helpers = Some(helpers.iter()
+                    .map(|it| base.join(it).to_string_lossy().to_string())
+                    .collect());
+            }
+            if run.timeout.is_some() {
+                self.run.timeout = run.timeout;
+            }
+            if run.timezone.is_some() {
+                self.run.timezone = run.timezone;
+            }
+        }
+        if let Some(serve) = other.serve {
+            if serve.listen.is_some() {
+                self.serve.listen = serve.listen;
+            }
+            if serve.token.is_some() {
+                self.serve.token = serve.token;
+            }
+            if serve.path.is_some() {
+                self.serve.path = serve.path;
+            }
+        }
+        if let Some(android) = other.android {
+            if let Some(mut apk_runner) = android.apk_runner {
+                apk_runner.path = file.parent().unwrap_or(path::Path::new("/"))
+                    .join(&apk_runner.path)
+                    .to_string_lossy()
+                    .to_string();
+                self.android.apk_runner = Some(apk_runner);
+            }
+        }
+        if let Some(ios) = other.ios {
+            let base = file.parent().unwrap_or(path::Path::new("/"));
+            if let Some(cbindgen) = ios.cbindgen {
+                self.ios.cbindgen = Some(base.join(cbindgen).to_string_lossy().to_string());
+            }
+            if ios.keychain.is_some() {
+                self.ios.keychain = ios.keychain;
+            }
+            if ios.keychain_password.is_some() {
+                self.ios.keychain_password = ios.keychain_password;
+            }
+            if ios.keychain_password_env.is_some() {
+                self.ios.keychain_password_env = ios.keychain_password_env;
+            }
+            if ios.signing_identity.is_some() {
+                self.ios.signing_identity = ios.signing_identity;
+            }
+            if let Some(mut xcode) = ios.xcode {
+                xcode.project = base.join(&xcode.project).to_string_lossy().to_string();
+                self.ios.xcode = Some(xcode);
+            }
+        }
+        if let Some(wasi) = other.wasi {
+            if wasi.runtime.is_some() {
+                self.wasi.runtime = wasi.runtime;
+            }
+            if wasi.runtime_args.is_some() {
+                self.wasi.runtime_args = wasi.runtime_args;
+            }
+        }
+        if let Some(bundle) = other.bundle {
+            let base = file.parent().unwrap_or(path::Path::new("/"));
+            if let Some(entitlements) = bundle.entitlements {
+                self.bundle_entitlements.push(base.join(entitlements));
+            }
+            if bundle.identifier.is_some() {
+                self.bundle_identifier = bundle.identifier;
+            }
+            self.bundle_info_plist
+                .extend(bundle.info_plist.unwrap_or(collections::BTreeMap::new()));
+            for (id, source) in bundle.resources.unwrap_or(collections::BTreeMap::new()) {
+                self.bundle_resources.push(BundleResource {
+                    id,
+                    base: file.to_path_buf(),
+                    source,
+                })
+            }
         }
         Ok(())
     }
@@ -171,10 +747,12 @@ pub fn dinghy_config<P: AsRef<path::Path>>(dir: P) -> Result<Configuration> {
     #[cfg(target_os = "macos")] {
         for arch in &["armv7", "armv7s", "aarch64", "i386", "x86_64" ] {
             let id = format!("ios-{}", arch);
-            let rustc_triple = Some(format!("{}-apple-ios", arch));
+            let rustc_triple = Some(Triple::ios(arch).to_string());
             conf.platforms.insert(id, PlatformConfiguration{ rustc_triple, ..PlatformConfiguration::default() });
         }
     }
+    conf.platforms.insert("wasm32-wasi".to_string(),
+                          PlatformConfiguration { rustc_triple: Some("wasm32-wasi".to_string()), ..PlatformConfiguration::default() });
 
     let mut files_to_try = vec![];
     let dir = dir.as_ref().to_path_buf();
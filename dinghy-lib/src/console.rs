@@ -0,0 +1,185 @@
+//! The consoles of the devices, streamed by `cargo dinghy log`: logcat on
+//! Android, the syslog relay on iOS devices, `log stream` on simulators.
+//!
+//! Each console message is printed as the device wrote it, colored after its
+//! priority when stdout is a terminal, or with `--json` as a JSON object per
+//! line, the same for all devices:
+//! `{"time", "process", "pid", "priority", "tag", "message"}`.
+
+use errors::*;
+use json::JsonValue;
+use std::io::stdout;
+use std::io::Write;
+
+/// How to stream a console.
+#[derive(Clone, Debug, Default)]
+pub struct ConsoleOptions {
+    /// Keep the messages of this process only.
+    pub process: Option<String>,
+    /// Clear the messages logged before streaming (logcat keeps them).
+    pub clear: bool,
+    pub json: bool,
+    pub colored: bool,
+}
+
+impl ConsoleOptions {
+    /// Print `message` to stdout, the way the options say.
+    pub fn print(&self, message: &ConsoleMessage) -> Result<()> {
+        let out = stdout();
+        let mut out = out.lock();
+        if self.json {
+            writeln!(out, "{}", message.to_json().dump())?;
+        } else if self.colored {
+            writeln!(out, "\x1b[{}m{}\x1b[0m", message.priority.color(), message.line)?;
+        } else {
+            writeln!(out, "{}", message.line)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Priority of a console message, from the least to the most severe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Verbose,
+    Debug,
+    Info,
+    Notice,
+    Warning,
+    Error,
+    Fatal,
+}
+
+impl Priority {
+    /// The priority letter of logcat (`V`, `D`, `I`, `W`, `E`, `F`).
+    pub fn from_logcat(letter: &str) -> Option<Priority> {
+        match letter {
+            "V" => Some(Priority::Verbose),
+            "D" => Some(Priority::Debug),
+            "I" => Some(Priority::Info),
+            "W" => Some(Priority::Warning),
+            "E" => Some(Priority::Error),
+            "F" | "A" => Some(Priority::Fatal),
+            _ => None,
+        }
+    }
+
+    /// The levels of syslog (`<Notice>`) and of os_log (`Default`, `Fault`).
+    pub fn from_apple(level: &str) -> Option<Priority> {
+        match level.to_lowercase().as_str() {
+            "debug" => Some(Priority::Debug),
+            "info" => Some(Priority::Info),
+            "notice" | "default" => Some(Priority::Notice),
+            "warning" => Some(Priority::Warning),
+            "error" => Some(Priority::Error),
+            "critical" | "alert" | "emergency" | "fault" => Some(Priority::Fatal),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Priority::Verbose => "verbose",
+            Priority::Debug => "debug",
+            Priority::Info => "info",
+            Priority::Notice => "notice",
+            Priority::Warning => "warning",
+            Priority::Error => "error",
+            Priority::Fatal => "fatal",
+        }
+    }
+
+    /// The ANSI color of the messages, the ones of `logcat -v color`.
+    fn color(&self) -> &'static str {
+        match *self {
+            Priority::Verbose | Priority::Notice => "0",
+            Priority::Debug => "34",
+            Priority::Info => "32",
+            Priority::Warning => "33",
+            Priority::Error | Priority::Fatal => "31",
+        }
+    }
+}
+
+/// A message of the console of a device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConsoleMessage {
+    /// The message as the device wrote it.
+    pub line: String,
+    pub time: String,
+    pub process: Option<String>,
+    pub pid: Option<u32>,
+    pub priority: Priority,
+    pub tag: Option<String>,
+    pub message: String,
+}
+
+impl ConsoleMessage {
+    /// A line of `logcat -v threadtime`:
+    /// `01-02 10:12:01.123  1234  1250 I Tag     : message`.
+    pub fn from_logcat(line: &str) -> Option<ConsoleMessage> {
+        let mut fields = line.split_whitespace();
+        let date = fields.next()?;
+        let time = fields.next()?;
+        let pid = fields.next()?.parse().ok()?;
+        let _tid = fields.next()?.parse::<u32>().ok()?;
+        let priority = Priority::from_logcat(fields.next()?)?;
+        let rest = skip_fields(line, 5)?;
+        let (tag, message) = match rest.find(": ") {
+            Some(index) => (&rest[..index], &rest[index + 2..]),
+            None => (rest.trim_right().trim_right_matches(':'), ""),
+        };
+        let tag = tag.trim();
+        Some(ConsoleMessage {
+            line: line.to_string(),
+            time: format!("{} {}", date, time),
+            process: None,
+            pid: Some(pid),
+            priority,
+            tag: if tag.is_empty() { None } else { Some(tag.to_string()) },
+            message: message.to_string(),
+        })
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut message = JsonValue::new_object();
+        message["time"] = self.time.as_str().into();
+        message["process"] = self.process.as_ref().map(|it| it.as_str()).into();
+        message["pid"] = self.pid.into();
+        message["priority"] = self.priority.name().into();
+        message["tag"] = self.tag.as_ref().map(|it| it.as_str()).into();
+        message["message"] = self.message.as_str().into();
+        message
+    }
+}
+
+/// What follows the first `count` fields of `line`.
+fn skip_fields(line: &str, count: usize) -> Option<&str> {
+    let mut rest = line;
+    for _ in 0..count {
+        rest = rest.trim_left();
+        rest = &rest[rest.find(char::is_whitespace)?..];
+    }
+    Some(rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logcat_messages() {
+        let message = ConsoleMessage::from_logcat(
+            "01-02 10:12:01.123  1234  1250 I dinghy  : running 3 tests: ok").unwrap();
+        assert_eq!(message.time, "01-02 10:12:01.123");
+        assert_eq!((message.pid, message.priority), (Some(1234), Priority::Info));
+        assert_eq!(message.tag, Some("dinghy".to_string()));
+        assert_eq!(message.message, "running 3 tests: ok");
+        let message = ConsoleMessage::from_logcat(
+            "01-02 10:12:02.456   301   301 F DEBUG   : *** *** ***").unwrap();
+        assert_eq!((message.priority, message.tag.as_ref().map(|it| it.as_str())), (Priority::Fatal, Some("DEBUG")));
+        assert_eq!(message.to_json()["priority"].as_str(), Some("fatal"));
+        assert_eq!(ConsoleMessage::from_logcat("--------- beginning of main"), None);
+    }
+}
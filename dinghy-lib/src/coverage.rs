@@ -0,0 +1,61 @@
+//! Source-based code coverage of runs on devices.
+//!
+//! Coverage builds are instrumented with `-C instrument-coverage`. Runnables
+//! write their `.profraw` files in a device-writable directory pointed to by
+//! `LLVM_PROFILE_FILE`, which devices pull back to `ArtifactStore::coverage_dir()`.
+
+use dinghy_build::build_env::set_env;
+use errors::*;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use utils::path_to_str;
+
+/// Add the instrumentation flag to the rustc flags used by cargo.
+pub fn setup_instrumentation() {
+    let rustflags = env::var("RUSTFLAGS").unwrap_or(String::new());
+    if !rustflags.contains("instrument-coverage") {
+        set_env("RUSTFLAGS", format!("{} -C instrument-coverage", rustflags).trim());
+    }
+}
+
+/// Value of `LLVM_PROFILE_FILE` making runnables write in `dir`.
+pub fn profile_file<P: AsRef<Path>>(dir: P) -> Result<String> {
+    Ok(format!("{}/%p-%m.profraw", path_to_str(dir.as_ref())?))
+}
+
+/// Merge the raw profiles of `dir` into `dir/dinghy.profdata` if llvm-profdata
+/// is available.
+pub fn merge_profiles(dir: &Path) -> Result<Option<PathBuf>> {
+    let profraws = fs::read_dir(dir)?
+        .filter_map(|it| it.ok())
+        .map(|it| it.path())
+        .filter(|it| it.extension().map(|ext| ext == "profraw").unwrap_or(false))
+        .collect::<Vec<_>>();
+    if profraws.is_empty() {
+        warn!("No coverage data was collected in {}", dir.display());
+        return Ok(None);
+    }
+
+    let llvm_profdata = match ::which::which("llvm-profdata") {
+        Ok(llvm_profdata) => llvm_profdata,
+        Err(_) => {
+            info!("llvm-profdata not found, coverage data left unmerged in {}", dir.display());
+            return Ok(None);
+        }
+    };
+    let profdata = dir.join("dinghy.profdata");
+    let status = Command::new(llvm_profdata)
+        .arg("merge")
+        .arg("-sparse")
+        .args(&profraws)
+        .arg("-o")
+        .arg(&profdata)
+        .status()?;
+    if !status.success() {
+        bail!("Couldn't merge coverage data of {}", dir.display())
+    }
+    Ok(Some(profdata))
+}
@@ -0,0 +1,80 @@
+//! Benchmarks run with criterion: regular executables under `benches/`, built
+//! with `harness = false` and run like the libtest ones.
+//!
+//! Criterion keeps its data, the measures of the last run and the baselines
+//! saved with `--save-baseline`, in `CRITERION_HOME`. Devices point it to a
+//! directory of their own and pull it back to `ArtifactStore::criterion_dir()`
+//! after the run. It is then merged into the data of the host
+//! (`target/criterion`), for criterion or critcmp to compare the runs there.
+
+use errors::*;
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+pub const HOME_ENV: &str = "CRITERION_HOME";
+
+/// The criterion data of the host, where `cargo bench` keeps it.
+pub fn host_dir() -> Result<PathBuf> {
+    match env::var_os(HOME_ENV) {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => Ok(::compiler::base_target_dir()?.join("criterion")),
+    }
+}
+
+/// Copies the criterion data of `from` into `into`, replacing the measures of
+/// the same benchmarks and baselines there. Returns the benchmarks merged.
+pub fn merge(from: &Path, into: &Path) -> Result<Vec<String>> {
+    let mut benchmarks = BTreeSet::new();
+    if from.is_dir() {
+        merge_dir(from, from, into, &mut benchmarks)
+            .chain_err(|| format!("Couldn't merge the criterion data of {} into {}", from.display(), into.display()))?;
+    }
+    Ok(benchmarks.into_iter().collect())
+}
+
+fn merge_dir(root: &Path, from: &Path, into: &Path, benchmarks: &mut BTreeSet<String>) -> Result<()> {
+    fs::create_dir_all(into)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let (source, target) = (entry.path(), into.join(entry.file_name()));
+        if entry.file_type()?.is_dir() {
+            merge_dir(root, &source, &target, benchmarks)?;
+        } else {
+            fs::copy(&source, &target)?;
+            if let Ok(relative) = source.strip_prefix(root) {
+                benchmarks.extend(benchmark_id(relative));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The benchmark a file of the criterion data belongs to: measures are in
+/// `<benchmark>/<baseline>/benchmark.json`, the baseline being `new`, `base`
+/// or a name given to `--save-baseline`.
+fn benchmark_id(relative: &Path) -> Option<String> {
+    if relative.file_name().map(|it| it != "benchmark.json").unwrap_or(true) {
+        return None;
+    }
+    let benchmark = relative.parent()?.parent()?;
+    if benchmark.as_os_str().is_empty() {
+        return None;
+    }
+    Some(benchmark.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn benchmark_ids() {
+        assert_eq!(benchmark_id(Path::new("fib/20/new/benchmark.json")), Some("fib/20".to_string()));
+        assert_eq!(benchmark_id(Path::new("parse/main/benchmark.json")), Some("parse".to_string()));
+        assert_eq!(benchmark_id(Path::new("parse/new/estimates.json")), None);
+        assert_eq!(benchmark_id(Path::new("new/benchmark.json")), None);
+    }
+}
@@ -0,0 +1,180 @@
+//! Separation of the host and the target in cross builds.
+//!
+//! Build scripts and proc macros are built and run on the host, the crates
+//! for the device. Target settings leaking to the host side (the C compiler of
+//! the toolchain in the plain `CC`, a stale `CARGO_BUILD_TARGET`) make build
+//! scripts link against the libraries of the target, which fails with obscure
+//! linker errors. Dinghy keeps both sides apart before building, and explains
+//! the build script failures that look like such a mix.
+//!
+//! The `-sys` crates of C libraries (openssl-sys...) also need to be told
+//! where the libraries of the target are: the `[target_env."<triple>"]`
+//! variables of the configuration are exported for that.
+
+use config::Configuration;
+use dinghy_build::build_env::envify;
+use dinghy_build::build_env::set_env;
+use dinghy_build::build_env::set_target_env;
+use errors::*;
+use std::env;
+
+/// The C tools cc-rs looks up for the host too, with the host command used
+/// when the plain variable is taken by the target.
+const HOST_TOOLS: &[(&str, &str)] = &[("CC", "cc"), ("CXX", "c++"), ("AR", "ar")];
+
+/// `-sys` crates known to fail cross builds without being told where the
+/// libraries of the target are, with the variable to set.
+pub const SYS_CRATES: &[(&str, &str)] = &[
+    ("openssl-sys", "OPENSSL_DIR"),
+    ("libsodium-sys", "SODIUM_LIB_DIR"),
+];
+
+/// What linkers and loaders say when given objects of another architecture.
+const MISMATCH_SIGNS: &[&str] = &[
+    "wrong elf class",
+    "file format not recognized",
+    "file in wrong format",
+    "skipping incompatible",
+    "exec format error",
+    "cannot execute binary file",
+    "relocations in generic elf",
+    "object file built for",
+    "was built for",
+    "incompatible target",
+];
+
+/// Prepares the environment of a build for `rustc_triple`, or for the host
+/// when `None`.
+pub fn separate_host_and_target(rustc_triple: Option<&str>) {
+    match rustc_triple {
+        Some(rustc_triple) => {
+            set_env("CARGO_BUILD_TARGET", rustc_triple);
+            protect_host_tools(rustc_triple);
+        }
+        None => {
+            if let Ok(target) = env::var("CARGO_BUILD_TARGET") {
+                warn!("Ignoring CARGO_BUILD_TARGET={} for a build on the host", target);
+                env::remove_var("CARGO_BUILD_TARGET");
+            }
+        }
+    }
+}
+
+/// Exports the `[target_env."<rustc_triple>"]` variables of the
+/// configuration, scoped to the target when the build scripts reading them
+/// support it: `PKG_CONFIG_*` get the triple as a suffix, `OPENSSL_*` as a
+/// prefix (the way openssl-sys looks them up), the others are set as is.
+pub fn export_target_env(conf: &Configuration, rustc_triple: Option<&str>) {
+    let rustc_triple = match rustc_triple {
+        Some(rustc_triple) => rustc_triple,
+        None => return,
+    };
+    for (var, value) in conf.target_env.get(rustc_triple).into_iter().flat_map(|it| it) {
+        if var.starts_with("PKG_CONFIG_") {
+            set_target_env(var, Some(rustc_triple), value);
+        } else if var.starts_with("OPENSSL_") {
+            set_env(format!("{}_{}", envify(rustc_triple), var), value);
+        } else {
+            set_env(var, value);
+        }
+    }
+}
+
+/// Whether `var` is set for `rustc_triple`, in `[target_env]` or in the
+/// environment.
+pub fn has_target_env(conf: &Configuration, rustc_triple: &str, var: &str) -> bool {
+    conf.target_env.get(rustc_triple).map(|it| it.contains_key(var)).unwrap_or(false)
+        || env::var_os(var).is_some()
+        || env::var_os(format!("{}_{}", envify(rustc_triple), var)).is_some()
+}
+
+/// Gives the host its own C tools when the plain `CC`, `CXX` or `AR` point to
+/// the ones of the target (e.g. set in the `env` of a platform): cc-rs would
+/// use them for the build scripts too.
+fn protect_host_tools(rustc_triple: &str) {
+    for &(var, host_command) in HOST_TOOLS {
+        let host_var = format!("HOST_{}", var);
+        if env::var_os(&host_var).is_some() {
+            continue;
+        }
+        if let Ok(command) = env::var(var) {
+            let target_command = env::var(format!("TARGET_{}", var)).ok();
+            if is_target_tool(&command, rustc_triple, target_command.as_ref().map(|it| it.as_str())) {
+                warn!("{}={} is a tool of {}, build scripts will use {} on the host (set {} to change it)",
+                      var, command, rustc_triple, host_command, host_var);
+                set_env(host_var, host_command);
+            }
+        }
+    }
+}
+
+fn is_target_tool(command: &str, rustc_triple: &str, target_command: Option<&str>) -> bool {
+    target_command == Some(command) || command.contains(rustc_triple)
+}
+
+/// Whether the build failure `message` is a build script hitting objects of
+/// another architecture.
+fn is_build_script_mismatch(message: &str) -> bool {
+    let message = message.to_lowercase();
+    (message.contains("build script") || message.contains("custom build command"))
+        && MISMATCH_SIGNS.iter().any(|it| message.contains(it))
+}
+
+/// The `-sys` crate of `SYS_CRATES` whose build script failed in `message`,
+/// not finding its library.
+fn missing_sys_library(message: &str) -> Option<&'static (&'static str, &'static str)> {
+    SYS_CRATES.iter().find(|&&(krate, var)| {
+        message.contains(&format!("custom build command for `{} ", krate))
+            && (message.contains(var) || message.contains("pkg-config") || message.contains("Could not find"))
+    })
+}
+
+/// Chains an explanation to a build failure for `rustc_triple`, when it looks
+/// like a `-sys` crate not finding its library, or like a build script mixing
+/// up the host and the target.
+pub fn explain_build_failure(error: Error, rustc_triple: Option<&str>) -> Error {
+    let rustc_triple = match rustc_triple {
+        Some(rustc_triple) => rustc_triple,
+        None => return error,
+    };
+    let message = error.iter().map(|it| it.to_string()).collect::<Vec<_>>().join("\n");
+    if let Some(&(krate, var)) = missing_sys_library(&message) {
+        return Error::with_chain(error, format!(
+            "{} couldn't find the library for {}. Build or install it for the target, and point \
+             dinghy at it in .dinghy.toml:\n\n[target_env.\"{}\"]\n{} = \"/path/to/the/library\"",
+            krate, rustc_triple, rustc_triple, var));
+    }
+    if !is_build_script_mismatch(&message) {
+        return error;
+    }
+    Error::with_chain(error, format!(
+        "A build script failed with an architecture mismatch while building for {}. Build scripts \
+         are built and run on the host: they must not link against the libraries of the target nor \
+         use its C compiler. Check that the target settings only go to the target variables \
+         (TARGET_CC, CC_{}, PKG_CONFIG_SYSROOT_DIR_{}...), and that CC, CXX, AR, LIBRARY_PATH or \
+         PKG_CONFIG_PATH, if set, are the ones of the host.",
+        rustc_triple, rustc_triple, rustc_triple))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_and_target() {
+        assert!(is_target_tool("/opt/ndk/bin/aarch64-linux-android-clang", "aarch64-linux-android", None));
+        assert!(is_target_tool("/target/x/cc", "aarch64-linux-android", Some("/target/x/cc")));
+        assert!(!is_target_tool("gcc", "aarch64-linux-android", Some("/target/x/cc")));
+        assert!(is_build_script_mismatch("failed to run custom build command for `openssl-sys v0.9.24`\n\
+                                          /usr/bin/ld: skipping incompatible /opt/sysroot/usr/lib/libssl.so"));
+        assert!(is_build_script_mismatch("error: failed to run custom build command for `ring`\n\
+                                          build-script-build: Exec format error (os error 8)"));
+        assert!(!is_build_script_mismatch("failed to run custom build command for `ring`\nNo such file or directory"));
+        let openssl = "failed to run custom build command for `openssl-sys v0.9.24`\n\
+                       Could not find directory of OpenSSL installation, and this `-sys` crate cannot\n\
+                       proceed without this knowledge. If OpenSSL is installed and this crate had\n\
+                       trouble finding it,  you can set the `OPENSSL_DIR` environment variable";
+        assert_eq!(missing_sys_library(openssl), Some(&("openssl-sys", "OPENSSL_DIR")));
+        assert_eq!(missing_sys_library("failed to run custom build command for `ring v0.12.1`"), None);
+    }
+}
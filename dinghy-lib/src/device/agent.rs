@@ -0,0 +1,516 @@
+//! Devices attached to a remote dinghy agent.
+//!
+//! An agent is a host running dinghy with devices plugged in (e.g. a Mac mini
+//! with phones), reached through ssh. `cargo dinghy agent-devices` lists its
+//! devices. Bundles are made locally, synced to the agent and run there by
+//! `cargo dinghy agent-run`, whose output is streamed back and parsed locally.
+//!
+//! Agents can also be reached without ssh, when they run `cargo dinghy serve`
+//! (see `serve`): files and runs then go through its TCP protocol.
+
+use cleanup;
+use compiler::CompileMode;
+use config::{AgentConfiguration, Configuration};
+use device::bundle_size;
+use device::describe_remote_app;
+use device::make_remote_app;
+use errors::*;
+use logging;
+use forward::PortForward;
+use output::OutputSink;
+#[cfg(target_os = "macos")]
+use platform::ios::IosPlatform;
+use platform::custom_platform::CustomPlatform;
+use platform::regular_platform::RegularPlatform;
+use platform::wasi::WasiPlatform;
+use project::Project;
+use report::RunResult;
+use report::TestReport;
+use report::TestReportParser;
+use serve::Connection;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+use timings;
+use timings::Phase;
+use toml;
+use utils::path_to_str;
+use utils::run_with_report;
+use Build;
+use BuildArgs;
+use BuildBundle;
+use Device;
+use DeviceCompatibility;
+use Dinghy;
+use Platform;
+use PlatformManager;
+use RunArgs;
+use Runnable;
+
+#[derive(Clone)]
+pub struct AgentDevice {
+    agent: String,
+    conf: AgentConfiguration,
+    id: String,
+    name: String,
+    remote_id: String,
+    rustc_triples: Vec<String>,
+}
+
+impl AgentDevice {
+    fn install_app(&self, project: &Project, build: &Build, runnable: &Runnable) -> Result<(BuildBundle, BuildBundle)> {
+        let _span = logging::span(format!("install {}", runnable.id));
+        let _timer = timings::start(Phase::Transfer);
+        let build_bundle = make_remote_app(project, build, &build.artifacts(&self.id), runnable)?;
+        let remote_bundle = self.to_remote_bundle(&build_bundle)?;
+
+        info!("Install {} to {}", runnable.id, self.id);
+        if let Some(mut connection) = connect(&self.conf)? {
+            connection.sync(&build_bundle.bundle_dir, path_to_str(&remote_bundle.bundle_dir)?)?;
+            connection.sync(&build_bundle.lib_dir, path_to_str(&remote_bundle.lib_dir)?)?;
+            timings::transferred(bundle_size(&build_bundle)?);
+            return Ok((build_bundle, remote_bundle));
+        }
+
+        let _ = ssh_command(&self.conf)?
+            .arg(&format!("mkdir -p '{}' '{}'",
+                          path_to_str(&remote_bundle.bundle_dir)?,
+                          path_to_str(&remote_bundle.lib_dir)?))
+            .status();
+        self.sync(&build_bundle.bundle_dir, &remote_bundle.bundle_dir)?;
+        self.sync(&build_bundle.lib_dir, &remote_bundle.lib_dir)?;
+        timings::transferred(bundle_size(&build_bundle)?);
+        Ok((build_bundle, remote_bundle))
+    }
+
+    fn kill_remote(&self, remote_bundle: &BuildBundle) -> Result<()> {
+        debug!("Killing the run of {} on {}", remote_bundle.id, self.id);
+        let _ = ssh_command(&self.conf)?
+            .arg(&format!("pkill -f '{}'", path_to_str(&remote_bundle.bundle_dir)?))
+            .status()?;
+        Ok(())
+    }
+
+    fn run_command(&self, build: &Build, remote_bundle: &BuildBundle, run_args: &RunArgs) -> Result<String> {
+        let escape = |it: &str| ::shell_escape::escape(it.to_string().into()).to_string();
+        let envs = run_args.envs.iter()
+            .map(|it| format!("--env {}", escape(it)))
+            .collect::<Vec<_>>();
+        let args = run_args.args.iter().map(|it| escape(it)).collect::<Vec<_>>();
+        Ok(format!("{} -d {} agent-run --bundle {} --libs {} --mode {} {} -- {}",
+                   self.conf.dinghy.as_ref().map(|it| it.as_str()).unwrap_or("cargo dinghy"),
+                   escape(&self.remote_id),
+                   escape(path_to_str(&remote_bundle.bundle_dir)?),
+                   escape(path_to_str(&remote_bundle.lib_dir)?),
+                   run_mode(build),
+                   envs.join(" "),
+                   args.join(" ")))
+    }
+
+    /// Run a synced bundle through a `cargo dinghy serve` connection, feeding
+    /// its output to `sink`. Returns the exit code, `None` on timeout.
+    fn run_served(&self,
+                  connection: &mut Connection,
+                  build: &Build,
+                  remote_bundle: &BuildBundle,
+                  run_args: &RunArgs,
+                  sink: &OutputSink) -> Result<Option<i32>> {
+        connection.run(&self.remote_id,
+                       run_mode(build),
+                       path_to_str(&remote_bundle.bundle_dir)?,
+                       path_to_str(&remote_bundle.lib_dir)?,
+                       &run_args.envs,
+                       &run_args.args,
+                       run_args.timeout,
+                       |stream, chunk| sink.write(stream, chunk))
+    }
+
+    fn ssh_run_command(&self) -> Result<Command> {
+        let mut command = ssh_command(&self.conf)?;
+        if ::isatty::stdout_isatty() {
+            command.arg("-t").arg("-o").arg("LogLevel=QUIET");
+        }
+        Ok(command)
+    }
+
+    fn sync<FP: AsRef<Path>, TP: AsRef<Path>>(&self, from_path: FP, to_path: TP) -> Result<()> {
+        let mut command = self.sync_command(from_path, to_path)?;
+        if !log_enabled!(::log::Level::Debug) {
+            command.stdout(::std::process::Stdio::null());
+            command.stderr(::std::process::Stdio::null());
+        }
+        debug!("Running {:?}", command);
+        if !command.status()?.success() {
+            bail!("Error syncing to agent {} ({:?})", self.agent, command)
+        }
+        Ok(())
+    }
+
+    fn sync_command<FP: AsRef<Path>, TP: AsRef<Path>>(&self, from_path: FP, to_path: TP) -> Result<Command> {
+        let mut command = Command::new("/usr/bin/rsync");
+        command.arg("-a").arg("-v");
+        if let Some(port) = self.conf.port {
+            command.arg("-e").arg(&*format!("ssh -p {}", port));
+        };
+        command
+            .arg(&format!("{}/", path_to_str(&from_path.as_ref())?))
+            .arg(&format!("{}:{}/", ssh_destination(&self.conf)?, path_to_str(&to_path.as_ref())?));
+        Ok(command)
+    }
+
+    /// Bundles go in a directory of their device, relative to the work
+    /// directory of served agents.
+    fn to_remote_bundle(&self, build_bundle: &BuildBundle) -> Result<BuildBundle> {
+        let remote_prefix = if self.conf.address.is_some() {
+            PathBuf::from(&self.remote_id)
+        } else {
+            PathBuf::from(self.conf.path.clone().unwrap_or("/tmp".into()))
+                .join("dinghy-agent")
+                .join(&self.remote_id)
+        };
+        build_bundle.replace_prefix_with(remote_prefix)
+    }
+
+    fn supports(&self, rustc_triple: &str) -> bool {
+        self.rustc_triples.iter().any(|it| it == rustc_triple)
+    }
+}
+
+impl DeviceCompatibility for AgentDevice {
+    fn is_compatible_with_custom_platform(&self, platform: &CustomPlatform) -> bool {
+        self.supports(&platform.toolchain.rustc_triple)
+    }
+
+    fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
+        self.supports(&platform.toolchain.rustc_triple)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn is_compatible_with_ios_platform(&self, platform: &IosPlatform) -> bool {
+        !platform.sim && platform.rustc_triples().into_iter().any(|it| self.supports(it))
+    }
+
+    fn is_compatible_with_wasi_platform(&self, platform: &WasiPlatform) -> bool {
+        self.supports(&platform.rustc_triple)
+    }
+}
+
+impl Device for AgentDevice {
+    fn clean_app(&self, build_bundle: &BuildBundle) -> Result<()> {
+        let remote_bundle = self.to_remote_bundle(build_bundle)?;
+        if let Some(mut connection) = connect(&self.conf)? {
+            return connection.remove(path_to_str(&remote_bundle.bundle_dir)?);
+        }
+        let status = ssh_command(&self.conf)?
+            .arg(&format!("rm -rf '{}'", path_to_str(&remote_bundle.bundle_dir)?))
+            .status()?;
+        if !status.success() {
+            bail!("Couldn't clean up {} on agent {}", remote_bundle.bundle_dir.display(), self.agent)
+        }
+        Ok(())
+    }
+
+    fn debug_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<BuildBundle> {
+        bail!("Debugging is not supported on agent devices")
+    }
+
+    fn dry_run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<String>> {
+        let mut lines = vec![];
+        let artifacts = build.artifacts(&self.id);
+        for runnable in &build.runnables {
+            let (build_bundle, bundle_lines) = describe_remote_app(project, build, &artifacts, runnable, None)?;
+            let remote_bundle = self.to_remote_bundle(&build_bundle)?;
+            lines.extend(bundle_lines);
+            if let Some(ref address) = self.conf.address {
+                lines.push(format!("sync {} to {} on {}", build_bundle.bundle_dir.display(), remote_bundle.bundle_dir.display(), address));
+                lines.push(format!("sync {} to {} on {}", build_bundle.lib_dir.display(), remote_bundle.lib_dir.display(), address));
+                lines.push(format!("run {} on {} through {}", remote_bundle.bundle_dir.display(), self.remote_id, address));
+                continue;
+            }
+            lines.push(format!("{:?}", self.sync_command(&build_bundle.bundle_dir, &remote_bundle.bundle_dir)?));
+            lines.push(format!("{:?}", self.sync_command(&build_bundle.lib_dir, &remote_bundle.lib_dir)?));
+            lines.push(format!("{:?}", self.ssh_run_command()?.arg(&self.run_command(build, &remote_bundle, run_args)?)));
+        }
+        Ok(lines)
+    }
+
+    fn forward_port(&self, _local: u16, _remote: u16) -> Result<PortForward> {
+        bail!("Port forwarding is not supported on agent devices")
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn profile_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<(BuildBundle, PathBuf)> {
+        bail!("Profiling is not supported on agent devices")
+    }
+
+    fn reverse_port(&self, _local: u16, _remote: u16) -> Result<(PortForward, String)> {
+        bail!("Port forwarding is not supported on agent devices")
+    }
+
+    fn rustc_triples(&self) -> Vec<String> {
+        self.rustc_triples.clone()
+    }
+
+    fn run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<RunResult>> {
+        if run_args.coverage {
+            bail!("Coverage is not supported on agent devices")
+        }
+        let mut build_bundles = vec![];
+        for runnable in &build.runnables {
+            let (build_bundle, remote_bundle) = self.install_app(project, build, runnable)?;
+            info!("Run {} on {} ({:?})", runnable.id, self.id, build.build_args.compile_mode);
+            if let Some(mut connection) = connect(&self.conf)? {
+                // Dropping the connection kills the run on the agent.
+                let sink = OutputSink::new(TestReportParser::new(&self.id, &runnable.id))?;
+                match self.run_served(&mut connection, build, &remote_bundle, run_args, &sink)? {
+                    Some(code) => build_bundles.push(RunResult::new(build_bundle, sink.finish().finish_code(code))),
+                    None => Err(ErrorKind::RunTimeout(runnable.id.clone(),
+                                                      run_args.timeout.map(|it| it.as_secs()).unwrap_or(0)))?,
+                }
+                continue;
+            }
+            let command = self.run_command(build, &remote_bundle, run_args)?;
+
+            let _cleanup = {
+                let (device, remote_bundle) = (self.clone(), remote_bundle.clone());
+                cleanup::on_interrupt(move || { let _ = device.kill_remote(&remote_bundle); })
+            };
+            let (status, parser) = run_with_report(self.ssh_run_command()?.arg(&command),
+                                                   TestReportParser::new(&self.id, &runnable.id),
+                                                   run_args.timeout)?;
+            match status {
+                Some(status) => build_bundles.push(RunResult::new(build_bundle, parser.finish_status(status))),
+                None => {
+                    self.kill_remote(&remote_bundle)?;
+                    Err(ErrorKind::RunTimeout(runnable.id.clone(),
+                                              run_args.timeout.map(|it| it.as_secs()).unwrap_or(0)))?
+                }
+            }
+        }
+        Ok(build_bundles)
+    }
+
+    fn run_shell(&self, command: &str) -> Result<()> {
+        debug!("Running `{}' on {}", command, self.id);
+        if !ssh_command(&self.conf)?.arg(command).status()?.success() {
+            bail!("`{}' failed on {}", command, self.id)
+        }
+        Ok(())
+    }
+
+    fn start_remote_lldb(&self) -> Result<String> {
+        bail!("Debugging is not supported on agent devices")
+    }
+}
+
+impl Display for AgentDevice {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        Ok(fmt.write_str(format!("Agent {{ \"id\": \"{}\", \"agent\": \"{}\", \"name\": \"{}\", \"targets\": \"{}\" }}",
+                                 self.id,
+                                 self.agent,
+                                 self.name,
+                                 self.rustc_triples.join(",")).as_str())?)
+    }
+}
+
+pub struct AgentManager {
+    conf: Arc<Configuration>
+}
+
+impl AgentManager {
+    pub fn probe(conf: Arc<Configuration>) -> Option<AgentManager> {
+        if conf.agents.is_empty() {
+            None
+        } else {
+            Some(AgentManager { conf })
+        }
+    }
+}
+
+impl PlatformManager for AgentManager {
+    fn devices(&self) -> Result<Vec<Box<Device>>> {
+        let mut devices = vec![];
+        for (agent, conf) in &self.conf.agents {
+            let lines = match list_devices(conf) {
+                Ok(lines) => lines,
+                Err(e) => {
+                    warn!("Couldn't list the devices of agent {}: {}", agent, e);
+                    continue;
+                }
+            };
+            for (remote_id, rustc_triples, name) in lines.iter().filter_map(|it| parse_device_line(it)) {
+                devices.push(Box::new(AgentDevice {
+                    agent: agent.clone(),
+                    conf: conf.clone(),
+                    id: format!("{}/{}", agent, remote_id),
+                    name,
+                    remote_id,
+                    rustc_triples,
+                }) as _);
+            }
+        }
+        Ok(devices)
+    }
+}
+
+/// Connection to agents running `cargo dinghy serve`, `None` for ssh agents.
+fn connect(conf: &AgentConfiguration) -> Result<Option<Connection>> {
+    match conf.address {
+        Some(ref address) => Ok(Some(Connection::connect(address, conf.token.as_ref().map(|it| it.as_str()).unwrap_or(""))?)),
+        None => Ok(None),
+    }
+}
+
+fn list_devices(conf: &AgentConfiguration) -> Result<Vec<String>> {
+    if let Some(mut connection) = connect(conf)? {
+        return connection.devices();
+    }
+    let mut command = ssh_command(conf)?;
+    command.arg(&format!("{} agent-devices", conf.dinghy.as_ref().map(|it| it.as_str()).unwrap_or("cargo dinghy")));
+    debug!("Running {:?}", command);
+    let output = command.output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim())
+    }
+    Ok(String::from_utf8(output.stdout)?.lines().map(|it| it.to_string()).collect())
+}
+
+fn run_mode(build: &Build) -> &'static str {
+    match build.build_args.compile_mode {
+        CompileMode::Bench => "bench",
+        CompileMode::Test => "test",
+        _ => "build",
+    }
+}
+
+fn ssh_command(conf: &AgentConfiguration) -> Result<Command> {
+    let mut command = Command::new("ssh");
+    command.arg(ssh_destination(conf)?);
+    if let Some(port) = conf.port {
+        command.arg("-p").arg(&format!("{}", port));
+    }
+    Ok(command)
+}
+
+fn ssh_destination(conf: &AgentConfiguration) -> Result<String> {
+    match (conf.username.as_ref(), conf.hostname.as_ref()) {
+        (Some(username), Some(hostname)) => Ok(format!("{}@{}", username, hostname)),
+        (None, Some(hostname)) => Ok(hostname.to_string()),
+        _ => bail!("Agents need either an address (for `cargo dinghy serve`) or a hostname (for ssh)"),
+    }
+}
+
+/// Lines printed by `cargo dinghy agent-devices`: the id, the comma separated
+/// targets and the name of each device of the agent, separated by tabs.
+pub fn describe_devices(dinghy: &Dinghy) -> Vec<String> {
+    dinghy.devices().iter()
+        .map(|device| {
+            let rustc_triples = dinghy.platforms().iter()
+                .filter(|platform| platform.is_compatible_with(&***device))
+                .filter_map(|platform| platform.rustc_triple().map(|it| it.to_string()))
+                .collect::<Vec<_>>();
+            (device, rustc_triples)
+        })
+        .filter(|&(_, ref rustc_triples)| !rustc_triples.is_empty())
+        .map(|(device, rustc_triples)| format!("{}\t{}\t{}", device.id(), rustc_triples.join(","), device.name()))
+        .collect()
+}
+
+fn parse_device_line(line: &str) -> Option<(String, Vec<String>, String)> {
+    let mut fields = line.splitn(3, '\t');
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some(id), Some(rustc_triples), Some(name)) if !id.is_empty() => Some((
+            id.to_string(),
+            rustc_triples.split(',').filter(|it| !it.is_empty()).map(|it| it.to_string()).collect(),
+            name.to_string())),
+        _ => None,
+    }
+}
+
+/// Run, on one of the devices of this agent, a bundle synced by an agent
+/// client. The bundle already holds the test data and resources, declared
+/// again in a configuration file of the bundle so that bundling it for the
+/// device keeps them.
+pub fn run_shipped_bundle(device: &Device,
+                          bundle_dir: &Path,
+                          lib_dir: &Path,
+                          compile_mode: CompileMode,
+                          run_args: &RunArgs) -> Result<Vec<TestReport>> {
+    let id = bundle_dir.file_name()
+        .and_then(|it| it.to_str())
+        .ok_or(format!("Invalid bundle {}", bundle_dir.display()))?
+        .to_string();
+
+    let mut test_data_conf = String::from("[test_data]\n");
+    if let Ok(entries) = fs::read_dir(bundle_dir.join("test_data")) {
+        for entry in entries.filter_map(|it| it.ok()) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            test_data_conf.push_str(&format!("{} = {{ source = {}, copy_git_ignored = true }}\n",
+                                             toml::Value::String(name.clone()),
+                                             toml::Value::String(format!("test_data/{}", name))));
+        }
+    }
+    fs::File::create(bundle_dir.join(".dinghy.toml"))?.write_all(test_data_conf.as_bytes())?;
+
+    let dynamic_libraries = fs::read_dir(lib_dir)
+        .chain_err(|| format!("Couldn't read {}", lib_dir.display()))?
+        .filter_map(|it| it.ok())
+        .map(|it| it.path())
+        .filter(|it| it.is_file())
+        .collect();
+    let build = Build {
+        build_args: BuildArgs {
+            check: None,
+            compile_mode,
+            coverage: run_args.coverage,
+            dry_run: false,
+            verbose: false,
+            forced_overlays: vec![],
+        },
+        cdylibs: vec![],
+        doctests: vec![],
+        dynamic_libraries,
+        runnables: vec![Runnable {
+            exe: bundle_dir.join(&id),
+            id,
+            source: bundle_dir.to_path_buf(),
+            stripped_exe: None,
+        }],
+        rustc_triple: None,
+        staticlibs: vec![],
+        target_path: lib_dir.parent().unwrap_or(lib_dir).to_path_buf(),
+    };
+    let project = Project::new(&Arc::new(Configuration::default()));
+    Ok(device.run_app(&project, &build, run_args)?
+        .into_iter()
+        .map(|it| it.report)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_device_lines() {
+        assert_eq!(parse_device_line("abc123\taarch64-apple-ios,armv7-apple-ios\tJane's iPhone"),
+                   Some(("abc123".to_string(),
+                         vec!["aarch64-apple-ios".to_string(), "armv7-apple-ios".to_string()],
+                         "Jane's iPhone".to_string())));
+        assert_eq!(parse_device_line("abc123"), None);
+        assert_eq!(parse_device_line(""), None);
+    }
+}
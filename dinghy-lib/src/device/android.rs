@@ -1,89 +1,645 @@
+use artifacts;
+use cleanup;
+use config::ApkRunnerConfiguration;
+use config::Configuration;
+use console::ConsoleMessage;
+use console::ConsoleOptions;
+use coverage;
+use criterion;
 use errors::*;
+use logging;
+use network::NetworkCondition;
+use network::NetworkProfile;
+use forward::PortForward;
+use health;
+use installs;
+use health::DeviceHealth;
+use device::bundle_hash;
+use device::bundle_size;
+use device::check_exec_probe;
+use dinghy_build::triple::Triple;
+use device::describe_remote_app;
+use device::exec_probe_command;
 use device::make_remote_app;
+use output;
+use output::OutputSink;
+use output::Stream;
+use platform::custom_platform::CustomPlatform;
+use recording::ScreenRecording;
 use platform::regular_platform::RegularPlatform;
+use processes;
+use processes::DeviceProcess;
+use processes::KillTarget;
 use project::Project;
+use region::Region;
+use report::RunResult;
+use report::TestReport;
+use report::TestReportParser;
+use std::collections::BTreeMap;
+use std::collections::HashSet;
 use std::env;
+use std::fs;
 use std::fmt;
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::io::stderr;
+use std::io::BufRead;
+use std::io::BufReader;
 use std::io::stdout;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use sync;
+use sync::Manifest;
+use sync::MANIFEST_FILE;
+use timings;
+use timings::Phase;
 use utils::path_to_str;
+use utils::run_with_sink;
+use utils::wait_with_timeout;
 use Build;
 use BuildBundle;
 use Device;
 use DeviceCompatibility;
 use PlatformManager;
 use Runnable;
+use RunArgs;
+use symbolicate;
 
 
+/// Default work directory, see `DeviceConfiguration::work_dir`.
 static ANDROID_WORK_DIR: &str = "/data/local/tmp/dinghy";
+/// How long to wait for a device to boot again, in seconds.
+static BOOT_TIMEOUT: u64 = 300;
+/// The longest screen recording `screenrecord` makes, in seconds.
+static RECORDING_TIME_LIMIT: u32 = 180;
+/// Files per adb invocation, to keep command lines reasonably short.
+static SYNC_CHUNK_SIZE: usize = 100;
+/// Daemons throttling the CPU behind the back of the governors.
+static THERMAL_DAEMONS: &[&str] = &["thermal-engine", "thermald", "mpdecision", "perfd"];
 
+#[derive(Clone)]
 pub struct AndroidDevice {
     adb: PathBuf,
     id: String,
-    supported_targets: Vec<&'static str>,
+    supported_targets: Vec<String>,
+    failure: Option<String>,
+    work_dir: PathBuf,
+}
+
+/// CPU settings changed for benchmarks, restored when dropped.
+struct CpuPinning {
+    device: AndroidDevice,
+    restore: String,
+    applied: Vec<String>,
+    _cleanup: cleanup::CleanupGuard,
+}
+
+impl Drop for CpuPinning {
+    fn drop(&mut self) {
+        if self.restore.is_empty() {
+            return;
+        }
+        debug!("Restoring CPU settings of {}", self.device.id);
+        if let Err(e) = self.device.root_shell(&self.restore) {
+            warn!("Couldn't restore the CPU settings of {}: {}", self.device.id, e)
+        }
+    }
+}
+
+/// Keeps the device awake while plugged on USB, restoring the previous
+/// setting when dropped.
+struct StayAwake {
+    device: AndroidDevice,
+    previous: String,
+    _cleanup: cleanup::CleanupGuard,
+}
+
+impl Drop for StayAwake {
+    fn drop(&mut self) {
+        debug!("Restoring stay-on setting of {} ({})", self.device.id, self.previous);
+        let _ = self.device.restore_stay_on(&self.previous);
+    }
+}
+
+/// Locale and timezone of the device set for a run, restoring the previous
+/// ones when dropped.
+struct DeviceRegion {
+    device: AndroidDevice,
+    restore: Vec<String>,
+    applied: Vec<String>,
+    _cleanup: cleanup::CleanupGuard,
+}
+
+impl Drop for DeviceRegion {
+    fn drop(&mut self) {
+        debug!("Restoring the locale and timezone of {}", self.device.id);
+        for command in &self.restore {
+            if let Err(e) = self.device.run_shell(command) {
+                warn!("Couldn't restore the region of {}: {}", self.device.id, e)
+            }
+        }
+    }
+}
+
+/// Sets the timezone, with the shell command of Android 11 and later, or the
+/// binder call of the older versions.
+fn set_timezone_command(timezone: &str) -> String {
+    let timezone = ::shell_escape::escape(timezone.to_string().into());
+    format!("cmd alarm set-timezone {} 2>/dev/null || service call alarm 3 s16 {} >/dev/null", timezone, timezone)
+}
+
+/// Write a line of the output of an instrumentation to `sink`. Returns the
+/// exit code of the runner, if the line reports it.
+fn forward_instrumentation_line(line: &str, sink: &OutputSink) -> Option<i32> {
+    if line.starts_with("INSTRUMENTATION_STATUS: stream=") {
+        let line = line.trim_left_matches("INSTRUMENTATION_STATUS: stream=");
+        sink.write(Stream::Stdout, format!("{}\n", line).as_bytes());
+    } else if line.starts_with("INSTRUMENTATION_RESULT: exit_code=") {
+        return line.trim_left_matches("INSTRUMENTATION_RESULT: exit_code=").trim().parse::<i32>().ok();
+    } else if line.starts_with("INSTRUMENTATION_") {
+        debug!("{}", line);
+    } else {
+        // The rest of a multi-line stream status.
+        sink.write(Stream::Stdout, format!("{}\n", line).as_bytes());
+    }
+    None
 }
 
 impl AndroidDevice {
-    fn from_id(adb: PathBuf, id: &str) -> Result<AndroidDevice> {
+    fn from_id(adb: PathBuf, id: &str, conf: &Configuration) -> Result<AndroidDevice> {
         let getprop_output = Command::new(&adb)
             .args(&["-s", id, "shell", "getprop", "ro.product.cpu.abilist"])
             .output()?;
-        let abilist = String::from_utf8(getprop_output.stdout)?;
+        let abilist = String::from_utf8(getprop_output.stdout);
+        ::trace::record(id, "property", "ro.product.cpu.abilist", &abilist, |it| it.trim().into());
+        let abilist = abilist?;
         let supported_targets = abilist
             .trim()
             .split(",")
-            .filter_map(|abi| {
-                Some(match abi {
-                    "arm64-v8a" => "aarch64-linux-android",
-                    "armeabi-v7a" => "armv7-linux-androideabi",
-                    "armeabi" => "arm-linux-androideabi",
-                    "x86" => "i686-linux-android",
-                    _ => return None,
-                })
-            })
+            .filter_map(Triple::android_abi)
+            .map(|triple| conf.rustc_triple_for(&triple))
             .collect::<Vec<_>>();
 
         let device = AndroidDevice {
             adb,
             id: id.into(),
             supported_targets: supported_targets,
+            failure: None,
+            work_dir: PathBuf::from(conf.device(id).work_dir.unwrap_or(ANDROID_WORK_DIR.to_string())),
         };
         Ok(device)
     }
 
+    /// A device adb can't talk to, listed with the steps to fix it.
+    fn unavailable(adb: PathBuf, id: &str, state: &str) -> AndroidDevice {
+        let failure = match state {
+            "unauthorized" => "USB debugging is not authorized for this computer. Unlock the phone and accept the \
+                               'Allow USB debugging?' prompt. If it doesn't show up, use 'Revoke USB debugging \
+                               authorizations' in Settings > System > Developer options, and plug the phone again.".to_string(),
+            "offline" => "the device is offline. Unplug and plug it again, or restart adb with `adb kill-server`.".to_string(),
+            state if state.starts_with("no permissions") => "adb is not allowed to access the device. Add a udev rule for \
+                               it (see https://developer.android.com/studio/run/device), or select the 'File transfer' \
+                               USB mode on the phone.".to_string(),
+            state => format!("the device is in the '{}' state", state),
+        };
+        AndroidDevice {
+            adb,
+            id: id.into(),
+            supported_targets: vec![],
+            failure: Some(failure),
+            work_dir: PathBuf::from(ANDROID_WORK_DIR),
+        }
+    }
+
+    fn ensure_available(&self) -> Result<()> {
+        match self.failure {
+            Some(ref failure) => bail!("Android device {} is unavailable: {}", self.id, failure),
+            None => Ok(()),
+        }
+    }
+
     fn adb(&self) -> Result<Command> {
         let mut command = Command::new(&self.adb);
         command.arg("-s").arg(&self.id);
         Ok(command)
     }
 
-    fn install_app(&self, project: &Project, build: &Build, runnable: &Runnable) -> Result<(BuildBundle, BuildBundle)> {
-        info!("Install {} to {}", runnable.id, self.id);
-        if !self.adb()?.arg("shell").arg("mkdir").arg("-p").arg(ANDROID_WORK_DIR).status()?.success() {
-            Err(format!("Failure to create dinghy work dir '{}' on target android device", ANDROID_WORK_DIR))?;
+    /// Run a shell script as root, through `su` unless adbd already runs as
+    /// root (eng builds, `adb root`).
+    fn root_shell(&self, script: &str) -> Result<String> {
+        let uid = self.adb()?.args(&["shell", "id", "-u"]).output()?;
+        let mut command = self.adb()?;
+        command.arg("shell");
+        if String::from_utf8_lossy(&uid.stdout).trim() == "0" {
+            command.arg(script);
+        } else {
+            command.arg(format!("su 0 sh -c {}", ::shell_escape::escape(script.into())));
+        }
+        let output = command.output()?;
+        if !output.status.success() {
+            bail!("Couldn't run a root shell on {} (is it rooted?)", self.id)
+        }
+        Ok(String::from_utf8(output.stdout)?)
+    }
+
+    /// Pin the CPU governors to `performance` and stop the thermal daemons,
+    /// keeping track of what to restore afterwards.
+    fn pin_cpu(&self) -> Result<CpuPinning> {
+        let script = format!(
+            "for g in /sys/devices/system/cpu/cpu[0-9]*/cpufreq/scaling_governor; do \
+               p=$(cat $g); echo performance > $g && echo \"governor $g $p\"; done; \
+             for s in {}; do \
+               if [ \"$(getprop init.svc.$s)\" = running ]; then stop $s && echo \"stopped $s\"; fi; done",
+            THERMAL_DAEMONS.join(" "));
+        let output = self.root_shell(&script)?;
+        let mut restore = vec![];
+        let mut applied = vec![];
+        for line in output.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match (words.get(0).cloned(), words.len()) {
+                (Some("governor"), 3) => {
+                    restore.push(format!("echo {} > {}", words[2], words[1]));
+                    applied.push(format!("{}: performance (was {})", words[1], words[2]));
+                }
+                (Some("stopped"), 2) => {
+                    restore.push(format!("start {}", words[1]));
+                    applied.push(format!("stopped {}", words[1]));
+                }
+                _ => {}
+            }
+        }
+        if applied.is_empty() {
+            warn!("Couldn't pin the CPU settings of {}", self.id);
+        }
+        for setting in &applied {
+            info!("Pinned {} on {}", setting, self.id);
+        }
+        let restore = restore.join("; ");
+        let device = self.clone();
+        let interrupted_restore = restore.clone();
+        let _cleanup = cleanup::on_interrupt(move || {
+            let _ = device.root_shell(&interrupted_restore);
+        });
+        Ok(CpuPinning { device: self.clone(), restore, applied, _cleanup })
+    }
+
+    /// Wake the screen up and keep the device from dozing (and killing
+    /// background executables) during the run.
+    fn stay_awake(&self) -> Result<StayAwake> {
+        let previous = self.adb()?.args(&["shell", "settings", "get", "global", "stay_on_while_plugged_in"]).output()?;
+        let previous = String::from_utf8(previous.stdout)?.trim().to_string();
+        // `null` when never set.
+        let previous = if previous.parse::<u32>().is_ok() { previous } else { "0".to_string() };
+        if !self.adb()?.args(&["shell", "svc", "power", "stayon", "usb"]).status()?.success() {
+            bail!("Couldn't keep {} awake", self.id)
+        }
+        let _ = self.adb()?.args(&["shell", "input", "keyevent", "KEYCODE_WAKEUP"]).status();
+        let device = self.clone();
+        let interrupted_previous = previous.clone();
+        let _cleanup = cleanup::on_interrupt(move || {
+            let _ = device.restore_stay_on(&interrupted_previous);
+        });
+        Ok(StayAwake { device: self.clone(), previous, _cleanup })
+    }
+
+    /// Set the locale and timezone of the device for the run. The locale is
+    /// the one of the system settings, picked up by the apps started after.
+    fn set_region(&self, region: &Region) -> Result<DeviceRegion> {
+        let shell_output = |command: &str| -> Result<String> {
+            let output = self.adb()?.arg("shell").arg(command).output()?;
+            Ok(String::from_utf8(output.stdout)?.trim().to_string())
+        };
+        let mut restore = vec![];
+        let mut applied = vec![];
+        if let Some(ref timezone) = region.timezone {
+            let previous = shell_output("getprop persist.sys.timezone")?;
+            self.run_shell(&set_timezone_command(timezone))
+                .chain_err(|| format!("Couldn't set the timezone of {}", self.id))?;
+            if !previous.is_empty() {
+                restore.push(set_timezone_command(&previous));
+            }
+            applied.push(format!("device timezone {} (was {})", timezone, previous));
+        }
+        if let Some(tag) = region.language_tag() {
+            // `null` when never set.
+            let previous = shell_output("settings get system system_locales")?;
+            self.run_shell(&format!("settings put system system_locales {}", tag))
+                .chain_err(|| format!("Couldn't set the locale of {}", self.id))?;
+            restore.push(if previous.is_empty() || previous == "null" {
+                "settings delete system system_locales >/dev/null".to_string()
+            } else {
+                format!("settings put system system_locales {}", previous)
+            });
+            applied.push(format!("device locale {} (was {})", tag, previous));
         }
+        for setting in &applied {
+            info!("Set {} on {}", setting, self.id);
+        }
+        let device = self.clone();
+        let interrupted_restore = restore.clone();
+        let _cleanup = cleanup::on_interrupt(move || {
+            for command in &interrupted_restore {
+                let _ = device.run_shell(command);
+            }
+        });
+        Ok(DeviceRegion { device: self.clone(), restore, applied, _cleanup })
+    }
+
+    fn restore_stay_on(&self, previous: &str) -> Result<()> {
+        self.adb()?.args(&["shell", "settings", "put", "global", "stay_on_while_plugged_in", previous]).status()?;
+        Ok(())
+    }
 
-        let build_bundle = make_remote_app(project, build, runnable)?;
-        let remote_bundle = AndroidDevice::to_remote_bundle(&build_bundle)?;
+    fn install_app(&self, project: &Project, build: &Build, runnable: &Runnable, run_args: &RunArgs)
+                   -> Result<(BuildBundle, BuildBundle)> {
+        let _span = logging::span(format!("install {}", runnable.id));
+        let _timer = timings::start(Phase::Transfer);
+        self.ensure_available()?;
+        let probe = self.adb()?.arg("shell").arg(exec_probe_command(&self.work_dir)).output()?;
+        check_exec_probe(&self.id, &self.work_dir,
+                         &format!("{}{}", String::from_utf8_lossy(&probe.stdout), String::from_utf8_lossy(&probe.stderr)))?;
+
+        let build_bundle = make_remote_app(project, build, &build.artifacts(&self.id), runnable)?;
+        let remote_bundle = self.to_remote_bundle(&build_bundle)?;
+        if run_args.fresh {
+            self.reset_app(project, &remote_bundle)?;
+        }
+        installs::record(&self.id, path_to_str(&remote_bundle.bundle_dir)?);
 
-        self.sync(&build_bundle.bundle_dir, &remote_bundle.bundle_dir.parent()
-            .ok_or(format!("Invalid path {}", remote_bundle.bundle_dir.display()))?)?;
-        self.sync(&build_bundle.lib_dir, &remote_bundle.lib_dir.parent()
-            .ok_or(format!("Invalid path {}", remote_bundle.lib_dir.display()))?)?;
+        // The hash of the installed bundle is kept in a marker file next to it.
+        let hash = bundle_hash(&build_bundle, &[])?;
+        let marker = remote_bundle.bundle_dir.join(".dinghy-bundle-hash");
+        let installed_hash = self.adb()?.arg("shell").arg("cat").arg(&marker).arg("2>/dev/null").output()?;
+        if String::from_utf8_lossy(&installed_hash.stdout).trim() == hash {
+            info!("{} is already installed on {}", runnable.id, self.id);
+            return Ok((build_bundle, remote_bundle));
+        }
+
+        info!("Install {} to {}", runnable.id, self.id);
+        // A partial install must not be taken for a complete one.
+        let _ = self.adb()?.arg("shell").arg("rm").arg("-f").arg(&marker).status()?;
+        self.sync_incremental(build, &build_bundle.bundle_dir, &remote_bundle.bundle_dir, run_args.compress)?;
+        let remote_lib_parent = remote_bundle.lib_dir.parent()
+            .ok_or(format!("Invalid path {}", remote_bundle.lib_dir.display()))?;
+        if run_args.compress {
+            self.push_dir_compressed(build, &build_bundle.lib_dir, remote_lib_parent)?;
+        } else {
+            self.sync(&build_bundle.lib_dir, remote_lib_parent)?;
+        }
 
         debug!("Chmod target exe {}", remote_bundle.bundle_exe.display());
         if !self.adb()?.arg("shell").arg("chmod").arg("755").arg(&remote_bundle.bundle_exe).status()?.success() {
             Err("Failure in android install")?;
         }
+        timings::transferred(bundle_size(&build_bundle)?);
+        let write_marker = format!("echo {} > '{}'", hash, path_to_str(&marker)?);
+        if !self.adb()?.arg("shell").arg(&write_marker).status()?.success() {
+            warn!("Couldn't write install marker {} on {}", marker.display(), self.id);
+        }
         Ok((build_bundle, remote_bundle))
     }
 
+    /// Run `script` in the data directory of the app `app`, as the app. The
+    /// app has to be debuggable.
+    fn run_as(&self, app: &str, script: &str) -> Result<String> {
+        let output = self.adb()?
+            .arg("shell")
+            .arg(format!("run-as {} sh -c {}", ::shell_escape::escape(app.into()), ::shell_escape::escape(script.into())))
+            .output()?;
+        if !output.status.success() {
+            bail!("Couldn't access the data of {} on {} (is it installed and debuggable?): {}",
+                  app, self.id, String::from_utf8_lossy(&output.stderr).trim())
+        }
+        Ok(String::from_utf8(output.stdout)?)
+    }
+
+    /// Kill what still runs from the bundle and remove it, with the files the
+    /// runnable wrote next to it, and clear the data of the APK runner.
+    fn reset_app(&self, project: &Project, remote_bundle: &BuildBundle) -> Result<()> {
+        info!("Clear {} and its data from {}", remote_bundle.id, self.id);
+        let bundle_dir = ::shell_escape::escape(path_to_str(&remote_bundle.bundle_dir)?.into());
+        let mut script = format!("pkill -f {} ; rm -rf {}", bundle_dir, bundle_dir);
+        if let Some(ref apk_runner) = project.conf.android.apk_runner {
+            script.push_str(&format!(" ; am force-stop {} ; pm clear {} >/dev/null 2>&1", apk_runner.package(), apk_runner.package()));
+        }
+        if !self.adb()?.arg("shell").arg(format!("{} ; true", script)).status()?.success() {
+            bail!("Couldn't clear {} from {}", remote_bundle.id, self.id)
+        }
+        Ok(())
+    }
+
+    fn install_apk_runner(&self, apk_runner: &ApkRunnerConfiguration) -> Result<()> {
+        info!("Install apk runner {} to {}", apk_runner.package(), self.id);
+        let mut command = self.adb()?;
+        command.arg("install").arg("-r").arg("-t").arg(&apk_runner.path);
+        if !log_enabled!(::log::Level::Debug) {
+            command.stdout(::std::process::Stdio::null());
+        }
+        debug!("Running {:?}", command);
+        if !command.status()?.success() {
+            bail!("Couldn't install apk runner {} on {}", apk_runner.path, self.id)
+        }
+        Ok(())
+    }
+
+    /// Run the executable from inside the instrumentation of the runner apk, so
+    /// that it executes in an app process with a JVM available. The runner gets
+    /// the remote bundle layout as instrumentation arguments and is expected to
+    /// report `INSTRUMENTATION_RESULT: exit_code=<code>`.
+    fn run_in_apk_runner(&self,
+                         apk_runner: &ApkRunnerConfiguration,
+                         runnable: &Runnable,
+                         remote_bundle: &BuildBundle,
+                         run_args: &RunArgs) -> Result<TestReport> {
+        let command = AndroidDevice::apk_runner_command(apk_runner, remote_bundle, run_args)?;
+        debug!("Running {} through {}", runnable.id, apk_runner.instrumentation);
+
+        // The output is streamed as the instrumentation reports it, `-r`
+        // making `am` print each status as it comes.
+        let sink = OutputSink::new(TestReportParser::new(&self.id, &runnable.id))?;
+        let mut child = self.adb()?.arg("shell").arg(&command).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()
+            .chain_err(|| format!("Couldn't run {} using apk runner.", runnable.exe.display()))?;
+        let code = Arc::new(Mutex::new(None));
+        let mut forwarders = vec![];
+        if let Some(child_stderr) = child.stderr.take() {
+            forwarders.push(output::relay(child_stderr, Stream::Stderr, sink.clone()));
+        }
+        if let Some(child_stdout) = child.stdout.take() {
+            let (sink, code) = (sink.clone(), code.clone());
+            forwarders.push(thread::spawn(move || {
+                for line in BufReader::new(child_stdout).lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => break,
+                    };
+                    if let Some(exit_code) = forward_instrumentation_line(&line, &sink) {
+                        if let Ok(mut code) = code.lock() {
+                            *code = Some(exit_code);
+                        }
+                    }
+                }
+            }));
+        }
+        let status = wait_with_timeout(&mut child, run_args.timeout)?;
+        if status.is_none() {
+            let _ = self.adb()?.arg("shell").arg("am").arg("force-stop").arg(apk_runner.package()).status()?;
+        }
+        for forwarder in forwarders {
+            forwarder.join().map_err(|_| "Couldn't read the output of the apk runner")?;
+        }
+        if status.is_none() {
+            Err(ErrorKind::RunTimeout(runnable.id.clone(),
+                                      run_args.timeout.map(|it| it.as_secs()).unwrap_or(0)))?
+        }
+        let parser = sink.finish();
+        let code = code.lock().ok().and_then(|it| *it);
+        Ok(match code {
+            Some(code) => parser.finish_code(code),
+            None => parser.finish(false),
+        })
+    }
+
+    /// Run the executable from an adb shell, which doesn't forward its exit
+    /// code: it is echoed after the run instead.
+    fn run_executable(&self,
+                      build: &Build,
+                      runnable: &Runnable,
+                      remote_bundle: &BuildBundle,
+                      args: &[String],
+                      run_args: &RunArgs) -> Result<TestReport> {
+        let command = AndroidDevice::executable_command(build, remote_bundle, args, run_args)?;
+        // adb keeps the stdout and the stderr of the shell apart, the sink
+        // gets them as they come.
+        let sink = OutputSink::new(TestReportParser::new(&self.id, &runnable.id))?;
+        let ran = run_with_sink(self.adb()?.arg("shell").arg(&command), &sink, run_args.timeout);
+        ::trace::record(&self.id, "command", &command, &ran, |&status| match status {
+            Some(status) => status.code().map(|it| it.into()).unwrap_or("signal".into()),
+            None => "timeout".into(),
+        });
+        let status = ran.chain_err(|| format!("Couldn't run {} using adb.", runnable.exe.display()))?;
+        match status {
+            None => {
+                self.kill_remote(&remote_bundle)?;
+                Err(ErrorKind::RunTimeout(runnable.id.clone(),
+                                          run_args.timeout.map(|it| it.as_secs()).unwrap_or(0)))?
+            }
+            Some(status) => if !status.success() {
+                bail!("Couldn't run {} using adb.", runnable.exe.display())
+            }
+        }
+        let code = sink.last_line()
+            .and_then(|it| it.split("FORWARD_RESULT_TO_DINGHY_BECAUSE_ADB_DOES_NOT=").nth(1).map(|it| it.to_string()))
+            .and_then(|it| it.trim().parse::<i32>().ok());
+        let parser = sink.finish();
+
+        // The shell reports the runnables killed by a signal as exiting with
+        // 128 + the signal.
+        Ok(match code {
+            Some(code) if code > 128 => parser.finish_signal(code - 128),
+            Some(code) => parser.finish_code(code),
+            None => parser.finish(false),
+        })
+    }
+
+    /// Each argument is an extra of its own (`dinghy_arg_<index>`, after
+    /// `dinghy_argc`), given to the runner as it is, only escaped once for the
+    /// command line of the shell.
+    fn apk_runner_command(apk_runner: &ApkRunnerConfiguration,
+                          remote_bundle: &BuildBundle,
+                          run_args: &RunArgs) -> Result<String> {
+        fn extra(key: &str, value: &str) -> String {
+            format!("-e {} {}", key, ::shell_escape::escape(value.into()))
+        }
+
+        let mut extras = vec![
+            extra("dinghy_exe", path_to_str(&remote_bundle.bundle_exe)?),
+            extra("dinghy_dir", path_to_str(&remote_bundle.bundle_dir)?),
+            extra("dinghy_lib_dir", path_to_str(&remote_bundle.lib_dir)?),
+            extra("dinghy_argc", &run_args.args.len().to_string()),
+        ];
+        for (index, arg) in run_args.args.iter().enumerate() {
+            extras.push(extra(&format!("dinghy_arg_{}", index), arg));
+        }
+        extras.push(extra("dinghy_envs", &run_args.envs.join(" ")));
+        Ok(format!("am instrument -w -r {} {}", extras.join(" "), apk_runner.instrumentation))
+    }
+
+    fn executable_command(build: &Build,
+                          remote_bundle: &BuildBundle,
+                          args: &[String],
+                          run_args: &RunArgs) -> Result<String> {
+        Ok(format!(
+            "cd '{}'; {} DINGHY=1 RUST_BACKTRACE=1 LD_LIBRARY_PATH=\"{}:$LD_LIBRARY_PATH\" {} {} {} ; echo FORWARD_RESULT_TO_DINGHY_BECAUSE_ADB_DOES_NOT=$?",
+            path_to_str(&remote_bundle.bundle_dir)?,
+            run_args.envs.join(" "),
+            path_to_str(&remote_bundle.lib_dir)?,
+            path_to_str(&remote_bundle.bundle_exe)?,
+            if build.build_args.compile_mode == ::cargo::ops::CompileMode::Bench { "--bench" } else { "" },
+            args.join(" ")))
+    }
+
+    /// Save the crash buffer of logcat and the tombstones, when they can be
+    /// read, after `runnable` failed.
+    fn collect_crashes(&self, build: &Build, runnable: &Runnable) -> Result<()> {
+        let crashes_dir = artifacts::ensure_dir(artifacts::crashes_dir(&self.id)?)?;
+
+        let logcat = self.adb()?.args(&["logcat", "-b", "crash", "-d"]).output()?;
+        if logcat.status.success() && !logcat.stdout.is_empty() {
+            fs::File::create(crashes_dir.join(format!("{}-logcat-crash.txt", runnable.id)))?
+                .write_all(&logcat.stdout)?;
+        }
+
+        // Tombstones are only readable on rooted devices or debuggable builds.
+        let tombstones = self.adb()?
+            .arg("pull").arg("/data/tombstones/.").arg(crashes_dir.join("tombstones"))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()?;
+        if !tombstones.success() {
+            debug!("Couldn't pull tombstones from {}", self.id);
+        }
+
+        let mut binaries = vec![runnable.exe.clone()];
+        binaries.extend(build.dynamic_libraries.iter().cloned());
+        let mut reports = fs::read_dir(&crashes_dir.join("tombstones"))
+            .map(|it| it.filter_map(|it| it.ok()).map(|it| it.path()).collect::<Vec<_>>())
+            .unwrap_or(vec![]);
+        reports.push(crashes_dir.join(format!("{}-logcat-crash.txt", runnable.id)));
+        for report in reports {
+            if report.is_file() {
+                if let Err(e) = symbolicate::symbolicate_android_report(&report, &binaries) {
+                    warn!("Couldn't symbolicate {}: {}", report.display(), e);
+                }
+            }
+        }
+        info!("Crash logs of {} saved in {}", runnable.id, crashes_dir.display());
+        Ok(())
+    }
+
+    /// The pids of the processes named `name` on the device.
+    fn pids_of(&self, name: &str) -> Result<Vec<u32>> {
+        let output = self.adb()?.args(&["shell", "pidof", name]).stderr(Stdio::null()).output()?;
+        Ok(String::from_utf8_lossy(&output.stdout).split_whitespace().filter_map(|it| it.parse().ok()).collect())
+    }
+
+    fn kill_remote(&self, remote_bundle: &BuildBundle) -> Result<()> {
+        debug!("Killing {} on {}", remote_bundle.bundle_exe.display(), self.id);
+        let _ = self.adb()?
+            .arg("shell")
+            .arg("pkill")
+            .arg("-f")
+            .arg(&remote_bundle.bundle_exe)
+            .status()?;
+        Ok(())
+    }
+
     fn sync<FP: AsRef<Path>, TP: AsRef<Path>>(&self, from_path: FP, to_path: TP) -> Result<()> {
         // Seems overkill...
         // let _ = self.adb()?.arg("shell").arg("rm").arg("-rf").arg(to_path.as_ref()).status()?;
@@ -96,27 +652,164 @@ impl AndroidDevice {
             command.stderr(::std::process::Stdio::null());
         }
         debug!("Running {:?}", command);
-        if !command.status()?.success() {
+        let status = command.status();
+        ::trace::record(&self.id, "push", &format!("{} -> {}", from_path.as_ref().display(), to_path.as_ref().display()),
+                        &status, |it| it.success().into());
+        if !status?.success() {
             bail!("Error syncing android directory ({:?})", command)
         } else {
             Ok(())
         }
     }
 
-    fn to_remote_bundle(build_bundle: &BuildBundle) -> Result<BuildBundle> {
-        build_bundle.replace_prefix_with(PathBuf::from(ANDROID_WORK_DIR))
+    /// Send `files` of `from_dir` to `to_dir` as a gzipped tarball, unpacked on
+    /// the device: much faster than pushing them one by one on slow links.
+    fn push_compressed(&self, build: &Build, from_dir: &Path, files: &[String], to_dir: &Path) -> Result<()> {
+        let archive = build.artifacts(&self.id).scratch_dir("push")?.join("push.tar.gz");
+        sync::tarball(from_dir, files, &archive)?;
+        let remote_archive = self.work_dir.join(".dinghy-push.tar.gz");
+        debug!("Pushing {} ({} bytes) to {}", archive.display(), fs::metadata(&archive)?.len(), self.id);
+        let status = self.adb()?.arg("push").arg(&archive).arg(&remote_archive).stdout(Stdio::null()).status();
+        ::trace::record(&self.id, "push", &format!("{} -> {}", from_dir.display(), to_dir.display()),
+                        &status, |it| it.success().into());
+        if !status?.success() {
+            bail!("Error pushing {} to {}", archive.display(), self.id)
+        }
+        let unpack = format!("mkdir -p '{dir}' && tar xzf '{archive}' -C '{dir}'; status=$?; rm -f '{archive}'; echo $status",
+                             dir = path_to_str(to_dir)?, archive = path_to_str(&remote_archive)?);
+        let output = self.adb()?.arg("shell").arg(unpack).output()?;
+        if String::from_utf8_lossy(&output.stdout).lines().last().map(|it| it.trim()) != Some("0") {
+            bail!("Couldn't unpack the files sent to {} ({}{}), the tar of the device may not support gzip: \
+                   try without compression", self.id, String::from_utf8_lossy(&output.stdout).trim(),
+                  String::from_utf8_lossy(&output.stderr).trim())
+        }
+        Ok(())
+    }
+
+    /// Like `sync`, through `push_compressed`.
+    fn push_dir_compressed(&self, build: &Build, from_dir: &Path, to_path: &Path) -> Result<()> {
+        let parent = from_dir.parent().ok_or(format!("Invalid path {}", from_dir.display()))?;
+        let name = from_dir.file_name().and_then(|it| it.to_str()).ok_or(format!("Invalid path {}", from_dir.display()))?;
+        self.push_compressed(build, parent, &[name.to_string()], to_path)
+    }
+
+    /// Push the files of `from_dir` that changed since the last sync to
+    /// `to_dir`, and remove the ones that are gone, according to the manifest
+    /// kept on the device. Without manifest, fall back to a plain `sync`.
+    fn sync_incremental(&self, build: &Build, from_dir: &Path, to_dir: &Path, compress: bool) -> Result<()> {
+        let manifest = Manifest::scan(from_dir)?;
+        let remote_manifest = to_dir.join(MANIFEST_FILE);
+        let installed = self.adb()?.arg("shell").arg("cat").arg(&remote_manifest).arg("2>/dev/null").output()?;
+        let installed = Manifest::parse(&String::from_utf8_lossy(&installed.stdout));
+        // A partial sync must not be taken for a complete one.
+        let _ = self.adb()?.arg("shell").arg("rm").arg("-f").arg(&remote_manifest).status()?;
+
+        if installed.is_empty() {
+            debug!("No sync manifest in {} on {}, pushing everything", to_dir.display(), self.id);
+            let to_parent = to_dir.parent().ok_or(format!("Invalid path {}", to_dir.display()))?;
+            if compress {
+                self.push_dir_compressed(build, from_dir, to_parent)?;
+            } else {
+                self.sync(from_dir, to_parent)?;
+            }
+        } else {
+            let plan = manifest.plan_from(&installed);
+            debug!("Syncing {} to {}: {} changed, {} removed files",
+                   from_dir.display(), to_dir.display(), plan.changed.len(), plan.removed.len());
+            let remote_path = |path: &str| -> Result<String> {
+                Ok(::shell_escape::escape(path_to_str(&to_dir.join(path))?.to_string().into()).to_string())
+            };
+            for files in plan.removed.chunks(SYNC_CHUNK_SIZE) {
+                let files = files.iter().map(|it| remote_path(it)).collect::<Result<Vec<_>>>()?;
+                self.adb()?.arg("shell").arg(format!("rm -f {}", files.join(" "))).status()?;
+            }
+
+            if compress && !plan.changed.is_empty() {
+                self.push_compressed(build, from_dir, &plan.changed, to_dir)?;
+                return self.write_manifest(build, &manifest, &remote_manifest);
+            }
+            // Files going to the same directory are pushed together.
+            let mut by_dir: BTreeMap<String, Vec<&String>> = BTreeMap::new();
+            for file in &plan.changed {
+                let dir = file.rfind('/').map(|it| file[..it].to_string()).unwrap_or(String::new());
+                by_dir.entry(dir).or_insert(vec![]).push(file);
+            }
+            let dirs = by_dir.keys().map(|it| remote_path(it)).collect::<Result<Vec<_>>>()?;
+            for dirs in dirs.chunks(SYNC_CHUNK_SIZE) {
+                self.adb()?.arg("shell").arg(format!("mkdir -p {}", dirs.join(" "))).status()?;
+            }
+            for (dir, files) in by_dir {
+                for files in files.chunks(SYNC_CHUNK_SIZE) {
+                    let mut command = self.adb()?;
+                    command.arg("push");
+                    command.args(files.iter().map(|it| from_dir.join(it)));
+                    command.arg(format!("{}/", path_to_str(&to_dir.join(&dir))?));
+                    if !log_enabled!(::log::Level::Debug) {
+                        command.stdout(Stdio::null());
+                        command.stderr(Stdio::null());
+                    }
+                    debug!("Running {:?}", command);
+                    if !command.status()?.success() {
+                        bail!("Error syncing android directory ({:?})", command)
+                    }
+                }
+            }
+        }
+
+        self.write_manifest(build, &manifest, &remote_manifest)
+    }
+
+    fn write_manifest(&self, build: &Build, manifest: &Manifest, remote_manifest: &Path) -> Result<()> {
+        let local_manifest = build.artifacts(&self.id).scratch_dir("sync")?.join(MANIFEST_FILE);
+        manifest.write(&local_manifest)?;
+        if !self.adb()?.arg("push").arg(&local_manifest).arg(remote_manifest).stdout(Stdio::null()).status()?.success() {
+            warn!("Couldn't write sync manifest {} on {}", remote_manifest.display(), self.id);
+        }
+        Ok(())
+    }
+
+    fn to_remote_bundle(&self, build_bundle: &BuildBundle) -> Result<BuildBundle> {
+        build_bundle.replace_prefix_with(&self.work_dir)
     }
 }
 
 impl DeviceCompatibility for AndroidDevice {
+    fn is_compatible_with_custom_platform(&self, platform: &CustomPlatform) -> bool {
+        self.supported_targets.contains(&platform.toolchain.rustc_triple)
+    }
+
     fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
-        self.supported_targets.contains(&platform.toolchain.toolchain_triple.as_str())
+        self.supported_targets.contains(&platform.toolchain.toolchain_triple)
     }
 }
 
 impl Device for AndroidDevice {
+    /// Tarballs of the bundles, to unpack in a work directory of the device,
+    /// along with the runner apk if one is configured.
+    fn archive_app(&self, project: &Project, build: &Build) -> Result<Vec<PathBuf>> {
+        let artifacts = build.artifacts(&self.id);
+        let archives_dir = artifacts::ensure_dir(artifacts.archives_dir())?;
+        let mut archives = vec![];
+        for runnable in &build.runnables {
+            let build_bundle = make_remote_app(project, build, &artifacts, runnable)?;
+            let tarball = artifacts.scratch_dir("archive")?.join("bundle.tar.gz");
+            sync::tarball(&build_bundle.root_dir, &[runnable.id.clone(), "overlay".to_string()], &tarball)?;
+            let archive = archives_dir.join(format!("{}.tar.gz", runnable.id));
+            fs::copy(&tarball, &archive)
+                .chain_err(|| format!("Couldn't copy {} to {}", tarball.display(), archive.display()))?;
+            archives.push(archive);
+        }
+        if let Some(ref apk_runner) = project.conf.android.apk_runner {
+            let archive = archives_dir.join(format!("{}.apk", apk_runner.package()));
+            fs::copy(&apk_runner.path, &archive)
+                .chain_err(|| format!("Couldn't copy apk runner {} to {}", apk_runner.path, archive.display()))?;
+            archives.push(archive);
+        }
+        Ok(archives)
+    }
+
     fn clean_app(&self, build_bundle: &BuildBundle) -> Result<()> {
-        let remote_bundle = AndroidDevice::to_remote_bundle(build_bundle)?;
+        let remote_bundle = self.to_remote_bundle(build_bundle)?;
         debug!("Cleaup device");
         if !self.adb()?.arg("shell").arg("rm").arg("-rf").arg(&remote_bundle.bundle_dir).status()?.success() {
             Err("Failure in android clean")?;
@@ -127,69 +820,475 @@ impl Device for AndroidDevice {
         Ok(())
     }
 
-    fn debug_app(&self, _project: &Project, _build: &Build, _args: &[&str], _envs: &[&str]) -> Result<BuildBundle> {
+    fn debug_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<BuildBundle> {
         unimplemented!()
     }
 
+    fn dry_run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<String>> {
+        let mut lines = vec![];
+        let mut run_args = run_args.clone();
+        let remote_coverage_dir = self.work_dir.join("coverage");
+        if run_args.coverage {
+            run_args.envs.push(format!("LLVM_PROFILE_FILE='{}'", coverage::profile_file(&remote_coverage_dir)?));
+        }
+        let remote_criterion_dir = self.work_dir.join("criterion");
+        let bench = build.build_args.compile_mode == ::cargo::ops::CompileMode::Bench;
+        if bench {
+            run_args.envs.push(format!("{}='{}'", criterion::HOME_ENV, path_to_str(&remote_criterion_dir)?));
+        }
+        let apk_runner = project.conf.android.apk_runner.as_ref();
+        if let Some(apk_runner) = apk_runner {
+            lines.push(format!("{:?}", self.adb()?.arg("install").arg("-r").arg("-t").arg(&apk_runner.path)));
+        }
+        let args: Vec<String> = run_args.args.iter().map(|a| ::shell_escape::escape(a.as_str().into()).to_string()).collect();
+        let artifacts = build.artifacts(&self.id);
+        for runnable in &build.runnables {
+            let (build_bundle, bundle_lines) = describe_remote_app(project, build, &artifacts, runnable, None)?;
+            let remote_bundle = self.to_remote_bundle(&build_bundle)?;
+            lines.extend(bundle_lines);
+            lines.push(format!("push the changed files of {} to {}",
+                               build_bundle.bundle_dir.display(), remote_bundle.bundle_dir.display()));
+            let remote_lib_parent = remote_bundle.lib_dir.parent()
+                .ok_or(format!("Invalid path {}", remote_bundle.lib_dir.display()))?;
+            lines.push(format!("{:?}", self.adb()?.arg("push").arg("--sync").arg(&build_bundle.lib_dir).arg(remote_lib_parent)));
+            let command = match apk_runner {
+                Some(apk_runner) => AndroidDevice::apk_runner_command(apk_runner, &remote_bundle, &run_args)?,
+                None => AndroidDevice::executable_command(build, &remote_bundle, &args, &run_args)?,
+            };
+            lines.push(format!("{:?}", self.adb()?.arg("shell").arg(&command)));
+        }
+        if run_args.coverage {
+            lines.push(format!("{:?}", self.adb()?.arg("pull").arg(remote_coverage_dir.join(".")).arg(artifacts.coverage_dir())));
+        }
+        if bench {
+            lines.push(format!("{:?}", self.adb()?.arg("pull").arg(remote_criterion_dir.join(".")).arg(artifacts.criterion_dir())));
+        }
+        Ok(lines)
+    }
+
+    fn failure(&self) -> Option<&str> {
+        self.failure.as_ref().map(|it| it.as_str())
+    }
+
+    fn forward_port(&self, local: u16, remote: u16) -> Result<PortForward> {
+        let (local_spec, remote_spec) = (format!("tcp:{}", local), format!("tcp:{}", remote));
+        if !self.adb()?.arg("forward").arg(&local_spec).arg(&remote_spec).status()?.success() {
+            bail!("Couldn't forward port {} to {} on {}", local, remote, self.id)
+        }
+        let device = self.clone();
+        Ok(PortForward::new(local, remote, move || {
+            let _ = device.adb().map(|mut it| it.arg("forward").arg("--remove").arg(&local_spec).status());
+        }))
+    }
+
+    fn health(&self) -> Result<DeviceHealth> {
+        let battery = self.adb()?.args(&["shell", "dumpsys", "battery"]).output()?;
+        // The thermal service only exists since Android 10.
+        let thermal = self.adb()?.args(&["shell", "dumpsys", "thermalservice"]).output()?;
+        Ok(DeviceHealth {
+            thermal: health::parse_dumpsys_thermal(&String::from_utf8_lossy(&thermal.stdout)),
+            ..health::parse_dumpsys_battery(&String::from_utf8_lossy(&battery.stdout))
+        })
+    }
+
     fn id(&self) -> &str {
         &self.id
     }
 
+    /// `kill -9`, as the shell user the runs are made with.
+    fn kill(&self, target: &KillTarget) -> Result<Vec<DeviceProcess>> {
+        let killed = self.processes()?.into_iter().filter(|it| target.matches(it)).collect::<Vec<_>>();
+        for process in &killed {
+            debug!("Killing {} ({}) on {}", process.name, process.pid, self.id);
+            if !self.adb()?.args(&["shell", "kill", "-9"]).arg(process.pid.to_string()).status()?.success() {
+                bail!("Couldn't kill {} ({}) on {}", process.name, process.pid, self.id)
+            }
+        }
+        Ok(killed)
+    }
+
+    fn list_app_files(&self, app: &str, path: &str) -> Result<Vec<String>> {
+        let dir = if path.is_empty() { "." } else { path };
+        let script = format!(
+            "cd {} && for f in * .[!.]*; do [ -e \"$f\" ] || continue; \
+               if [ -d \"$f\" ]; then echo \"$f/\"; else echo \"$f\"; fi; done",
+            ::shell_escape::escape(dir.into()));
+        Ok(self.run_as(app, &script)?.lines().map(|it| it.to_string()).collect())
+    }
+
     fn name(&self) -> &str {
         "android device"
     }
 
-    fn run_app(&self, project: &Project, build: &Build, args: &[&str], envs: &[&str]) -> Result<Vec<BuildBundle>> {
+    /// Shapes every interface but the loopback with `tc netem`, or drops
+    /// packets with iptables for the loss-only profiles when the kernel has
+    /// no netem. Both need a rooted device.
+    fn network_condition(&self, profile: &NetworkProfile) -> Result<NetworkCondition> {
+        let shape = format!(
+            "for i in $(ls /sys/class/net); do \
+               [ $i = lo ] && continue; \
+               tc qdisc replace dev $i root netem {} 2>/dev/null && echo \"shaped $i\"; done",
+            profile.netem());
+        let shaped: Vec<String> = self.root_shell(&shape)?.lines()
+            .filter(|it| it.starts_with("shaped "))
+            .map(|it| it["shaped ".len()..].trim().to_string())
+            .collect();
+        let (restore, applied) = if !shaped.is_empty() {
+            let restore = shaped.iter().map(|it| format!("tc qdisc del dev {} root", it)).collect::<Vec<_>>().join("; ");
+            (restore, format!("{} on {}", profile.description(), shaped.join(", ")))
+        } else if profile.delay_ms == 0 && profile.rate_kbps.is_none() && profile.loss_percent > 0.0 {
+            let rule = format!("-m statistic --mode random --probability {} -j DROP", profile.loss_percent.min(100.0) / 100.0);
+            let chains = ["INPUT", "OUTPUT"];
+            let insert = chains.iter().map(|it| format!("iptables -I {} {}", it, rule)).collect::<Vec<_>>().join(" && ");
+            self.root_shell(&insert).chain_err(|| format!("Couldn't drop packets with iptables on {}", self.id))?;
+            let restore = chains.iter().map(|it| format!("iptables -D {} {}", it, rule)).collect::<Vec<_>>().join("; ");
+            (restore, format!("{} with iptables", profile.description()))
+        } else {
+            bail!("Couldn't shape the network of {} (its kernel may lack tc netem)", self.id)
+        };
+        info!("Applied {} on {}", applied, self.id);
+        let device = self.clone();
+        Ok(NetworkCondition::new(applied, move || {
+            if let Err(e) = device.root_shell(&restore) {
+                warn!("Couldn't restore the network of {}: {}", device.id, e)
+            }
+        }))
+    }
+
+    fn rustc_triples(&self) -> Vec<String> {
+        self.supported_targets.clone()
+    }
+
+    /// The `ps` of toybox needs `-A` to list all the processes, the one of
+    /// the toolbox of older versions lists them all and knows no options.
+    fn processes(&self) -> Result<Vec<DeviceProcess>> {
+        self.ensure_available()?;
+        let output = self.adb()?.args(&["shell", "ps", "-A", "-o", "PID,USER,NAME"]).stderr(Stdio::null()).output()?;
+        let processes = processes::parse_ps(&String::from_utf8_lossy(&output.stdout));
+        if !processes.is_empty() {
+            return Ok(processes);
+        }
+        let output = self.adb()?.args(&["shell", "ps"]).output()?;
+        if !output.status.success() {
+            bail!("Couldn't list the processes of {}", self.id)
+        }
+        Ok(processes::parse_ps(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn profile_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<(BuildBundle, PathBuf)> {
+        let runnable = build.runnables.iter().next().ok_or("No executable compiled")?;
+        let (build_bundle, remote_bundle) = self.install_app(&project, &build, &runnable, run_args)?;
+        let _awake = if run_args.keep_awake { Some(self.stay_awake()?) } else { None };
+        let remote_trace = self.work_dir.join("perf.data");
+        let args: Vec<String> = run_args.args.iter().map(|a| ::shell_escape::escape(a.as_str().into()).to_string()).collect();
+        let command = format!(
+            "cd '{}'; {} DINGHY=1 LD_LIBRARY_PATH=\"{}:$LD_LIBRARY_PATH\" simpleperf record -g -o '{}' {} {} ; echo FORWARD_RESULT_TO_DINGHY_BECAUSE_ADB_DOES_NOT=$?",
+            path_to_str(&remote_bundle.bundle_dir)?,
+            run_args.envs.join(" "),
+            path_to_str(&remote_bundle.lib_dir)?,
+            path_to_str(&remote_trace)?,
+            path_to_str(&remote_bundle.bundle_exe)?,
+            args.join(" "));
+        info!("Profile {} on {} with simpleperf", runnable.id, self.id);
+        let output = self.adb()?.arg("shell").arg(&command).output()?;
+        let _ = stdout().write(output.stdout.as_slice());
+        let _ = stderr().write(output.stderr.as_slice());
+        if !String::from_utf8(output.stdout)?.contains("FORWARD_RESULT_TO_DINGHY_BECAUSE_ADB_DOES_NOT=0") {
+            bail!("Couldn't profile {} with simpleperf (requires Android 8 or later)", runnable.id)
+        }
+
+        let trace = artifacts::ensure_dir(build.artifacts(&self.id).profiles_dir())?
+            .join(format!("{}.perf.data", runnable.id));
+        if !self.adb()?.arg("pull").arg(&remote_trace).arg(&trace).stdout(Stdio::null()).status()?.success() {
+            bail!("Couldn't pull {} from {}", remote_trace.display(), self.id)
+        }
+        let _ = self.adb()?.arg("shell").arg("rm").arg("-f").arg(&remote_trace).status();
+        Ok((build_bundle, trace))
+    }
+
+    /// The files are streamed as a tar archive, the app not being allowed to
+    /// write where adb pulls from.
+    fn pull_app_files(&self, app: &str, path: &str, to_dir: &Path) -> Result<()> {
+        let name = Path::new(path).file_name().and_then(|it| it.to_str()).ok_or(format!("Invalid path {}", path))?;
+        let parent = Path::new(path).parent().map(|it| path_to_str(it)).unwrap_or(Ok(""))?;
+        let parent = if parent.is_empty() { "." } else { parent };
+        self.run_as(app, &format!("test -e {}", ::shell_escape::escape(path.into())))
+            .chain_err(|| format!("No {} in the data of {}", path, app))?;
+        fs::create_dir_all(to_dir)?;
+        let archive = format!("run-as {} tar cf - -C {} {}",
+                              ::shell_escape::escape(app.into()),
+                              ::shell_escape::escape(parent.into()),
+                              ::shell_escape::escape(name.into()));
+        let mut pull = self.adb()?.arg("exec-out").arg(archive).stdout(Stdio::piped()).spawn()?;
+        let extracted = Command::new("tar")
+            .arg("xf").arg("-").arg("-C").arg(to_dir)
+            .stdin(pull.stdout.take().ok_or("No output from adb")?)
+            .status()?;
+        pull.wait()?;
+        if !extracted.success() {
+            bail!("Couldn't pull {} of {} from {}", path, app, self.id)
+        }
+        Ok(())
+    }
+
+    /// The file is streamed to `run-as` on its standard input, without a
+    /// copy left outside of the data of the app.
+    fn push_app_file(&self, app: &str, from: &Path, path: &str) -> Result<()> {
+        let parent = Path::new(path).parent().map(|it| path_to_str(it)).unwrap_or(Ok(""))?;
+        let mut script = format!("cat > {}", ::shell_escape::escape(path.into()));
+        if !parent.is_empty() {
+            script = format!("mkdir -p {} && {}", ::shell_escape::escape(parent.into()), script);
+        }
+        let file = fs::File::open(from).chain_err(|| format!("Couldn't read {}", from.display()))?;
+        let output = self.adb()?
+            .arg("shell")
+            .arg(format!("run-as {} sh -c {}", ::shell_escape::escape(app.into()), ::shell_escape::escape(script.into())))
+            .stdin(file)
+            .output()?;
+        if !output.status.success() {
+            bail!("Couldn't push {} to the data of {} on {} (is it installed and debuggable?): {}",
+                  from.display(), app, self.id, String::from_utf8_lossy(&output.stderr).trim())
+        }
+        Ok(())
+    }
+
+    /// Returns once the device booted again.
+    fn reboot(&self) -> Result<()> {
+        self.ensure_available()?;
+        info!("Rebooting {}", self.id);
+        if !self.adb()?.arg("reboot").status()?.success() {
+            bail!("Couldn't reboot {}", self.id)
+        }
+        self.adb()?.arg("wait-for-device").status()?;
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(BOOT_TIMEOUT) {
+            let boot_completed = self.adb()?
+                .args(&["shell", "getprop", "sys.boot_completed"])
+                .stderr(Stdio::null())
+                .output()?;
+            if String::from_utf8_lossy(&boot_completed.stdout).trim() == "1" {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_secs(2));
+        }
+        bail!("{} didn't boot again within {}s", self.id, BOOT_TIMEOUT)
+    }
+
+    /// `screenrecord` finishes the video when interrupted, or after
+    /// `RECORDING_TIME_LIMIT`, it is then pulled back.
+    fn record_screen(&self, path: &Path) -> Result<ScreenRecording> {
+        let remote = self.work_dir.join("dinghy-recording.mp4");
+        let remote = path_to_str(&remote)?.to_string();
+        let mut child = self.adb()?
+            .arg("shell")
+            .arg(format!("screenrecord --time-limit {} {}", RECORDING_TIME_LIMIT, remote))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        thread::sleep(Duration::from_secs(1));
+        if let Some(status) = child.try_wait()? {
+            bail!("Couldn't record the screen of {} ({})", self.id, status)
+        }
+        info!("Recording the screen of {} (for {}s at most)", self.id, RECORDING_TIME_LIMIT);
+        let device = self.clone();
+        let child = Mutex::new(child);
+        let local = path.to_path_buf();
+        Ok(ScreenRecording::new(path.to_path_buf(), move || {
+            let _ = device.adb()?.args(&["shell", "pkill", "-INT", "screenrecord"]).status();
+            if let Ok(mut child) = child.lock() {
+                let _ = child.wait();
+            }
+            if !device.adb()?.arg("pull").arg(&remote).arg(&local).stdout(Stdio::null()).status()?.success() {
+                bail!("Couldn't pull the screen recording of {}", device.id)
+            }
+            let _ = device.adb()?.args(&["shell", "rm", "-f", &remote]).status();
+            Ok(())
+        }))
+    }
+
+    fn reverse_port(&self, local: u16, remote: u16) -> Result<(PortForward, String)> {
+        let (local_spec, remote_spec) = (format!("tcp:{}", local), format!("tcp:{}", remote));
+        if !self.adb()?.arg("reverse").arg(&remote_spec).arg(&local_spec).status()?.success() {
+            bail!("Couldn't reverse forward port {} of {} to {}", remote, self.id, local)
+        }
+        let device = self.clone();
+        let forward = PortForward::new(local, remote, move || {
+            let _ = device.adb().map(|mut it| it.arg("reverse").arg("--remove").arg(&remote_spec).status());
+        });
+        Ok((forward, format!("127.0.0.1:{}", remote)))
+    }
+
+    fn run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<RunResult>> {
         let mut build_bundles = vec![];
-        let args:Vec<String> = args.iter().map(|&a| ::shell_escape::escape(a.into()).to_string()).collect();
+        let mut run_args = run_args.clone();
+        let remote_coverage_dir = self.work_dir.join("coverage");
+        if run_args.coverage {
+            let _ = self.adb()?.arg("shell").arg("rm").arg("-rf").arg(&remote_coverage_dir).status()?;
+            let _ = self.adb()?.arg("shell").arg("mkdir").arg("-p").arg(&remote_coverage_dir).status()?;
+            run_args.envs.push(format!("LLVM_PROFILE_FILE='{}'", coverage::profile_file(&remote_coverage_dir)?));
+        }
+        // Kept between the runs, for the baselines saved on the device.
+        let remote_criterion_dir = self.work_dir.join("criterion");
+        let bench = build.build_args.compile_mode == ::cargo::ops::CompileMode::Bench;
+        if bench {
+            let _ = self.adb()?.arg("shell").arg("mkdir").arg("-p").arg(&remote_criterion_dir).status()?;
+            run_args.envs.push(format!("{}='{}'", criterion::HOME_ENV, path_to_str(&remote_criterion_dir)?));
+        }
+        let run_args = &run_args;
+        let apk_runner = project.conf.android.apk_runner.as_ref();
+        if let Some(apk_runner) = apk_runner {
+            self.install_apk_runner(apk_runner)?;
+        }
+        let args:Vec<String> = run_args.args.iter().map(|a| ::shell_escape::escape(a.as_str().into()).to_string()).collect();
+        let pinning = if bench && project.conf.bench.pin_cpu.unwrap_or(false) {
+            Some(self.pin_cpu()?)
+        } else {
+            None
+        };
+        let _awake = if run_args.keep_awake { Some(self.stay_awake()?) } else { None };
+        let region = if run_args.device_region && !run_args.region.is_empty() {
+            Some(self.set_region(&run_args.region)?)
+        } else {
+            None
+        };
         for runnable in &build.runnables {
-            let (build_bundle, remote_bundle) = self.install_app(&project, &build, &runnable)?;
-            let command = format!(
-                "cd '{}'; {} DINGHY=1 RUST_BACKTRACE=1 LD_LIBRARY_PATH=\"{}:$LD_LIBRARY_PATH\" {} {} {} ; echo FORWARD_RESULT_TO_DINGHY_BECAUSE_ADB_DOES_NOT=$?",
-                path_to_str(&remote_bundle.bundle_dir)?,
-                envs.join(" "),
-                path_to_str(&remote_bundle.lib_dir)?,
-                path_to_str(&remote_bundle.bundle_exe)?,
-                if build.build_args.compile_mode == ::cargo::ops::CompileMode::Bench { "--bench" } else { "" },
-                args.join(" "));
-            info!("Run {} on {} ({:?})", runnable.id, self.id, build.build_args.compile_mode);
-
-            if !self.adb()?
-                .arg("shell")
-                .arg(&command)
-                .output()
-                .chain_err(|| format!("Couldn't run {} using adb.", runnable.exe.display()))
-                .and_then(|output| if output.status.success() {
-                    let _ = stdout().write(output.stdout.as_slice());
-                    let _ = stderr().write(output.stderr.as_slice());
-                    String::from_utf8(output.stdout).chain_err(|| format!("Couldn't run {} using adb.", runnable.exe.display()))
-                } else {
-                    bail!("Couldn't run {} using adb.", runnable.exe.display())
+            let (build_bundle, remote_bundle) = self.install_app(&project, &build, &runnable, run_args)?;
+            let _cleanup = {
+                let device = self.clone();
+                let remote_bundle = remote_bundle.clone();
+                let package = apk_runner.map(|it| it.package().to_string());
+                cleanup::on_interrupt(move || {
+                    let _ = device.kill_remote(&remote_bundle);
+                    if let Some(ref package) = package {
+                        let _ = device.adb().map(|mut it| it.arg("shell").arg("am").arg("force-stop").arg(package).status());
+                    }
                 })
-                .map(|output| output.lines().last().unwrap_or("").to_string())
-                .map(|last_line| last_line.contains("FORWARD_RESULT_TO_DINGHY_BECAUSE_ADB_DOES_NOT=0"))? {
-                Err("Test failed 🐛")?
+            };
+            // Only keep the crashes of this run in the crash buffer.
+            let _ = self.adb()?.args(&["logcat", "-b", "crash", "-c"]).status();
+            let mut report = match apk_runner {
+                Some(apk_runner) => {
+                    info!("Run {} on {} through apk runner ({:?})", runnable.id, self.id, build.build_args.compile_mode);
+                    self.run_in_apk_runner(apk_runner, runnable, &remote_bundle, run_args)?
+                }
+                None => {
+                    info!("Run {} on {} ({:?})", runnable.id, self.id, build.build_args.compile_mode);
+                    self.run_executable(build, runnable, &remote_bundle, &args, run_args)?
+                }
+            };
+            if let Some(ref pinning) = pinning {
+                report.conditions = pinning.applied.clone();
+            }
+            if let Some(ref region) = region {
+                report.conditions.extend(region.applied.iter().cloned());
+            }
+            if !report.exit_success {
+                if let Err(e) = self.collect_crashes(build, runnable) {
+                    warn!("Couldn't collect crash logs of {}: {}", runnable.id, e);
+                }
+            }
+            build_bundles.push(RunResult::new(build_bundle, report));
+        }
+        if run_args.coverage {
+            debug!("Pulling coverage data from {}", self.id);
+            if !self.adb()?.arg("pull").arg(remote_coverage_dir.join(".")).arg(build.artifacts(&self.id).coverage_dir()).status()?.success() {
+                bail!("Couldn't pull coverage data from {}", self.id)
+            }
+        }
+        if bench {
+            debug!("Pulling criterion data from {}", self.id);
+            if !self.adb()?.arg("pull").arg(remote_criterion_dir.join(".")).arg(build.artifacts(&self.id).criterion_dir()).status()?.success() {
+                warn!("Couldn't pull criterion data from {}", self.id)
             }
-
-            build_bundles.push(build_bundle);
         }
         Ok(build_bundles)
     }
 
+    fn run_shell(&self, command: &str) -> Result<()> {
+        debug!("Running `{}' on {}", command, self.id);
+        if !self.adb()?.arg("shell").arg(command).status()?.success() {
+            bail!("`{}' failed on {}", command, self.id)
+        }
+        Ok(())
+    }
+
+    fn screenshot(&self, path: &Path) -> Result<()> {
+        let output = self.adb()?.args(&["exec-out", "screencap", "-p"]).output()?;
+        if !output.status.success() || output.stdout.is_empty() {
+            bail!("Couldn't take a screenshot of {}", self.id)
+        }
+        fs::File::create(path)?.write_all(&output.stdout)?;
+        Ok(())
+    }
+
     fn start_remote_lldb(&self) -> Result<String> {
         unimplemented!()
     }
+
+    /// `logcat -v threadtime`, cleared first unless asked not to. The process
+    /// to keep is looked up by name as messages of unknown pids arrive (a
+    /// second apart at most), for its messages to be kept across restarts.
+    fn stream_console(&self, options: &ConsoleOptions) -> Result<()> {
+        self.ensure_available()?;
+        if options.clear && !self.adb()?.args(&["logcat", "-c"]).status()?.success() {
+            warn!("Couldn't clear the log of {}", self.id)
+        }
+        info!("Streaming the console of {}", self.id);
+        let mut child = self.adb()?.args(&["logcat", "-v", "threadtime"]).stdout(Stdio::piped()).spawn()?;
+        let stdout = child.stdout.take().ok_or("Couldn't read the output of logcat")?;
+        let mut pids = HashSet::new();
+        let mut looked_up: Option<Instant> = None;
+        for line in BufReader::new(stdout).split(b'\n') {
+            let line = String::from_utf8_lossy(&line?).trim_right().to_string();
+            let mut message = match ConsoleMessage::from_logcat(&line) {
+                Some(message) => message,
+                None => continue,
+            };
+            if let Some(ref process) = options.process {
+                let pid = message.pid.unwrap_or(0);
+                if !pids.contains(&pid) && looked_up.map(|it| it.elapsed() >= Duration::from_secs(1)).unwrap_or(true) {
+                    pids.extend(self.pids_of(process)?);
+                    looked_up = Some(Instant::now());
+                }
+                if !pids.contains(&pid) {
+                    continue;
+                }
+                message.process = Some(process.clone());
+            }
+            options.print(&message)?;
+        }
+        if !child.wait()?.success() {
+            bail!("logcat failed on {}", self.id)
+        }
+        Ok(())
+    }
+
+    fn uninstall(&self, location: &str) -> Result<()> {
+        if !Path::new(location).starts_with(&self.work_dir) {
+            bail!("{} is not a bundle of the work directory of {}", location, self.id)
+        }
+        if !self.adb()?.args(&["shell", "rm", "-rf", location]).status()?.success() {
+            bail!("Couldn't remove {} from {}", location, self.id)
+        }
+        Ok(())
+    }
 }
 
 impl Display for AndroidDevice {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        if let Some(ref failure) = self.failure {
+            return Ok(fmt.write_str(format!("Android {{ \"id\": \"{}\", \"failure\": {:?} }}",
+                                            self.id,
+                                            failure).as_str())?);
+        }
         Ok(fmt.write_str(format!("Android {{ \"id\": \"{}\", \"supported_targets\": {:?} }}",
                                  self.id,
                                  self.supported_targets).as_str())?)
     }
 }
 
-fn adb() -> Result<PathBuf> {
+/// The adb of the Android SDK.
+pub fn adb() -> Result<PathBuf> {
     fn try_out(command: &str) -> bool {
         match Command::new(command)
             .arg("--version")
@@ -225,16 +1324,21 @@ fn adb() -> Result<PathBuf> {
 
 pub struct AndroidManager {
     adb: PathBuf,
+    conf: Arc<Configuration>,
 }
 
 impl PlatformManager for AndroidManager {
     fn devices(&self) -> Result<Vec<Box<Device>>> {
         let result = Command::new(&self.adb).arg("devices").output()?;
         let mut devices = vec![];
-        let device_regex = ::regex::Regex::new(r#"^(\S+)\tdevice\r?$"#)?;
+        let device_regex = ::regex::Regex::new(r#"^(\S+)\t([^\r]+)\r?$"#)?;
         for line in String::from_utf8(result.stdout)?.split("\n").skip(1) {
             if let Some(caps) = device_regex.captures(line) {
-                let d = AndroidDevice::from_id(self.adb.clone(), &caps[1])?;
+                let d = if &caps[2] == "device" {
+                    AndroidDevice::from_id(self.adb.clone(), &caps[1], &self.conf)?
+                } else {
+                    AndroidDevice::unavailable(self.adb.clone(), &caps[1], &caps[2])
+                };
                 debug!("Discovered Android device {}", d);
                 devices.push(Box::new(d) as Box<Device>);
             }
@@ -244,11 +1348,11 @@ impl PlatformManager for AndroidManager {
 }
 
 impl AndroidManager {
-    pub fn probe() -> Option<AndroidManager> {
+    pub fn probe(conf: Arc<Configuration>) -> Option<AndroidManager> {
         match adb() {
             Ok(adb) => {
                 debug!("ADB found: {:?}", adb);
-                Some(AndroidManager { adb })
+                Some(AndroidManager { adb, conf })
             }
             Err(_) => {
                 info!("adb not found in path, android disabled");
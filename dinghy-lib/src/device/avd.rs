@@ -0,0 +1,187 @@
+//! Android emulators (AVDs): listing them, booting them headless on demand
+//! (`--avd`), and shutting them down.
+
+use device::android::adb;
+use errors::*;
+use std::env;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
+use std::process::Command;
+use std::process::Stdio;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How long to wait for an emulator to boot, in seconds.
+static BOOT_TIMEOUT: u64 = 300;
+/// Console ports of the emulators, their serial being `emulator-<port>`.
+static FIRST_PORT: u16 = 5554;
+static LAST_PORT: u16 = 5682;
+
+/// The emulator of the Android SDK.
+pub fn emulator() -> Result<PathBuf> {
+    if let Ok(emulator) = ::which::which("emulator") {
+        return Ok(emulator);
+    }
+    let sdks = ["ANDROID_HOME", "ANDROID_SDK_ROOT", "ANDROID_SDK"].iter()
+        .filter_map(|it| env::var(it).ok())
+        .map(PathBuf::from)
+        .chain(env::var("HOME").ok().map(|home| Path::new(&home).join("Library/Android/sdk")));
+    for sdk in sdks {
+        let emulator = sdk.join("emulator").join("emulator");
+        if emulator.is_file() {
+            return Ok(emulator);
+        }
+    }
+    bail!("The Android emulator couldn't be found, set ANDROID_HOME to the Android SDK")
+}
+
+/// The AVDs that can be booted.
+pub fn list() -> Result<Vec<String>> {
+    let output = Command::new(emulator()?).arg("-list-avds").output()?;
+    Ok(String::from_utf8(output.stdout)?
+        .lines()
+        .map(str::trim)
+        .filter(|it| !it.is_empty())
+        .map(|it| it.to_string())
+        .collect())
+}
+
+/// The running emulators, as serial number and AVD name.
+pub fn running() -> Result<Vec<(String, String)>> {
+    let adb = adb()?;
+    let devices = Command::new(&adb).arg("devices").output()?;
+    let mut emulators = vec![];
+    for line in String::from_utf8(devices.stdout)?.lines().skip(1) {
+        let serial = match line.split_whitespace().next() {
+            Some(serial) if serial.starts_with("emulator-") => serial.to_string(),
+            _ => continue,
+        };
+        let name = Command::new(&adb).args(&["-s", &serial, "emu", "avd", "name"]).output()?;
+        if let Some(name) = parse_avd_name(&String::from_utf8_lossy(&name.stdout)) {
+            emulators.push((serial, name));
+        }
+    }
+    Ok(emulators)
+}
+
+/// The output of `adb emu avd name` is the name followed by `OK`.
+fn parse_avd_name(output: &str) -> Option<String> {
+    match output.lines().map(str::trim).next() {
+        Some(name) if !name.is_empty() && name != "OK" => Some(name.to_string()),
+        _ => None,
+    }
+}
+
+/// An emulator booted for this run, shut down when dropped unless it was
+/// already running or is to be kept alive.
+pub struct BootedEmulator {
+    serial: String,
+    child: Option<Child>,
+    shutdown: bool,
+}
+
+impl BootedEmulator {
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+}
+
+impl Drop for BootedEmulator {
+    fn drop(&mut self) {
+        if self.shutdown {
+            info!("Shutting down emulator {}", self.serial);
+            if let Err(e) = kill(&self.serial) {
+                warn!("Couldn't shut down emulator {}: {}", self.serial, e);
+            }
+            if let Some(ref mut child) = self.child {
+                let _ = child.wait();
+            }
+        }
+    }
+}
+
+/// Boot the AVD `name`, headless unless `window` is set, and wait for the end
+/// of its boot. An emulator already running it is used as is.
+pub fn boot(name: &str, window: bool, keep_alive: bool) -> Result<BootedEmulator> {
+    let running = running()?;
+    if let Some(&(ref serial, _)) = running.iter().find(|&&(_, ref avd)| avd == name) {
+        debug!("AVD {} is already running as {}", name, serial);
+        return Ok(BootedEmulator { serial: serial.clone(), child: None, shutdown: false });
+    }
+    if !list()?.iter().any(|it| it == name) {
+        bail!("No AVD named {}, see `cargo dinghy avd list`", name)
+    }
+    let port = (FIRST_PORT..LAST_PORT + 1)
+        .filter(|it| it % 2 == 0)
+        .find(|port| !running.iter().any(|&(ref serial, _)| *serial == format!("emulator-{}", port)))
+        .ok_or("No console port left for another emulator")?;
+
+    info!("Booting AVD {} as emulator-{}", name, port);
+    let mut command = Command::new(emulator()?);
+    command.arg("-avd").arg(name).arg("-port").arg(port.to_string()).arg("-no-boot-anim");
+    if !window {
+        command.args(&["-no-window", "-no-audio"]);
+    }
+    if !log_enabled!(::log::Level::Debug) {
+        command.stdout(Stdio::null()).stderr(Stdio::null());
+    }
+    debug!("Running {:?}", command);
+    let mut booted = BootedEmulator {
+        serial: format!("emulator-{}", port),
+        child: Some(command.spawn()?),
+        shutdown: !keep_alive,
+    };
+    wait_for_boot(&mut booted)?;
+    Ok(booted)
+}
+
+fn wait_for_boot(emulator: &mut BootedEmulator) -> Result<()> {
+    let adb = adb()?;
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_secs(BOOT_TIMEOUT) {
+        if let Some(ref mut child) = emulator.child {
+            if let Some(status) = child.try_wait()? {
+                bail!("The emulator {} exited while booting ({})", emulator.serial, status)
+            }
+        }
+        let boot_completed = Command::new(&adb)
+            .args(&["-s", &emulator.serial, "shell", "getprop", "sys.boot_completed"])
+            .stderr(Stdio::null())
+            .output()?;
+        if String::from_utf8_lossy(&boot_completed.stdout).trim() == "1" {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_secs(2));
+    }
+    bail!("The emulator {} didn't boot within {}s", emulator.serial, BOOT_TIMEOUT)
+}
+
+/// Shut down the emulator running the AVD, or of serial number, `name`.
+pub fn kill(name: &str) -> Result<()> {
+    let serial = if name.starts_with("emulator-") {
+        name.to_string()
+    } else {
+        running()?.into_iter()
+            .find(|&(_, ref avd)| avd == name)
+            .map(|(serial, _)| serial)
+            .ok_or(format!("AVD {} is not running", name))?
+    };
+    if !Command::new(adb()?).args(&["-s", &serial, "emu", "kill"]).status()?.success() {
+        bail!("Couldn't shut down emulator {}", serial)
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn avd_name() {
+        assert_eq!(parse_avd_name("pixel_api_31\r\nOK\r\n"), Some("pixel_api_31".to_string()));
+        assert_eq!(parse_avd_name("OK\r\n"), None);
+        assert_eq!(parse_avd_name(""), None);
+    }
+}
@@ -0,0 +1,264 @@
+use cleanup;
+use config::{Configuration, DockerDeviceConfiguration};
+use coverage;
+use criterion;
+use errors::*;
+use forward::PortForward;
+use device::describe_remote_app;
+use device::make_remote_app;
+use platform::custom_platform::CustomPlatform;
+use platform::regular_platform::RegularPlatform;
+use project::Project;
+use report::RunResult;
+use report::TestReportParser;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::Arc;
+use utils::path_to_str;
+use utils::run_with_report;
+use Build;
+use Device;
+use DeviceCompatibility;
+use PlatformManager;
+use BuildBundle;
+use RunArgs;
+
+/// A Docker image used as a device: each runnable is copied into a new
+/// container of the image, run there, and the container removed.
+#[derive(Clone)]
+pub struct DockerDevice {
+    id: String,
+    conf: DockerDeviceConfiguration,
+    work_dir: PathBuf,
+}
+
+impl DockerDevice {
+    fn container_name(&self) -> String {
+        format!("dinghy-{}-{}", self.id, process::id())
+    }
+
+    fn create_command(&self, name: &str, build: &Build, remote_bundle: &BuildBundle, envs: &[String], args: &[String]) -> Result<Command> {
+        let mut command = Command::new("docker");
+        command.arg("create").arg("--name").arg(name)
+            .arg("-w").arg(&remote_bundle.bundle_dir)
+            .arg("-e").arg("RUST_BACKTRACE=1")
+            .arg("-e").arg("DINGHY=1")
+            .arg("-e").arg(format!("LD_LIBRARY_PATH={}", path_to_str(&remote_bundle.lib_dir)?));
+        for env in envs {
+            command.arg("-e").arg(env);
+        }
+        command.args(self.conf.args.as_ref().unwrap_or(&vec![]))
+            .arg(&self.conf.image)
+            .arg(&remote_bundle.bundle_exe);
+        if build.build_args.compile_mode == ::cargo::ops::CompileMode::Bench {
+            command.arg("--bench");
+        }
+        command.args(args);
+        Ok(command)
+    }
+
+    fn docker(&self, args: &[&str]) -> Result<()> {
+        let mut command = Command::new("docker");
+        command.args(args).stdout(Stdio::null());
+        debug!("Running {:?}", command);
+        if !command.status().chain_err(|| "Couldn't run docker")?.success() {
+            bail!("docker {} failed on {}", args[0], self.id)
+        }
+        Ok(())
+    }
+
+    fn envs(&self, build: &Build, run_args: &RunArgs) -> Result<Vec<String>> {
+        let mut envs = run_args.envs.clone();
+        if run_args.coverage {
+            envs.push(format!("LLVM_PROFILE_FILE={}", coverage::profile_file(&self.remote_coverage_dir())?));
+        }
+        if build.build_args.compile_mode == ::cargo::ops::CompileMode::Bench {
+            envs.push(format!("{}={}", criterion::HOME_ENV, path_to_str(&self.remote_criterion_dir())?));
+        }
+        Ok(envs)
+    }
+
+    fn remote_coverage_dir(&self) -> PathBuf {
+        self.work_dir.join("coverage")
+    }
+
+    fn remote_criterion_dir(&self) -> PathBuf {
+        self.work_dir.join("criterion")
+    }
+
+    fn remove_container(name: &str) {
+        let _ = Command::new("docker").args(&["rm", "-f", name]).stdout(Stdio::null()).stderr(Stdio::null()).status();
+    }
+
+    fn to_remote_bundle(&self, build_bundle: &BuildBundle) -> Result<BuildBundle> {
+        build_bundle.replace_prefix_with(&self.work_dir)
+    }
+}
+
+impl DeviceCompatibility for DockerDevice {
+    fn is_compatible_with_custom_platform(&self, platform: &CustomPlatform) -> bool {
+        self.conf.platform.as_ref().map_or(false, |it| *it == platform.id)
+    }
+
+    fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
+        self.conf.platform.as_ref().map_or(false, |it| *it == platform.id)
+    }
+}
+
+impl Device for DockerDevice {
+    fn clean_app(&self, _build_bundle: &BuildBundle) -> Result<()> {
+        debug!("No cleanup performed as the containers are removed after each run");
+        Ok(())
+    }
+
+    fn debug_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<BuildBundle> {
+        unimplemented!()
+    }
+
+    fn dry_run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<String>> {
+        let mut lines = vec![];
+        let name = self.container_name();
+        let envs = self.envs(build, run_args)?;
+        let artifacts = build.artifacts(&self.id);
+        for runnable in &build.runnables {
+            let (build_bundle, bundle_lines) = describe_remote_app(project, build, &artifacts, runnable, None)?;
+            let remote_bundle = self.to_remote_bundle(&build_bundle)?;
+            lines.extend(bundle_lines);
+            lines.push(format!("{:?}", self.create_command(&name, build, &remote_bundle, &envs, &run_args.args)?));
+            lines.push(format!("docker cp {}/. {}:{}", build_bundle.root_dir.display(), name, self.work_dir.display()));
+            lines.push(format!("docker start -a {}", name));
+            lines.push(format!("docker rm -f {}", name));
+        }
+        if run_args.coverage {
+            lines.push(format!("copy {} out of the containers to {}", self.remote_coverage_dir().display(), artifacts.coverage_dir().display()));
+        }
+        if build.build_args.compile_mode == ::cargo::ops::CompileMode::Bench {
+            lines.push(format!("copy {} out of the containers to {}", self.remote_criterion_dir().display(), artifacts.criterion_dir().display()));
+        }
+        Ok(lines)
+    }
+
+    fn forward_port(&self, _local: u16, _remote: u16) -> Result<PortForward> {
+        bail!("Port forwarding is not supported on Docker devices, publish ports with `-p` in the args of {}", self.id)
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.conf.image
+    }
+
+    fn profile_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<(BuildBundle, PathBuf)> {
+        bail!("Profiling is only supported on Android and iOS devices")
+    }
+
+    fn reverse_port(&self, _local: u16, _remote: u16) -> Result<(PortForward, String)> {
+        bail!("Port forwarding is not supported on Docker devices")
+    }
+
+    fn run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<RunResult>> {
+        let mut build_bundles = vec![];
+        let envs = self.envs(build, run_args)?;
+        let coverage_dir = build.artifacts(&self.id).coverage_dir();
+        if run_args.coverage {
+            let _ = fs::remove_dir_all(&coverage_dir);
+            fs::create_dir_all(&coverage_dir)?;
+        }
+        for runnable in &build.runnables {
+            let build_bundle = make_remote_app(project, build, &build.artifacts(&self.id), runnable)?;
+            let remote_bundle = self.to_remote_bundle(&build_bundle)?;
+            let name = self.container_name();
+            DockerDevice::remove_container(&name);
+
+            let mut create = self.create_command(&name, build, &remote_bundle, &envs, &run_args.args)?;
+            create.stdout(Stdio::null());
+            debug!("Running {:?}", create);
+            if !create.status().chain_err(|| "Couldn't run docker")?.success() {
+                bail!("Couldn't create a container of {} for {}", self.conf.image, runnable.id)
+            }
+            let _cleanup = {
+                let name = name.clone();
+                cleanup::on_interrupt(move || DockerDevice::remove_container(&name))
+            };
+            info!("Install {} to {}", runnable.id, self.id);
+            let source = format!("{}/.", path_to_str(&build_bundle.root_dir)?);
+            let target = format!("{}:{}", name, path_to_str(&self.work_dir)?);
+            let copied = self.docker(&["cp", &source, &target]);
+            if copied.is_err() {
+                DockerDevice::remove_container(&name);
+                copied?;
+            }
+
+            info!("Run {} on {} ({:?})", runnable.id, self.id, build.build_args.compile_mode);
+            let result = run_with_report(Command::new("docker").args(&["start", "-a", &name]),
+                                         TestReportParser::new(&self.id, &runnable.id),
+                                         run_args.timeout);
+            if run_args.coverage {
+                let source = format!("{}:{}/.", name, path_to_str(&self.remote_coverage_dir())?);
+                if let Err(e) = self.docker(&["cp", &source, path_to_str(&coverage_dir)?]) {
+                    warn!("Couldn't copy the coverage data of {}: {}", runnable.id, e);
+                }
+            }
+            if build.build_args.compile_mode == ::cargo::ops::CompileMode::Bench {
+                let source = format!("{}:{}/.", name, path_to_str(&self.remote_criterion_dir())?);
+                let criterion_dir = ::artifacts::ensure_dir(build.artifacts(&self.id).criterion_dir())?;
+                if let Err(e) = self.docker(&["cp", &source, path_to_str(&criterion_dir)?]) {
+                    warn!("Couldn't copy the criterion data of {}: {}", runnable.id, e);
+                }
+            }
+            DockerDevice::remove_container(&name);
+            match result? {
+                (Some(status), parser) => build_bundles.push(RunResult::new(build_bundle, parser.finish_status(status))),
+                (None, _) => Err(ErrorKind::RunTimeout(runnable.id.clone(),
+                                                       run_args.timeout.map(|it| it.as_secs()).unwrap_or(0)))?,
+            }
+        }
+        Ok(build_bundles)
+    }
+
+    fn start_remote_lldb(&self) -> Result<String> {
+        unimplemented!()
+    }
+}
+
+impl Display for DockerDevice {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        Ok(fmt.write_str(format!("Docker {{ \"id\": \"{}\", \"image\": \"{}\" }}", self.id, self.conf.image).as_str())?)
+    }
+}
+
+pub struct DockerDeviceManager {
+    conf: Arc<Configuration>
+}
+
+impl DockerDeviceManager {
+    pub fn probe(conf: Arc<Configuration>) -> Option<DockerDeviceManager> {
+        Some(DockerDeviceManager { conf })
+    }
+}
+
+impl PlatformManager for DockerDeviceManager {
+    fn devices(&self) -> Result<Vec<Box<Device>>> {
+        Ok(self.conf.docker_devices
+            .iter()
+            .map(|(k, conf)| {
+                Box::new(DockerDevice {
+                    id: k.clone(),
+                    conf: conf.clone(),
+                    work_dir: self.conf.device(k).work_dir
+                        .map(PathBuf::from)
+                        .unwrap_or(Path::new("/tmp").join("dinghy")),
+                }) as _
+            })
+            .collect())
+    }
+}
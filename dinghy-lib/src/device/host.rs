@@ -1,11 +1,20 @@
+use compiler::CompileMode;
 use compiler::Compiler;
+use coverage;
+use criterion;
 use dinghy_build::build_env::set_env;
+use forward;
+use forward::PortForward;
 use itertools::Itertools;
 use platform::host::HostPlatform;
 use project::Project;
+use report::RunResult;
+use report::TestReport;
 use std::fmt;
 use std::fmt::Display;
 use std::fmt::Formatter;
+use std::path::PathBuf;
+use std::process::Command;
 use std::sync::Arc;
 use Build;
 use BuildBundle;
@@ -13,6 +22,7 @@ use Device;
 use PlatformManager;
 use DeviceCompatibility;
 use Result;
+use RunArgs;
 
 pub struct HostManager {
     compiler: Arc<Compiler>
@@ -45,7 +55,7 @@ impl HostDevice {
     }
 
     fn install_all_apps(&self, project: &Project, build: &Build) -> Result<Vec<BuildBundle>> {
-        let root_dir = build.target_path.join("dinghy");
+        let root_dir = build.artifacts(self.id()).bundles_dir();
         let bundle_libs_path = build.target_path.clone();
 
         let mut build_bundles = vec![];
@@ -73,10 +83,29 @@ impl Device for HostDevice {
         Ok(())
     }
 
-    fn debug_app(&self, _project: &Project, _build: &Build, _args: &[&str], _envs: &[&str]) -> Result<BuildBundle> {
+    fn debug_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<BuildBundle> {
         unimplemented!()
     }
 
+    fn dry_run_app(&self, _project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<String>> {
+        let root_dir = build.artifacts(self.id()).bundles_dir();
+        let mut lines = vec![];
+        for runnable in &build.runnables {
+            lines.push(format!("link test data of {} in {}", runnable.id, root_dir.join(&runnable.id).display()));
+        }
+        let command = match build.build_args.compile_mode {
+            CompileMode::Bench => "bench",
+            CompileMode::Test => "test",
+            _ => "run",
+        };
+        lines.push(format!("cargo {} with args {:?} and envs {:?}", command, run_args.args, run_args.envs));
+        Ok(lines)
+    }
+
+    fn forward_port(&self, local: u16, remote: u16) -> Result<PortForward> {
+        forward::local_forward(local, remote)
+    }
+
     fn id(&self) -> &str {
         "HOST"
     }
@@ -85,13 +114,46 @@ impl Device for HostDevice {
         "host device"
     }
 
-    fn run_app(&self, project: &Project, build: &Build, args: &[&str], envs: &[&str]) -> Result<Vec<BuildBundle>> {
-        for (env_key, env_value) in envs.iter().tuples() {
+    fn profile_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<(BuildBundle, PathBuf)> {
+        bail!("Profiling is only supported on Android and iOS devices")
+    }
+
+    fn reverse_port(&self, local: u16, remote: u16) -> Result<(PortForward, String)> {
+        Ok((forward::local_forward(remote, local)?, format!("127.0.0.1:{}", remote)))
+    }
+
+    fn run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<RunResult>> {
+        for (env_key, env_value) in run_args.envs.iter().tuples() {
             set_env(env_key, env_value);
         }
+        if run_args.timeout.is_some() {
+            warn!("Timeout is not supported on the host device and will be ignored");
+        }
+        if run_args.coverage {
+            set_env("LLVM_PROFILE_FILE", coverage::profile_file(build.artifacts(self.id()).coverage_dir())?);
+        }
+        if build.build_args.compile_mode == CompileMode::Bench {
+            // Cargo builds in a target directory of its own, where criterion
+            // would keep its data by default.
+            set_env(criterion::HOME_ENV, criterion::host_dir()?);
+        }
         let build_bundles = self.install_all_apps(project, build)?;
-        self.compiler.run(None, &build.build_args, args)?;
-        Ok(build_bundles)
+        self.compiler.run(None, &build.build_args, &run_args.args())?;
+        // Cargo runs the executables itself, so only their overall success is known.
+        Ok(build_bundles.into_iter()
+            .map(|build_bundle| {
+                let report = TestReport::unparsed(self.id(), &build_bundle.id, true);
+                RunResult::new(build_bundle, report)
+            })
+            .collect())
+    }
+
+    fn run_shell(&self, command: &str) -> Result<()> {
+        debug!("Running `{}' on the host", command);
+        if !Command::new("sh").arg("-c").arg(command).status()?.success() {
+            bail!("`{}' failed on the host", command)
+        }
+        Ok(())
     }
 
     fn start_remote_lldb(&self) -> Result<String> {
@@ -0,0 +1,257 @@
+//! Minimal client of the gdb remote protocol spoken by debugserver, to launch
+//! an app and wait for its exit without lldb (e.g. on CI images without a full
+//! Xcode).
+//!
+//! Only what a run needs is implemented: launching with arguments and
+//! environment, relaying the output of the app (`O` packets), and reading its
+//! exit status. Besides, it lists the processes of the device and kills them,
+//! for `cargo dinghy ps` and `kill`.
+
+use errors::*;
+use output::OutputSink;
+use output::Stream;
+use processes::DeviceProcess;
+use report::TestReport;
+use report::TestReportParser;
+use std::io::Read;
+use std::io::Write;
+use std::io::ErrorKind as IoErrorKind;
+use std::net::TcpStream;
+use std::time::Duration;
+use std::time::Instant;
+
+pub struct GdbRemote {
+    stream: TcpStream,
+    ack: bool,
+}
+
+impl GdbRemote {
+    pub fn new(stream: TcpStream) -> Result<GdbRemote> {
+        let mut remote = GdbRemote { stream, ack: true };
+        if remote.request("QStartNoAckMode")? == "OK" {
+            remote.stream.write_all(b"+")?;
+            remote.ack = false;
+        }
+        Ok(remote)
+    }
+
+    pub fn send(&mut self, packet: &str) -> Result<()> {
+        let checksum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        self.stream.write_all(format!("${}#{:02x}", packet, checksum).as_bytes())?;
+        if self.ack {
+            let mut ack = [0u8; 1];
+            self.stream.read_exact(&mut ack)?;
+            if ack[0] != b'+' {
+                bail!("debugserver rejected packet {}", packet)
+            }
+        }
+        Ok(())
+    }
+
+    /// Next packet from debugserver, or `None` if `timeout` expired first.
+    pub fn receive_with_timeout(&mut self, timeout: Option<Duration>) -> Result<Option<String>> {
+        self.stream.set_read_timeout(timeout)?;
+        let mut byte = [0u8; 1];
+        loop {
+            match self.stream.read_exact(&mut byte) {
+                Ok(()) if byte[0] == b'$' => break,
+                Ok(()) => continue,
+                Err(ref e) if e.kind() == IoErrorKind::WouldBlock || e.kind() == IoErrorKind::TimedOut => return Ok(None),
+                Err(e) => Err(e)?,
+            }
+        }
+        self.stream.set_read_timeout(None)?;
+        let mut data = vec![];
+        loop {
+            self.stream.read_exact(&mut byte)?;
+            if byte[0] == b'#' {
+                break;
+            }
+            data.push(byte[0]);
+        }
+        let mut checksum = [0u8; 2];
+        self.stream.read_exact(&mut checksum)?;
+        if self.ack {
+            self.stream.write_all(b"+")?;
+        }
+        Ok(Some(String::from_utf8_lossy(&decode(&data)).into_owned()))
+    }
+
+    pub fn receive(&mut self) -> Result<String> {
+        Ok(self.receive_with_timeout(None)?.ok_or("debugserver closed the connection")?)
+    }
+
+    pub fn request(&mut self, packet: &str) -> Result<String> {
+        self.send(packet)?;
+        self.receive()
+    }
+}
+
+/// Launch `exe` (its path on the device) and relay its output until it
+/// exits, killing it after `timeout`.
+pub fn launch(stream: TcpStream,
+              exe: &str,
+              args: &[&str],
+              envs: &[&str],
+              timeout: Option<Duration>,
+              parser: TestReportParser) -> Result<TestReport> {
+    let mut remote = GdbRemote::new(stream)?;
+    for env in envs {
+        expect_ok(&mut remote, &format!("QEnvironmentHexEncoded:{}", hex(env)), "set the environment")?;
+    }
+    let mut launch = vec![];
+    for (index, arg) in Some(&exe).into_iter().chain(args.iter()).enumerate() {
+        let arg = hex(arg);
+        launch.push(format!("{},{},{}", arg.len(), index, arg));
+    }
+    expect_ok(&mut remote, &format!("A{}", launch.join(",")), "launch the app")?;
+    expect_ok(&mut remote, "qLaunchSuccess", "launch the app")?;
+    remote.send("c")?;
+
+    let deadline = timeout.map(|it| Instant::now() + it);
+    let sink = OutputSink::new(parser)?;
+    loop {
+        let remaining = match deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                let _ = remote.stream.write_all(b"\x03");
+                let _ = remote.send("k");
+                Err(ErrorKind::RunTimeout(exe.to_string(), timeout.map(|it| it.as_secs()).unwrap_or(0)))?
+            }
+            Some(deadline) => Some(deadline - Instant::now()),
+            None => None,
+        };
+        let packet = match remote.receive_with_timeout(remaining)? {
+            Some(packet) => packet,
+            None => continue,
+        };
+        match packet.chars().next() {
+            Some('O') => sink.write(Stream::Stdout, &unhex(&packet[1..])),
+            Some('W') => {
+                let status = u8::from_str_radix(packet[1..].split(';').next().unwrap_or(""), 16).unwrap_or(1);
+                return Ok(sink.finish().finish_code(status as i32));
+            }
+            Some('X') => {
+                let signal = &packet[1..::std::cmp::min(3, packet.len())];
+                warn!("{} was killed by signal {}", exe, signal);
+                let parser = sink.finish();
+                return Ok(match i32::from_str_radix(signal, 16) {
+                    Ok(signal) => parser.finish_signal(signal),
+                    Err(_) => parser.finish(false),
+                });
+            }
+            Some('T') | Some('S') => {
+                // Stopped on a signal (crash, abort...): there is no debugger
+                // to hand it to, so kill the app.
+                let signal = &packet[1..::std::cmp::min(3, packet.len())];
+                warn!("{} stopped on signal {}", exe, signal);
+                let _ = remote.send("k");
+                let parser = sink.finish();
+                return Ok(match i32::from_str_radix(signal, 16) {
+                    Ok(signal) => parser.finish_signal(signal),
+                    Err(_) => parser.finish(false),
+                });
+            }
+            _ => debug!("Ignoring debugserver packet {}", packet),
+        }
+    }
+}
+
+/// The processes of all the users of the device.
+pub fn processes(stream: TcpStream) -> Result<Vec<DeviceProcess>> {
+    let mut remote = GdbRemote::new(stream)?;
+    let mut processes = vec![];
+    let mut reply = remote.request("qfProcessInfo:all_users:1;")?;
+    // Each process gets a reply, until an error says there are no more.
+    while !reply.is_empty() && !reply.starts_with('E') {
+        processes.extend(process_info(&reply));
+        reply = remote.request("qsProcessInfo")?;
+    }
+    Ok(processes)
+}
+
+/// Attach to the process `pid`, and kill it.
+pub fn kill(stream: TcpStream, pid: u32) -> Result<()> {
+    let mut remote = GdbRemote::new(stream)?;
+    let reply = remote.request(&format!("vAttach;{:x}", pid))?;
+    if !reply.starts_with('T') && !reply.starts_with('S') {
+        bail!("debugserver couldn't attach to {} ({})", pid, reply)
+    }
+    remote.send("k")?;
+    let _ = remote.receive_with_timeout(Some(Duration::from_secs(5)))?;
+    Ok(())
+}
+
+/// A process of a reply to `qfProcessInfo`, like
+/// `pid:42;ppid:1;uid:501;gid:501;euid:501;egid:501;name:<hex>;`.
+fn process_info(reply: &str) -> Option<DeviceProcess> {
+    let field = |key: &str| reply.split(';')
+        .filter_map(|it| it.find(':').map(|colon| (&it[..colon], &it[colon + 1..])))
+        .find(|&(name, _)| name == key)
+        .map(|(_, value)| value);
+    Some(DeviceProcess {
+        pid: field("pid")?.parse().ok()?,
+        user: field("uid").map(|it| it.to_string()),
+        name: String::from_utf8_lossy(&unhex(field("name")?)).into_owned(),
+    })
+}
+
+fn expect_ok(remote: &mut GdbRemote, packet: &str, action: &str) -> Result<()> {
+    match remote.request(packet)? {
+        ref reply if reply == "OK" => Ok(()),
+        reply => bail!("debugserver couldn't {} ({})", action, reply),
+    }
+}
+
+fn hex(s: &str) -> String {
+    s.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Vec<u8> {
+    s.as_bytes()
+        .chunks(2)
+        .filter_map(|pair| ::std::str::from_utf8(pair).ok().and_then(|it| u8::from_str_radix(it, 16).ok()))
+        .collect()
+}
+
+/// Undo the escaping (`}` then the byte xor 0x20) and the run-length encoding
+/// (`*` then the repeat count + 29) of a packet.
+fn decode(data: &[u8]) -> Vec<u8> {
+    let mut decoded: Vec<u8> = vec![];
+    let mut bytes = data.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            b'}' => if let Some(&escaped) = bytes.next() {
+                decoded.push(escaped ^ 0x20)
+            },
+            b'*' => {
+                let last = decoded.last().cloned();
+                if let (Some(&count), Some(last)) = (bytes.next(), last) {
+                    for _ in 0..(count as usize).saturating_sub(29) {
+                        decoded.push(last);
+                    }
+                }
+            }
+            b => decoded.push(b),
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_packets() {
+        assert_eq!(decode(b"O30*\""), b"O3000000".to_vec());
+        assert_eq!(decode(b"a}]b"), b"a}b".to_vec());
+        assert_eq!(unhex(&hex("hi\n")), b"hi\n".to_vec());
+    }
+
+    #[test]
+    fn process_infos() {
+        let process = process_info(&format!("pid:42;ppid:1;uid:501;gid:501;name:{};", hex("Dinghy"))).unwrap();
+        assert_eq!(process, DeviceProcess { pid: 42, user: Some("501".to_string()), name: "Dinghy".to_string() });
+        assert_eq!(process_info("E04"), None);
+    }
+}
@@ -1,6 +1,23 @@
 import os
 import lldb
 import shlex
+import sys
+
+# Keep in sync with HELPERS_VERSION in lldb.rs.
+HELPERS_VERSION = 3
+
+ENVS = []
+OUTPUT = []
+
+def __lldb_init_module(debugger, internal_dict):
+    debugger.HandleCommand('command script add -f %s.add_env env' % __name__)
+    debugger.HandleCommand('command script add -f %s.connect_command connect' % __name__)
+    debugger.HandleCommand('command script add -f %s.set_output output' % __name__)
+    debugger.HandleCommand('command script add -f %s.set_remote_path set_remote_path' % __name__)
+    debugger.HandleCommand('command script add -s asynchronous -f %s.start start' % __name__)
+
+def add_env(debugger, command, result, internal_dict):
+    ENVS.append(command)
 
 def connect_command(debugger, command, result, internal_dict):
     connect_url = command
@@ -12,23 +29,57 @@ def set_remote_path(debugger, command, result, internal_dict):
     error = lldb.SBError()
     lldb.target.modules[0].SetPlatformFileSpec(lldb.SBFileSpec(device_app))
 
+def set_output(debugger, command, result, internal_dict):
+    OUTPUT.append(command)
+
+def relay(out, read):
+    while True:
+        data = read(4096)
+        if not data:
+            break
+        out.write(data)
+        out.flush()
+
 def start(debugger, command, result, internal_dict):
+    # The app output goes to the file given to `output` (or to lldb's stdout),
+    # away from the messages of lldb.
+    out = open(OUTPUT[-1], 'a') if OUTPUT else sys.stdout
     error = lldb.SBError()
-    proc = lldb.target.Launch(lldb.SBLaunchInfo(shlex.split(command)), error)
+    launch_info = lldb.SBLaunchInfo(shlex.split(command))
+    launch_info.SetEnvironmentEntries(ENVS, True)
+    debugger.SetAsync(True)
+    proc = lldb.target.Launch(launch_info, error)
     lockedstr = ': Locked'
-    if proc.GetState() != lldb.eStateExited:
-        print("process left in lldb state: %s"%(debugger.StateAsCString(proc.GetState())))
     if lockedstr in str(error):
         print('\nDevice Locked\n')
         os._exit(254)
-    elif proc.GetState() == lldb.eStateStopped:
+    elif 'explicitly trusted' in str(error) or ': Security' in str(error):
+        print('\nUntrusted developer\n')
+        os._exit(253)
+    elif not error.Success():
+        print(str(error))
+        os._exit(1)
+    listener = debugger.GetListener()
+    listener.StartListeningForEvents(proc.GetBroadcaster(),
+                                     lldb.SBProcess.eBroadcastBitStateChanged
+                                     | lldb.SBProcess.eBroadcastBitSTDOUT
+                                     | lldb.SBProcess.eBroadcastBitSTDERR)
+    state = proc.GetState()
+    while state not in (lldb.eStateExited, lldb.eStateStopped, lldb.eStateCrashed, lldb.eStateDetached):
+        event = lldb.SBEvent()
+        if listener.WaitForEvent(1, event) and lldb.SBProcess.EventIsProcessEvent(event):
+            relay(out, proc.GetSTDOUT)
+            relay(out, proc.GetSTDERR)
+            state = proc.GetState()
+    relay(out, proc.GetSTDOUT)
+    relay(out, proc.GetSTDERR)
+    if state != lldb.eStateExited:
+        print("process left in lldb state: %s"%(debugger.StateAsCString(state)))
+    if state in (lldb.eStateStopped, lldb.eStateCrashed):
         thread = proc.GetSelectedThread();
-        print(thread)
+        out.write("%s\n"%(thread))
         for frame in thread:
-            print("  %s"%(frame))
+            out.write("  %s\n"%(frame))
+        out.flush()
         os._exit(-1)
-    elif not error.Success():
-        print(str(error))
-    if proc.exit_state != 0:
-        os._exit(proc.exit_state)
-
+    os._exit(proc.GetExitStatus())
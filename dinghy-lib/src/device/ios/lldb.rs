@@ -0,0 +1,131 @@
+//! The Python helpers loaded in lldb to launch apps, and their installation.
+//!
+//! The helpers are embedded in dinghy, and register their commands themselves
+//! (`__lldb_init_module`), so the generated scripts only import them. Users can
+//! add their own modules with `lldb_helpers` under `[run]`: they are imported
+//! after the embedded ones, so they can redefine their commands (e.g. to look up
+//! the remote path of the app differently), and a module named like an embedded
+//! one replaces it.
+//!
+//! The output of the app is written by the helpers to a file of its own, away
+//! from the messages of lldb, and followed from there.
+
+use errors::*;
+use report::TestReportParser;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use utils::ReportedChild;
+use RunArgs;
+
+/// Version of the interface of the embedded helpers (the commands they define
+/// and their arguments). Bump it on incompatible changes, so that replacement
+/// modules written for an older version are reported.
+pub static HELPERS_VERSION: u32 = 3;
+
+/// Embedded helper modules, in import order.
+static HELPERS: &[(&str, &str)] = &[("helpers.py", include_str!("helpers.py"))];
+/// Files of the app output and of the lldb messages, in the lldb directory.
+static APP_OUTPUT: &str = "app-output";
+static LLDB_LOG: &str = "lldb.log";
+
+/// Write the helper modules into `dir`, and the commands importing them into
+/// `script`.
+pub fn import_helpers<W: Write>(script: &mut W, dir: &Path, user_helpers: &[PathBuf]) -> Result<()> {
+    let mut modules = vec![];
+    for &(name, source) in HELPERS {
+        let path = dir.join(name);
+        fs::File::create(&path)?.write_all(source.as_bytes())?;
+        modules.push(path);
+    }
+    for helper in user_helpers {
+        let name = helper.file_name().ok_or(format!("Invalid lldb helper path {}", helper.display()))?;
+        let mut source = String::new();
+        fs::File::open(helper)
+            .chain_err(|| format!("Couldn't read lldb helper {}", helper.display()))?
+            .read_to_string(&mut source)?;
+        let path = dir.join(name);
+        if HELPERS.iter().any(|&(builtin, _)| name.to_str() == Some(builtin)) {
+            match declared_version(&source) {
+                Some(version) if version == HELPERS_VERSION => {}
+                version => warn!("{} replaces the dinghy lldb helpers version {}, but is written for version {}",
+                                 helper.display(), HELPERS_VERSION,
+                                 version.map(|it| it.to_string()).unwrap_or("?".to_string())),
+            }
+            debug!("Replacing lldb helpers {:?} by {}", name, helper.display());
+            modules.retain(|it| it != &path);
+        }
+        fs::File::create(&path)?.write_all(source.as_bytes())?;
+        modules.push(path);
+    }
+    for module in modules {
+        writeln!(script, "command script import {:?}", module)?;
+    }
+    Ok(())
+}
+
+/// End of the lldb scripts: the environment, the user commands, and the launch
+/// of the app unless a debugger is wanted.
+pub fn write_launch<W: Write>(script: &mut W, dir: &Path, run_args: &RunArgs, debugger: bool) -> Result<()> {
+    for env in &run_args.envs {
+        writeln!(script, "env {}", env)?;
+    }
+    for command in &run_args.lldb_commands {
+        writeln!(script, "{}", command)?;
+    }
+    if !debugger {
+        writeln!(script, "output {}", dir.join(APP_OUTPUT).display())?;
+        writeln!(script, "start {}", run_args.args.join(" "))?;
+        writeln!(script, "quit")?;
+    }
+    Ok(())
+}
+
+/// Run lldb on a script written by `write_launch`, reporting the output of
+/// the app.
+pub fn spawn(command: &mut Command, dir: &Path, parser: TestReportParser) -> Result<ReportedChild> {
+    ReportedChild::spawn_following(command, &dir.join(APP_OUTPUT), &dir.join(LLDB_LOG), parser)
+}
+
+/// Show the messages of lldb if the run failed, they may tell why.
+pub fn report_log(dir: &Path, success: bool) {
+    let log = fs::File::open(dir.join(LLDB_LOG)).and_then(|mut file| {
+        let mut log = String::new();
+        file.read_to_string(&mut log).map(|_| log)
+    });
+    match log {
+        Ok(ref log) if !success && !log.trim().is_empty() => {
+            let _ = writeln!(io::stderr(), "lldb output:\n{}", log.trim_right());
+        }
+        Ok(log) => debug!("lldb output:\n{}", log),
+        Err(e) => debug!("Couldn't read the lldb output: {}", e),
+    }
+}
+
+/// The `HELPERS_VERSION = <n>` declared by a helper module.
+fn declared_version(source: &str) -> Option<u32> {
+    source.lines()
+        .filter_map(|line| {
+            let mut split = line.splitn(2, '=');
+            match (split.next().map(str::trim), split.next().map(str::trim)) {
+                (Some("HELPERS_VERSION"), Some(version)) => version.parse().ok(),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedded_helpers_version() {
+        assert_eq!(declared_version(HELPERS[0].1), Some(HELPERS_VERSION));
+        assert_eq!(declared_version("import lldb\n"), None);
+    }
+}
@@ -0,0 +1,463 @@
+//! Safe wrappers over the MobileDevice framework bindings.
+
+use core_foundation::array::CFArray;
+use core_foundation::base::{CFType, CFTypeRef, TCFType};
+use core_foundation::boolean::CFBoolean;
+use core_foundation::data::CFData;
+use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
+use core_foundation::number::CFNumber;
+use core_foundation::string::CFString;
+use core_foundation::url::CFURL;
+use core_foundation_sys::number::kCFBooleanTrue;
+use errors::*;
+use libc::{c_int, c_void};
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::net::TcpStream;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::ptr;
+use super::mobiledevice_sys::*;
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    String(String),
+    Data(Vec<u8>),
+    I64(i64),
+    Boolean(bool),
+}
+
+/// A device known to MobileDevice.
+#[derive(Clone, Copy, Debug)]
+pub struct AmDevice(*const am_device);
+
+unsafe impl Send for AmDevice {}
+unsafe impl Sync for AmDevice {}
+
+/// A connected device with an open session, closed on drop.
+pub struct Session(AmDevice);
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.0.disconnect()
+    }
+}
+
+/// Subscribe to device notifications. The callback is called from a dedicated
+/// thread running the notification loop, and must not panic.
+pub fn subscribe<F: Fn(AmDevice) + Send + 'static>(callback: F) {
+    extern "C" fn device_callback(info: *mut am_device_notification_callback_info, callback: *mut c_void) {
+        let callback: &Box<Fn(AmDevice) + Send> = unsafe { mem::transmute(callback) };
+        callback(AmDevice(unsafe { (*info).dev }));
+    }
+
+    let callback: Box<Box<Fn(AmDevice) + Send>> = Box::new(Box::new(callback));
+    ::std::thread::spawn(move || {
+        let notify: *const am_device_notification = ptr::null();
+        unsafe {
+            AMDeviceNotificationSubscribe(
+                device_callback,
+                0,
+                0,
+                Box::into_raw(callback) as *mut c_void,
+                &mut notify.into(),
+            );
+        }
+        ::core_foundation::runloop::CFRunLoop::run_current();
+    });
+}
+
+impl AmDevice {
+    pub fn connect(&self) -> Result<Session> {
+        unsafe {
+            mk_result(AMDeviceConnect(self.0))?;
+            if AMDeviceIsPaired(self.0) == 0 {
+                Err("This computer is not trusted by the device. Unlock it and answer 'Trust' to the 'Trust This \
+                     Computer?' prompt (plug it again if the prompt doesn't show up).")?
+            };
+            mk_result(AMDeviceValidatePairing(self.0))?;
+            mk_result(AMDeviceStartSession(self.0))?;
+        }
+        Ok(Session(*self))
+    }
+
+    /// Stop the session and disconnect, regardless of any `Session` handle.
+    pub fn disconnect(&self) {
+        unsafe {
+            if let Err(e) = mk_result(AMDeviceStopSession(self.0)) {
+                debug!("Error closing session {:?}", e);
+            }
+            if let Err(e) = mk_result(AMDeviceDisconnect(self.0)) {
+                error!("Error disconnecting {:?}", e);
+            }
+        }
+    }
+
+    pub fn identifier(&self) -> Result<String> {
+        match rustify(unsafe { AMDeviceCopyDeviceIdentifier(self.0) })? {
+            Value::String(id) => Ok(id),
+            _ => Err("unexpected id format")?,
+        }
+    }
+
+    pub fn read_value(&self, key: &str) -> Result<Option<Value>> {
+        self.read_domain_value(None, key)
+    }
+
+    /// Read a value of a lockdown domain (e.g. `com.apple.mobile.battery`).
+    pub fn read_domain_value(&self, domain: Option<&str>, key: &str) -> Result<Option<Value>> {
+        let cf_domain = domain.map(CFString::new);
+        let cf_key = CFString::new(key);
+        let raw = unsafe {
+            AMDeviceCopyValue(self.0,
+                              cf_domain.as_ref().map(|it| it.as_concrete_TypeRef()).unwrap_or(ptr::null()),
+                              cf_key.as_concrete_TypeRef())
+        };
+        let value = if raw.is_null() { Ok(None) } else { rustify(raw).map(Some) };
+        if ::trace::is_recording() {
+            let path = domain.map(|it| format!("{}/{}", it, key)).unwrap_or(key.to_string());
+            ::trace::record(&self.identifier().unwrap_or(String::new()), "property", &path, &value,
+                            |it| format!("{:?}", it).into());
+        }
+        value
+    }
+
+    /// Mount a disk image. An already mounted image is not an error.
+    pub fn mount_image(&self, image: &Path, signature: &[u8]) -> Result<()> {
+        let signature = CFData::from_buffer(signature);
+        let options = [
+            (
+                CFString::from_static_string("ImageType"),
+                CFString::from_static_string("Developper").as_CFType(),
+            ),
+            (
+                CFString::from_static_string("ImageSignature"),
+                signature.as_CFType(),
+            ),
+        ];
+        let options = CFDictionary::from_CFType_pairs(&options);
+        let image = image.to_str().ok_or("failure to convert")?;
+        let r = unsafe {
+            AMDeviceMountImage(
+                self.0,
+                CFString::new(image).as_concrete_TypeRef(),
+                options.as_concrete_TypeRef(),
+                mem::transmute(0usize),
+                0,
+            )
+        };
+        if r as u32 == 0xe8000076 {
+            // already mounted, that's fine.
+            return Ok(());
+        }
+        mk_result(r)
+    }
+
+    /// Start a service on the device. The connection is closed when the
+    /// returned stream is dropped.
+    pub fn start_service(&self, name: &str) -> Result<TcpStream> {
+        let mut fd: c_int = 0;
+        let started = unsafe {
+            mk_result(AMDeviceStartService(
+                self.0,
+                CFString::new(name).as_concrete_TypeRef(),
+                &mut fd,
+                ptr::null(),
+            ))
+        };
+        if ::trace::is_recording() {
+            ::trace::record(&self.identifier().unwrap_or(String::new()), "service", name, &started, |_| ::json::JsonValue::Null);
+        }
+        started?;
+        Ok(unsafe { TcpStream::from_raw_fd(fd) })
+    }
+
+    /// Open a TCP connection to a port of the device, through usbmuxd.
+    pub fn connect_to_port(&self, port: u16) -> Result<TcpStream> {
+        let mut fd: c_int = 0;
+        unsafe {
+            mk_result(USBMuxConnectByPort(
+                AMDeviceGetConnectionID(self.0),
+                port.to_be() as c_int,
+                &mut fd,
+            ))
+            .chain_err(|| format!("Couldn't connect to port {} of the device", port))?;
+            Ok(TcpStream::from_raw_fd(fd))
+        }
+    }
+
+    /// Start a service speaking the AFC file protocol.
+    pub fn start_afc_service(&self, name: &str) -> Result<AfcConnection> {
+        AfcConnection::open(self.start_service(name)?)
+    }
+
+    pub fn transfer(&self, app: &Path) -> Result<()> {
+        let (url, options) = install_params(app)?;
+        mk_result(unsafe {
+            AMDeviceSecureTransferPath(
+                0,
+                self.0,
+                url.as_concrete_TypeRef(),
+                options.as_concrete_TypeRef(),
+                ptr::null(),
+                ptr::null(),
+            )
+        })
+    }
+
+    pub fn install(&self, app: &Path) -> Result<()> {
+        let (url, options) = install_params(app)?;
+        mk_result(unsafe {
+            AMDeviceSecureInstallApplication(
+                0,
+                self.0,
+                url.as_concrete_TypeRef(),
+                options.as_concrete_TypeRef(),
+                ptr::null(),
+                ptr::null(),
+            )
+        })
+    }
+
+    /// Transfer and install an app, only sending the files that changed since
+    /// the previous install: the framework keeps track of what it sent in
+    /// `deltas_dir`.
+    pub fn install_incremental(&self, app: &Path, deltas_dir: &Path) -> Result<()> {
+        let (url, _) = install_params(app)?;
+        let deltas = deltas_dir.to_str().ok_or("failure to convert")?;
+        let options = [
+            (
+                CFString::from_static_string("ShadowParentKey"),
+                CFURL::from_file_system_path(CFString::new(deltas), 0, true).as_CFType(),
+            ),
+        ];
+        let options = CFDictionary::from_CFType_pairs(&options);
+        mk_result(unsafe {
+            AMDeviceSecureInstallApplicationBundle(
+                self.0,
+                url.as_concrete_TypeRef(),
+                options.as_concrete_TypeRef(),
+                ptr::null(),
+                ptr::null(),
+            )
+        })
+    }
+
+    /// Remove an application and its data from the device.
+    pub fn uninstall(&self, bundle_id: &str) -> Result<()> {
+        mk_result(unsafe {
+            AMDeviceSecureUninstallApplication(
+                0,
+                self.0,
+                CFString::new(bundle_id).as_concrete_TypeRef(),
+                ptr::null(),
+                ptr::null(),
+                ptr::null(),
+            )
+        })
+    }
+
+    /// Find the on-device path of an installed application.
+    pub fn lookup_application_path(&self, bundle_id: &str) -> Result<String> {
+        match self.lookup_application_value(bundle_id, "Path")? {
+            Some(Value::String(remote)) => Ok(remote),
+            None => Err(format!("Application {} is not installed", bundle_id))?,
+            _ => Err("Invalid info")?,
+        }
+    }
+
+    /// Read an attribute (e.g. an `Info.plist` entry) of an installed
+    /// application, if the application and the attribute exist.
+    pub fn lookup_application_value(&self, bundle_id: &str, key: &str) -> Result<Option<Value>> {
+        let key = CFString::new(key);
+        let options = [
+            (
+                CFString::from_static_string("ReturnAttributes"),
+                CFArray::from_CFTypes(&[
+                    CFString::from_static_string("CFBundleIdentifier").as_CFType(),
+                    key.as_CFType(),
+                ]),
+            ),
+        ];
+        let options = CFDictionary::from_CFType_pairs(&options);
+        let apps: CFDictionaryRef = ptr::null();
+        unsafe {
+            mk_result(AMDeviceLookupApplications(
+                self.0,
+                options.as_concrete_TypeRef(),
+                mem::transmute(&apps),
+            ))?;
+        }
+        if apps.is_null() {
+            Err("No application list returned by device")?
+        }
+        let apps: CFDictionary = unsafe { TCFType::wrap_under_get_rule(apps) };
+        let bundle_id = CFString::new(bundle_id);
+        let app_info = match apps.find(bundle_id.as_concrete_TypeRef() as *const c_void) {
+            Some(app_info) => app_info,
+            None => return Ok(None),
+        };
+        let app_info: CFDictionary = unsafe { TCFType::wrap_under_get_rule(mem::transmute(app_info)) };
+        match app_info.find(key.as_concrete_TypeRef() as *const c_void) {
+            Some(raw) => Ok(Some(rustify(raw)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A connection to an AFC service, closed on drop.
+pub struct AfcConnection {
+    conn: *mut afc_connection,
+    _stream: TcpStream,
+}
+
+impl AfcConnection {
+    /// Speak AFC over a service connection, e.g. once house_arrest vended a
+    /// container.
+    pub fn open(stream: TcpStream) -> Result<AfcConnection> {
+        let mut conn: *mut afc_connection = ptr::null_mut();
+        mk_result(unsafe { AFCConnectionOpen(stream.as_raw_fd(), 0, &mut conn) })?;
+        Ok(AfcConnection { conn, _stream: stream })
+    }
+
+    pub fn read_dir(&self, path: &str) -> Result<Vec<String>> {
+        let path = CString::new(path).map_err(|_| format!("Invalid AFC path {:?}", path))?;
+        let mut dir: *mut afc_directory = ptr::null_mut();
+        mk_result(unsafe { AFCDirectoryOpen(self.conn, path.as_ptr(), &mut dir) })?;
+        let mut entries = vec![];
+        loop {
+            let mut entry: *mut ::libc::c_char = ptr::null_mut();
+            if let Err(e) = mk_result(unsafe { AFCDirectoryRead(self.conn, dir, &mut entry) }) {
+                unsafe { AFCDirectoryClose(self.conn, dir) };
+                return Err(e);
+            }
+            if entry.is_null() {
+                break;
+            }
+            let entry = unsafe { CStr::from_ptr(entry) }.to_string_lossy().into_owned();
+            if entry != "." && entry != ".." {
+                entries.push(entry);
+            }
+        }
+        unsafe { AFCDirectoryClose(self.conn, dir) };
+        Ok(entries)
+    }
+
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        let path = CString::new(path).map_err(|_| format!("Invalid AFC path {:?}", path))?;
+        let mut file: afc_file_ref = 0;
+        mk_result(unsafe { AFCFileRefOpen(self.conn, path.as_ptr(), AFC_FILE_MODE_READ, &mut file) })?;
+        let mut content = vec![];
+        let mut buffer = [0u8; 65536];
+        let result = loop {
+            let mut len = buffer.len() as ::libc::c_uint;
+            if let Err(e) = mk_result(unsafe {
+                AFCFileRefRead(self.conn, file, buffer.as_mut_ptr() as *mut c_void, &mut len)
+            }) {
+                break Err(e);
+            }
+            if len == 0 {
+                break Ok(());
+            }
+            content.extend_from_slice(&buffer[..len as usize]);
+        };
+        unsafe { AFCFileRefClose(self.conn, file) };
+        result.map(|_| content)
+    }
+
+    pub fn write_file(&self, path: &str, content: &[u8]) -> Result<()> {
+        let path = CString::new(path).map_err(|_| format!("Invalid AFC path {:?}", path))?;
+        let mut file: afc_file_ref = 0;
+        mk_result(unsafe { AFCFileRefOpen(self.conn, path.as_ptr(), AFC_FILE_MODE_WRITE, &mut file) })?;
+        let result = content.chunks(65536)
+            .map(|chunk| mk_result(unsafe {
+                AFCFileRefWrite(self.conn, file, chunk.as_ptr() as *const c_void, chunk.len() as ::libc::c_uint)
+            }))
+            .collect::<Result<Vec<()>>>();
+        unsafe { AFCFileRefClose(self.conn, file) };
+        result.map(|_| ())
+    }
+
+    /// Create a directory, and its missing parents.
+    pub fn make_dir(&self, path: &str) -> Result<()> {
+        let path = CString::new(path).map_err(|_| format!("Invalid AFC path {:?}", path))?;
+        mk_result(unsafe { AFCDirectoryCreate(self.conn, path.as_ptr()) })
+    }
+
+    /// Remove a file, or an empty directory.
+    pub fn remove(&self, path: &str) -> Result<()> {
+        let path = CString::new(path).map_err(|_| format!("Invalid AFC path {:?}", path))?;
+        mk_result(unsafe { AFCRemovePath(self.conn, path.as_ptr()) })
+    }
+}
+
+impl Drop for AfcConnection {
+    fn drop(&mut self) {
+        unsafe { AFCConnectionClose(self.conn) };
+    }
+}
+
+/// The URL of an app directory or IPA, and the install options matching it.
+fn install_params(app: &Path) -> Result<(CFURL, CFDictionary)> {
+    let path = app.to_str().ok_or("failure to convert")?;
+    let is_dir = app.is_dir();
+    let url = CFURL::from_file_system_path(CFString::new(path), 0, is_dir);
+    // IPAs are installed like the App Store ones.
+    let package_type = if is_dir { "Developper" } else { "Customer" };
+    let options = [
+        (
+            CFString::from_static_string("PackageType"),
+            CFString::new(package_type).as_CFType(),
+        ),
+    ];
+    Ok((url, CFDictionary::from_CFType_pairs(&options)))
+}
+
+pub fn mk_result(rv: i32) -> Result<()> {
+    if rv as u32 == 0xe80000e2 {
+        Err(format!("error: Device is locked. ({:x}) Unlock it and run again.", rv))?
+    } else if rv as u32 == 0xe8008015 {
+        Err("error: 0xe8008015, A valid provisioning profile for this executable was not found. Make sure the \
+             provisioning profile of the signing identity includes this device (see docs/ios.md).")?
+    } else if rv as u32 == 0xe8008016 {
+        Err("error: 0xe8008016, The executable was signed with invalid entitlements. Check the entitlements of the \
+             bundle against the provisioning profile.")?
+    } else if rv as u32 == 0xe8008018 {
+        Err("error: 0xe8008018, The identity used to sign the executable is no longer valid. Renew the signing \
+             certificate (see docs/ios.md).")?
+    } else if rv as u32 == 0xe8008022 {
+        Err(
+            "error: 0xe8000022, kAMDInvalidServiceError. (This one is relatively hard to diagnose. Try erasing the Dinghy app from the phone, rebooting the device, the computer, check for ios and xcode updates.)",
+        )?
+    } else if rv != 0 {
+        Err(format!("error: {:x}", rv))?
+    } else {
+        Ok(())
+    }
+}
+
+fn rustify(raw: CFTypeRef) -> Result<Value> {
+    unsafe {
+        let cftype: CFType = TCFType::wrap_under_get_rule(mem::transmute(raw));
+        if cftype.type_of() == CFString::type_id() {
+            let value: CFString = TCFType::wrap_under_get_rule(mem::transmute(raw));
+            return Ok(Value::String(value.to_string()));
+        }
+
+        if cftype.type_of() == CFData::type_id() {
+            let value: CFData = TCFType::wrap_under_get_rule(mem::transmute(raw));
+            return Ok(Value::Data(value.bytes().to_vec()));
+        }
+        if cftype.type_of() == CFNumber::type_id() {
+            let value: CFNumber = TCFType::wrap_under_get_rule(mem::transmute(raw));
+            if let Some(i) = value.to_i64() {
+                return Ok(Value::I64(i));
+            }
+        }
+        if cftype.type_of() == CFBoolean::type_id() {
+            return Ok(Value::Boolean(raw == mem::transmute(kCFBooleanTrue)));
+        }
+        cftype.show();
+        Err("unknown value")?
+    }
+}
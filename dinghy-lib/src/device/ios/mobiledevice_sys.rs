@@ -53,6 +53,15 @@ pub type am_device_notification_callback = extern "C" fn(
 
 
 pub type am_device_mount_callback = extern "C" fn(CFDictionaryRef, *mut c_void);
+
+pub enum afc_connection {}
+pub enum afc_directory {}
+pub type afc_file_ref = u64;
+
+pub const AFC_FILE_MODE_READ: u64 = 1;
+/// Write only, created or truncated.
+pub const AFC_FILE_MODE_WRITE: u64 = 3;
+
 #[link(name = "MobileDevice", kind = "framework")]
 extern "C" {
     pub fn AMDeviceNotificationSubscribe(
@@ -83,6 +92,9 @@ extern "C" {
         cbarg: c_int,
     ) -> c_int;
 
+    pub fn AMDeviceGetConnectionID(device: *const am_device) -> c_uint;
+    pub fn USBMuxConnectByPort(connection_id: c_uint, port: c_int, socket_fd: *mut c_int) -> c_int;
+
     pub fn AMDeviceStartService(
         device: *const am_device,
         service_name: CFStringRef,
@@ -105,6 +117,33 @@ extern "C" {
         callback: *const c_void,
         cbarg: *const c_void,
     ) -> c_int;
+    pub fn AMDeviceSecureInstallApplicationBundle(
+        device: *const am_device,
+        url: CFURLRef,
+        options: CFDictionaryRef,
+        callback: *const c_void,
+        cbarg: *const c_void,
+    ) -> c_int;
+    pub fn AMDeviceSecureUninstallApplication(
+        zero: c_int,
+        device: *const am_device,
+        bundle_id: CFStringRef,
+        options: CFDictionaryRef,
+        callback: *const c_void,
+        cbarg: *const c_void,
+    ) -> c_int;
+    pub fn AFCConnectionOpen(socket: c_int, io_timeout: c_uint, conn: *mut *mut afc_connection) -> c_int;
+    pub fn AFCConnectionClose(conn: *mut afc_connection) -> c_int;
+    pub fn AFCDirectoryOpen(conn: *mut afc_connection, path: *const c_char, dir: *mut *mut afc_directory) -> c_int;
+    pub fn AFCDirectoryRead(conn: *mut afc_connection, dir: *mut afc_directory, dirent: *mut *mut c_char) -> c_int;
+    pub fn AFCDirectoryClose(conn: *mut afc_connection, dir: *mut afc_directory) -> c_int;
+    pub fn AFCDirectoryCreate(conn: *mut afc_connection, path: *const c_char) -> c_int;
+    pub fn AFCRemovePath(conn: *mut afc_connection, path: *const c_char) -> c_int;
+    pub fn AFCFileRefOpen(conn: *mut afc_connection, path: *const c_char, mode: u64, file: *mut afc_file_ref) -> c_int;
+    pub fn AFCFileRefRead(conn: *mut afc_connection, file: afc_file_ref, buf: *mut c_void, len: *mut c_uint) -> c_int;
+    pub fn AFCFileRefWrite(conn: *mut afc_connection, file: afc_file_ref, buf: *const c_void, len: c_uint) -> c_int;
+    pub fn AFCFileRefClose(conn: *mut afc_connection, file: afc_file_ref) -> c_int;
+
     pub fn AMDeviceSecureTransferPath(
         zero: c_int,
         device: *const am_device,
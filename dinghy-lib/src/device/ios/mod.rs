@@ -1,21 +1,40 @@
-use core_foundation::array::CFArray;
-use core_foundation::base::{CFType, CFTypeRef, TCFType};
-use core_foundation::string::CFString;
-use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
-use core_foundation::data::CFData;
-use core_foundation::number::CFNumber;
-use core_foundation::boolean::CFBoolean;
-use core_foundation_sys::number::kCFBooleanTrue;
+use artifacts;
+use artifacts::ArtifactStore;
+use cleanup;
+use coverage;
+use device::bundle_hash;
+use device::bundle_size;
+use device::describe_remote_app;
 use device::make_remote_app_with_name;
+use device::simctl;
+use forward;
+use forward::PortForward;
+use config::Configuration;
+use console::ConsoleMessage;
+use console::ConsoleOptions;
+use console::Priority;
+use dinghy_build::triple::Triple;
+use health::DeviceHealth;
+use installs;
 use errors::*;
+use logging;
+use network::NetworkCondition;
+use network::NetworkProfile;
 use libc::*;
 use project::Project;
+use recording::ScreenRecording;
+use report::RunResult;
+use report::TestReport;
+use report::TestReportParser;
 use std::fs;
-use std::mem;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process;
-use std::ptr;
 use std::sync;
 use std::thread;
 use std::collections::HashMap;
@@ -23,25 +42,54 @@ use std::fmt;
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::time::Duration;
+use std::time::SystemTime;
 use platform::ios::IosPlatform;
+use processes::DeviceProcess;
+use processes::KillTarget;
+use timings;
+use timings::Phase;
+use utils::path_to_str;
+use utils::xml_escape;
 use Build;
 use BuildBundle;
 use Device;
 use DeviceCompatibility;
 use PlatformManager;
 use Runnable;
-use self::mobiledevice_sys::*;
+use RunArgs;
+use self::mobiledevice::{AfcConnection, AmDevice, Value};
+use symbolicate;
 
+mod debugserver;
+mod lldb;
+mod mobiledevice;
 mod mobiledevice_sys;
+mod syslog;
 mod xcode;
 
+/// Info.plist key of the content hash of the app.
+static BUNDLE_HASH_KEY: &str = "DinghyBundleHash";
+/// Directory of the coverage data in the app containers, `tmp` being where
+/// `%t` of `LLVM_PROFILE_FILE` points to.
+static COVERAGE_DIR: &str = "tmp/dinghy-coverage";
+/// Exit codes of lldb when the app couldn't be launched, see `helpers.py`.
+static LLDB_EXIT_LOCKED: i32 = 254;
+static LLDB_EXIT_UNTRUSTED: i32 = 253;
+/// How long to wait for the user to unlock a device, in seconds.
+static UNLOCK_TIMEOUT: u64 = 120;
+/// How long to keep a device awake when the run has no timeout, in seconds.
+static KEEP_AWAKE_DURATION: u64 = 3600;
+
 #[derive(Clone, Debug)]
 pub struct IosDevice {
-    ptr: *const am_device,
+    device: AmDevice,
     id: String,
     name: String,
     arch_cpu: &'static str,
+    /// iOS version, e.g. `11.2.6`.
+    os_version: Option<String>,
     rustc_triple: String,
+    failure: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -51,6 +99,10 @@ pub struct SignatureSettings {
     pub entitlements: String,
     pub name: String,
     pub profile: String,
+    /// UUID of the provisioning profile.
+    pub uuid: String,
+    /// Whether the profile signs any bundle identifier of the team.
+    pub wildcard: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -58,6 +110,8 @@ pub struct SigningIdentity {
     pub id: String,
     pub name: String,
     pub team: String,
+    /// The keychain holding the identity, `None` for the default ones.
+    pub keychain: Option<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -67,101 +121,500 @@ pub struct IosSimDevice {
     os: String,
 }
 
-unsafe impl Send for IosDevice {}
-
 impl IosDevice {
-    fn from(ptr: *const am_device) -> Result<IosDevice> {
-        let _session = ensure_session(ptr)?;
-        let name = match device_read_value(ptr, "DeviceName")? {
+    fn from(device: AmDevice, conf: &Configuration) -> Result<IosDevice> {
+        let _session = device.connect()?;
+        let name = match device.read_value("DeviceName")? {
             Some(Value::String(s)) => s,
             x => Err(format!("DeviceName should have been a string, was {:?}", x))?,
         };
-        let cpu = match device_read_value(ptr, "CPUArchitecture")? {
-            Some(Value::String(ref v)) if v == "arm64" => "aarch64",
-            _ => "armv7",
+        let read_string = |key: &str| -> Result<Option<String>> {
+            Ok(match device.read_value(key)? {
+                Some(Value::String(s)) => Some(s),
+                _ => None,
+            })
         };
-        let id =
-            if let Value::String(id) = rustify(unsafe { AMDeviceCopyDeviceIdentifier(ptr) })? {
-                id
-            } else {
-                Err("unexpected id format")?
-            };
+        let product_type = read_string("ProductType")?;
+        debug!("iOS device {} is a {:?} ({:?})", name, product_type, read_string("HardwareModel")?);
+        let cpu = cpu_arch(read_string("CPUArchitecture")?.as_ref().map(|it| it.as_str()),
+                           product_type.as_ref().map(|it| it.as_str()));
+        let id = device.identifier()?;
+        let rustc_triple = match conf.device(&id).rustc_triple {
+            Some(triple) => triple,
+            None if cpu == "armv7k" || cpu == "arm64_32" => conf.rustc_triple_for(&Triple::watchos(cpu)),
+            // arm64e slices need a nightly toolchain: run the plain arm64 ones by default.
+            None if cpu == "arm64e" => conf.rustc_triple_for(&Triple::ios("aarch64")),
+            None => conf.rustc_triple_for(&Triple::ios(cpu)),
+        };
+        // Only reported since iOS 16, where apps can't be launched without it.
+        if let Some(Value::Boolean(false)) = device.read_domain_value(Some("com.apple.security.mac.amfi"), "DeveloperModeStatus")? {
+            bail!("Developer Mode is disabled. Enable it in Settings > Privacy & Security > Developer Mode, and restart the phone.")
+        }
         Ok(IosDevice {
-            ptr: ptr,
+            device: device,
             name: name,
             id: id,
-            arch_cpu: cpu.into(),
-            rustc_triple: format!("{}-apple-ios", cpu),
+            arch_cpu: cpu,
+            os_version: read_string("ProductVersion")?,
+            rustc_triple: rustc_triple,
+            failure: None,
         })
     }
 
+    /// A device that was detected but couldn't be probed. It is kept around so
+    /// that it shows up in the device list, but can't be used.
+    fn failed(device: AmDevice, error: &Error) -> IosDevice {
+        IosDevice {
+            device: device,
+            name: "<unavailable>".to_string(),
+            id: device.identifier().unwrap_or("<unknown>".to_string()),
+            arch_cpu: "unknown",
+            os_version: None,
+            rustc_triple: String::new(),
+            failure: Some(error.to_string()),
+        }
+    }
+
+    fn ensure_available(&self) -> Result<()> {
+        match self.failure {
+            Some(ref failure) => bail!("iOS device {} is unavailable ({})", self.id, failure),
+            None => Ok(()),
+        }
+    }
+
+    /// iOS 11 dropped 32-bit apps, and only tells at install or launch, with
+    /// a cryptic error: check the executable before signing it.
+    fn ensure_runs_exe(&self, exe: &Path) -> Result<()> {
+        let major = self.os_version.as_ref()
+            .and_then(|it| it.split('.').next())
+            .and_then(|it| it.parse::<u32>().ok());
+        if major.map(|it| it < 11).unwrap_or(true) {
+            return Ok(());
+        }
+        let mut header = vec![];
+        fs::File::open(exe)
+            .and_then(|file| file.take(4096).read_to_end(&mut header))
+            .chain_err(|| format!("Couldn't read {}", exe.display()))?;
+        if let Some(arch) = only_32_bit_arch(&header) {
+            bail!("{} runs iOS {} and cannot run {} binaries; use aarch64-apple-ios",
+                  self.id, self.os_version.as_ref().unwrap(), arch)
+        }
+        Ok(())
+    }
+
+    /// Apps can't be launched on a locked device: ask the user to unlock it,
+    /// and wait for it.
+    fn wait_unlocked(&self) -> Result<()> {
+        let start = SystemTime::now();
+        let mut prompted = false;
+        loop {
+            let locked = {
+                let _session = self.device.connect()?;
+                match self.device.read_value("PasswordProtected")? {
+                    Some(Value::Boolean(locked)) => locked,
+                    _ => false,
+                }
+            };
+            if !locked {
+                return Ok(());
+            }
+            if !prompted {
+                println!("{} is locked, unlock it to continue...", self);
+                prompted = true;
+            }
+            if start.elapsed().map(|it| it.as_secs() > UNLOCK_TIMEOUT).unwrap_or(false) {
+                bail!("{} is still locked after {}s", self.id, UNLOCK_TIMEOUT)
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    /// Prepare the device for launches: unlocked, and kept awake during the
+    /// run if asked to (for as long as the returned connection is open).
+    fn prepare_launch(&self, run_args: &RunArgs) -> Result<Option<TcpStream>> {
+        self.ensure_available()?;
+        self.wait_unlocked()?;
+        if !run_args.keep_awake {
+            return Ok(None);
+        }
+        let duration = run_args.timeout.unwrap_or(Duration::from_secs(KEEP_AWAKE_DURATION));
+        match keep_awake(self.device, duration) {
+            Ok(assertion) => Ok(Some(assertion)),
+            Err(e) => {
+                warn!("Couldn't keep {} awake: {}", self.id, e);
+                Ok(None)
+            }
+        }
+    }
+
     fn make_app(&self, project: &Project, build: &Build, runnable: &Runnable) -> Result<BuildBundle> {
-        let signing = xcode::look_for_signature_settings(&self.id)?
+        let _timer = timings::start(Phase::Bundle);
+        let signing = xcode::look_for_signature_settings(&self.id, &project.conf.ios)?
             .pop()
-            .ok_or("no signing identity found")?;
-        let app_id = signing.name.split(" ").last().ok_or("no app id ?")?;
+            .ok_or(format!("No provisioning profile of the signing identities covers {}", self.id))?;
+        let base_app_id = signing.name.split(" ").last().ok_or("no app id ?")?;
+        let template = project.for_runnable(runnable)?.conf.bundle_identifier.clone();
+        let app_id = match template {
+            Some(ref template) if signing.wildcard => app_identifier(template, base_app_id, &runnable.id),
+            Some(_) => {
+                warn!("The provisioning profile of {} only signs {}, ignoring the bundle identifier template", self.id, base_app_id);
+                base_app_id.to_string()
+            }
+            None => base_app_id.to_string(),
+        };
 
-        let build_bundle = make_ios_app(project, build, runnable, &app_id)?;
+        let mut extra_entitlements = String::new();
+        for entitlements in &project.for_runnable(runnable)?.conf.bundle_entitlements {
+            debug!("Adding entitlements from {}", entitlements.display());
+            let mut template = String::new();
+            fs::File::open(entitlements)
+                .and_then(|mut file| file.read_to_string(&mut template))
+                .chain_err(|| format!("Couldn't read entitlements {}", entitlements.display()))?;
+            extra_entitlements.push_str(&xcode::expand_entitlements(&template, &signing.identity.team, &app_id));
+            extra_entitlements.push('\n');
+        }
 
-        xcode::sign_app(&build_bundle, &signing)?;
+        // A change of signature makes another app, even from the same files.
+        let signing_inputs = [&*signing.identity.id, &*signing.uuid, &*signing.entitlements, &*extra_entitlements];
+        let build_bundle = make_ios_app(project, build, &build.artifacts(&self.id), runnable, &app_id, &signing_inputs)?;
+        xcode::sign_app(&build_bundle, &signing, &extra_entitlements)?;
         Ok(build_bundle)
     }
 
-    fn install_app(&self, project: &Project, build: &Build, runnable: &Runnable) -> Result<BuildBundle> {
+    fn install_app(&self, project: &Project, build: &Build, runnable: &Runnable, run_args: &RunArgs) -> Result<BuildBundle> {
+        let _span = logging::span(format!("install {}", runnable.id));
+        let _timer = timings::start(Phase::Transfer);
+        self.ensure_available()?;
+        self.ensure_runs_exe(&runnable.exe)?;
         let build_bundle = self.make_app(project, build, runnable)?;
-        install_app(self.ptr, &build_bundle.bundle_dir)?;
+        let artifacts = build.artifacts(&self.id);
+        let bundle_id = bundle_identifier(&build_bundle.bundle_dir)?;
+        installs::record(&self.id, &bundle_id);
+        if run_args.fresh {
+            info!("Uninstall {} from {}, with its data", bundle_id, self.id);
+            let _session = self.device.connect()?;
+            self.device.uninstall(&bundle_id)
+                .chain_err(|| format!("Couldn't uninstall {} from {}", bundle_id, self.id))?;
+            // What was sent before is gone with the app.
+            artifacts::reset_dir(artifacts.deltas_dir())?;
+        } else if self.is_installed(&build_bundle).unwrap_or_else(|e| {
+            debug!("Couldn't check the app installed on {}: {}", self.id, e);
+            false
+        }) {
+            info!("{} is already installed on {}", runnable.id, self.id);
+            return Ok(build_bundle);
+        }
+        info!("Install {} to {}", runnable.id, self.id);
+        let ipa_dir = if run_args.compress { Some(artifacts.scratch_dir("ipa")?) } else { None };
+        install_app(self.device, &build_bundle.bundle_dir, &artifacts.deltas_dir(), ipa_dir.as_ref().map(|it| it.as_path()))?;
+        timings::transferred(bundle_size(&build_bundle)?);
         Ok(build_bundle)
     }
+
+    /// Whether the very same bundle is already installed on the device.
+    fn is_installed(&self, build_bundle: &BuildBundle) -> Result<bool> {
+        let hash = match info_plist_string(&build_bundle.bundle_dir, BUNDLE_HASH_KEY)? {
+            Some(hash) => hash,
+            None => return Ok(false),
+        };
+        let _session = self.device.connect()?;
+        match self.device.lookup_application_value(&bundle_identifier(&build_bundle.bundle_dir)?, BUNDLE_HASH_KEY)? {
+            Some(Value::String(ref installed)) => Ok(*installed == hash),
+            _ => Ok(false),
+        }
+    }
 }
 
 impl Device for IosDevice {
+    fn archive_app(&self, project: &Project, build: &Build) -> Result<Vec<PathBuf>> {
+        let artifacts = build.artifacts(&self.id);
+        let archives_dir = artifacts::ensure_dir(artifacts.archives_dir())?;
+        let mut archives = vec![];
+        for runnable in &build.runnables {
+            let build_bundle = self.make_app(project, build, runnable)?;
+            let ipa = package_ipa(&build_bundle.bundle_dir, &artifacts.scratch_dir("ipa")?)?;
+            let archive = archives_dir.join(format!("{}.ipa", runnable.id));
+            fs::copy(&ipa, &archive).chain_err(|| format!("Couldn't copy {} to {}", ipa.display(), archive.display()))?;
+            archives.push(archive);
+        }
+        Ok(archives)
+    }
+
     fn clean_app(&self, _build_bundle: &BuildBundle) -> Result<()> {
         unimplemented!()
     }
 
-    fn debug_app(&self, project: &Project, build: &Build, args: &[&str], _envs: &[&str]) -> Result<BuildBundle> {
+    fn debug_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<BuildBundle> {
         let runnable = build.runnables.iter().next().ok_or("No executable compiled")?;
-        let build_bundle = self.install_app(project, build, runnable)?;
+        let build_bundle = self.install_app(project, build, runnable, run_args)?;
+        let _awake = self.prepare_launch(run_args)?;
         let lldb_proxy = self.start_remote_lldb()?;
-        run_remote(self.ptr, &lldb_proxy, &build_bundle.bundle_dir, args, true)?;
+        let lldb_dir = build.artifacts(&self.id).scratch_dir("lldb")?;
+        run_remote(self.device, &lldb_proxy, &lldb_dir, &build_bundle.bundle_dir, run_args, true, None,
+                   TestReportParser::new(&self.id, &runnable.id))?;
         Ok(build_bundle)
     }
 
+    fn dry_run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<String>> {
+        let mut lines = vec![];
+        let artifacts = build.artifacts(&self.id);
+        for runnable in &build.runnables {
+            let (build_bundle, bundle_lines) = describe_remote_app(project, build, &artifacts, runnable, Some("Dinghy.app"))?;
+            lines.extend(bundle_lines);
+            lines.push(format!("write Info.plist and sign {}", build_bundle.bundle_dir.display()));
+            lines.push(format!("install {} on {} (changes since {})",
+                               build_bundle.bundle_dir.display(), self.id, artifacts.deltas_dir().display()));
+            lines.push(format!("launch Dinghy through debugserver with args {:?} and envs {:?}", run_args.args, run_args.envs));
+        }
+        Ok(lines)
+    }
+
+    fn failure(&self) -> Option<&str> {
+        self.failure.as_ref().map(|it| it.as_str())
+    }
+
+    fn forward_port(&self, local: u16, remote: u16) -> Result<PortForward> {
+        self.ensure_available()?;
+        let device = self.device;
+        forward::relay_listener(local, remote, move || device.connect_to_port(remote))
+    }
+
+    /// Lockdown tells the battery state, but not the thermal state.
+    fn health(&self) -> Result<DeviceHealth> {
+        self.ensure_available()?;
+        let _session = self.device.connect()?;
+        let battery_level = match self.device.read_domain_value(Some("com.apple.mobile.battery"), "BatteryCurrentCapacity")? {
+            Some(Value::I64(level)) => Some(level as u8),
+            _ => None,
+        };
+        let charging = match self.device.read_domain_value(Some("com.apple.mobile.battery"), "BatteryIsCharging")? {
+            Some(Value::Boolean(charging)) => Some(charging),
+            _ => None,
+        };
+        Ok(DeviceHealth { battery_level, charging, thermal: None })
+    }
+
     fn id(&self) -> &str {
         &self.id
     }
 
+    /// Through debugserver, attaching to each process to kill it.
+    fn kill(&self, target: &KillTarget) -> Result<Vec<DeviceProcess>> {
+        let killed = self.processes()?.into_iter().filter(|it| target.matches(it)).collect::<Vec<_>>();
+        for process in &killed {
+            debug!("Killing {} ({}) on {}", process.name, process.pid, self.id);
+            let _session = self.device.connect()?;
+            let stream = start_remote_debug_server(self.device)?;
+            debugserver::kill(stream, process.pid)
+                .chain_err(|| format!("Couldn't kill {} ({}) on {}", process.name, process.pid, self.id))?;
+        }
+        Ok(killed)
+    }
+
+    fn list_app_files(&self, app: &str, path: &str) -> Result<Vec<String>> {
+        self.ensure_available()?;
+        let afc = app_container(self.device, app)?;
+        let path = afc_path(path);
+        Ok(afc.read_dir(&path)?.into_iter()
+            .map(|entry| {
+                let is_dir = afc.read_dir(&format!("{}/{}", path.trim_right_matches('/'), entry)).is_ok();
+                if is_dir { format!("{}/", entry) } else { entry }
+            })
+            .collect())
+    }
+
     fn name(&self) -> &str {
         &self.name
     }
 
-    fn run_app(&self, project: &Project, build: &Build, args: &[&str], _envs: &[&str]) -> Result<Vec<BuildBundle>> {
+    /// The condition inducer of the developer services keeps a condition for
+    /// as long as the connection asking for it is open, `pymobiledevice3`
+    /// holding it for the run.
+    fn network_condition(&self, profile: &NetworkProfile) -> Result<NetworkCondition> {
+        self.ensure_available()?;
+        let condition = profile.ios_condition.as_ref()
+            .ok_or_else(|| format!("Network profile {} has no ios_condition for {}", profile.name, self.id))?;
+        let pymobiledevice3 = ::which::which("pymobiledevice3")
+            .map_err(|_| "Network profiles on iOS devices need pymobiledevice3 (pip3 install pymobiledevice3)")?;
+        let mut child = process::Command::new(pymobiledevice3)
+            .args(&["developer", "dvt", "condition", "set", condition, "--udid", &self.id])
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::null())
+            .spawn()
+            .chain_err(|| format!("Couldn't start pymobiledevice3 for {}", self.id))?;
+        // It exits right away when the condition couldn't be set.
+        thread::sleep(Duration::from_secs(3));
+        if let Some(status) = child.try_wait()? {
+            bail!("Couldn't set network condition {} on {} ({})", condition, self.id, status)
+        }
+        let applied = format!("{} as {}", profile.description(), condition);
+        info!("Applied {} on {}", applied, self.id);
+        let child = sync::Mutex::new(child);
+        Ok(NetworkCondition::new(applied, move || {
+            if let Ok(mut child) = child.lock() {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }))
+    }
+
+    /// The processes debugserver sees, the apps and daemons of all users.
+    fn processes(&self) -> Result<Vec<DeviceProcess>> {
+        self.ensure_available()?;
+        let _session = self.device.connect()?;
+        let stream = start_remote_debug_server(self.device)?;
+        debugserver::processes(stream).chain_err(|| format!("Couldn't list the processes of {}", self.id))
+    }
+
+    fn profile_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<(BuildBundle, PathBuf)> {
+        let runnable = build.runnables.iter().next().ok_or("No executable compiled")?;
+        let build_bundle = self.install_app(project, build, runnable, run_args)?;
+        let trace = profile_with_instruments(&self.id, build, runnable, &build_bundle, run_args)?;
+        Ok((build_bundle, trace))
+    }
+
+    fn pull_app_files(&self, app: &str, path: &str, to_dir: &Path) -> Result<()> {
+        self.ensure_available()?;
+        let afc = app_container(self.device, app)?;
+        let name = Path::new(path).file_name().ok_or(format!("Invalid path {}", path))?;
+        fs::create_dir_all(to_dir)?;
+        pull_afc(&afc, &afc_path(path), &to_dir.join(name))
+    }
+
+    fn push_app_file(&self, app: &str, from: &Path, path: &str) -> Result<()> {
+        self.ensure_available()?;
+        let afc = app_container(self.device, app)?;
+        let path = afc_path(path);
+        if let Some(parent) = Path::new(&path).parent() {
+            afc.make_dir(path_to_str(parent)?)?;
+        }
+        let mut content = vec![];
+        fs::File::open(from)?.read_to_end(&mut content)?;
+        afc.write_file(&path, &content).chain_err(|| format!("Couldn't write {} in the container of {}", path, app))
+    }
+
+    /// Returns once the restart is requested, the device being back a
+    /// minute or so later.
+    fn reboot(&self) -> Result<()> {
+        self.ensure_available()?;
+        info!("Rebooting {}", self.id);
+        restart(self.device).chain_err(|| format!("Couldn't reboot {}", self.id))
+    }
+
+    fn rustc_triples(&self) -> Vec<String> {
+        if self.failure.is_some() { vec![] } else { vec![self.rustc_triple.clone()] }
+    }
+
+    fn run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<RunResult>> {
         let mut build_bundles = vec![];
+        let run_args = &with_coverage(run_args)?;
+        let coverage_dir = build.artifacts(&self.id).coverage_dir();
+        if run_args.coverage {
+            let _ = fs::remove_dir_all(&coverage_dir);
+            fs::create_dir_all(&coverage_dir)?;
+        }
+        let _awake = self.prepare_launch(run_args)?;
         for runnable in &build.runnables {
-            let build_bundle = self.install_app(&project, &build, &runnable)?;
-            let lldb_proxy = self.start_remote_lldb()?;
-            run_remote(self.ptr, &lldb_proxy, &build_bundle.bundle_dir, args, false)?;
-            build_bundles.push(build_bundle)
+            let build_bundle = self.install_app(&project, &build, &runnable, run_args)?;
+            let bundle_id = bundle_identifier(&build_bundle.bundle_dir)?;
+            if run_args.coverage {
+                // The app stays installed between runs, and so does its data.
+                if let Err(e) = clear_afc_dir(&app_container(self.device, &bundle_id)?, &afc_path(COVERAGE_DIR)) {
+                    debug!("Couldn't clear the coverage data of {}: {}", runnable.id, e);
+                }
+            }
+            let parser = TestReportParser::new(&self.id, &runnable.id);
+            let ran = if ::which::which("lldb").is_ok() {
+                let lldb_proxy = self.start_remote_lldb()?;
+                let lldb_dir = build.artifacts(&self.id).scratch_dir("lldb")?;
+                run_remote(self.device, &lldb_proxy, &lldb_dir, &build_bundle.bundle_dir, run_args, false, run_args.timeout,
+                           parser)
+            } else {
+                info!("lldb not found, launching {} through debugserver directly", runnable.id);
+                if !run_args.lldb_commands.is_empty() {
+                    warn!("lldb not found, ignoring the lldb commands");
+                }
+                spawn_remote(self.device, &build_bundle.bundle_dir, &run_args.args(), &run_args.envs(), run_args.timeout, parser)
+            };
+            let report = match ran {
+                Ok(report) => report,
+                Err(e) => {
+                    // As on Android, don't leave a hung app behind: the
+                    // debugger going away doesn't always take it down.
+                    if let ErrorKind::RunTimeout(..) = *e.kind() {
+                        if let Err(kill) = self.kill(&KillTarget::Name("Dinghy".to_string())) {
+                            warn!("Couldn't kill {} on {} after its timeout: {}", runnable.id, self.id, kill);
+                        }
+                    }
+                    return Err(e)
+                }
+            };
+            if run_args.coverage {
+                let pulled = app_container(self.device, &bundle_id)
+                    .and_then(|afc| pull_afc(&afc, &afc_path(COVERAGE_DIR), &coverage_dir));
+                if let Err(e) = pulled {
+                    warn!("Couldn't pull the coverage data of {}: {}", runnable.id, e);
+                }
+            }
+            if !report.exit_success {
+                let crashes_dir = artifacts::crashes_dir(&self.id)?;
+                save_crash_reports(runnable, &crashes_dir, collect_crash_reports(self.device, &crashes_dir));
+            }
+            build_bundles.push(RunResult::new(build_bundle, report))
         }
         Ok(build_bundles)
     }
 
+    fn screenshot(&self, path: &Path) -> Result<()> {
+        self.ensure_available()?;
+        let image = take_screenshot(self.device).chain_err(|| format!("Couldn't take a screenshot of {}", self.id))?;
+        fs::File::create(path)?.write_all(&image)?;
+        Ok(())
+    }
+
     fn start_remote_lldb(&self) -> Result<String> {
-        let _ = ensure_session(self.ptr);
-        let fd = start_remote_debug_server(self.ptr)?;
+        self.ensure_available()?;
+        let _ = self.device.connect();
         debug!("start local lldb proxy");
-        let proxy = start_lldb_proxy(fd)?;
+        let proxy = start_lldb_proxy(self.device)?;
         debug!("start lldb");
         Ok(format!("localhost:{}", proxy))
     }
+
+    fn stream_console(&self, options: &ConsoleOptions) -> Result<()> {
+        self.ensure_available()?;
+        info!("Streaming the console of {}", self.id);
+        syslog::stream(self.device, options).chain_err(|| format!("Couldn't stream the console of {}", self.id))
+    }
+
+    fn uninstall(&self, location: &str) -> Result<()> {
+        self.ensure_available()?;
+        let _session = self.device.connect()?;
+        self.device.uninstall(location).chain_err(|| format!("Couldn't uninstall {} from {}", location, self.id))
+    }
 }
 
 
 impl IosSimDevice {
+    /// The data container of the app `app` on the host.
+    fn app_data_dir(&self, app: &str) -> Result<PathBuf> {
+        let output = process::Command::new("xcrun")
+            .args(&["simctl", "get_app_container", &self.id, app, "data"])
+            .output()?;
+        if !output.status.success() {
+            bail!("Couldn't find the data of {} on {} (is it installed?)", app, self.id)
+        }
+        Ok(PathBuf::from(String::from_utf8(output.stdout)?.trim()))
+    }
+
     fn install_app(&self, project: &Project, build: &Build, runnable: &Runnable) -> Result<BuildBundle> {
-        let build_bundle = IosSimDevice::make_app(project, build, runnable)?;
+        let _span = logging::span(format!("install {}", runnable.id));
+        let _timer = timings::start(Phase::Transfer);
+        let build_bundle = self.make_app(project, build, runnable)?;
+        let bundle_id = bundle_identifier(&build_bundle.bundle_dir)?;
+        installs::record(&self.id, &bundle_id);
         let _ = process::Command::new("xcrun")
-            .args(&["simctl", "uninstall", &self.id, "Dinghy"])
+            .args(&["simctl", "uninstall", &self.id, &bundle_id])
             .status()?;
         let stat = process::Command::new("xcrun")
             .args(&[
@@ -172,14 +625,20 @@ impl IosSimDevice {
             ])
             .status()?;
         if stat.success() {
+            timings::transferred(bundle_size(&build_bundle)?);
             Ok(build_bundle)
         } else {
             Err(format!("Failed to install {} for {}", runnable.exe.display(), self.id))?
         }
     }
 
-    fn make_app(project: &Project, build: &Build, runnable: &Runnable) -> Result<BuildBundle> {
-        make_ios_app(project, build, runnable, "Dinghy")
+    fn make_app(&self, project: &Project, build: &Build, runnable: &Runnable) -> Result<BuildBundle> {
+        let _timer = timings::start(Phase::Bundle);
+        let app_id = match project.for_runnable(runnable)?.conf.bundle_identifier {
+            Some(ref template) => app_identifier(template, "Dinghy", &runnable.id),
+            None => "Dinghy".to_string(),
+        };
+        make_ios_app(project, build, &build.artifacts(&self.id), runnable, &app_id, &[])
     }
 }
 
@@ -188,50 +647,255 @@ impl Device for IosSimDevice {
         unimplemented!()
     }
 
-    fn debug_app(&self, project: &Project, build: &Build, args: &[&str], _envs: &[&str]) -> Result<BuildBundle> {
+    fn debug_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<BuildBundle> {
         let runnable = build.runnables.iter().next().ok_or("No executable compiled")?;
         let build_bundle = self.install_app(project, build, runnable)?;
         let install_path = String::from_utf8(
             process::Command::new("xcrun")
-                .args(&["simctl", "get_app_container", &self.id, "Dinghy"])
+                .args(&["simctl", "get_app_container", &self.id, &bundle_identifier(&build_bundle.bundle_dir)?])
                 .output()?
                 .stdout,
         )?;
-        launch_lldb_simulator(&self, &install_path, args, true)?;
+        let lldb_dir = build.artifacts(&self.id).scratch_dir("lldb")?;
+        launch_lldb_simulator(&self, &lldb_dir, &install_path, run_args, true, None,
+                              TestReportParser::new(&self.id, &runnable.id))?;
         Ok(build_bundle)
     }
 
+    fn dry_run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<String>> {
+        let mut lines = vec![];
+        let artifacts = build.artifacts(&self.id);
+        for runnable in &build.runnables {
+            let (build_bundle, bundle_lines) = describe_remote_app(project, build, &artifacts, runnable, Some("Dinghy.app"))?;
+            lines.extend(bundle_lines);
+            lines.push(format!("write Info.plist of {}", build_bundle.bundle_dir.display()));
+            lines.push(format!("xcrun simctl install {} {}", self.id, build_bundle.bundle_dir.display()));
+            lines.push(format!("launch Dinghy through lldb with args {:?} and envs {:?}", run_args.args, run_args.envs));
+        }
+        Ok(lines)
+    }
+
+    fn forward_port(&self, local: u16, remote: u16) -> Result<PortForward> {
+        // The simulator shares the network of the host.
+        forward::local_forward(local, remote)
+    }
+
     fn id(&self) -> &str {
         &self.id
     }
 
+    /// The processes of a simulator are processes of the host.
+    fn kill(&self, target: &KillTarget) -> Result<Vec<DeviceProcess>> {
+        let killed = self.processes()?.into_iter().filter(|it| target.matches(it)).collect::<Vec<_>>();
+        for process in &killed {
+            debug!("Killing {} ({}) on {}", process.name, process.pid, self.id);
+            if !process::Command::new("kill").arg("-9").arg(process.pid.to_string()).status()?.success() {
+                bail!("Couldn't kill {} ({}) on {}", process.name, process.pid, self.id)
+            }
+        }
+        Ok(killed)
+    }
+
+    fn list_app_files(&self, app: &str, path: &str) -> Result<Vec<String>> {
+        let dir = self.app_data_dir(app)?.join(path.trim_left_matches('/'));
+        let mut entries = fs::read_dir(&dir)?
+            .map(|entry| {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                Ok(if entry.file_type()?.is_dir() { format!("{}/", name) } else { name })
+            })
+            .collect::<Result<Vec<String>>>()?;
+        entries.sort();
+        Ok(entries)
+    }
+
     fn name(&self) -> &str {
         &self.name
     }
 
-    fn run_app(&self, project: &Project, build: &Build, args: &[&str], _envs: &[&str]) -> Result<Vec<BuildBundle>> {
+    /// The jobs of the launchd of the simulator which are running.
+    fn processes(&self) -> Result<Vec<DeviceProcess>> {
+        let output = process::Command::new("xcrun")
+            .args(&["simctl", "spawn", &self.id, "launchctl", "list"])
+            .output()?;
+        if !output.status.success() {
+            bail!("Couldn't list the processes of {} (is it booted?)", self.id)
+        }
+        Ok(launchctl_processes(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn profile_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<(BuildBundle, PathBuf)> {
+        let runnable = build.runnables.iter().next().ok_or("No executable compiled")?;
+        let build_bundle = self.install_app(project, build, runnable)?;
+        let trace = profile_with_instruments(&self.id, build, runnable, &build_bundle, run_args)?;
+        Ok((build_bundle, trace))
+    }
+
+    fn pull_app_files(&self, app: &str, path: &str, to_dir: &Path) -> Result<()> {
+        let from = self.app_data_dir(app)?.join(path.trim_left_matches('/'));
+        fs::create_dir_all(to_dir)?;
+        if !process::Command::new("cp").arg("-R").arg(&from).arg(to_dir).status()?.success() {
+            bail!("Couldn't copy {}", from.display())
+        }
+        Ok(())
+    }
+
+    fn push_app_file(&self, app: &str, from: &Path, path: &str) -> Result<()> {
+        let to = self.app_data_dir(app)?.join(path.trim_left_matches('/'));
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(from, &to).chain_err(|| format!("Couldn't copy {} to {}", from.display(), to.display()))?;
+        Ok(())
+    }
+
+    fn reboot(&self) -> Result<()> {
+        info!("Rebooting {}", self.id);
+        let _ = process::Command::new("xcrun").args(&["simctl", "shutdown", &self.id]).status()?;
+        if !process::Command::new("xcrun").args(&["simctl", "boot", &self.id]).status()?.success() {
+            bail!("Couldn't boot {} again", self.id)
+        }
+        Ok(())
+    }
+
+    /// `simctl io` finishes the video when interrupted.
+    fn record_screen(&self, path: &Path) -> Result<ScreenRecording> {
+        let mut child = process::Command::new("xcrun")
+            .args(&["simctl", "io", &self.id, "recordVideo", "--force"])
+            .arg(path)
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .spawn()?;
+        thread::sleep(Duration::from_secs(1));
+        if let Some(status) = child.try_wait()? {
+            bail!("Couldn't record the screen of {} ({})", self.id, status)
+        }
+        info!("Recording the screen of {}", self.id);
+        let child = sync::Mutex::new(child);
+        Ok(ScreenRecording::new(path.to_path_buf(), move || {
+            if let Ok(mut child) = child.lock() {
+                unsafe {
+                    kill(child.id() as pid_t, SIGINT);
+                }
+                if !child.wait()?.success() {
+                    bail!("simctl failed to save the screen recording")
+                }
+            }
+            Ok(())
+        }))
+    }
+
+    fn reverse_port(&self, local: u16, remote: u16) -> Result<(PortForward, String)> {
+        // The simulator shares the network of the host.
+        Ok((forward::local_forward(remote, local)?, format!("127.0.0.1:{}", remote)))
+    }
+
+    fn rustc_triples(&self) -> Vec<String> {
+        let arch = if cfg!(target_arch = "aarch64") { "aarch64" } else { "x86_64" };
+        vec![Triple::ios_simulator(arch).to_string()]
+    }
+
+    fn run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<RunResult>> {
         let mut build_bundles = vec![];
+        let run_args = &with_coverage(run_args)?;
+        let coverage_dir = build.artifacts(&self.id).coverage_dir();
+        if run_args.coverage {
+            let _ = fs::remove_dir_all(&coverage_dir);
+            fs::create_dir_all(&coverage_dir)?;
+        }
         for runnable in &build.runnables {
             let build_bundle = self.install_app(&project, &build, &runnable)?;
             let install_path = String::from_utf8(
                 process::Command::new("xcrun")
-                    .args(&["simctl", "get_app_container", &self.id, "Dinghy"])
+                    .args(&["simctl", "get_app_container", &self.id, &bundle_identifier(&build_bundle.bundle_dir)?])
                     .output()?
                     .stdout,
             )?;
-            launch_lldb_simulator(&self, &install_path, args, false)?;
-            build_bundles.push(build_bundle);
+            let started = SystemTime::now();
+            let lldb_dir = build.artifacts(&self.id).scratch_dir("lldb")?;
+            let report = launch_lldb_simulator(&self, &lldb_dir, &install_path, run_args, false, run_args.timeout,
+                                               TestReportParser::new(&self.id, &runnable.id))?;
+            if run_args.coverage {
+                // The app is installed afresh for each run, with empty data.
+                let pulled = self.app_data_dir(&bundle_identifier(&build_bundle.bundle_dir)?)
+                    .and_then(|data_dir| copy_files(&data_dir.join(COVERAGE_DIR), &coverage_dir));
+                if let Err(e) = pulled {
+                    warn!("Couldn't copy the coverage data of {}: {}", runnable.id, e);
+                }
+            }
+            if !report.exit_success {
+                let crashes_dir = artifacts::crashes_dir(&self.id)?;
+                save_crash_reports(runnable, &crashes_dir, collect_simulator_crash_reports(started, &crashes_dir));
+            }
+            build_bundles.push(RunResult::new(build_bundle, report));
         }
         Ok(build_bundles)
     }
 
+    fn run_shell(&self, command: &str) -> Result<()> {
+        debug!("Running `{}' on {}", command, self.id);
+        let status = process::Command::new("xcrun")
+            .args(&["simctl", "spawn", &self.id, "/bin/sh", "-c", command])
+            .status()?;
+        if !status.success() {
+            bail!("`{}' failed on {}", command, self.id)
+        }
+        Ok(())
+    }
+
+    fn screenshot(&self, path: &Path) -> Result<()> {
+        let status = process::Command::new("xcrun")
+            .args(&["simctl", "io", &self.id, "screenshot"])
+            .arg(path)
+            .stdout(process::Stdio::null())
+            .status()?;
+        if !status.success() {
+            bail!("Couldn't take a screenshot of {}", self.id)
+        }
+        Ok(())
+    }
+
     fn start_remote_lldb(&self) -> Result<String> {
         unimplemented!()
     }
+
+    fn stream_console(&self, options: &ConsoleOptions) -> Result<()> {
+        let mut command = process::Command::new("xcrun");
+        command.args(&["simctl", "spawn", &self.id, "log", "stream", "--style", "ndjson"]);
+        if let Some(ref process) = options.process {
+            command.arg("--predicate").arg(format!("process == \"{}\"", process));
+        }
+        info!("Streaming the console of {}", self.id);
+        debug!("Running {:?}", command);
+        let mut child = command.stdout(process::Stdio::piped()).spawn()?;
+        let stdout = child.stdout.take().ok_or("Couldn't read the console of the simulator")?;
+        for line in BufReader::new(stdout).lines() {
+            // The first line says what is filtered, the others are messages.
+            if let Some(message) = ::json::parse(&line?).ok().and_then(|it| os_log_message(&it)) {
+                options.print(&message)?;
+            }
+        }
+        if !child.wait()?.success() {
+            bail!("Couldn't stream the console of {}", self.id)
+        }
+        Ok(())
+    }
+
+    fn uninstall(&self, location: &str) -> Result<()> {
+        if !process::Command::new("xcrun").args(&["simctl", "uninstall", &self.id, location]).status()?.success() {
+            bail!("Couldn't uninstall {} from {}", location, self.id)
+        }
+        Ok(())
+    }
 }
 
 impl Display for IosDevice {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        if let Some(ref failure) = self.failure {
+            return Ok(fmt.write_str(format!("IosDevice {{ \"id\": \"{}\", \"failure\": {:?} }}",
+                                            self.id,
+                                            failure).as_str())?);
+        }
         Ok(fmt.write_str(format!("IosDevice {{ \"id\": \"{}\", \"name\": {}, \"arch_cpu\": {} }}",
                                  self.id,
                                  self.name,
@@ -239,6 +903,46 @@ impl Display for IosDevice {
     }
 }
 
+/// The running jobs of `launchctl list`: `PID Status Label`, the pid being
+/// `-` for the jobs not running. Apps are named after their bundle
+/// identifier, without the `UIKitApplication:` prefix and `[...]` suffixes.
+fn launchctl_processes(output: &str) -> Vec<DeviceProcess> {
+    output.lines()
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let label = fields.get(2)?;
+            let label = label.splitn(2, ':').last().unwrap_or(label);
+            Some(DeviceProcess {
+                pid: fields[0].parse().ok()?,
+                user: None,
+                name: label.split('[').next().unwrap_or(label).to_string(),
+            })
+        })
+        .collect()
+}
+
+/// A message of `log stream --style ndjson`, shown the way `--style syslog`
+/// does.
+fn os_log_message(json: &::json::JsonValue) -> Option<ConsoleMessage> {
+    let message = json["eventMessage"].as_str()?;
+    let time = json["timestamp"].as_str().unwrap_or("");
+    let process = json["processImagePath"].as_str()
+        .and_then(|it| Path::new(it).file_name())
+        .map(|it| it.to_string_lossy().to_string());
+    let pid = json["processID"].as_u32();
+    let level = json["messageType"].as_str().unwrap_or("Default");
+    Some(ConsoleMessage {
+        line: format!("{} {}[{}] <{}>: {}", time, process.as_ref().map(|it| it.as_str()).unwrap_or("?"),
+                      pid.unwrap_or(0), level, message),
+        time: time.to_string(),
+        process,
+        pid,
+        priority: Priority::from_apple(level).unwrap_or(Priority::Notice),
+        tag: json["subsystem"].as_str().and_then(|it| if it.is_empty() { None } else { Some(it.to_string()) }),
+        message: message.to_string(),
+    })
+}
+
 impl Display for IosSimDevice {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
         Ok(fmt.write_str(format!("IosSimDevice {{ \"id\": \"{}\", \"name\": {}, \"os\": {} }}",
@@ -248,26 +952,94 @@ impl Display for IosSimDevice {
     }
 }
 
-impl DeviceCompatibility for IosDevice {
-    fn is_compatible_with_ios_platform(&self, platform: &IosPlatform) -> bool {
-        if platform.sim { return false; }
+/// The precise architecture of a device, from its `CPUArchitecture`, or
+/// failing that its `ProductType` (e.g. `iPhone5,2`, `Watch4,1`).
+fn cpu_arch(cpu_architecture: Option<&str>, product_type: Option<&str>) -> &'static str {
+    match cpu_architecture {
+        Some("arm64") => return "aarch64",
+        Some("arm64e") => return "arm64e",
+        Some("arm64_32") => return "arm64_32",
+        Some("armv7s") => return "armv7s",
+        Some("armv7k") => return "armv7k",
+        _ => (),
+    }
+    let product_type = product_type.unwrap_or("");
+    let family = product_type.trim_right_matches(|c: char| c.is_digit(10) || c == ',');
+    let mut version = product_type[family.len()..].split(',').map(|it| it.parse::<u32>().unwrap_or(0));
+    let (major, minor) = (version.next().unwrap_or(0), version.next().unwrap_or(0));
+    match family {
+        "Watch" if major >= 4 => "arm64_32",
+        "Watch" => "armv7k",
+        "iPhone" if major >= 11 => "arm64e",
+        "iPhone" if major >= 6 => "aarch64",
+        "iPhone" if major == 5 => "armv7s",
+        "iPad" if major >= 8 => "arm64e",
+        "iPad" if major >= 4 => "aarch64",
+        "iPad" if major == 3 && minor >= 4 => "armv7s",
+        "iPod" if major >= 9 => "arm64e",
+        "iPod" if major >= 7 => "aarch64",
+        _ => "armv7",
+    }
+}
 
-        if platform.toolchain.rustc_triple == self.rustc_triple.as_str() {
-            return true;
-        }
-        if platform.toolchain.rustc_triple == "armv7-apple-ios" && (self.arch_cpu == "armv7s" || self.arch_cpu == "aarch64") {
-            return true;
-        }
-        if platform.toolchain.rustc_triple == "armv7s-apple-ios" && self.arch_cpu == "aarch64" {
-            return true;
+/// The architecture of a Mach-O executable (thin or universal) from its
+/// `header`, if it only has 32-bit ARM slices.
+fn only_32_bit_arch(header: &[u8]) -> Option<&'static str> {
+    fn u32_at(bytes: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+        let b = bytes.get(offset..offset + 4)?;
+        let (b0, b1, b2, b3) = (b[0] as u32, b[1] as u32, b[2] as u32, b[3] as u32);
+        Some(if big_endian { b0 << 24 | b1 << 16 | b2 << 8 | b3 } else { b3 << 24 | b2 << 16 | b1 << 8 | b0 })
+    }
+    // (cputype, cpusubtype) of each slice.
+    let slices = match u32_at(header, 0, true)? {
+        0xcafebabe => {
+            let count = u32_at(header, 4, true)? as usize;
+            (0..count)
+                .map(|i| Some((u32_at(header, 8 + 20 * i, true)?, u32_at(header, 12 + 20 * i, true)?)))
+                .collect::<Option<Vec<_>>>()?
         }
-        return false;
+        0xcefaedfe => vec![(u32_at(header, 4, false)?, u32_at(header, 8, false)?)],
+        _ => return None,
+    };
+    // CPU_TYPE_ARM, the 64-bit types having the CPU_ARCH_ABI64 bit set.
+    if slices.is_empty() || slices.iter().any(|&(cputype, _)| cputype != 12) {
+        return None;
+    }
+    Some(if slices.iter().any(|&(_, subtype)| subtype == 11) { "armv7s" } else { "armv7" })
+}
+
+/// The architectures whose iOS binaries run on a device of architecture `cpu`.
+fn compatible_archs(cpu: &str) -> &'static [&'static str] {
+    match cpu {
+        "arm64e" => &["arm64e", "aarch64", "armv7s", "armv7"],
+        "aarch64" => &["aarch64", "armv7s", "armv7"],
+        "armv7s" => &["armv7s", "armv7"],
+        "armv7" => &["armv7"],
+        _ => &[],
+    }
+}
+
+impl DeviceCompatibility for IosDevice {
+    fn is_compatible_with_ios_platform(&self, platform: &IosPlatform) -> bool {
+        if platform.sim || self.failure.is_some() { return false; }
+
+        // Universal binaries run on the devices supporting any of their slices.
+        let archs = compatible_archs(self.arch_cpu);
+        platform.rustc_triples().into_iter().any(|rustc_triple| {
+            rustc_triple == self.rustc_triple.as_str()
+                || Triple::parse(rustc_triple).map_or(false, |it| {
+                    it.is_ios() && it.env.is_none() && archs.contains(&it.arch.as_str())
+                })
+        })
     }
 }
 
 impl DeviceCompatibility for IosSimDevice {
     fn is_compatible_with_ios_platform(&self, platform: &IosPlatform) -> bool {
-        platform.sim && platform.toolchain.rustc_triple == "x86_64-apple-ios"
+        // Intel simulators run everywhere, through Rosetta on Apple silicon.
+        platform.sim && Triple::parse(&platform.toolchain.rustc_triple).map_or(false, |it| {
+            it.is_ios() && (it.arch == "x86_64" || (cfg!(target_arch = "aarch64") && it.arch == "aarch64"))
+        })
     }
 }
 
@@ -276,151 +1048,58 @@ pub struct IosManager {
 }
 
 impl IosManager {
-    pub fn new() -> Result<Option<IosManager>> {
-        let devices = sync::Arc::new(sync::Mutex::new(vec![]));
-
-        let devices_to_take_away = Box::new(devices.clone());
-        thread::spawn(move || {
-            let notify: *const am_device_notification = ptr::null();
-            unsafe {
-                AMDeviceNotificationSubscribe(
-                    device_callback,
-                    0,
-                    0,
-                    Box::into_raw(devices_to_take_away) as *mut c_void,
-                    &mut notify.into(),
-                );
+    pub fn new(conf: sync::Arc<Configuration>) -> Result<Option<IosManager>> {
+        let devices: sync::Arc<sync::Mutex<Vec<IosDevice>>> = sync::Arc::new(sync::Mutex::new(vec![]));
+
+        let devices_to_take_away = devices.clone();
+        // Runs on the notification thread, behind the FFI boundary: must never panic.
+        mobiledevice::subscribe(move |device| {
+            let device = IosDevice::from(device, &conf).unwrap_or_else(|e| {
+                error!("Failed to probe iOS device: {}", e);
+                IosDevice::failed(device, &e)
+            });
+            match devices_to_take_away.lock() {
+                Ok(mut devices) => {
+                    devices.retain(|it| it.id != device.id);
+                    devices.push(device)
+                }
+                Err(_) => error!("Poisoned iOS device list, ignoring device {}", device.id),
             }
-            ::core_foundation::runloop::CFRunLoop::run_current();
         });
 
-        extern "C" fn device_callback(
-            info: *mut am_device_notification_callback_info,
-            devices: *mut c_void,
-        ) {
-            let device = unsafe { (*info).dev };
-            let devices: &sync::Arc<sync::Mutex<Vec<IosDevice>>> =
-                unsafe { mem::transmute(devices) };
-            let _ = devices
-                .lock()
-                .map(|mut devices| devices.push(IosDevice::from(device).unwrap()));
-        }
-
         Ok(Some(IosManager { devices: devices }))
     }
 }
 
 impl PlatformManager for IosManager {
     fn devices(&self) -> Result<Vec<Box<Device>>> {
-        let sims_list = ::std::process::Command::new("xcrun")
-            .args(&["simctl", "list", "--json", "devices"])
-            .output()?;
-        if !sims_list.status.success() {
-            info!(
-                "Failed while looking for ios simulators. It this is not expected, you need to make sure `xcrun simctl list --json` works."
-            );
-            return Ok(vec![]);
-        }
-        let sims_list = String::from_utf8(sims_list.stdout)?;
-        let sims_list = ::json::parse(&sims_list)?;
-        let mut sims: Vec<Box<Device>> = vec![];
-        for (ref k, ref v) in sims_list["devices"].entries() {
-            for ref sim in v.members() {
-                if sim["state"] == "Booted" {
-                    sims.push(Box::new(IosSimDevice {
-                        name: sim["name"]
-                            .as_str()
-                            .ok_or("unexpected simulator list format (missing name)")?
-                            .to_string(),
-                        id: sim["udid"]
-                            .as_str()
-                            .ok_or("unexpected simulator list format (missing udid)")?
-                            .to_string(),
-                        os: k.split(" ").last().unwrap().to_string(),
-                    }))
-                }
+        let sims = match simctl::list() {
+            Ok(sims) => sims,
+            Err(e) => {
+                info!("Failed while looking for ios simulators ({}). It this is not expected, you need to make sure `xcrun simctl list --json` works.", e);
+                vec![]
             }
-        }
+        };
+        let sims = sims.into_iter()
+            .filter(|it| it.booted)
+            .map(|sim| Box::new(IosSimDevice { id: sim.udid, name: sim.name, os: sim.os }) as Box<Device>);
         let devices = self.devices.lock().map_err(|_| "poisoned lock")?;
         Ok(devices
             .iter()
             .map(|d| Box::new(d.clone()) as Box<Device>)
-            .chain(sims.into_iter())
+            .chain(sims)
             .collect())
     }
 }
 
-#[derive(Clone, Debug)]
-enum Value {
-    String(String),
-    Data(Vec<u8>),
-    I64(i64),
-    Boolean(bool),
-}
-
-fn mk_result(rv: i32) -> Result<()> {
-    if rv as u32 == 0xe80000e2 {
-        Err(format!("error: Device is locked. ({:x})", rv))?
-    } else if rv as u32 == 0xe8008015 {
-        Err("error: 0xe8008015, A valid provisioning profile for this executable was not found.")?
-    } else if rv as u32 == 0xe8008016 {
-        Err("error: 0xe8008016, The executable was signed with invalid entitlements.")?
-    } else if rv as u32 == 0xe8008022 {
-        Err(
-            "error: 0xe8000022, kAMDInvalidServiceError. (This one is relatively hard to diagnose. Try erasing the Dinghy app from the phone, rebooting the device, the computer, check for ios and xcode updates.)",
-        )?
-    } else if rv != 0 {
-        Err(format!("error: {:x}", rv))?
-    } else {
-        Ok(())
-    }
-}
-
-fn rustify(raw: CFTypeRef) -> Result<Value> {
-    unsafe {
-        let cftype: CFType = TCFType::wrap_under_get_rule(mem::transmute(raw));
-        if cftype.type_of() == CFString::type_id() {
-            let value: CFString = TCFType::wrap_under_get_rule(mem::transmute(raw));
-            return Ok(Value::String(value.to_string()));
-        }
-
-        if cftype.type_of() == CFData::type_id() {
-            let value: CFData = TCFType::wrap_under_get_rule(mem::transmute(raw));
-            return Ok(Value::Data(value.bytes().to_vec()));
-        }
-        if cftype.type_of() == CFNumber::type_id() {
-            let value: CFNumber = TCFType::wrap_under_get_rule(mem::transmute(raw));
-            if let Some(i) = value.to_i64() {
-                return Ok(Value::I64(i));
-            }
-        }
-        if cftype.type_of() == CFBoolean::type_id() {
-            return Ok(Value::Boolean(raw == mem::transmute(kCFBooleanTrue)));
-        }
-        cftype.show();
-        Err("unknown value")?
-    }
-}
-
-fn device_read_value(dev: *const am_device, key: &str) -> Result<Option<Value>> {
-    unsafe {
-        let key = CFString::new(key);
-        let raw = AMDeviceCopyValue(dev, ptr::null(), key.as_concrete_TypeRef());
-        if raw.is_null() {
-            return Ok(None);
-        }
-        Ok(Some(rustify(raw)?))
-    }
-}
-
 fn xcode_dev_path() -> Result<PathBuf> {
     use std::process::Command;
     let command = Command::new("xcode-select").arg("-print-path").output()?;
     Ok(String::from_utf8(command.stdout)?.trim().into())
 }
 
-fn device_support_path(dev: *const am_device) -> Result<PathBuf> {
-    let os_version = device_read_value(dev, "ProductVersion")?.ok_or("Could not get OS version")?;
+fn device_support_path(dev: AmDevice) -> Result<PathBuf> {
+    let os_version = dev.read_value("ProductVersion")?.ok_or("Could not get OS version")?;
     if let Value::String(v) = os_version {
         platform_support_path("iPhoneOS.platform", &v)
     } else {
@@ -465,202 +1144,403 @@ fn platform_support_path(platform: &str, os_version: &str) -> Result<PathBuf> {
     ))?
 }
 
-fn mount_developper_image(dev: *const am_device) -> Result<()> {
-    use std::io::Read;
-    unsafe {
-        let _session = ensure_session(dev);
-        let ds_path = device_support_path(dev)?;
-        let image_path = ds_path.join("DeveloperDiskImage.dmg");
-        let sig_image_path = ds_path.join("DeveloperDiskImage.dmg.signature");
-        let mut sig: Vec<u8> = vec![];
-        fs::File::open(sig_image_path)?.read_to_end(&mut sig)?;
-        let sig = CFData::from_buffer(&sig);
-
-        let options = [
-            (
-                CFString::from_static_string("ImageType"),
-                CFString::from_static_string("Developper").as_CFType(),
-            ),
-            (
-                CFString::from_static_string("ImageSignature"),
-                sig.as_CFType(),
-            ),
-        ];
-        let options = CFDictionary::from_CFType_pairs(&options);
-        let r = AMDeviceMountImage(
-            dev,
-            CFString::new(image_path.to_str().unwrap()).as_concrete_TypeRef(),
-            options.as_concrete_TypeRef(),
-            ::std::mem::transmute(0usize),
-            0,
-        );
-        if r as u32 == 0xe8000076 {
-            // already mounted, that's fine.
-            return Ok(());
+fn mount_developper_image(dev: AmDevice) -> Result<()> {
+    let _session = dev.connect();
+    let ds_path = device_support_path(dev)?;
+    let image_path = ds_path.join("DeveloperDiskImage.dmg");
+    let sig_image_path = ds_path.join("DeveloperDiskImage.dmg.signature");
+    let mut sig: Vec<u8> = vec![];
+    fs::File::open(sig_image_path)?.read_to_end(&mut sig)?;
+    dev.mount_image(&image_path, &sig)
+}
+
+fn save_crash_reports(runnable: &Runnable, crashes_dir: &Path, collected: Result<Vec<PathBuf>>) {
+    match collected {
+        Ok(ref reports) if reports.is_empty() => debug!("No crash report found for {}", runnable.id),
+        Ok(reports) => {
+            for report in reports {
+                if let Err(e) = symbolicate::symbolicate_ios_report(&report, &runnable.exe) {
+                    warn!("Couldn't symbolicate {}: {}", report.display(), e);
+                }
+            }
+            info!("Crash reports of {} saved in {}", runnable.id, crashes_dir.display());
+        }
+        Err(e) => warn!("Couldn't collect crash reports of {}: {}", runnable.id, e),
+    }
+}
+
+/// Copy the crash reports of the Dinghy app from the device to `dest`, and
+/// return the new reports.
+fn collect_crash_reports(dev: AmDevice, dest: &Path) -> Result<Vec<PathBuf>> {
+    let _session = dev.connect()?;
+    {
+        // The mover flushes pending reports to the copy service directory and
+        // pings back when done.
+        let mut mover = dev.start_service("com.apple.crashreportmover")?;
+        mover.set_read_timeout(Some(Duration::from_secs(10)))?;
+        let mut ping = [0u8; 4];
+        mover.read_exact(&mut ping)?;
+    }
+    let afc = dev.start_afc_service("com.apple.crashreportcopymobile")?;
+    fs::create_dir_all(dest)?;
+    let mut copied = vec![];
+    for entry in afc.read_dir("/")? {
+        if !entry.starts_with("Dinghy") || dest.join(&entry).exists() {
+            continue;
+        }
+        let content = afc.read_file(&format!("/{}", entry))?;
+        fs::File::create(dest.join(&entry))?.write_all(&content)?;
+        copied.push(dest.join(&entry));
+    }
+    Ok(copied)
+}
+
+/// Copy the crash reports of the Dinghy app written by the simulator since
+/// `since` to `dest`, and return the copied reports.
+fn collect_simulator_crash_reports(since: SystemTime, dest: &Path) -> Result<Vec<PathBuf>> {
+    let reports_dir = ::std::env::home_dir().ok_or("Couldn't find home directory")?.join("Library/Logs/DiagnosticReports");
+    if !reports_dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut copied = vec![];
+    for entry in fs::read_dir(&reports_dir)? {
+        let entry = entry?;
+        let is_dinghy = entry.file_name().to_str().map(|it| it.starts_with("Dinghy")).unwrap_or(false);
+        let is_recent = entry.metadata()?.modified().map(|it| it >= since).unwrap_or(false);
+        if is_dinghy && is_recent {
+            fs::create_dir_all(dest)?;
+            fs::copy(entry.path(), dest.join(entry.file_name()))?;
+            copied.push(dest.join(entry.file_name()));
         }
-        mk_result(r)?;
-        Ok(())
     }
+    Ok(copied)
+}
+
+fn make_ios_app(project: &Project,
+                build: &Build,
+                artifacts: &ArtifactStore,
+                runnable: &Runnable,
+                app_id: &str,
+                signing_inputs: &[&str]) -> Result<BuildBundle> {
+    let build_bundle = make_remote_app_with_name(project, build, artifacts, runnable, Some("Dinghy.app"))?;
+    fs::copy(runnable.deployed_exe(), build_bundle.bundle_dir.join("Dinghy"))?;
+    let target = required_arch(&runnable.exe)?;
+    let target = target.as_str();
+    let project = project.for_runnable(runnable)?;
+    // Keep the hash of the app in its Info.plist, where it can be read back
+    // from the device to skip reinstalling an identical app. It covers the
+    // bundle files, the Info.plist entries and the signature.
+    let mut plist_entries = project.conf.bundle_info_plist.clone();
+    let hash = {
+        let mut inputs = vec![target, app_id];
+        for (key, value) in &plist_entries {
+            inputs.push(key);
+            inputs.push(value);
+        }
+        inputs.extend(signing_inputs);
+        bundle_hash(&build_bundle, &inputs)?
+    };
+    plist_entries.insert(BUNDLE_HASH_KEY.to_string(), hash);
+    xcode::add_plist_to_app(&build_bundle, target, app_id, &plist_entries)?;
+    Ok(build_bundle)
 }
 
-fn make_ios_app(project: &Project, build: &Build, runnable: &Runnable, app_id:&str) -> Result<BuildBundle> {
-    let build_bundle = make_remote_app_with_name(project, build, runnable, Some("Dinghy.app"))?;
-    fs::copy(&runnable.exe, build_bundle.bundle_dir.join("Dinghy"))?;
+/// Architecture an executable requires from devices. Universal binaries only
+/// require their oldest slice, so that the app installs on all the devices one
+/// of their slices can run on.
+fn required_arch(exe: &Path) -> Result<String> {
+    let archs = process::Command::new("xcrun")
+        .arg("lipo")
+        .arg("-archs")
+        .arg(exe)
+        .output()?;
+    let archs = String::from_utf8(archs.stdout)?;
+    let archs = archs.split_whitespace().collect::<Vec<_>>();
+    if archs.len() > 1 {
+        if let Some(arch) = ["armv7", "armv7s", "arm64"].iter().find(|it| archs.contains(it)) {
+            return Ok(arch.to_string());
+        }
+    }
     let magic = process::Command::new("file")
-        .arg(runnable.exe.to_str().ok_or("path conversion to string")?)
+        .arg(exe.to_str().ok_or("path conversion to string")?)
         .output()?;
     let magic = String::from_utf8(magic.stdout)?;
-    let target = magic.split(" ").last().ok_or("empty magic")?;
-    xcode::add_plist_to_app(&build_bundle, target, app_id)?;
-    Ok(build_bundle)
+    Ok(magic.split(" ").last().ok_or("empty magic")?.to_string())
 }
 
-struct Session(*const am_device);
-
-fn ensure_session(dev: *const am_device) -> Result<Session> {
-    unsafe {
-        mk_result(AMDeviceConnect(dev))?;
-        if AMDeviceIsPaired(dev) == 0 {
-            Err("lost pairing")?
-        };
-        mk_result(AMDeviceValidatePairing(dev))?;
-        mk_result(AMDeviceStartSession(dev))?;
-        Ok(Session(dev))
-        // debug!("ensure session 4 ({:x})", rv);
-        // if rv as u32 == 0xe800001d {
-        // Ok(Session(::std::ptr::null()))
-        // } else {
-        // mk_result(rv)?;
-        // Ok(Session(dev))
-        // }
-        //
-    }
-}
-
-impl Drop for Session {
-    fn drop(&mut self) {
-        unsafe {
-            if !self.0.is_null() {
-                if let Err(e) = mk_result(AMDeviceStopSession(self.0)) {
-                    debug!("Error closing session {:?}", e);
-                }
-                if let Err(e) = mk_result(AMDeviceDisconnect(self.0)) {
-                    error!("Error disconnecting {:?}", e);
-                }
+/// Install an app, incrementally if possible: `deltas_dir` keeps track of what
+/// was already sent to the device. When the whole app has to be sent, it is
+/// sent as an IPA packed in `ipa_dir`, if given.
+pub fn install_app<P: AsRef<Path>>(dev: AmDevice, app: P, deltas_dir: &Path, ipa_dir: Option<&Path>) -> Result<()> {
+    let _session = dev.connect()?;
+    artifacts::ensure_dir(deltas_dir)?;
+    if let Err(e) = dev.install_incremental(app.as_ref(), deltas_dir) {
+        debug!("Incremental install failed ({}), sending the whole app", e);
+        artifacts::reset_dir(deltas_dir)?;
+        if let Some(ipa_dir) = ipa_dir {
+            match package_ipa(app.as_ref(), ipa_dir).and_then(|ipa| dev.transfer(&ipa).and_then(|_| dev.install(&ipa))) {
+                Ok(()) => return Ok(()),
+                Err(e) => warn!("Couldn't install {} as an IPA ({}), sending it uncompressed", app.as_ref().display(), e),
             }
         }
+        dev.transfer(app.as_ref())?;
+        dev.install(app.as_ref())?;
     }
+    Ok(())
 }
 
-pub fn install_app<P: AsRef<Path>>(dev: *const am_device, app: P) -> Result<()> {
-    unsafe {
-        let _session = ensure_session(dev)?;
-        let path = app.as_ref().to_str().ok_or("failure to convert")?;
-        let url =
-            ::core_foundation::url::CFURL::from_file_system_path(CFString::new(path), 0, true);
-        let options = [
-            (
-                CFString::from_static_string("PackageType"),
-                CFString::from_static_string("Developper").as_CFType(),
-            ),
-        ];
-        let options = CFDictionary::from_CFType_pairs(&options);
-        mk_result(AMDeviceSecureTransferPath(
-            0,
-            dev,
-            url.as_concrete_TypeRef(),
-            options.as_concrete_TypeRef(),
-            ptr::null(),
-            ptr::null(),
-        ))?;
-        mk_result(AMDeviceSecureInstallApplication(
-            0,
-            dev,
-            url.as_concrete_TypeRef(),
-            options.as_concrete_TypeRef(),
-            ptr::null(),
-            ptr::null(),
-        ))?;
+/// Zip the app into an IPA (`Payload/<app>`) in `dir`.
+fn package_ipa(app: &Path, dir: &Path) -> Result<PathBuf> {
+    let name = app.file_name().ok_or(format!("Invalid app path {}", app.display()))?;
+    let payload = dir.join("Payload");
+    fs::create_dir_all(&payload)?;
+    ::std::os::unix::fs::symlink(app, payload.join(name))?;
+    let ipa = dir.join("app.ipa");
+    // zip follows the symlink, and keeps the code signature as is.
+    let status = process::Command::new("zip").arg("-qr").arg(&ipa).arg("Payload").current_dir(dir).status()?;
+    if !status.success() {
+        bail!("Couldn't zip {} into {}", app.display(), ipa.display())
     }
+    debug!("Packed {} into {} ({} bytes)", app.display(), ipa.display(), fs::metadata(&ipa)?.len());
+    Ok(ipa)
+}
+
+/// Send a message to a lockdown service: the XML plist of `content`,
+/// prefixed with its length.
+fn send_plist(stream: &mut TcpStream, content: &str) -> Result<()> {
+    let message = format!(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        r#"<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">"#,
+        r#"<plist version="1.0">{}</plist>"#), content);
+    let len = message.len() as u32;
+    stream.write_all(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8])?;
+    stream.write_all(message.as_bytes())?;
     Ok(())
 }
 
-fn start_remote_debug_server(dev: *const am_device) -> Result<c_int> {
-    unsafe {
-        debug!("mount developper image");
-        mount_developper_image(dev)?;
-        debug!("start debugserver on phone");
-        let _session = ensure_session(dev)?;
-        let mut fd: c_int = 0;
-        mk_result(AMDeviceStartService(
-            dev,
-            CFString::from_static_string("com.apple.debugserver").as_concrete_TypeRef(),
-            &mut fd,
-            ptr::null(),
-        ))?;
-        debug!("debug server running");
-        Ok(fd)
-    }
-}
-
-fn start_lldb_proxy(fd: c_int) -> Result<u16> {
-    use std::net::{TcpListener, TcpStream};
-    use std::os::unix::io::FromRawFd;
-    use std::io::{Read, Write};
-    let device = unsafe { TcpStream::from_raw_fd(fd) };
+/// Read a message of a lockdown service, XML or binary.
+fn receive_plist(stream: &mut TcpStream) -> Result<::plist::Plist> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let mut message = vec![0u8; len.iter().fold(0usize, |acc, &b| (acc << 8) | b as usize)];
+    stream.read_exact(&mut message)?;
+    Ok(::plist::Plist::read(::std::io::Cursor::new(message))?)
+}
+
+/// Hold a power assertion keeping the display on for `duration`, or until the
+/// returned connection is closed.
+fn keep_awake(dev: AmDevice, duration: Duration) -> Result<TcpStream> {
+    let _session = dev.connect()?;
+    let mut agent = dev.start_service("com.apple.mobile.assertion_agent")?;
+    send_plist(&mut agent, &format!(concat!(
+        r#"<dict>"#,
+        r#"<key>CommandKey</key><string>CommandCreateAssertion</string>"#,
+        r#"<key>AssertionTypeKey</key><string>PreventUserIdleDisplaySleep</string>"#,
+        r#"<key>AssertionNameKey</key><string>dinghy</string>"#,
+        r#"<key>AssertionTimeoutKey</key><integer>{}</integer>"#,
+        r#"</dict>"#), duration.as_secs()))?;
+    // The agent acknowledges with a plist of its own.
+    agent.set_read_timeout(Some(Duration::from_secs(10)))?;
+    debug!("Power assertion created: {:?}", receive_plist(&mut agent)?);
+    Ok(agent)
+}
+
+/// Take a screenshot with the screenshotr service of the developer disk image,
+/// which speaks the DeviceLink protocol: a version exchange, then a request
+/// answered with the image.
+fn take_screenshot(dev: AmDevice) -> Result<Vec<u8>> {
+    fn receive_array(stream: &mut TcpStream) -> Result<Vec<::plist::Plist>> {
+        match receive_plist(stream)? {
+            ::plist::Plist::Array(items) => Ok(items),
+            other => bail!("Unexpected DeviceLink message: {:?}", other),
+        }
+    }
+    mount_developper_image(dev)?;
+    let _session = dev.connect()?;
+    let mut stream = dev.start_service("com.apple.mobile.screenshotr")?;
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    let version = receive_array(&mut stream)?;
+    let major = match version.get(1).and_then(|it| it.as_integer()) {
+        Some(major) => major,
+        None => bail!("Unexpected version exchange: {:?}", version),
+    };
+    send_plist(&mut stream, &format!(
+        "<array><string>DLMessageVersionExchange</string><string>DLVersionsOk</string><integer>{}</integer></array>",
+        major))?;
+    // DLMessageDeviceReady
+    receive_array(&mut stream)?;
+    send_plist(&mut stream, concat!(
+        "<array><string>DLMessageProcessMessage</string>",
+        "<dict><key>MessageType</key><string>ScreenShotRequest</string></dict></array>"))?;
+    let reply = receive_array(&mut stream)?;
+    let image = reply.get(1)
+        .and_then(|it| it.as_dictionary())
+        .and_then(|it| it.get("ScreenShotData"))
+        .and_then(|it| it.as_data())
+        .ok_or("No screenshot in the reply of screenshotr")?;
+    Ok(image.to_vec())
+}
+
+/// Ask the diagnostics relay to restart the device, which drops off right
+/// after acknowledging.
+fn restart(dev: AmDevice) -> Result<()> {
+    let _session = dev.connect()?;
+    let mut relay = dev.start_service("com.apple.mobile.diagnostics_relay")?;
+    send_plist(&mut relay, concat!(
+        r#"<dict>"#,
+        r#"<key>Request</key><string>Restart</string>"#,
+        r#"<key>WaitForDisconnect</key><true/>"#,
+        r#"</dict>"#))?;
+    relay.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let reply = receive_plist(&mut relay)?;
+    debug!("Restart requested: {:?}", reply);
+    match reply.as_dictionary().and_then(|it| it.get("Status")).and_then(|it| it.as_string()) {
+        Some("Success") => Ok(()),
+        _ => bail!("The device refused to restart: {:?}", reply),
+    }
+}
+
+/// Open the container of the app `bundle_id` with the house_arrest service,
+/// the connection speaking AFC afterwards, rooted at the container.
+fn app_container(dev: AmDevice, bundle_id: &str) -> Result<AfcConnection> {
+    let _session = dev.connect()?;
+    let mut house_arrest = dev.start_service("com.apple.mobile.house_arrest")?;
+    send_plist(&mut house_arrest, &format!(concat!(
+        r#"<dict>"#,
+        r#"<key>Command</key><string>VendContainer</string>"#,
+        r#"<key>Identifier</key><string>{}</string>"#,
+        r#"</dict>"#), xml_escape(bundle_id)))?;
+    house_arrest.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let reply = receive_plist(&mut house_arrest)?;
+    let reply = reply.as_dictionary().ok_or("Unexpected reply of house_arrest")?;
+    if let Some(error) = reply.get("Error").and_then(|it| it.as_string()) {
+        bail!("Couldn't open the container of {}: {} (is the app installed, and signed for development?)", bundle_id, error)
+    }
+    house_arrest.set_read_timeout(None)?;
+    AfcConnection::open(house_arrest)
+}
+
+/// Remove the files of `path` in an AFC connection.
+fn clear_afc_dir(afc: &AfcConnection, path: &str) -> Result<()> {
+    for entry in afc.read_dir(path)? {
+        afc.remove(&format!("{}/{}", path.trim_right_matches('/'), entry))?;
+    }
+    Ok(())
+}
+
+/// Copy the files of `from` to `to`.
+fn copy_files(from: &Path, to: &Path) -> Result<()> {
+    for entry in fs::read_dir(from).chain_err(|| format!("Couldn't read {}", from.display()))? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+/// `run_args`, with the profiling runtime of coverage runs writing in the
+/// `COVERAGE_DIR` of the app container.
+fn with_coverage(run_args: &RunArgs) -> Result<RunArgs> {
+    let mut run_args = run_args.clone();
+    if run_args.coverage {
+        // The container path isn't known before launch, `%t` is the temporary
+        // directory of the app.
+        run_args.envs.push(format!("LLVM_PROFILE_FILE={}", coverage::profile_file("%t/dinghy-coverage")?));
+    }
+    Ok(run_args)
+}
+
+/// The path of `path` in an AFC container, which is rooted at `/`.
+fn afc_path(path: &str) -> String {
+    format!("/{}", path.trim_left_matches('/'))
+}
+
+/// Copy `path` of an AFC connection to `to`, directories recursively.
+fn pull_afc(afc: &AfcConnection, path: &str, to: &Path) -> Result<()> {
+    match afc.read_dir(path) {
+        Ok(entries) => {
+            fs::create_dir_all(to)?;
+            for entry in entries {
+                pull_afc(afc, &format!("{}/{}", path.trim_right_matches('/'), entry), &to.join(&entry))?;
+            }
+        }
+        Err(_) => {
+            let content = afc.read_file(path).chain_err(|| format!("Couldn't read {}", path))?;
+            fs::File::create(to)?.write_all(&content)?;
+        }
+    }
+    Ok(())
+}
+
+fn start_remote_debug_server(dev: AmDevice) -> Result<TcpStream> {
+    debug!("mount developper image");
+    mount_developper_image(dev)?;
+    debug!("start debugserver on phone");
+    let _session = dev.connect()?;
+    let stream = dev.start_service("com.apple.debugserver")?;
+    debug!("debug server running");
+    Ok(stream)
+}
+
+/// Proxy local lldb connections to the device debugserver. Each connection gets
+/// its own debugserver service, so that lldb can attach and detach repeatedly.
+fn start_lldb_proxy(dev: AmDevice) -> Result<u16> {
+    use std::net::TcpListener;
+    // Start the first service right away so that setup errors surface here.
+    let first = start_remote_debug_server(dev)?;
     let proxy = TcpListener::bind("127.0.0.1:0")?;
     let addr = proxy.local_addr()?;
-    device.set_nonblocking(true)?;
     thread::spawn(move || {
-        fn server(proxy: TcpListener, mut device: TcpStream) -> Result<()> {
+        fn server(proxy: TcpListener, dev: AmDevice, first: TcpStream) -> Result<()> {
+            let mut first = Some(first);
             for stream in proxy.incoming() {
-                let mut stream = stream.expect("Failure while accepting connection");
-                stream.set_nonblocking(true)?;
-                let mut buffer = [0; 16384];
-                loop {
-                    if let Ok(n) = device.read(&mut buffer) {
-                        if n == 0 {
-                            break;
-                        }
-                        stream.write_all(&buffer[0..n])?;
-                    } else if let Ok(n) = stream.read(&mut buffer) {
-                        if n == 0 {
-                            break;
+                let stream = stream.chain_err(|| "Failure while accepting lldb connection")?;
+                let device = match first.take() {
+                    Some(device) => device,
+                    None => match start_remote_debug_server(dev) {
+                        Ok(device) => device,
+                        Err(e) => {
+                            error!("Couldn't start debugserver for new lldb connection: {}", e);
+                            continue;
                         }
-                        device.write_all(&buffer[0..n])?;
-                    } else {
-                        thread::sleep(Duration::new(0, 100));
-                    }
-                }
+                    },
+                };
+                thread::spawn(move || if let Err(e) = forward::relay(stream, device) {
+                    error!("lldb proxy connection failed: {}", e);
+                });
             }
             Ok(())
         }
-        server(proxy, device).unwrap();
+
+        if let Err(e) = server(proxy, dev, first) {
+            error!("lldb proxy failed: {}", e);
+        }
     });
     Ok(addr.port())
 }
 
 fn launch_lldb_device<P: AsRef<Path>, P2: AsRef<Path>>(
-    dev: *const am_device,
+    dev: AmDevice,
     proxy: &str,
+    lldb_dir: &Path,
     local: P,
     remote: P2,
-    args: &[&str],
+    run_args: &RunArgs,
     debugger: bool,
-) -> Result<()> {
+    timeout: Option<Duration>,
+    parser: TestReportParser,
+) -> Result<TestReport> {
     use std::process::Command;
     use std::io::Write;
-    let _session = ensure_session(dev);
-    let dir = ::tempdir::TempDir::new("mobiledevice-rs-lldb")?;
-    let tmppath = dir.path();
+    let _session = dev.connect();
+    let tmppath = lldb_dir;
     let lldb_script_filename = tmppath.join("lldb-script");
     let sysroot = device_support_path(dev)?
         .to_str()
         .ok_or("could not read sysroot")?
         .to_owned();
     {
-        let python_lldb_support = tmppath.join("helpers.py");
-        fs::File::create(&python_lldb_support)?.write_all(include_bytes!("helpers.py"))?;
         let mut script = fs::File::create(&lldb_script_filename)?;
         writeln!(script, "platform select remote-ios --sysroot '{}'", sysroot)?;
         writeln!(
@@ -669,40 +1549,53 @@ fn launch_lldb_device<P: AsRef<Path>, P2: AsRef<Path>>(
             local.as_ref().to_str().ok_or("untranslatable path")?
         )?;
         writeln!(script, "script pass")?;
-
-        writeln!(script, "command script import {:?}", python_lldb_support)?;
-        writeln!(
-            script,
-            "command script add -f helpers.set_remote_path set_remote_path"
-        )?;
-        writeln!(
-            script,
-            "command script add -f helpers.connect_command connect"
-        )?;
-        writeln!(
-            script,
-            "command script add -s synchronous -f helpers.start start"
-        )?;
-
+        lldb::import_helpers(&mut script, tmppath, &run_args.lldb_helpers)?;
         writeln!(script, "connect connect://{}", proxy)?;
         writeln!(
             script,
             "set_remote_path {}",
             remote.as_ref().to_str().unwrap()
         )?;
-        if !debugger {
-            writeln!(script, "start {}", args.join(" "))?;
-            writeln!(script, "quit")?;
-        }
+        lldb::write_launch(&mut script, tmppath, run_args, debugger)?;
     }
 
-    let stat = Command::new("lldb")
-        .arg("-Q")
-        .arg("-s")
-        .arg(lldb_script_filename)
-        .status()?;
+    let mut command = Command::new("lldb");
+    command.arg("-Q").arg("-s").arg(lldb_script_filename);
+    if debugger {
+        return run_lldb_debugger(&mut command, parser);
+    }
+    let child = lldb::spawn(&mut command, tmppath, parser)?;
+    let _cleanup = {
+        // Killing lldb drops the proxied debugserver connection, which in turn
+        // kills the app on the device.
+        let lldb = child.child.id() as pid_t;
+        cleanup::on_interrupt(move || {
+            unsafe { kill(lldb, SIGKILL); }
+            dev.disconnect();
+        })
+    };
+    let (status, parser) = child.wait_with_timeout(timeout)?;
+    lldb::report_log(tmppath, status.map(|it| it.success()).unwrap_or(false));
+    match (status, parser) {
+        // Exit codes of the helpers `start` command.
+        (Some(ref stat), _) if stat.code() == Some(LLDB_EXIT_LOCKED) =>
+            bail!("The device is locked. Unlock it and run again."),
+        (Some(ref stat), _) if stat.code() == Some(LLDB_EXIT_UNTRUSTED) =>
+            bail!("The app couldn't be launched, its developer is not trusted on the device. Trust it in Settings > \
+                   General > VPN & Device Management (Profiles & Device Management on older iOS versions), under \
+                   'Developer App'."),
+        (Some(stat), parser) => Ok(parser.finish(stat.success())),
+        (None, _) => Err(ErrorKind::RunTimeout(local.as_ref().display().to_string(),
+                                               timeout.map(|it| it.as_secs()).unwrap_or(0)))?,
+    }
+}
+
+/// Run an interactive lldb session, with the terminal (and Ctrl-C) left to lldb.
+fn run_lldb_debugger(command: &mut ::std::process::Command, parser: TestReportParser) -> Result<TestReport> {
+    let _interrupts = cleanup::forward_interrupts();
+    let stat = command.status()?;
     if stat.success() {
-        Ok(())
+        Ok(parser.finish(true))
     } else {
         Err(format!("LLDB returned error code {:?}", stat.code()))?
     }
@@ -710,107 +1603,169 @@ fn launch_lldb_device<P: AsRef<Path>, P2: AsRef<Path>>(
 
 fn launch_lldb_simulator(
     dev: &IosSimDevice,
+    lldb_dir: &Path,
     installed: &str,
-    args: &[&str],
+    run_args: &RunArgs,
     debugger: bool,
-) -> Result<()> {
+    timeout: Option<Duration>,
+    parser: TestReportParser,
+) -> Result<TestReport> {
     use std::process::Command;
     use std::io::Write;
-    let dir = ::tempdir::TempDir::new("mobiledevice-rs-lldb")?;
-    let tmppath = dir.path();
+    let bundle_id = bundle_identifier(Path::new(installed.trim()))?;
+    let tmppath = lldb_dir;
     let lldb_script_filename = tmppath.join("lldb-script");
     {
-        let python_lldb_support = tmppath.join("helpers.py");
-        fs::File::create(&python_lldb_support)?.write_all(include_bytes!("helpers.py"))?;
         let mut script = fs::File::create(&lldb_script_filename)?;
         writeln!(script, "platform select ios-simulator")?;
         writeln!(script, "target create {}", installed)?;
         writeln!(script, "script pass")?;
-        writeln!(script, "command script import {:?}", python_lldb_support)?;
-        writeln!(
-            script,
-            "command script add -s synchronous -f helpers.start start"
-        )?;
-        writeln!(
-            script,
-            "command script add -f helpers.connect_command connect"
-        )?;
+        lldb::import_helpers(&mut script, tmppath, &run_args.lldb_helpers)?;
         writeln!(script, "connect connect://{}", dev.id)?;
-        if !debugger {
-            writeln!(script, "start {}", args.join(" "))?;
-            writeln!(script, "quit")?;
+        lldb::write_launch(&mut script, tmppath, run_args, debugger)?;
+    }
+
+    let mut command = Command::new("xcrun");
+    command.arg("lldb").arg("-Q").arg("-s").arg(lldb_script_filename);
+    if debugger {
+        return run_lldb_debugger(&mut command, parser);
+    }
+    let child = lldb::spawn(&mut command, tmppath, parser)?;
+    let _cleanup = {
+        let (lldb, sim_id, bundle_id) = (child.child.id() as pid_t, dev.id.clone(), bundle_id.clone());
+        cleanup::on_interrupt(move || {
+            unsafe { kill(lldb, SIGKILL); }
+            let _ = Command::new("xcrun").args(&["simctl", "terminate", &sim_id, &bundle_id]).status();
+        })
+    };
+    let (status, parser) = child.wait_with_timeout(timeout)?;
+    lldb::report_log(tmppath, status.map(|it| it.success()).unwrap_or(false));
+    match (status, parser) {
+        (Some(stat), parser) => Ok(parser.finish(stat.success())),
+        (None, _) => {
+            let _ = Command::new("xcrun").args(&["simctl", "terminate", &dev.id, &bundle_id]).status();
+            Err(ErrorKind::RunTimeout(installed.trim().to_string(),
+                                      timeout.map(|it| it.as_secs()).unwrap_or(0)))?
         }
     }
+}
 
-    let stat = Command::new("xcrun")
-        .arg("lldb")
-        .arg("-Q")
-        .arg("-s")
-        .arg(lldb_script_filename)
-        .status()?;
-    if stat.success() {
-        Ok(())
-    } else {
-        Err(format!("LLDB returned error code {:?}", stat.code()))?
+/// Expand a bundle identifier template: `{base}` is the identifier the
+/// provisioning profile was made for, `{crate}` the name of the runnable, and
+/// `{job}` `DINGHY_JOB_ID`. Without `DINGHY_JOB_ID`, `{job}` and the separator
+/// before it are left out, so that the identifier stays the same from a run
+/// to the next and the installed apps get replaced instead of piling up.
+fn app_identifier(template: &str, base: &str, runnable_id: &str) -> String {
+    // Bundle identifiers only allow alphanumerics, dots and hyphens.
+    fn sanitize(part: &str) -> String {
+        part.chars().map(|c| if (c.is_ascii() && c.is_alphanumeric()) || c == '.' { c } else { '-' }).collect()
     }
+    // Executables of tests and benches are suffixed with a hash by cargo.
+    let crate_name = match runnable_id.rfind('-') {
+        Some(index) if runnable_id[index + 1..].chars().all(|c| c.is_digit(16)) => &runnable_id[..index],
+        _ => runnable_id,
+    };
+    let template = match ::std::env::var("DINGHY_JOB_ID") {
+        Ok(job) => template.replace("{job}", &sanitize(&job)),
+        Err(_) => template.replace(".{job}", "").replace("-{job}", "").replace("{job}", ""),
+    };
+    template.replace("{base}", base)
+        .replace("{crate}", &sanitize(crate_name))
+}
+
+fn bundle_identifier(app_path: &Path) -> Result<String> {
+    Ok(info_plist_string(app_path, "CFBundleIdentifier")?.ok_or("failed to read CFBundleIdentifier")?)
+}
+
+fn info_plist_string(app_path: &Path, key: &str) -> Result<Option<String>> {
+    let plist_file = fs::File::open(app_path.join("Info.plist"))?;
+    let plist = ::plist::Plist::read(plist_file)?;
+    Ok(plist
+        .as_dictionary()
+        .and_then(|btreemap| btreemap.get(key))
+        .and_then(|value| value.as_string())
+        .map(|value| value.to_string()))
 }
 
+/// Record a Time Profiler trace of the installed app, with xctrace when
+/// available, falling back to the older instruments command line.
+fn profile_with_instruments(device_id: &str,
+                            build: &Build,
+                            runnable: &Runnable,
+                            build_bundle: &BuildBundle,
+                            run_args: &RunArgs) -> Result<PathBuf> {
+    let bundle_id = bundle_identifier(&build_bundle.bundle_dir)?;
+    let trace = artifacts::ensure_dir(build.artifacts(device_id).profiles_dir())?
+        .join(format!("{}.trace", runnable.id));
+    let _ = fs::remove_dir_all(&trace);
+
+    let has_xctrace = process::Command::new("xcrun")
+        .args(&["--find", "xctrace"])
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .status()?
+        .success();
+    let mut command = process::Command::new("xcrun");
+    if has_xctrace {
+        command.args(&["xctrace", "record", "--template", "Time Profiler", "--device", device_id, "--output"])
+            .arg(&trace);
+        for env in &run_args.envs {
+            command.arg("--env").arg(env);
+        }
+        command.arg("--launch").arg("--").arg(&bundle_id);
+    } else {
+        command.args(&["instruments", "-t", "Time Profiler", "-w", device_id, "-D"]).arg(&trace);
+        for env in &run_args.envs {
+            let mut key_value = env.splitn(2, '=');
+            command.arg("-e").arg(key_value.next().unwrap_or("")).arg(key_value.next().unwrap_or(""));
+        }
+        command.arg(&bundle_id);
+    }
+    command.args(&run_args.args);
+    info!("Profile {} on {} with the Time Profiler", runnable.id, device_id);
+    debug!("Running {:?}", command);
+    if !command.status()?.success() {
+        bail!("Couldn't profile {} on {}", runnable.id, device_id)
+    }
+    Ok(trace)
+}
 
 pub fn run_remote<P: AsRef<Path>>(
-    dev: *const am_device,
+    dev: AmDevice,
     lldb_proxy: &str,
+    lldb_dir: &Path,
     app_path: P,
-    args: &[&str],
+    run_args: &RunArgs,
     debugger: bool,
-) -> Result<()> {
-    let _session = ensure_session(dev)?;
-    let plist_file = fs::File::open(app_path.as_ref().join("Info.plist"))?;
-    let plist = ::plist::Plist::read(plist_file)?;
-    let bundle_id = plist
-        .as_dictionary()
-        .and_then(|btreemap| btreemap.get("CFBundleIdentifier"))
-        .and_then(|bi| bi.as_string())
-        .expect("failed to read CFBundleIdentifier");
-
-    let options = [
-        (
-            CFString::from_static_string("ReturnAttributes"),
-            CFArray::from_CFTypes(&[
-                CFString::from_static_string("CFBundleIdentifier").as_CFType(),
-                CFString::from_static_string("Path").as_CFType(),
-            ]),
-        ),
-    ];
-    let options = CFDictionary::from_CFType_pairs(&options);
-    let apps: CFDictionaryRef = ptr::null();
-    unsafe {
-        mk_result(AMDeviceLookupApplications(
-            dev,
-            options.as_concrete_TypeRef(),
-            ::std::mem::transmute(&apps),
-        ))?;
-    }
-    let apps: CFDictionary = unsafe { TCFType::wrap_under_get_rule(apps) };
-    let app_info: CFDictionary = unsafe {
-        TCFType::wrap_under_get_rule(::std::mem::transmute(apps.get(::std::mem::transmute(
-            CFString::new(bundle_id).as_concrete_TypeRef(),
-        ))))
-    };
-    let remote: String = if let Ok(Value::String(remote)) = unsafe {
-        rustify(app_info.get(::std::mem::transmute(
-            CFString::from_static_string("Path").as_concrete_TypeRef(),
-        )))
-    } {
-        remote
-    } else {
-        Err("Invalid info")?
-    };
-    launch_lldb_device(dev, lldb_proxy, app_path, remote, args, debugger)?;
-    Ok(())
+    timeout: Option<Duration>,
+    parser: TestReportParser,
+) -> Result<TestReport> {
+    let _session = dev.connect()?;
+    let bundle_id = bundle_identifier(app_path.as_ref())?;
+    let remote = dev.lookup_application_path(&bundle_id)?;
+    launch_lldb_device(dev, lldb_proxy, lldb_dir, app_path, remote, run_args, debugger, timeout, parser)
+}
+
+/// Launch the app without lldb, talking to debugserver directly. Crashes are
+/// not symbolicated nor debuggable this way, but Xcode command line tools are
+/// not needed.
+pub fn spawn_remote<P: AsRef<Path>>(
+    dev: AmDevice,
+    app_path: P,
+    args: &[&str],
+    envs: &[&str],
+    timeout: Option<Duration>,
+    parser: TestReportParser,
+) -> Result<TestReport> {
+    let _session = dev.connect()?;
+    let bundle_id = bundle_identifier(app_path.as_ref())?;
+    let remote = dev.lookup_application_path(&bundle_id)?;
+    let stream = start_remote_debug_server(dev)?;
+    debugserver::launch(stream, &format!("{}/Dinghy", remote), args, envs, timeout, parser)
 }
 
 #[allow(dead_code)]
-fn properties(dev: *const am_device) -> Result<HashMap<&'static str, Value>> {
+fn properties(dev: AmDevice) -> Result<HashMap<&'static str, Value>> {
     let properties = [
         "ActivationPublicKey",
         "ActivationState",
@@ -872,9 +1827,40 @@ fn properties(dev: *const am_device) -> Result<HashMap<&'static str, Value>> {
     ];
     let mut props = HashMap::new();
     for p in properties.iter() {
-        if let Some(v) = device_read_value(dev, p)? {
+        if let Some(v) = dev.read_value(p)? {
             props.insert(*p, v);
         }
     }
     Ok(props)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precise_cpu_arch() {
+        assert_eq!(cpu_arch(Some("arm64e"), Some("iPhone14,2")), "arm64e");
+        assert_eq!(cpu_arch(Some("armv7"), Some("iPhone5,2")), "armv7s");
+        assert_eq!(cpu_arch(None, Some("iPad3,1")), "armv7");
+        assert_eq!(cpu_arch(None, Some("iPad3,4")), "armv7s");
+        assert_eq!(cpu_arch(None, Some("Watch3,2")), "armv7k");
+        assert_eq!(cpu_arch(None, Some("Watch6,1")), "arm64_32");
+        assert_eq!(cpu_arch(None, None), "armv7");
+    }
+
+    #[test]
+    fn arm_32_bit_executables() {
+        // Thin armv7 executable.
+        assert_eq!(only_32_bit_arch(&[0xce, 0xfa, 0xed, 0xfe, 12, 0, 0, 0, 9, 0, 0, 0]), Some("armv7"));
+        // Thin arm64 executable.
+        assert_eq!(only_32_bit_arch(&[0xcf, 0xfa, 0xed, 0xfe, 12, 0, 0, 1, 0, 0, 0, 0]), None);
+        // Universal armv7s and arm64 executable.
+        let mut fat = vec![0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 2];
+        fat.extend(&[0, 0, 0, 12, 0, 0, 0, 11, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        fat.extend(&[1, 0, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(only_32_bit_arch(&fat), None);
+        fat[7] = 1;
+        assert_eq!(only_32_bit_arch(&fat), Some("armv7s"));
+    }
+}
@@ -0,0 +1,123 @@
+//! The console of iOS devices, relayed by the `com.apple.syslog_relay`
+//! service: NUL-terminated messages, like
+//! `Mar  4 10:12:01 iPhone SpringBoard(UIKit)[58] <Notice>: message`, their
+//! non-ASCII bytes escaped the way vis(3) does (`\M-b\M^@\M-&`).
+
+use console::ConsoleMessage;
+use console::ConsoleOptions;
+use console::Priority;
+use errors::*;
+use std::io::Read;
+use super::mobiledevice::AmDevice;
+
+/// Stream the console of `dev` to stdout until the device goes away.
+pub fn stream(dev: AmDevice, options: &ConsoleOptions) -> Result<()> {
+    let _session = dev.connect()?;
+    let mut relay = dev.start_service("com.apple.syslog_relay")?;
+    let mut pending = vec![];
+    let mut buffer = [0; 4096];
+    loop {
+        let read = relay.read(&mut buffer)?;
+        if read == 0 {
+            bail!("The device closed its console")
+        }
+        pending.extend_from_slice(&buffer[..read]);
+        for message in take_messages(&mut pending) {
+            let message = parse(&message);
+            if options.process.is_none() || message.process == options.process {
+                options.print(&message)?;
+            }
+        }
+    }
+}
+
+/// Removes the complete messages from `pending`, unescaped.
+fn take_messages(pending: &mut Vec<u8>) -> Vec<String> {
+    let mut messages = vec![];
+    while let Some(end) = pending.iter().position(|&it| it == 0) {
+        let message = pending.drain(..end + 1).collect::<Vec<_>>();
+        let message = unescape(&message[..end]);
+        let message = message.trim_right_matches('\n');
+        if !message.is_empty() {
+            messages.push(message.to_string());
+        }
+    }
+    messages
+}
+
+fn unescape(raw: &[u8]) -> String {
+    let mut bytes = Vec::with_capacity(raw.len());
+    let mut index = 0;
+    while index < raw.len() {
+        let rest = &raw[index..];
+        if rest.starts_with(b"\\M-") && rest.len() > 3 {
+            bytes.push(rest[3] | 0x80);
+            index += 4;
+        } else if rest.starts_with(b"\\M^") && rest.len() > 3 {
+            bytes.push((rest[3] ^ 0x40) | 0x80);
+            index += 4;
+        } else if rest.starts_with(b"\\^") && rest.len() > 2 {
+            bytes.push(rest[2] ^ 0x40);
+            index += 3;
+        } else if rest.starts_with(b"\\\\") {
+            bytes.push(b'\\');
+            index += 2;
+        } else {
+            bytes.push(rest[0]);
+            index += 1;
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn parse(line: &str) -> ConsoleMessage {
+    let level = line.find("] <").and_then(|start| {
+        line[start + 3..].find('>').map(|end| &line[start + 3..start + 3 + end])
+    });
+    let pid = line.find("] <")
+        .and_then(|end| line[..end].rfind('[').and_then(|start| line[start + 1..end].parse().ok()));
+    let message = line.find(">: ").map(|it| &line[it + 3..]).unwrap_or(line);
+    ConsoleMessage {
+        line: line.to_string(),
+        time: line.get(..15).unwrap_or("").to_string(),
+        process: process_of(line).map(|it| it.to_string()),
+        pid,
+        priority: level.and_then(Priority::from_apple).unwrap_or(Priority::Notice),
+        tag: None,
+        message: message.to_string(),
+    }
+}
+
+/// The process that logged `message`: the name before the `(image)[pid]`
+/// in front of the level.
+fn process_of(message: &str) -> Option<&str> {
+    let end = message.find("] <")?;
+    let start = message[..end].rfind(' ').map(|it| it + 1).unwrap_or(0);
+    let token = &message[start..end];
+    let name_end = token.find(|c| c == '(' || c == '[').unwrap_or(token.len());
+    Some(&token[..name_end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn console_messages() {
+        let mut pending = b"Mar  4 10:12:01 Bob's iPhone SpringBoard(UIKit)[58] <Notice>: caf\\M-C\\M-) ok\n\0\
+                            Mar  4 10:12:02 Bob's iPhone kernel[0] <Error>: esc \\^[ back\\\\slash\n\0Mar  4"
+            .to_vec();
+        let messages = take_messages(&mut pending);
+        assert_eq!(messages, vec![
+            "Mar  4 10:12:01 Bob's iPhone SpringBoard(UIKit)[58] <Notice>: café ok".to_string(),
+            "Mar  4 10:12:02 Bob's iPhone kernel[0] <Error>: esc \x1b back\\slash".to_string(),
+        ]);
+        assert_eq!(pending, b"Mar  4".to_vec());
+        assert_eq!(process_of(&messages[0]), Some("SpringBoard"));
+        assert_eq!(process_of(&messages[1]), Some("kernel"));
+        assert_eq!(process_of("no process here"), None);
+        let message = parse(&messages[1]);
+        assert_eq!((message.pid, message.priority), (Some(0), Priority::Error));
+        assert_eq!((message.time.as_str(), message.message.as_str()), ("Mar  4 10:12:02", "esc \x1b back\\slash"));
+    }
+}
@@ -1,11 +1,14 @@
+use config::IosConfiguration;
 use errors::*;
 use std::{env, fs, io, process};
+use std::collections::BTreeMap;
 use std::io::Write;
 use super::{SignatureSettings, SigningIdentity};
+use utils::xml_escape;
 
 use BuildBundle;
 
-pub fn add_plist_to_app(bundle:&BuildBundle, arch:&str, app_bundle_id:&str) -> Result<()> {
+pub fn add_plist_to_app(bundle:&BuildBundle, arch:&str, app_bundle_id:&str, extra_entries: &BTreeMap<String, String>) -> Result<()> {
     let mut plist = fs::File::create(bundle.bundle_dir.join("Info.plist"))?;
     writeln!(plist, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
     writeln!(plist, r#"<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">"#)?;
@@ -21,6 +24,9 @@ pub fn add_plist_to_app(bundle:&BuildBundle, arch:&str, app_bundle_id:&str) -> R
     )?;
     writeln!(plist, "<key>UIRequiredDeviceCapabilities</key>")?;
     writeln!(plist, "<array><string>{}</string></array>", arch)?;
+    for (key, value) in extra_entries {
+        writeln!(plist, "<key>{}</key><string>{}</string>", xml_escape(key), xml_escape(value))?;
+    }
     writeln!(plist, r#"</dict></plist>"#)?;
     /*
     let app_name = app_bundle_id.split(".").last().unwrap();
@@ -59,7 +65,73 @@ pub fn add_plist_to_app(bundle:&BuildBundle, arch:&str, app_bundle_id:&str) -> R
     Ok(())
 }
 
-pub fn sign_app(bundle: &BuildBundle, settings: &SignatureSettings) -> Result<()> {
+/// Expand the variables of an entitlements template: `$(TEAM_ID)`,
+/// `$(AppIdentifierPrefix)` (the team followed by a dot), `$(BUNDLE_ID)` and
+/// its Xcode name `$(PRODUCT_BUNDLE_IDENTIFIER)`. A whole plist is reduced to
+/// the entries of its dictionary.
+pub fn expand_entitlements(template: &str, team: &str, bundle_id: &str) -> String {
+    let entries = match (template.find("<dict>"), template.rfind("</dict>")) {
+        (Some(start), Some(end)) if start < end => &template[start + "<dict>".len()..end],
+        _ => template,
+    };
+    entries.replace("$(TEAM_ID)", team)
+        .replace("$(AppIdentifierPrefix)", &format!("{}.", team))
+        .replace("$(BUNDLE_ID)", bundle_id)
+        .replace("$(PRODUCT_BUNDLE_IDENTIFIER)", bundle_id)
+}
+
+/// Split entitlements (`<key>` and value fragments) into their entries, as
+/// (key, text of the entry).
+fn split_entitlements(fragment: &str) -> Vec<(String, &str)> {
+    let mut entries = vec![];
+    let mut depth = 0;
+    let mut start = None;
+    let mut pos = 0;
+    while let Some(open) = fragment[pos..].find('<').map(|it| pos + it) {
+        let close = match fragment[open..].find('>') {
+            Some(close) => open + close,
+            None => break,
+        };
+        let tag = &fragment[open..close + 1];
+        if depth == 0 && tag == "<key>" {
+            if let Some(start) = start {
+                entries.push(&fragment[start..open]);
+            }
+            start = Some(open);
+        } else if tag.starts_with("</array") || tag.starts_with("</dict") {
+            depth -= 1;
+        } else if (tag.starts_with("<array") || tag.starts_with("<dict")) && !tag.ends_with("/>") {
+            depth += 1;
+        }
+        pos = close + 1;
+    }
+    if let Some(start) = start {
+        entries.push(&fragment[start..]);
+    }
+    entries.into_iter()
+        .map(|entry| {
+            let key = entry["<key>".len()..].split("</key>").next().unwrap_or("").trim().to_string();
+            (key, entry.trim())
+        })
+        .collect()
+}
+
+/// The entitlements of an app: the ones of the provisioning profile, replaced
+/// or completed by the `extra` ones, and `get-task-allow` so that the app can
+/// be debugged unless set otherwise.
+fn merge_entitlements(profile: &str, extra: &str) -> String {
+    let extra = split_entitlements(extra);
+    let mut entries = split_entitlements(profile).into_iter()
+        .filter(|&(ref key, _)| !extra.iter().any(|it| it.0 == *key))
+        .chain(extra.iter().cloned())
+        .collect::<Vec<_>>();
+    if !entries.iter().any(|it| it.0 == "get-task-allow") {
+        entries.push(("get-task-allow".to_string(), "<key>get-task-allow</key><true/>"));
+    }
+    entries.iter().map(|it| it.1).collect::<Vec<_>>().join("\n")
+}
+
+pub fn sign_app(bundle: &BuildBundle, settings: &SignatureSettings, extra_entitlements: &str) -> Result<()> {
     debug!(
         "Will sign {:?} with team: {} using key: {} and profile: {}",
         bundle.bundle_dir,
@@ -74,39 +146,92 @@ pub fn sign_app(bundle: &BuildBundle, settings: &SignatureSettings) -> Result<()
     writeln!(plist, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
     writeln!(plist, r#"<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">"#)?;
     writeln!(plist, r#"<plist version="1.0"><dict>"#)?;
-    writeln!(plist, "{}", settings.entitlements)?;
+    writeln!(plist, "{}", merge_entitlements(&settings.entitlements, extra_entitlements))?;
     writeln!(plist, r#"</dict></plist>"#)?;
 
-    process::Command::new("codesign")
-        .args(&[
-            "-s",
-            &*settings.identity.name,
-            "--entitlements"])
+    let mut codesign = process::Command::new("codesign");
+    codesign.arg("-s").arg(&settings.identity.id);
+    if let Some(ref keychain) = settings.identity.keychain {
+        codesign.arg("--keychain").arg(keychain);
+    }
+    let status = codesign.arg("--entitlements")
         .arg(entitlements)
         .arg(&bundle.bundle_dir)
-        .status()?;
+        .stdin(process::Stdio::null())
+        .status()
+        .chain_err(|| "Couldn't run codesign")?;
+    if !status.success() {
+        bail!("codesign failed to sign {} with {}", bundle.bundle_dir.display(), settings.identity.name)
+    }
+    Ok(())
+}
+
+/// Unlock the configured keychain when its password is given, and let
+/// codesign use its keys without the access prompt, which would hang
+/// unattended runs.
+fn unlock_keychain(conf: &IosConfiguration) -> Result<()> {
+    let keychain = match conf.keychain {
+        Some(ref keychain) => keychain,
+        None => return Ok(()),
+    };
+    let password_env = conf.keychain_password_env.as_ref().map(|it| it.as_str()).unwrap_or("DINGHY_KEYCHAIN_PASSWORD");
+    let password = match conf.keychain_password.clone().or_else(|| env::var(password_env).ok()) {
+        Some(password) => password,
+        None => {
+            debug!("No password for keychain {}, assuming it is unlocked", keychain);
+            return Ok(());
+        }
+    };
+    // The commands are not logged, they hold the password.
+    let unlocked = process::Command::new("security")
+        .args(&["unlock-keychain", "-p", &password, keychain])
+        .status()
+        .chain_err(|| "Couldn't run security")?;
+    if !unlocked.success() {
+        bail!("Couldn't unlock keychain {}, check its password (in {} or keychain_password)", keychain, password_env)
+    }
+    let partitions = process::Command::new("security")
+        .args(&["set-key-partition-list", "-S", "apple-tool:,apple:,codesign:", "-s", "-k", &password, keychain])
+        .stdout(process::Stdio::null())
+        .status()
+        .chain_err(|| "Couldn't run security")?;
+    if !partitions.success() {
+        warn!("Couldn't allow codesign to use the keys of {}, it may prompt for access", keychain);
+    }
     Ok(())
 }
 
-pub fn look_for_signature_settings(device_id: &str) -> Result<Vec<SignatureSettings>> {
+pub fn look_for_signature_settings(device_id: &str, conf: &IosConfiguration) -> Result<Vec<SignatureSettings>> {
     let identity_regex = ::regex::Regex::new(r#"^ *[0-9]+\) ([A-Z0-9]{40}) "(.+)"$"#)?;
     let subject_regex = ::regex::Regex::new(r#"OU=([^,]+)"#)?;
     let mut identities: Vec<SigningIdentity> = vec![];
+    unlock_keychain(conf)?;
+    let keychains = match conf.keychain {
+        Some(ref keychain) => format!("keychain {}", keychain),
+        None => "the default keychains".to_string(),
+    };
     let find_identities = process::Command::new("security")
         .args(&["find-identity", "-v", "-p", "codesigning"])
-        .output()?;
+        .args(conf.keychain.iter())
+        .output()
+        .chain_err(|| "Couldn't run security")?;
     for line in String::from_utf8(find_identities.stdout)?.split("\n") {
         if let Some(caps) = identity_regex.captures(&line) {
             let name: String = caps[2].into();
-            if !name.starts_with("iPhone Developer: ") {
+            let selected = match conf.signing_identity {
+                Some(ref selected) => *selected == name || *selected == caps[1],
+                None => name.starts_with("iPhone Developer: ") || name.starts_with("Apple Development: "),
+            };
+            if !selected {
                 continue;
             }
             let subject = process::Command::new("sh")
                 .arg("-c")
                 .arg(format!(
-                    "security find-certificate -a -c \"{}\" -p | openssl x509 -text | \
+                    "security find-certificate -a -c \"{}\" -p {} | openssl x509 -text | \
                      grep Subject:",
-                    name
+                    name,
+                    conf.keychain.as_ref().map(|it| format!("\"{}\"", it)).unwrap_or(String::new())
                 ))
                 .output()?;
             let subject = String::from_utf8(subject.stdout)?;
@@ -115,11 +240,19 @@ pub fn look_for_signature_settings(device_id: &str) -> Result<Vec<SignatureSetti
                     id: caps[1].into(),
                     name: caps[2].into(),
                     team: ou[1].into(),
+                    keychain: conf.keychain.clone(),
                 })
             }
         }
     }
     debug!("signing identities: {:?}", identities);
+    if identities.is_empty() {
+        match conf.signing_identity {
+            Some(ref selected) => bail!("Signing identity {} not found or not valid in {}", selected, keychains),
+            None => bail!("No valid development signing identity in {}, sign in with your Apple ID in Xcode to create one, \
+                           or set the keychain holding it with `keychain` under [ios]", keychains),
+        }
+    }
     let mut settings = vec![];
     for file in fs::read_dir(
         env::home_dir()
@@ -177,6 +310,9 @@ pub fn look_for_signature_settings(device_id: &str) -> Result<Vec<SignatureSetti
             continue;
         }
         let identity = identity.unwrap();
+        let uuid = dict.get("UUID")
+            .and_then(|it| it.as_string())
+            .ok_or(format!("No UUID in profile {:?}", file.path()))?;
         let entitlements = String::from_utf8(decoded.stdout)?
             .split("\n")
             .skip_while(|line| !line.contains("<key>Entitlements</key>"))
@@ -197,7 +333,27 @@ pub fn look_for_signature_settings(device_id: &str) -> Result<Vec<SignatureSetti
             },
             identity: identity.clone(),
             profile: file.path().to_str().unwrap().into(),
+            uuid: uuid.into(),
+            wildcard: name.ends_with(" *"),
         });
     }
     Ok(settings)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entitlements_template() {
+        let profile = "<key>application-identifier</key>\n<string>ABCDE12345.*</string>\n\
+                       <key>keychain-access-groups</key>\n<array>\n<string>ABCDE12345.*</string>\n</array>";
+        let template = "<plist version=\"1.0\"><dict><key>keychain-access-groups</key>\
+                        <array><string>$(AppIdentifierPrefix)$(BUNDLE_ID)</string></array></dict></plist>";
+        let extra = expand_entitlements(template, "ABCDE12345", "com.example.Dinghy");
+        assert_eq!(merge_entitlements(profile, &extra),
+                   "<key>application-identifier</key>\n<string>ABCDE12345.*</string>\n\
+                    <key>keychain-access-groups</key><array><string>ABCDE12345.com.example.Dinghy</string></array>\n\
+                    <key>get-task-allow</key><true/>");
+    }
+}
@@ -0,0 +1,312 @@
+//! Fake devices with scripted behaviour, for the tests of tools built on the
+//! dinghy library (`mock` feature).
+//!
+//! ```ignore
+//! let device = MockDevice::new("phone")
+//!     .with_rustc_triple("aarch64-linux-android")
+//!     .with_output("test it_works ... ok\ntest result: ok. 1 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out\n");
+//! let host = HostManager::probe(&compiler).unwrap();
+//! let mock = MockPlatformManager::new().device(device.clone());
+//! let dinghy = Dinghy::with_managers(&conf, &compiler, vec![Box::new(host), Box::new(mock)])?;
+//! // ... run through dinghy, then:
+//! assert_eq!(device.calls(), vec!["install it_works", "run it_works"]);
+//! ```
+
+use errors::*;
+use forward::PortForward;
+use health::DeviceHealth;
+use platform::custom_platform::CustomPlatform;
+use platform::regular_platform::RegularPlatform;
+use project::Project;
+use recording::ScreenRecording;
+use report::RunResult;
+use report::TestReport;
+use report::TestReportParser;
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use Build;
+use BuildBundle;
+use Device;
+use DeviceCompatibility;
+use Platform;
+use PlatformManager;
+use RunArgs;
+use Runnable;
+
+/// Lists the mock devices it was given.
+#[derive(Clone, Default)]
+pub struct MockPlatformManager {
+    devices: Vec<MockDevice>,
+}
+
+impl MockPlatformManager {
+    pub fn new() -> MockPlatformManager {
+        MockPlatformManager::default()
+    }
+
+    pub fn device(mut self, device: MockDevice) -> MockPlatformManager {
+        self.devices.push(device);
+        self
+    }
+}
+
+impl PlatformManager for MockPlatformManager {
+    fn devices(&self) -> Result<Vec<Box<Device>>> {
+        Ok(self.devices.iter().map(|it| Box::new(it.clone()) as Box<Device>).collect())
+    }
+}
+
+type InstallBehaviour = Arc<Fn(&Runnable) -> Result<()> + Send + Sync>;
+type RunBehaviour = Arc<Fn(&Runnable, &RunArgs) -> Result<TestReport> + Send + Sync>;
+
+/// A fake device. Its clones share the log of the calls made on them, so a
+/// test can keep one to check what dinghy did with the others.
+#[derive(Clone)]
+pub struct MockDevice {
+    id: String,
+    name: String,
+    rustc_triples: Vec<String>,
+    failure: Option<String>,
+    health: DeviceHealth,
+    install: InstallBehaviour,
+    run: RunBehaviour,
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockDevice {
+    /// A device installing anything, and running it successfully without any
+    /// output.
+    pub fn new(id: &str) -> MockDevice {
+        let device_id = id.to_string();
+        MockDevice {
+            id: id.to_string(),
+            name: format!("mock device {}", id),
+            rustc_triples: vec![],
+            failure: None,
+            health: DeviceHealth::default(),
+            install: Arc::new(|_: &Runnable| Ok(())),
+            run: Arc::new(move |runnable: &Runnable, _: &RunArgs| Ok(TestReport::unparsed(&device_id, &runnable.id, true))),
+            calls: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> MockDevice {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Make the device compatible with the platforms of this triple.
+    pub fn with_rustc_triple(mut self, rustc_triple: &str) -> MockDevice {
+        self.rustc_triples.push(rustc_triple.to_string());
+        self
+    }
+
+    /// Make the device unusable, for the given reason.
+    pub fn with_failure(mut self, failure: &str) -> MockDevice {
+        self.failure = Some(failure.to_string());
+        self
+    }
+
+    pub fn with_health(mut self, health: DeviceHealth) -> MockDevice {
+        self.health = health;
+        self
+    }
+
+    /// Decide whether installing a runnable works.
+    pub fn on_install<F>(mut self, install: F) -> MockDevice
+        where F: Fn(&Runnable) -> Result<()> + Send + Sync + 'static {
+        self.install = Arc::new(install);
+        self
+    }
+
+    /// Decide the outcome of running a runnable.
+    pub fn on_run<F>(mut self, run: F) -> MockDevice
+        where F: Fn(&Runnable, &RunArgs) -> Result<TestReport> + Send + Sync + 'static {
+        self.run = Arc::new(run);
+        self
+    }
+
+    /// Make every runnable print `output` (e.g. libtest output) and exit
+    /// successfully.
+    pub fn with_output(self, output: &str) -> MockDevice {
+        let (device_id, output) = (self.id.clone(), output.to_string());
+        self.on_run(move |runnable, _| {
+            let mut parser = TestReportParser::new(&device_id, &runnable.id);
+            parser.parse_all(&output);
+            Ok(parser.finish(true))
+        })
+    }
+
+    /// The calls made on the device so far, e.g. `run <runnable id>`.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().map(|it| it.clone()).unwrap_or(vec![])
+    }
+
+    fn call(&self, call: String) {
+        if let Ok(mut calls) = self.calls.lock() {
+            calls.push(call);
+        }
+    }
+
+    fn supports(&self, rustc_triple: &str) -> bool {
+        self.rustc_triples.iter().any(|it| it == rustc_triple)
+    }
+
+    fn bundle(runnable: &Runnable) -> BuildBundle {
+        BuildBundle {
+            id: runnable.id.clone(),
+            bundle_exe: runnable.exe.clone(),
+            ..BuildBundle::default()
+        }
+    }
+}
+
+impl fmt::Display for MockDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Mock {{ \"id\": \"{}\", \"name\": \"{}\" }}", self.id, self.name)
+    }
+}
+
+impl DeviceCompatibility for MockDevice {
+    fn is_compatible_with_custom_platform(&self, platform: &CustomPlatform) -> bool {
+        self.supports(&platform.toolchain.rustc_triple)
+    }
+
+    fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
+        self.supports(&platform.toolchain.toolchain_triple)
+    }
+
+    fn is_compatible_with_plugin_platform(&self, platform: &Platform) -> bool {
+        platform.rustc_triple().map(|it| self.supports(it)).unwrap_or(false)
+    }
+}
+
+impl Device for MockDevice {
+    fn clean_app(&self, build_bundle: &BuildBundle) -> Result<()> {
+        self.call(format!("clean {}", build_bundle.id));
+        Ok(())
+    }
+
+    fn debug_app(&self, _project: &Project, build: &Build, _run_args: &RunArgs) -> Result<BuildBundle> {
+        let runnable = build.runnables.iter().next().ok_or("No executable compiled")?;
+        self.call(format!("debug {}", runnable.id));
+        Ok(MockDevice::bundle(runnable))
+    }
+
+    fn dry_run_app(&self, _project: &Project, build: &Build, _run_args: &RunArgs) -> Result<Vec<String>> {
+        Ok(build.runnables.iter().map(|it| format!("run {} on mock device {}", it.id, self.id)).collect())
+    }
+
+    fn failure(&self) -> Option<&str> {
+        self.failure.as_ref().map(|it| it.as_str())
+    }
+
+    fn forward_port(&self, local: u16, remote: u16) -> Result<PortForward> {
+        self.call(format!("forward {}:{}", local, remote));
+        Ok(PortForward::noop(local, remote))
+    }
+
+    fn health(&self) -> Result<DeviceHealth> {
+        Ok(self.health.clone())
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn profile_app(&self, _project: &Project, build: &Build, _run_args: &RunArgs) -> Result<(BuildBundle, PathBuf)> {
+        let runnable = build.runnables.iter().next().ok_or("No executable compiled")?;
+        self.call(format!("profile {}", runnable.id));
+        Ok((MockDevice::bundle(runnable), build.target_path.join(format!("{}.trace", runnable.id))))
+    }
+
+    fn record_screen(&self, path: &Path) -> Result<ScreenRecording> {
+        self.call(format!("record {}", path.display()));
+        Ok(ScreenRecording::new(path.to_path_buf(), || Ok(())))
+    }
+
+    fn reverse_port(&self, local: u16, remote: u16) -> Result<(PortForward, String)> {
+        self.call(format!("reverse {}:{}", local, remote));
+        Ok((PortForward::noop(local, remote), format!("127.0.0.1:{}", local)))
+    }
+
+    fn rustc_triples(&self) -> Vec<String> {
+        self.rustc_triples.clone()
+    }
+
+    fn run_app(&self, _project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<RunResult>> {
+        let mut reports = vec![];
+        for runnable in &build.runnables {
+            self.call(format!("install {}", runnable.id));
+            (self.install)(runnable)?;
+            self.call(format!("run {}", runnable.id));
+            reports.push(RunResult::new(MockDevice::bundle(runnable), (self.run)(runnable, run_args)?));
+        }
+        Ok(reports)
+    }
+
+    fn run_shell(&self, command: &str) -> Result<()> {
+        self.call(format!("shell {}", command));
+        Ok(())
+    }
+
+    fn screenshot(&self, path: &Path) -> Result<()> {
+        self.call(format!("screenshot {}", path.display()));
+        Ok(())
+    }
+
+    fn start_remote_lldb(&self) -> Result<String> {
+        bail!("Mock devices can't be debugged")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compiler::CompileMode;
+    use config::Configuration;
+    use BuildArgs;
+
+    fn build(ids: &[&str]) -> Build {
+        Build {
+            build_args: BuildArgs {
+                check: None,
+                compile_mode: CompileMode::Test,
+                coverage: false,
+                dry_run: false,
+                verbose: false,
+                forced_overlays: vec![],
+            },
+            cdylibs: vec![],
+            doctests: vec![],
+            dynamic_libraries: vec![],
+            runnables: ids.iter()
+                .map(|id| Runnable { id: id.to_string(), exe: PathBuf::from(id), source: PathBuf::from("."), stripped_exe: None })
+                .collect(),
+            rustc_triple: None,
+            staticlibs: vec![],
+            target_path: PathBuf::from("target"),
+        }
+    }
+
+    #[test]
+    fn scripted_device() {
+        let device = MockDevice::new("phone")
+            .with_rustc_triple("aarch64-linux-android")
+            .on_install(|runnable| if runnable.id == "broken" { bail!("install failed") } else { Ok(()) });
+        let devices = MockPlatformManager::new().device(device.clone()).devices().unwrap();
+        assert_eq!(devices[0].rustc_triples(), vec!["aarch64-linux-android".to_string()]);
+        assert!(devices[0].forward_port(8000, 8000).is_ok());
+        let project = Project::new(&Arc::new(Configuration::default()));
+        let error = devices[0].run_app(&project, &build(&["works", "broken"]), &RunArgs::default()).err();
+        assert_eq!(error.map(|it| it.to_string()), Some("install failed".to_string()));
+        assert_eq!(device.calls(), vec!["forward 8000:8000", "install works", "run works", "install broken"]);
+    }
+}
@@ -1,25 +1,70 @@
+use artifacts::ArtifactStore;
 use errors::*;
+use logging;
 use project::Project;
+use std::collections::hash_map::DefaultHasher;
 use std::fs;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use timings;
+use timings::Phase;
 use utils::copy_and_sync_file;
+use walkdir::WalkDir;
 use Build;
 use BuildBundle;
 use Runnable;
 
+pub mod agent;
 pub mod android;
+pub mod avd;
+pub mod docker;
 pub mod host;
 #[cfg(target_os = "macos")]
 pub mod ios;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod qemu;
+pub mod replay;
+pub mod simctl;
 pub mod ssh;
+pub mod wasi;
 
+/// Printed by the work directory probe when executables can run there.
+static EXEC_PROBE_OK: &str = "dinghy-exec-ok";
 
-fn make_remote_app(project: &Project, build: &Build, runnable: &Runnable) -> Result<BuildBundle> {
-    make_remote_app_with_name(project, build, runnable, None)
+/// Shell command creating the work directory `dir` of a device and checking
+/// that executables can run from there (it may be mounted noexec).
+fn exec_probe_command(dir: &Path) -> String {
+    let probe = dir.join(".dinghy-exec-probe");
+    format!("mkdir -p '{dir}' && printf 'echo {ok}\\n' > '{probe}' && chmod 755 '{probe}' && '{probe}'; rm -f '{probe}'",
+            dir = dir.display(), probe = probe.display(), ok = EXEC_PROBE_OK)
 }
 
-fn make_remote_app_with_name(project: &Project, build: &Build, runnable: &Runnable, bundle_name: Option<&str>) -> Result<BuildBundle> {
+/// Check the output of `exec_probe_command`.
+fn check_exec_probe(device_id: &str, dir: &Path, output: &str) -> Result<()> {
+    if output.contains(EXEC_PROBE_OK) {
+        return Ok(());
+    }
+    bail!("Executables can't be run from {} on {} ({}). Set a `work_dir` allowing it (not mounted noexec) \
+           in the [devices.{}] configuration.", dir.display(), device_id, output.trim(), device_id)
+}
+
+fn make_remote_app(project: &Project, build: &Build, artifacts: &ArtifactStore, runnable: &Runnable) -> Result<BuildBundle> {
+    make_remote_app_with_name(project, build, artifacts, runnable, None)
+}
+
+fn make_remote_app_with_name(project: &Project,
+                             build: &Build,
+                             artifacts: &ArtifactStore,
+                             runnable: &Runnable,
+                             bundle_name: Option<&str>) -> Result<BuildBundle> {
+    let _span = logging::span(format!("bundle {}", runnable.id));
+    let _timer = timings::start(Phase::Bundle);
     let project = project.for_runnable(runnable)?;
-    let root_dir = build.target_path.join("dinghy");
+    let root_dir = artifacts.bundles_dir();
     let bundle_path = match bundle_name {
         Some(name) => root_dir.join(&runnable.id).join(name),
         None => root_dir.join(&runnable.id),
@@ -29,9 +74,13 @@ fn make_remote_app_with_name(project: &Project, build: &Build, runnable: &Runnab
     let bundle_exe_path = bundle_target_path.join(&runnable.id);
 
     debug!("Removing previous bundle {:?}", bundle_path);
-    let _ = fs::remove_dir_all(&bundle_path);
+    // Test data is kept, it is brought up to date incrementally below.
+    if let Ok(entries) = fs::read_dir(&bundle_path) {
+        for entry in entries.filter_map(|it| it.ok()).filter(|it| it.file_name().to_str() != Some("test_data")) {
+            let _ = fs::remove_dir_all(entry.path()).or_else(|_| fs::remove_file(entry.path()));
+        }
+    }
     let _ = fs::remove_dir_all(&bundle_libs_path);
-    let _ = fs::remove_dir_all(&bundle_target_path);
 
     debug!("Making bundle {:?}", bundle_path);
     fs::create_dir_all(&bundle_path)
@@ -41,9 +90,9 @@ fn make_remote_app_with_name(project: &Project, build: &Build, runnable: &Runnab
     fs::create_dir_all(&bundle_target_path)
         .chain_err(|| format!("Couldn't create {}", &bundle_target_path.display()))?;
 
-    debug!("Copying exe {:?} to bundle {:?}", &runnable.exe, bundle_path);
-    copy_and_sync_file(&runnable.exe, &bundle_exe_path)
-        .chain_err(|| format!("Couldn't copy {} to {}", &runnable.exe.display(), &bundle_exe_path.display()))?;
+    debug!("Copying exe {:?} to bundle {:?}", runnable.deployed_exe(), bundle_path);
+    copy_and_sync_file(runnable.deployed_exe(), &bundle_exe_path)
+        .chain_err(|| format!("Couldn't copy {} to {}", runnable.deployed_exe().display(), &bundle_exe_path.display()))?;
 
     debug!("Copying dynamic libs to bundle");
     for src_lib_path in &build.dynamic_libraries {
@@ -58,6 +107,8 @@ fn make_remote_app_with_name(project: &Project, build: &Build, runnable: &Runnab
     project.rec_copy_excl(&runnable.source, &bundle_path, false, &[runnable.source.join("target")])?;
     debug!("Copying test_data to bundle {}", bundle_path.display());
     project.copy_test_data(&bundle_path)?;
+    debug!("Copying resources to bundle {}", bundle_path.display());
+    project.copy_bundle_resources(&bundle_path)?;
 
     Ok(BuildBundle {
         id: runnable.id.clone(),
@@ -67,3 +118,87 @@ fn make_remote_app_with_name(project: &Project, build: &Build, runnable: &Runnab
         root_dir: root_dir,
     })
 }
+
+/// Describe what `make_remote_app_with_name` would put in the bundle of
+/// `runnable`, for dry runs. Also returns the bundle it would make.
+fn describe_remote_app(project: &Project,
+                       build: &Build,
+                       artifacts: &ArtifactStore,
+                       runnable: &Runnable,
+                       bundle_name: Option<&str>) -> Result<(BuildBundle, Vec<String>)> {
+    let project = project.for_runnable(runnable)?;
+    let root_dir = artifacts.bundles_dir();
+    let bundle_path = match bundle_name {
+        Some(name) => root_dir.join(&runnable.id).join(name),
+        None => root_dir.join(&runnable.id),
+    };
+    let mut lines = vec![format!("bundle {} in {}", runnable.id, bundle_path.display())];
+    lines.push(format!("  executable {}", runnable.deployed_exe().display()));
+    lines.push(format!("  sources of {} (without target)", runnable.source.display()));
+    for td in project.conf.test_data.iter() {
+        let file = td.base.parent().unwrap_or(&PathBuf::from("/")).join(&td.source);
+        lines.push(format!("  test data {} from {}", td.id, file.display()));
+    }
+    for resource in project.conf.bundle_resources.iter() {
+        let file = resource.base.parent().unwrap_or(&PathBuf::from("/")).join(&resource.source);
+        lines.push(format!("  bundle resource {} from {}", resource.id, file.display()));
+    }
+    if build.dynamic_libraries.is_empty() {
+        lines.push(format!("  dynamic libraries found by the build in {}", root_dir.join("overlay").display()));
+    }
+    for lib in &build.dynamic_libraries {
+        lines.push(format!("  dynamic library {} in {}", lib.display(), root_dir.join("overlay").display()));
+    }
+    let build_bundle = BuildBundle {
+        id: runnable.id.clone(),
+        bundle_exe: bundle_path.join(&runnable.id),
+        bundle_dir: bundle_path,
+        lib_dir: root_dir.join("overlay"),
+        root_dir,
+    };
+    Ok((build_bundle, lines))
+}
+
+/// Content hash of a bundle (files of the bundle and of its libraries), used to
+/// skip installing a bundle whose exact content is already on the device.
+/// `inputs` are what else makes the installed app, e.g. how it is signed.
+fn bundle_hash(build_bundle: &BuildBundle, inputs: &[&str]) -> Result<String> {
+    let mut hasher = DefaultHasher::new();
+    for input in inputs {
+        input.hash(&mut hasher);
+    }
+    for dir in &[&build_bundle.bundle_dir, &build_bundle.lib_dir] {
+        let entries = WalkDir::new(dir).sort_by(|a, b| a.file_name().cmp(b.file_name()));
+        for entry in entries {
+            let entry = entry.chain_err(|| format!("Couldn't walk {}", dir.display()))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            hasher.write(entry.path().strip_prefix(dir)?.to_string_lossy().as_bytes());
+            let mut file = fs::File::open(entry.path())?;
+            let mut buffer = [0u8; 65536];
+            loop {
+                let len = file.read(&mut buffer)?;
+                if len == 0 {
+                    break;
+                }
+                hasher.write(&buffer[..len]);
+            }
+        }
+    }
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+/// Size of the files of a bundle and of its libraries, in bytes.
+fn bundle_size(build_bundle: &BuildBundle) -> Result<u64> {
+    let mut size = 0;
+    for dir in &[&build_bundle.bundle_dir, &build_bundle.lib_dir] {
+        for entry in WalkDir::new(dir) {
+            let entry = entry.chain_err(|| format!("Couldn't walk {}", dir.display()))?;
+            if entry.file_type().is_file() {
+                size += entry.metadata().chain_err(|| format!("Couldn't stat {}", entry.path().display()))?.len();
+            }
+        }
+    }
+    Ok(size)
+}
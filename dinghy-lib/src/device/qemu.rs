@@ -0,0 +1,238 @@
+//! Linux platforms run under qemu-user emulation (`qemu-aarch64`,
+//! `qemu-arm`...), with the sysroot of their toolchain: a device for each
+//! Linux platform whose emulator is installed, no hardware needed.
+
+use config::Configuration;
+use config::PlatformConfiguration;
+use coverage;
+use criterion;
+use device::describe_remote_app;
+use device::make_remote_app;
+use dinghy_build::triple::Triple;
+use errors::*;
+use forward;
+use forward::PortForward;
+use platform::custom_platform::CustomPlatform;
+use platform::regular_platform::find_sysroot;
+use platform::regular_platform::RegularPlatform;
+use project::Project;
+use report::RunResult;
+use report::TestReportParser;
+use std::env::home_dir;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+use utils::path_to_str;
+use utils::run_with_report;
+use Build;
+use BuildBundle;
+use Device;
+use DeviceCompatibility;
+use PlatformManager;
+use RunArgs;
+
+pub struct QemuDevice {
+    id: String,
+    name: String,
+    platform: String,
+    qemu: PathBuf,
+    rustc_triple: String,
+    /// Prefix of the absolute paths the emulated executables open, for the
+    /// dynamic loader and libc of the target.
+    sysroot: Option<PathBuf>,
+}
+
+impl QemuDevice {
+    fn command(&self, build: &Build, build_bundle: &BuildBundle, envs: &[String], args: &[String]) -> Result<Command> {
+        let mut command = Command::new(&self.qemu);
+        if let Some(ref sysroot) = self.sysroot {
+            command.arg("-L").arg(sysroot);
+        }
+        // Set in the emulated process only, not in qemu itself.
+        command.arg("-E").arg("RUST_BACKTRACE=1")
+            .arg("-E").arg("DINGHY=1")
+            .arg("-E").arg(format!("LD_LIBRARY_PATH={}", path_to_str(&build_bundle.lib_dir)?));
+        for env in envs {
+            command.arg("-E").arg(env);
+        }
+        command.arg(&build_bundle.bundle_exe);
+        if build.build_args.compile_mode == ::cargo::ops::CompileMode::Bench {
+            command.arg("--bench");
+        }
+        command.args(args).current_dir(&build_bundle.bundle_dir);
+        Ok(command)
+    }
+
+    fn envs(&self, build: &Build, run_args: &RunArgs) -> Result<Vec<String>> {
+        let mut envs = run_args.envs.clone();
+        if run_args.coverage {
+            envs.push(format!("LLVM_PROFILE_FILE={}", coverage::profile_file(build.artifacts(&self.id).coverage_dir())?));
+        }
+        if build.build_args.compile_mode == ::cargo::ops::CompileMode::Bench {
+            // The emulated executables write to the file system of the host.
+            envs.push(format!("{}={}", criterion::HOME_ENV, path_to_str(&build.artifacts(&self.id).criterion_dir())?));
+        }
+        Ok(envs)
+    }
+}
+
+impl DeviceCompatibility for QemuDevice {
+    fn is_compatible_with_custom_platform(&self, platform: &CustomPlatform) -> bool {
+        self.platform == platform.id
+    }
+
+    fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
+        self.platform == platform.id
+    }
+}
+
+impl Device for QemuDevice {
+    fn clean_app(&self, build_bundle: &BuildBundle) -> Result<()> {
+        let _ = fs::remove_dir_all(&build_bundle.bundle_dir);
+        Ok(())
+    }
+
+    fn debug_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<BuildBundle> {
+        unimplemented!()
+    }
+
+    fn dry_run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<String>> {
+        let mut lines = vec![];
+        let envs = self.envs(build, run_args)?;
+        let artifacts = build.artifacts(&self.id);
+        for runnable in &build.runnables {
+            let (build_bundle, bundle_lines) = describe_remote_app(project, build, &artifacts, runnable, None)?;
+            lines.extend(bundle_lines);
+            lines.push(format!("{:?}", self.command(build, &build_bundle, &envs, &run_args.args)?));
+        }
+        Ok(lines)
+    }
+
+    fn forward_port(&self, local: u16, remote: u16) -> Result<PortForward> {
+        forward::local_forward(local, remote)
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn profile_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<(BuildBundle, PathBuf)> {
+        bail!("Profiling is only supported on Android and iOS devices")
+    }
+
+    fn reverse_port(&self, local: u16, remote: u16) -> Result<(PortForward, String)> {
+        // The emulated executables share the network of the host.
+        Ok((forward::local_forward(remote, local)?, format!("127.0.0.1:{}", remote)))
+    }
+
+    fn rustc_triples(&self) -> Vec<String> {
+        vec![self.rustc_triple.clone()]
+    }
+
+    fn run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<RunResult>> {
+        let envs = self.envs(build, run_args)?;
+        if run_args.coverage {
+            let coverage_dir = build.artifacts(&self.id).coverage_dir();
+            let _ = fs::remove_dir_all(&coverage_dir);
+            fs::create_dir_all(&coverage_dir)?;
+        }
+        let mut build_bundles = vec![];
+        for runnable in &build.runnables {
+            let build_bundle = make_remote_app(project, build, &build.artifacts(&self.id), runnable)?;
+            let mut command = self.command(build, &build_bundle, &envs, &run_args.args)?;
+            info!("Run {} on {} ({:?})", runnable.id, self.id, build.build_args.compile_mode);
+            debug!("Running {:?}", command);
+            let (status, parser) = run_with_report(&mut command,
+                                                   TestReportParser::new(&self.id, &runnable.id),
+                                                   run_args.timeout)?;
+            match status {
+                Some(status) => build_bundles.push(RunResult::new(build_bundle, parser.finish_status(status))),
+                None => Err(ErrorKind::RunTimeout(runnable.id.clone(),
+                                                  run_args.timeout.map(|it| it.as_secs()).unwrap_or(0)))?,
+            }
+        }
+        Ok(build_bundles)
+    }
+
+    fn start_remote_lldb(&self) -> Result<String> {
+        unimplemented!()
+    }
+}
+
+impl Display for QemuDevice {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        Ok(fmt.write_str(format!("Qemu {{ \"id\": \"{}\", \"qemu\": \"{}\", \"sysroot\": \"{}\" }}",
+                                 self.id,
+                                 self.qemu.display(),
+                                 self.sysroot.as_ref().map_or("none".to_string(), |it| it.display().to_string())).as_str())?)
+    }
+}
+
+pub struct QemuManager {
+    conf: Arc<Configuration>
+}
+
+impl QemuManager {
+    pub fn probe(conf: Arc<Configuration>) -> Option<QemuManager> {
+        Some(QemuManager { conf })
+    }
+
+    /// The sysroot of the platform: its own, the one of its custom toolchain,
+    /// or the one found in its regular toolchain.
+    fn sysroot(&self, id: &str, platform_conf: &PlatformConfiguration, rustc_triple: &str) -> Option<PathBuf> {
+        if let Some(ref sysroot) = platform_conf.sysroot {
+            return Some(PathBuf::from(sysroot));
+        }
+        if platform_conf.toolchain.is_none() {
+            if let Some(toolchain) = self.conf.toolchains.get(rustc_triple) {
+                return toolchain.sysroot.as_ref().map(PathBuf::from);
+            }
+        }
+        platform_conf.toolchain.as_ref()
+            .map(PathBuf::from)
+            .or(home_dir().map(|it| it.join(".dinghy").join("toolchain").join(id)))
+            .and_then(|it| find_sysroot(it).ok())
+    }
+}
+
+impl PlatformManager for QemuManager {
+    fn devices(&self) -> Result<Vec<Box<Device>>> {
+        let mut devices = vec![];
+        for (id, platform_conf) in &self.conf.platforms {
+            let rustc_triple = match platform_conf.rustc_triple {
+                Some(ref rustc_triple) => rustc_triple,
+                None => continue,
+            };
+            let arch = match Triple::parse(rustc_triple).and_then(|it| it.to_qemu_arch()) {
+                Some(arch) => arch,
+                None => continue,
+            };
+            // Debian and Ubuntu name the statically linked ones qemu-<arch>-static.
+            let qemu = match ::which::which(format!("qemu-{}", arch))
+                .or_else(|_| ::which::which(format!("qemu-{}-static", arch))) {
+                Ok(qemu) => qemu,
+                Err(_) => {
+                    debug!("No qemu-{} found to run platform {}", arch, id);
+                    continue;
+                }
+            };
+            devices.push(Box::new(QemuDevice {
+                id: if id.starts_with("qemu") { id.clone() } else { format!("qemu-{}", id) },
+                name: format!("qemu-{} for {}", arch, id),
+                platform: id.clone(),
+                qemu,
+                rustc_triple: rustc_triple.clone(),
+                sysroot: self.sysroot(id, platform_conf, rustc_triple),
+            }) as _);
+        }
+        Ok(devices)
+    }
+}
@@ -0,0 +1,217 @@
+//! Devices replaying a trace recorded with `--record` (see `trace`), so that
+//! runs can be reproduced, and dinghy tested, without hardware.
+//!
+//! Each replayed device answers the calls made on it with the recorded results
+//! of the same operations, in the recorded order. The lower level operations
+//! of the trace are only there for humans.
+
+use errors::*;
+use forward::PortForward;
+use health::DeviceHealth;
+use platform::custom_platform::CustomPlatform;
+use platform::regular_platform::RegularPlatform;
+use platform::wasi::WasiPlatform;
+use project::Project;
+use report::RunResult;
+use report::TestReport;
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use trace;
+use trace::Event;
+use Build;
+use BuildBundle;
+use Device;
+use DeviceCompatibility;
+use Platform;
+use PlatformManager;
+use RunArgs;
+
+pub struct ReplayManager {
+    events: Vec<Event>,
+}
+
+impl ReplayManager {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ReplayManager> {
+        Ok(ReplayManager { events: trace::load(path)? })
+    }
+
+    pub fn new(events: Vec<Event>) -> ReplayManager {
+        ReplayManager { events }
+    }
+}
+
+impl PlatformManager for ReplayManager {
+    fn devices(&self) -> Result<Vec<Box<Device>>> {
+        let mut devices = vec![];
+        for discovered in self.events.iter().filter(|it| it.operation == trace::DISCOVER) {
+            // The host is always there, for real.
+            if discovered.device == "HOST" || devices.iter().any(|it: &ReplayDevice| it.id == discovered.device) {
+                continue;
+            }
+            devices.push(ReplayDevice::new(discovered, &self.events)?);
+        }
+        Ok(devices.into_iter().map(|it| Box::new(it) as Box<Device>).collect())
+    }
+}
+
+pub struct ReplayDevice {
+    id: String,
+    name: String,
+    description: String,
+    failure: Option<String>,
+    rustc_triples: Vec<String>,
+    events: Mutex<VecDeque<Event>>,
+}
+
+impl ReplayDevice {
+    fn new(discovered: &Event, events: &[Event]) -> Result<ReplayDevice> {
+        let description = discovered.value()?;
+        Ok(ReplayDevice {
+            id: discovered.device.clone(),
+            name: description["name"].as_str().unwrap_or("replayed device").to_string(),
+            description: discovered.detail.clone(),
+            failure: description["failure"].as_str().map(|it| it.to_string()),
+            rustc_triples: description["rustc_triples"].members()
+                .filter_map(|it| it.as_str())
+                .map(|it| it.to_string())
+                .collect(),
+            events: Mutex::new(events.iter()
+                .filter(|it| it.device == discovered.device && it.operation != trace::DISCOVER)
+                .cloned()
+                .collect()),
+        })
+    }
+
+    /// The next recorded event of `operation`, consumed.
+    fn next(&self, operation: &str) -> Result<Event> {
+        let mut events = self.events.lock().map_err(|_| "Replayed events poisoned")?;
+        let position = events.iter().position(|it| it.operation == operation)
+            .ok_or(format!("No more {} recorded for {} in the trace", operation, self.id))?;
+        Ok(events.remove(position).ok_or("Replayed event vanished")?)
+    }
+
+    fn supports(&self, rustc_triple: &str) -> bool {
+        self.rustc_triples.iter().any(|it| it == rustc_triple)
+    }
+}
+
+impl fmt::Display for ReplayDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (replayed)", self.description)
+    }
+}
+
+impl DeviceCompatibility for ReplayDevice {
+    fn is_compatible_with_custom_platform(&self, platform: &CustomPlatform) -> bool {
+        self.supports(&platform.toolchain.rustc_triple)
+    }
+
+    fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
+        self.supports(&platform.toolchain.toolchain_triple)
+    }
+
+    fn is_compatible_with_wasi_platform(&self, platform: &WasiPlatform) -> bool {
+        self.supports(&platform.rustc_triple)
+    }
+
+    fn is_compatible_with_plugin_platform(&self, platform: &Platform) -> bool {
+        platform.rustc_triple().map(|it| self.supports(it)).unwrap_or(false)
+    }
+}
+
+impl Device for ReplayDevice {
+    fn clean_app(&self, _build_bundle: &BuildBundle) -> Result<()> {
+        self.next("clean_app")?.value()?;
+        Ok(())
+    }
+
+    fn debug_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<BuildBundle> {
+        bail!("Debugging can't be replayed")
+    }
+
+    fn dry_run_app(&self, _project: &Project, build: &Build, _run_args: &RunArgs) -> Result<Vec<String>> {
+        Ok(build.runnables.iter().map(|it| format!("replay the recorded run of {} on {}", it.id, self.id)).collect())
+    }
+
+    fn failure(&self) -> Option<&str> {
+        self.failure.as_ref().map(|it| it.as_str())
+    }
+
+    fn forward_port(&self, local: u16, remote: u16) -> Result<PortForward> {
+        self.next("forward_port")?.value()?;
+        Ok(PortForward::noop(local, remote))
+    }
+
+    fn health(&self) -> Result<DeviceHealth> {
+        Ok(trace::health_from_json(self.next("health")?.value()?))
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn profile_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<(BuildBundle, PathBuf)> {
+        let event = self.next("profile_app")?;
+        let trace = event.value()?.as_str().ok_or("Invalid recorded profile")?;
+        Ok((BuildBundle::default(), PathBuf::from(trace)))
+    }
+
+    fn reverse_port(&self, local: u16, remote: u16) -> Result<(PortForward, String)> {
+        let event = self.next("reverse_port")?;
+        let address = event.value()?.as_str().ok_or("Invalid recorded reverse port")?.to_string();
+        Ok((PortForward::noop(local, remote), address))
+    }
+
+    fn rustc_triples(&self) -> Vec<String> {
+        self.rustc_triples.clone()
+    }
+
+    fn run_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<Vec<RunResult>> {
+        let event = self.next("run_app")?;
+        Ok(event.value()?.members()
+            .map(|json| {
+                let report = TestReport::from_json(json);
+                RunResult::new(BuildBundle { id: report.runnable.clone(), ..BuildBundle::default() }, report)
+            })
+            .collect())
+    }
+
+    fn start_remote_lldb(&self) -> Result<String> {
+        let event = self.next("start_remote_lldb")?;
+        Ok(event.value()?.as_str().ok_or("Invalid recorded lldb address")?.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json;
+
+    #[test]
+    fn replay_recorded_device() {
+        let trace = r#"
+{"device":"emulator-5554","operation":"discover","detail":"Android/emulator-5554","result":{"ok":{"name":"android device","rustc_triples":["x86_64-linux-android"]}}}
+{"device":"emulator-5554","operation":"property","detail":"ro.product.cpu.abilist","result":{"ok":"x86_64"}}
+{"device":"emulator-5554","operation":"health","detail":"","result":{"ok":{"battery_level":50,"charging":true}}}
+{"device":"emulator-5554","operation":"health","detail":"","result":{"error":"adb died"}}
+"#;
+        let events = trace.lines()
+            .filter(|it| !it.is_empty())
+            .map(|it| Event::from_json(&json::parse(it).unwrap()).unwrap())
+            .collect();
+        let devices = ReplayManager::new(events).devices().unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].id(), "emulator-5554");
+        assert_eq!(devices[0].rustc_triples(), vec!["x86_64-linux-android".to_string()]);
+        assert_eq!(devices[0].health().unwrap().battery_level, Some(50));
+        assert!(devices[0].health().is_err());
+        assert!(devices[0].health().is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! The iOS simulators known to `xcrun simctl`, and their boot on demand
+//! (`--sim`).
+
+use errors::*;
+use json;
+use json::JsonValue;
+use std::process::Command;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Simulator {
+    pub udid: String,
+    /// Device type, e.g. `iPhone 8`.
+    pub name: String,
+    /// iOS version of the runtime, e.g. `11.2`.
+    pub os: String,
+    pub booted: bool,
+}
+
+/// The available iOS simulators.
+pub fn list() -> Result<Vec<Simulator>> {
+    let output = Command::new("xcrun").args(&["simctl", "list", "--json", "devices"]).output()
+        .chain_err(|| "Couldn't run xcrun simctl, simulators need Xcode")?;
+    if !output.status.success() {
+        bail!("xcrun simctl list failed: {}", String::from_utf8_lossy(&output.stderr).trim())
+    }
+    parse_list(&json::parse(&String::from_utf8(output.stdout)?)?)
+}
+
+fn parse_list(list: &JsonValue) -> Result<Vec<Simulator>> {
+    let mut simulators = vec![];
+    for (runtime, devices) in list["devices"].entries() {
+        let os = match runtime_version(runtime) {
+            Some(os) => os,
+            None => continue,
+        };
+        for device in devices.members() {
+            let available = device["isAvailable"].as_bool()
+                .unwrap_or(!device["availability"].as_str().map(|it| it.contains("unavailable")).unwrap_or(false));
+            if !available {
+                continue;
+            }
+            simulators.push(Simulator {
+                udid: device["udid"].as_str().ok_or("unexpected simulator list format (missing udid)")?.to_string(),
+                name: device["name"].as_str().ok_or("unexpected simulator list format (missing name)")?.to_string(),
+                os: os.clone(),
+                booted: device["state"] == "Booted",
+            });
+        }
+    }
+    Ok(simulators)
+}
+
+/// The iOS version of a runtime, named `iOS 11.2` by older Xcode versions, and
+/// `com.apple.CoreSimulator.SimRuntime.iOS-17-2` by the newer ones. None for
+/// the other platforms (watchOS, tvOS...).
+fn runtime_version(runtime: &str) -> Option<String> {
+    let name = runtime.rsplit('.').next().unwrap_or(runtime);
+    if runtime.starts_with("iOS ") {
+        Some(runtime["iOS ".len()..].to_string())
+    } else if name.starts_with("iOS-") {
+        Some(name["iOS-".len()..].replace('-', "."))
+    } else {
+        None
+    }
+}
+
+fn version_key(os: &str) -> Vec<u32> {
+    os.split('.').map(|it| it.parse().unwrap_or(0)).collect()
+}
+
+/// The simulator of device type `name` (case insensitive) running the iOS
+/// version `os` (or a more precise one, `17` matching `17.2`), preferably
+/// already booted, then with the most recent iOS.
+pub fn select<'a>(simulators: &'a [Simulator], name: &str, os: Option<&str>) -> Option<&'a Simulator> {
+    simulators.iter()
+        .filter(|it| it.name.to_lowercase() == name.to_lowercase())
+        .filter(|it| os.map(|os| it.os == os || it.os.starts_with(&format!("{}.", os))).unwrap_or(true))
+        .max_by_key(|it| (it.booted, version_key(&it.os)))
+}
+
+/// A simulator booted for this run, shut down when dropped unless it was
+/// already running or is to be kept alive.
+pub struct BootedSimulator {
+    udid: String,
+    shutdown: bool,
+}
+
+impl BootedSimulator {
+    pub fn udid(&self) -> &str {
+        &self.udid
+    }
+}
+
+impl Drop for BootedSimulator {
+    fn drop(&mut self) {
+        if self.shutdown {
+            info!("Shutting down simulator {}", self.udid);
+            if let Err(e) = Command::new("xcrun").args(&["simctl", "shutdown", &self.udid]).status() {
+                warn!("Couldn't shut down simulator {}: {}", self.udid, e);
+            }
+        }
+    }
+}
+
+/// Find the simulator of device type `name` and iOS version `os`, and boot it
+/// if needed.
+pub fn boot(name: &str, os: Option<&str>, keep_alive: bool) -> Result<BootedSimulator> {
+    let simulators = list()?;
+    let simulator = select(&simulators, name, os).ok_or_else(|| {
+        let available = simulators.iter().map(|it| format!("{} (iOS {})", it.name, it.os)).collect::<Vec<_>>();
+        format!("No {} simulator{} found, available ones: {}",
+                name, os.map(|it| format!(" with iOS {}", it)).unwrap_or(String::new()), available.join(", "))
+    })?;
+    if simulator.booted {
+        debug!("Simulator {} ({} iOS {}) is already booted", simulator.udid, simulator.name, simulator.os);
+        return Ok(BootedSimulator { udid: simulator.udid.clone(), shutdown: false });
+    }
+    info!("Booting simulator {} (iOS {})", simulator.name, simulator.os);
+    let booted = BootedSimulator { udid: simulator.udid.clone(), shutdown: !keep_alive };
+    if !Command::new("xcrun").args(&["simctl", "boot", &simulator.udid]).status()?.success() {
+        bail!("Couldn't boot simulator {}", simulator.udid)
+    }
+    // Wait for the simulator to be ready (not known by older Xcode versions,
+    // whose boot command waits).
+    let _ = Command::new("xcrun").args(&["simctl", "bootstatus", &simulator.udid]).status();
+    Ok(booted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_simulator() {
+        let list = json::parse(r#"{"devices": {
+            "iOS 11.2": [{"state": "Shutdown", "availability": "(available)", "name": "iPhone 8", "udid": "A"}],
+            "com.apple.CoreSimulator.SimRuntime.iOS-17-2": [
+                {"state": "Shutdown", "isAvailable": true, "name": "iPhone 15 Pro", "udid": "B"},
+                {"state": "Shutdown", "isAvailable": false, "name": "iPhone 8", "udid": "C"}],
+            "com.apple.CoreSimulator.SimRuntime.iOS-16-4": [{"state": "Booted", "isAvailable": true, "name": "iPhone 8", "udid": "D"}],
+            "com.apple.CoreSimulator.SimRuntime.watchOS-10-2": [{"state": "Booted", "isAvailable": true, "name": "Apple Watch", "udid": "E"}]
+        }}"#).unwrap();
+        let simulators = parse_list(&list).unwrap();
+        assert_eq!(simulators.len(), 3);
+        assert_eq!(select(&simulators, "iphone 15 pro", None).unwrap().udid, "B");
+        assert_eq!(select(&simulators, "iPhone 8", None).unwrap().udid, "D");
+        assert_eq!(select(&simulators, "iPhone 8", Some("11")).unwrap().udid, "A");
+        assert!(select(&simulators, "iPhone 8", Some("17.2")).is_none());
+    }
+}
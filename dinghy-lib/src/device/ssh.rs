@@ -1,8 +1,21 @@
+use cleanup;
 use config::{Configuration, SshDeviceConfiguration};
+use coverage;
+use criterion;
 use errors::*;
+use logging;
+use forward::PortForward;
+use installs;
+use device::bundle_size;
+use device::check_exec_probe;
+use device::describe_remote_app;
+use device::exec_probe_command;
 use device::make_remote_app;
+use platform::custom_platform::CustomPlatform;
 use platform::regular_platform::RegularPlatform;
 use project::Project;
+use report::RunResult;
+use report::TestReportParser;
 use std::fmt;
 use std::fmt::Display;
 use std::fmt::Formatter;
@@ -10,24 +23,37 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 use std::sync::Arc;
+use std::sync::Mutex;
+use timings;
+use timings::Phase;
 use utils::path_to_str;
+use utils::run_with_report;
 use Build;
 use Device;
 use DeviceCompatibility;
 use PlatformManager;
 use BuildBundle;
 use Runnable;
+use RunArgs;
 
+#[derive(Clone)]
 pub struct SshDevice {
     id: String,
     conf: SshDeviceConfiguration,
+    work_dir: PathBuf,
 }
 
 impl SshDevice {
     fn install_app(&self, project: &Project, build: &Build, runnable: &Runnable) -> Result<(BuildBundle, BuildBundle)> {
-        let build_bundle = make_remote_app(project, build, runnable)?;
+        let _span = logging::span(format!("install {}", runnable.id));
+        let _timer = timings::start(Phase::Transfer);
+        let build_bundle = make_remote_app(project, build, &build.artifacts(&self.id), runnable)?;
         let remote_bundle = self.to_remote_bundle(&build_bundle)?;
+        installs::record(&self.id, path_to_str(&remote_bundle.bundle_dir)?);
 
+        let probe = self.ssh_command()?.arg(exec_probe_command(&self.work_dir)).output()?;
+        check_exec_probe(&self.id, &self.work_dir,
+                         &format!("{}{}", String::from_utf8_lossy(&probe.stdout), String::from_utf8_lossy(&probe.stderr)))?;
         let _ = self.ssh_command()?
             .arg("mkdir").arg("-p").arg(&remote_bundle.bundle_dir)
             .status();
@@ -35,9 +61,39 @@ impl SshDevice {
         info!("Install {} to {}", runnable.id, self.id);
         self.sync(&build_bundle.bundle_dir, &remote_bundle.bundle_dir)?;
         self.sync(&build_bundle.lib_dir, &remote_bundle.lib_dir)?;
+        timings::transferred(bundle_size(&build_bundle)?);
         Ok((build_bundle, remote_bundle))
     }
 
+    fn kill_remote(&self, remote_bundle: &BuildBundle) -> Result<()> {
+        debug!("Killing {} on {}", remote_bundle.bundle_exe.display(), self.id);
+        let _ = self.ssh_command()?
+            .arg(&format!("pkill -f {}", path_to_str(&remote_bundle.bundle_exe)?))
+            .status()?;
+        Ok(())
+    }
+
+    /// Keep an ssh port forwarding tunnel (`-L` or `-R`) open as long as the
+    /// returned forward lives.
+    fn tunnel(&self, option: &str, spec: String, local: u16, remote: u16) -> Result<PortForward> {
+        let mut command = Command::new("ssh");
+        command.arg("-N")
+            .arg("-o").arg("ExitOnForwardFailure=yes")
+            .arg(option).arg(spec)
+            .arg(format!("{}@{}", self.conf.username, self.conf.hostname));
+        if let Some(port) = self.conf.port {
+            command.arg("-p").arg(&format!("{}", port));
+        }
+        debug!("Running {:?}", command);
+        let tunnel = Mutex::new(command.spawn()?);
+        Ok(PortForward::new(local, remote, move || {
+            if let Ok(mut tunnel) = tunnel.lock() {
+                let _ = tunnel.kill();
+                let _ = tunnel.wait();
+            }
+        }))
+    }
+
     fn ssh_command(&self) -> Result<Command> {
         let mut command = Command::new("ssh");
         command.arg(format!("{}@{}", self.conf.username, self.conf.hostname));
@@ -50,36 +106,82 @@ impl SshDevice {
         Ok(command)
     }
 
-    fn sync<FP: AsRef<Path>, TP: AsRef<Path>>(&self, from_path: FP, to_path: TP) -> Result<()> {
+    fn pull<FP: AsRef<Path>, TP: AsRef<Path>>(&self, from_path: FP, to_path: TP) -> Result<()> {
         let mut command = Command::new("/usr/bin/rsync");
-        command.arg("-a").arg("-v");
+        command.arg("-a");
         if let Some(port) = self.conf.port {
-            command.arg(&*format!("ssh -p {}", port));
+            command.arg("-e").arg(&*format!("ssh -p {}", port));
         };
+        command
+            .arg(&format!("{}@{}:{}/", self.conf.username, self.conf.hostname, path_to_str(&from_path.as_ref())?))
+            .arg(&format!("{}/", path_to_str(&to_path.as_ref())?));
+        debug!("Running {:?}", command);
+        if !command.status()?.success() {
+            bail!("Error pulling from ssh directory ({:?})", command)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn remote_coverage_dir(&self) -> PathBuf {
+        self.work_dir.join("coverage")
+    }
+
+    /// Kept between the runs, for the baselines saved on the device.
+    fn remote_criterion_dir(&self) -> PathBuf {
+        self.work_dir.join("criterion")
+    }
+
+    fn run_command(&self, build: &Build, remote_bundle: &BuildBundle, envs: &[String], args: &[String]) -> Result<String> {
+        Ok(format!(
+            "cd '{}' ; {} RUST_BACKTRACE=1 DINGHY=1 LD_LIBRARY_PATH=\"{}:$LD_LIBRARY_PATH\" {} {} {}",
+            path_to_str(&remote_bundle.bundle_dir)?,
+            envs.join(" "),
+            path_to_str(&remote_bundle.lib_dir)?,
+            path_to_str(&remote_bundle.bundle_exe)?,
+            if build.build_args.compile_mode == ::cargo::ops::CompileMode::Bench { "--bench" } else { "" },
+            args.join(" ")
+            ))
+    }
+
+    fn sync<FP: AsRef<Path>, TP: AsRef<Path>>(&self, from_path: FP, to_path: TP) -> Result<()> {
+        let mut command = self.sync_command(from_path, to_path)?;
         if !log_enabled!(::log::Level::Debug) {
             command.stdout(::std::process::Stdio::null());
             command.stderr(::std::process::Stdio::null());
         }
-        command
-            .arg(&format!("{}/", path_to_str(&from_path.as_ref())?))
-            .arg(&format!("{}@{}:{}/", self.conf.username, self.conf.hostname, path_to_str(&to_path.as_ref())?));
         debug!("Running {:?}", command);
-        if !command.status()?.success() {
+        let status = command.status();
+        ::trace::record(&self.id, "push", &format!("{:?}", command), &status, |it| it.success().into());
+        if !status?.success() {
             bail!("Error syncing ssh directory ({:?})", command)
         } else {
             Ok(())
         }
     }
 
+    fn sync_command<FP: AsRef<Path>, TP: AsRef<Path>>(&self, from_path: FP, to_path: TP) -> Result<Command> {
+        let mut command = Command::new("/usr/bin/rsync");
+        command.arg("-a").arg("-v");
+        if let Some(port) = self.conf.port {
+            command.arg(&*format!("ssh -p {}", port));
+        };
+        command
+            .arg(&format!("{}/", path_to_str(&from_path.as_ref())?))
+            .arg(&format!("{}@{}:{}/", self.conf.username, self.conf.hostname, path_to_str(&to_path.as_ref())?));
+        Ok(command)
+    }
+
     fn to_remote_bundle(&self, build_bundle: &BuildBundle) -> Result<BuildBundle> {
-        let remote_prefix = PathBuf::from(self.conf.path.clone()
-            .unwrap_or("/tmp".into()))
-            .join("dinghy");
-        build_bundle.replace_prefix_with(remote_prefix)
+        build_bundle.replace_prefix_with(&self.work_dir)
     }
 }
 
 impl DeviceCompatibility for SshDevice {
+    fn is_compatible_with_custom_platform(&self, platform: &CustomPlatform) -> bool {
+        self.conf.platform.as_ref().map_or(false, |it| *it == platform.id)
+    }
+
     fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
         self.conf.platform.as_ref().map_or(false, |it| *it == platform.id)
     }
@@ -96,10 +198,43 @@ impl Device for SshDevice {
         Ok(())
     }
 
-    fn debug_app(&self, _project: &Project, _build: &Build, _args: &[&str], _envs: &[&str]) -> Result<BuildBundle> {
+    fn debug_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<BuildBundle> {
         unimplemented!()
     }
 
+    fn dry_run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<String>> {
+        let mut lines = vec![];
+        let args: Vec<String> = run_args.args.iter().map(|a| ::shell_escape::escape(a.as_str().into()).to_string()).collect();
+        let mut envs = run_args.envs.clone();
+        if run_args.coverage {
+            envs.push(format!("LLVM_PROFILE_FILE='{}'", coverage::profile_file(&self.remote_coverage_dir())?));
+        }
+        let bench = build.build_args.compile_mode == ::cargo::ops::CompileMode::Bench;
+        if bench {
+            envs.push(format!("{}='{}'", criterion::HOME_ENV, path_to_str(&self.remote_criterion_dir())?));
+        }
+        let artifacts = build.artifacts(&self.id);
+        for runnable in &build.runnables {
+            let (build_bundle, bundle_lines) = describe_remote_app(project, build, &artifacts, runnable, None)?;
+            let remote_bundle = self.to_remote_bundle(&build_bundle)?;
+            lines.extend(bundle_lines);
+            lines.push(format!("{:?}", self.sync_command(&build_bundle.bundle_dir, &remote_bundle.bundle_dir)?));
+            lines.push(format!("{:?}", self.sync_command(&build_bundle.lib_dir, &remote_bundle.lib_dir)?));
+            lines.push(format!("{:?}", self.ssh_command()?.arg(&self.run_command(build, &remote_bundle, &envs, &args)?)));
+        }
+        if run_args.coverage {
+            lines.push(format!("pull {} to {}", self.remote_coverage_dir().display(), artifacts.coverage_dir().display()));
+        }
+        if bench {
+            lines.push(format!("pull {} to {}", self.remote_criterion_dir().display(), artifacts.criterion_dir().display()));
+        }
+        Ok(lines)
+    }
+
+    fn forward_port(&self, local: u16, remote: u16) -> Result<PortForward> {
+        self.tunnel("-L", format!("{}:localhost:{}", local, remote), local, remote)
+    }
+
     fn id(&self) -> &str {
         &self.id
     }
@@ -108,37 +243,90 @@ impl Device for SshDevice {
         &self.id
     }
 
-    fn run_app(&self, project: &Project, build: &Build, args: &[&str], envs: &[&str]) -> Result<Vec<BuildBundle>> {
+    fn profile_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<(BuildBundle, PathBuf)> {
+        bail!("Profiling is only supported on Android and iOS devices")
+    }
+
+    fn reverse_port(&self, local: u16, remote: u16) -> Result<(PortForward, String)> {
+        let forward = self.tunnel("-R", format!("{}:localhost:{}", remote, local), local, remote)?;
+        Ok((forward, format!("127.0.0.1:{}", remote)))
+    }
+
+    fn run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<RunResult>> {
         let mut build_bundles = vec![];
-        let args:Vec<String> = args.iter().map(|&a| ::shell_escape::escape(a.into()).to_string()).collect();
+        let args:Vec<String> = run_args.args.iter().map(|a| ::shell_escape::escape(a.as_str().into()).to_string()).collect();
+        let mut envs = run_args.envs.clone();
+        if run_args.coverage {
+            let remote_coverage_dir = self.remote_coverage_dir();
+            let _ = self.ssh_command()?
+                .arg(&format!("rm -rf '{0}' ; mkdir -p '{0}'", path_to_str(&remote_coverage_dir)?))
+                .status()?;
+            envs.push(format!("LLVM_PROFILE_FILE='{}'", coverage::profile_file(&remote_coverage_dir)?));
+        }
+        let bench = build.build_args.compile_mode == ::cargo::ops::CompileMode::Bench;
+        if bench {
+            let remote_criterion_dir = self.remote_criterion_dir();
+            let _ = self.ssh_command()?
+                .arg(&format!("mkdir -p '{}'", path_to_str(&remote_criterion_dir)?))
+                .status()?;
+            envs.push(format!("{}='{}'", criterion::HOME_ENV, path_to_str(&remote_criterion_dir)?));
+        }
         for runnable in &build.runnables {
             let (build_bundle, remote_bundle) = self.install_app(&project, &build, &runnable)?;
-            let command = format!(
-                "cd '{}' ; {} RUST_BACKTRACE=1 DINGHY=1 LD_LIBRARY_PATH=\"{}:$LD_LIBRARY_PATH\" {} {} {}",
-                path_to_str(&remote_bundle.bundle_dir)?,
-                envs.join(" "),
-                path_to_str(&remote_bundle.lib_dir)?,
-                path_to_str(&remote_bundle.bundle_exe)?,
-                if build.build_args.compile_mode == ::cargo::ops::CompileMode::Bench { "--bench" } else { "" },
-                args.join(" ")
-                );
+            let command = self.run_command(build, &remote_bundle, &envs, &args)?;
             info!("Run {} on {} ({:?})", runnable.id, self.id, build.build_args.compile_mode);
 
-            let status = self.ssh_command()?
-                .arg(&command)
-                .status()?;
-            if !status.success() {
-                Err("Test failed 🐛")?
+            let _cleanup = {
+                let (device, remote_bundle) = (self.clone(), remote_bundle.clone());
+                cleanup::on_interrupt(move || { let _ = device.kill_remote(&remote_bundle); })
+            };
+            let (status, parser) = run_with_report(self.ssh_command()?.arg(&command),
+                                                   TestReportParser::new(&self.id, &runnable.id),
+                                                   run_args.timeout)?;
+            match status {
+                Some(status) => build_bundles.push(RunResult::new(build_bundle, parser.finish_status(status))),
+                None => {
+                    self.kill_remote(&remote_bundle)?;
+                    Err(ErrorKind::RunTimeout(runnable.id.clone(),
+                                              run_args.timeout.map(|it| it.as_secs()).unwrap_or(0)))?
+                }
+            }
+        }
+        if run_args.coverage {
+            self.pull(self.remote_coverage_dir(), build.artifacts(&self.id).coverage_dir())?;
+        }
+        if bench {
+            if let Err(e) = self.pull(self.remote_criterion_dir(), build.artifacts(&self.id).criterion_dir()) {
+                warn!("Couldn't pull criterion data from {}: {}", self.id, e);
             }
-
-            build_bundles.push(build_bundle);
         }
         Ok(build_bundles)
     }
 
+    fn run_shell(&self, command: &str) -> Result<()> {
+        debug!("Running `{}' on {}", command, self.id);
+        if !self.ssh_command()?.arg(command).status()?.success() {
+            bail!("`{}' failed on {}", command, self.id)
+        }
+        Ok(())
+    }
+
     fn start_remote_lldb(&self) -> Result<String> {
         unimplemented!()
     }
+
+    fn uninstall(&self, location: &str) -> Result<()> {
+        if !Path::new(location).starts_with(&self.work_dir) {
+            bail!("{} is not a bundle of the work directory of {}", location, self.id)
+        }
+        let status = self.ssh_command()?
+            .arg(&format!("rm -rf {}", ::shell_escape::escape(location.into())))
+            .status()?;
+        if !status.success() {
+            bail!("Couldn't remove {} from {}", location, self.id)
+        }
+        Ok(())
+    }
 }
 
 impl Display for SshDevice {
@@ -169,6 +357,9 @@ impl PlatformManager for SshDeviceManager {
                 Box::new(SshDevice {
                     id: k.clone(),
                     conf: conf.clone(),
+                    work_dir: self.conf.device(k).work_dir
+                        .map(PathBuf::from)
+                        .unwrap_or(PathBuf::from(conf.path.clone().unwrap_or("/tmp".into())).join("dinghy")),
                 }) as _
             })
             .collect())
@@ -0,0 +1,175 @@
+//! WebAssembly (WASI) executables run by a runtime of the host, wasmtime or
+//! wasmer, their bundle being preopened as their current directory so that
+//! test data is found at the usual relative paths.
+
+use config::Configuration;
+use device::describe_remote_app;
+use device::make_remote_app;
+use errors::*;
+use forward;
+use forward::PortForward;
+use platform::wasi::WasiPlatform;
+use project::Project;
+use report::RunResult;
+use report::TestReportParser;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+use utils::path_to_str;
+use utils::run_with_report;
+use Build;
+use BuildBundle;
+use Device;
+use DeviceCompatibility;
+use PlatformManager;
+use RunArgs;
+
+pub struct WasiDevice {
+    runtime: PathBuf,
+    runtime_args: Vec<String>,
+}
+
+impl WasiDevice {
+    fn is_wasmer(&self) -> bool {
+        self.runtime.file_name().map(|it| it.to_string_lossy().contains("wasmer")).unwrap_or(false)
+    }
+
+    fn command(&self, build: &Build, build_bundle: &BuildBundle, envs: &[String], args: &[String]) -> Result<Command> {
+        let bundle_dir = path_to_str(&build_bundle.bundle_dir)?;
+        let mut command = Command::new(&self.runtime);
+        command.arg("run");
+        if self.is_wasmer() {
+            command.arg("--mapdir").arg(format!(".:{}", bundle_dir));
+        } else {
+            command.arg("--dir").arg(format!("{}::.", bundle_dir));
+        }
+        for env in ["RUST_BACKTRACE=1", "DINGHY=1"].iter().map(|it| it.to_string()).chain(envs.iter().cloned()) {
+            command.arg("--env").arg(env);
+        }
+        command.args(&self.runtime_args).arg(&build_bundle.bundle_exe);
+        if self.is_wasmer() {
+            command.arg("--");
+        }
+        if build.build_args.compile_mode == ::cargo::ops::CompileMode::Bench {
+            command.arg("--bench");
+        }
+        command.args(args).current_dir(&build_bundle.bundle_dir);
+        Ok(command)
+    }
+}
+
+impl DeviceCompatibility for WasiDevice {
+    fn is_compatible_with_wasi_platform(&self, _platform: &WasiPlatform) -> bool {
+        true
+    }
+}
+
+impl Device for WasiDevice {
+    fn clean_app(&self, build_bundle: &BuildBundle) -> Result<()> {
+        let _ = fs::remove_dir_all(&build_bundle.bundle_dir);
+        Ok(())
+    }
+
+    fn debug_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<BuildBundle> {
+        unimplemented!()
+    }
+
+    fn dry_run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<String>> {
+        let mut lines = vec![];
+        let artifacts = build.artifacts(self.id());
+        for runnable in &build.runnables {
+            let (build_bundle, bundle_lines) = describe_remote_app(project, build, &artifacts, runnable, None)?;
+            lines.extend(bundle_lines);
+            lines.push(format!("{:?}", self.command(build, &build_bundle, &run_args.envs, &run_args.args)?));
+        }
+        Ok(lines)
+    }
+
+    fn forward_port(&self, local: u16, remote: u16) -> Result<PortForward> {
+        forward::local_forward(local, remote)
+    }
+
+    fn id(&self) -> &str {
+        "wasi"
+    }
+
+    fn name(&self) -> &str {
+        if self.is_wasmer() { "wasmer" } else { "wasmtime" }
+    }
+
+    fn profile_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<(BuildBundle, PathBuf)> {
+        bail!("Profiling is only supported on Android and iOS devices")
+    }
+
+    fn reverse_port(&self, local: u16, remote: u16) -> Result<(PortForward, String)> {
+        Ok((forward::local_forward(remote, local)?, format!("127.0.0.1:{}", remote)))
+    }
+
+    fn run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<RunResult>> {
+        if run_args.coverage {
+            warn!("Coverage is not supported on WebAssembly and will be ignored");
+        }
+        let mut build_bundles = vec![];
+        for runnable in &build.runnables {
+            let build_bundle = make_remote_app(project, build, &build.artifacts(self.id()), runnable)?;
+            let mut command = self.command(build, &build_bundle, &run_args.envs, &run_args.args)?;
+            info!("Run {} with {} ({:?})", runnable.id, self.name(), build.build_args.compile_mode);
+            debug!("Running {:?}", command);
+            let (status, parser) = run_with_report(&mut command,
+                                                   TestReportParser::new(self.id(), &runnable.id),
+                                                   run_args.timeout)?;
+            match status {
+                Some(status) => build_bundles.push(RunResult::new(build_bundle, parser.finish_status(status))),
+                None => Err(ErrorKind::RunTimeout(runnable.id.clone(),
+                                                  run_args.timeout.map(|it| it.as_secs()).unwrap_or(0)))?,
+            }
+        }
+        Ok(build_bundles)
+    }
+
+    fn start_remote_lldb(&self) -> Result<String> {
+        unimplemented!()
+    }
+}
+
+impl Display for WasiDevice {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        Ok(fmt.write_str(format!("Wasi {{ \"runtime\": \"{}\" }}", self.runtime.display()).as_str())?)
+    }
+}
+
+pub struct WasiManager {
+    conf: Arc<Configuration>
+}
+
+impl WasiManager {
+    pub fn probe(conf: Arc<Configuration>) -> Option<WasiManager> {
+        Some(WasiManager { conf })
+    }
+}
+
+impl PlatformManager for WasiManager {
+    fn devices(&self) -> Result<Vec<Box<Device>>> {
+        // Without a runtime, there is no wasi device, but the other devices
+        // are still usable.
+        let runtime = match self.conf.wasi.runtime {
+            Some(ref runtime) => ::which::which(runtime)
+                .map_err(|_| warn!("WebAssembly runtime {} of the [wasi] configuration not found", runtime))
+                .ok(),
+            None => ::which::which("wasmtime").or_else(|_| ::which::which("wasmer")).ok(),
+        };
+        if runtime.is_none() {
+            debug!("No WebAssembly runtime, no wasi device");
+        }
+        Ok(runtime.into_iter()
+            .map(|runtime| Box::new(WasiDevice {
+                runtime,
+                runtime_args: self.conf.wasi.runtime_args.clone().unwrap_or(vec![]),
+            }) as _)
+            .collect())
+    }
+}
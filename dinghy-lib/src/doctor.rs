@@ -0,0 +1,260 @@
+//! `cargo dinghy doctor`: the environment dinghy silently relies on (Xcode,
+//! signing, Android SDK and NDK, rustup targets, tools in the `PATH`, C
+//! libraries of the `-sys` crates), checked one item at a time, with how to
+//! fix what is missing.
+
+use compiler;
+use compiler::Compiler;
+use config::Configuration;
+use crossbuild;
+use device::android;
+use errors::*;
+use setup;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+
+pub struct Check {
+    pub name: String,
+    /// What was found, or what is wrong.
+    pub detail: String,
+    /// How to fix the problem, `None` if the check passed.
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn pass<N: Into<String>, D: Into<String>>(name: N, detail: D) -> Check {
+        Check { name: name.into(), detail: detail.into(), fix: None }
+    }
+
+    fn fail<N: Into<String>, D: Into<String>, F: Into<String>>(name: N, detail: D, fix: F) -> Check {
+        Check { name: name.into(), detail: detail.into(), fix: Some(fix.into()) }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.fix.is_none()
+    }
+}
+
+/// Run all the checks relevant to this host and configuration.
+pub fn checks(conf: &Arc<Configuration>, compiler: &Arc<Compiler>) -> Result<Vec<Check>> {
+    let mut checks = vec![];
+    checks.push(tool("cargo", "cargo", "install Rust with rustup (https://rustup.rs)"));
+    checks.push(tool("rustup", "rustup", "install rustup (https://rustup.rs), dinghy uses it to install the targets"));
+    checks.extend(rustup_targets(conf, compiler)?);
+    checks.extend(sys_libraries(conf, compiler)?);
+    let android = conf.platforms.values()
+        .any(|it| it.rustc_triple.as_ref().map(|it| it.contains("-android")).unwrap_or(false));
+    if android || android::adb().is_ok() {
+        checks.push(adb());
+        checks.push(ndk());
+    }
+    if cfg!(target_os = "macos") {
+        checks.push(xcode());
+        checks.push(device_support());
+        checks.push(codesign_identities(conf));
+        checks.push(provisioning_profiles());
+        checks.push(usbmuxd());
+    }
+    if !conf.ssh_devices.is_empty() {
+        checks.push(tool("ssh", "ssh", "install an OpenSSH client, needed by the ssh devices"));
+        if Path::new("/usr/bin/rsync").is_file() {
+            checks.push(Check::pass("rsync", "/usr/bin/rsync"));
+        } else {
+            checks.push(Check::fail("rsync", "/usr/bin/rsync not found",
+                                    "install rsync, the ssh devices use it to copy the bundles"));
+        }
+    }
+    if !conf.docker_devices.is_empty() {
+        checks.push(tool("docker", "docker", "install Docker, needed by the Docker devices"));
+    }
+    if conf.ios.cbindgen.is_some() {
+        checks.push(tool("cbindgen", "cbindgen", "run `cargo install cbindgen`, needed by `cargo dinghy ios-xcode`"));
+    }
+    Ok(checks)
+}
+
+/// An executable expected in the `PATH`.
+fn tool(name: &str, executable: &str, fix: &str) -> Check {
+    match ::which::which(executable) {
+        Ok(path) => Check::pass(name, path.display().to_string()),
+        Err(_) => Check::fail(name, format!("{} not found in the PATH", executable), fix),
+    }
+}
+
+/// The standard library of every usable platform.
+fn rustup_targets(conf: &Arc<Configuration>, compiler: &Arc<Compiler>) -> Result<Vec<Check>> {
+    let mut checks = vec![];
+    for platform in setup::platforms(conf, compiler)? {
+        let name = format!("platform {}", platform.id);
+        if let Some(unavailable) = platform.unavailable {
+            checks.push(Check::fail(name, unavailable, "fix or remove its [platforms] configuration"));
+            continue;
+        }
+        let triple = match platform.rustc_triple {
+            Some(triple) => triple,
+            None => continue,
+        };
+        checks.push(match platform.target_installed {
+            Some(false) => Check::fail(name, format!("the {} standard library is not installed", triple),
+                                       format!("run `rustup target add {}`", triple)),
+            _ => Check::pass(name, triple),
+        });
+    }
+    Ok(checks)
+}
+
+/// The libraries of the `-sys` crates of the project that can't find them by
+/// themselves when cross compiling (the classic "openssl-sys can't cross
+/// compile"), for every cross platform. The crates built with their
+/// `vendored` feature bring their own.
+fn sys_libraries(conf: &Arc<Configuration>, compiler: &Arc<Compiler>) -> Result<Vec<Check>> {
+    let lock_file = match ::cargo::util::important_paths::find_root_manifest_for_wd(None, &env::current_dir()?) {
+        Ok(manifest) => manifest.with_file_name("Cargo.lock"),
+        Err(_) => return Ok(vec![]),
+    };
+    let mut lock = String::new();
+    if fs::File::open(&lock_file).and_then(|mut it| it.read_to_string(&mut lock)).is_err() {
+        return Ok(vec![]);
+    }
+    let used = crossbuild::SYS_CRATES.iter()
+        .filter(|&&(krate, _)| lock.contains(&format!("name = \"{}\"", krate)))
+        .filter(|&&(krate, _)| !compiler::resolved_features(krate).map(|it| it.contains("vendored")).unwrap_or(false))
+        .collect::<Vec<_>>();
+    if used.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut triples = setup::platforms(conf, compiler)?.into_iter()
+        .filter(|it| it.unavailable.is_none())
+        .filter_map(|it| it.rustc_triple)
+        .collect::<Vec<_>>();
+    triples.sort();
+    triples.dedup();
+    let mut checks = vec![];
+    for triple in triples {
+        for &&(krate, var) in &used {
+            let name = format!("{} for {}", krate, triple);
+            checks.push(if crossbuild::has_target_env(conf, &triple, var) {
+                Check::pass(name, format!("{} is set", var))
+            } else {
+                Check::fail(name, format!("{} is not set, its build script won't find the library of the target", var),
+                            format!("build the library for {} and add `{} = \"/path/to/it\"` to [target_env.\"{}\"] \
+                                     in .dinghy.toml (or enable the vendored feature of the crate, if it has one)",
+                                    triple, var, triple))
+            });
+        }
+    }
+    Ok(checks)
+}
+
+fn adb() -> Check {
+    let adb = match android::adb() {
+        Ok(adb) => adb,
+        Err(_) => return Check::fail("adb", "adb not found",
+                                     "install the Android SDK platform tools, and add them to the PATH or set ANDROID_HOME"),
+    };
+    let version = Command::new(&adb).arg("version").output().ok()
+        .and_then(|it| String::from_utf8(it.stdout).ok())
+        .and_then(|it| it.lines().next().map(|it| it.to_string()))
+        .unwrap_or("unknown version".to_string());
+    Check::pass("adb", format!("{} ({})", adb.display(), version))
+}
+
+fn ndk() -> Check {
+    let ndk = match setup::android_ndk() {
+        Some(ndk) => ndk,
+        None => return Check::fail("Android NDK", "no NDK found",
+                                   "install the NDK (sdkmanager \"ndk;<version>\") and set ANDROID_NDK_HOME to it"),
+    };
+    let mut properties = String::new();
+    let _ = fs::File::open(ndk.join("source.properties")).and_then(|mut it| it.read_to_string(&mut properties));
+    let version = properties.lines()
+        .filter(|it| it.starts_with("Pkg.Revision"))
+        .filter_map(|it| it.splitn(2, '=').nth(1))
+        .map(|it| it.trim().to_string())
+        .next()
+        .unwrap_or("unknown version".to_string());
+    Check::pass("Android NDK", format!("{} ({})", ndk.display(), version))
+}
+
+fn xcode() -> Check {
+    let developer_dir = match setup::xcode() {
+        Some(dir) => dir,
+        None => return Check::fail("Xcode", "no Xcode selected", "install Xcode, then run `sudo xcode-select -s /Applications/Xcode.app`"),
+    };
+    if !developer_dir.join("Platforms").join("iPhoneOS.platform").is_dir() {
+        return Check::fail("Xcode", format!("{} is not an Xcode with the iOS platform", developer_dir.display()),
+                           "run `sudo xcode-select -s /Applications/Xcode.app` (the command line tools alone can't build for iOS)");
+    }
+    let version = Command::new("xcodebuild").arg("-version").output().ok()
+        .and_then(|it| String::from_utf8(it.stdout).ok())
+        .and_then(|it| it.lines().next().map(|it| it.to_string()))
+        .unwrap_or("unknown version".to_string());
+    Check::pass("Xcode", format!("{} ({})", developer_dir.display(), version))
+}
+
+/// The disk images Xcode mounts on the devices, for debugging (one per iOS
+/// version).
+fn device_support() -> Check {
+    let dir = match setup::xcode() {
+        Some(dir) => dir.join("Platforms/iPhoneOS.platform/DeviceSupport"),
+        None => return Check::fail("DeviceSupport images", "no Xcode selected", "install and select Xcode first"),
+    };
+    let mut versions = fs::read_dir(&dir).into_iter()
+        .flat_map(|it| it)
+        .filter_map(|it| it.ok())
+        .filter_map(|it| it.file_name().into_string().ok())
+        .collect::<Vec<_>>();
+    versions.sort();
+    if versions.is_empty() {
+        Check::fail("DeviceSupport images", format!("none in {}", dir.display()),
+                    "update Xcode, or copy the DeviceSupport directory of the device iOS version from a newer Xcode")
+    } else {
+        Check::pass("DeviceSupport images", format!("iOS {}", versions.join(", ")))
+    }
+}
+
+fn codesign_identities(conf: &Configuration) -> Check {
+    let output = Command::new("security").args(&["find-identity", "-v", "-p", "codesigning"])
+        .args(conf.ios.keychain.iter())
+        .output();
+    let identities = output.ok()
+        .and_then(|it| String::from_utf8(it.stdout).ok())
+        .map(|it| it.lines()
+            .filter(|it| it.contains("\"iPhone Developer: ") || it.contains("\"Apple Development: "))
+            .count())
+        .unwrap_or(0);
+    if identities == 0 {
+        Check::fail("codesign identities", "no development signing identity in the keychain",
+                    "sign in with your Apple ID in Xcode (Settings > Accounts) and create an Apple Development certificate")
+    } else {
+        Check::pass("codesign identities", format!("{} development identities", identities))
+    }
+}
+
+fn provisioning_profiles() -> Check {
+    let dir = env::home_dir().unwrap_or("/".into()).join("Library/MobileDevice/Provisioning Profiles");
+    let profiles = fs::read_dir(&dir).into_iter()
+        .flat_map(|it| it)
+        .filter_map(|it| it.ok())
+        .filter(|it| it.path().extension().map(|it| it == "mobileprovision").unwrap_or(false))
+        .count();
+    if profiles == 0 {
+        Check::fail("provisioning profiles", format!("none in {}", dir.display()),
+                    "build and run any app on the device once from Xcode, so that it creates a profile for the device")
+    } else {
+        Check::pass("provisioning profiles", format!("{} profiles", profiles))
+    }
+}
+
+fn usbmuxd() -> Check {
+    if Path::new("/var/run/usbmuxd").exists() {
+        Check::pass("usbmuxd", "/var/run/usbmuxd")
+    } else {
+        Check::fail("usbmuxd", "/var/run/usbmuxd not found, USB devices can't be reached",
+                    "restart usbmuxd (`sudo launchctl kickstart -k system/com.apple.usbmuxd`)")
+    }
+}
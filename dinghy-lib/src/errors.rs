@@ -4,6 +4,7 @@ error_chain! {
         StringFromUtf8(::std::string::FromUtf8Error);
         PathStripPrefix(::std::path::StripPrefixError);
         Cargo(::cargo::CargoError);
+        Clap(::clap::Error);
         Plist(::plist::Error);
         Regex(::regex::Error);
         Json(::json::Error);
@@ -12,9 +13,21 @@ error_chain! {
     }
 
     errors {
+        DeviceBusy(device: String) {
+            description("Device used by another run")
+            display("Device {} is used by another dinghy run", device)
+        }
         PackagesCannotBeCompiledForPlatform(packages: Vec<String>) {
             description("Cannot compile selected packages for the selected platform")
             display("{:?} cannot be compiled for the selected platform (see project's [package.metadata.dinghy] in Cargo.toml)", packages)
         }
+        RunFailed(what: String) {
+            description("Run failed")
+            display("{} failed 🐛", what)
+        }
+        RunTimeout(runnable: String, seconds: u64) {
+            description("Run timed out")
+            display("{} didn't complete within {}s and was killed", runnable, seconds)
+        }
     }
 }
@@ -0,0 +1,139 @@
+//! Port forwarding between the host and the devices.
+//!
+//! Forwards are kept alive as long as the returned `PortForward` lives, and
+//! are also torn down if dinghy is interrupted.
+
+use cleanup;
+use cleanup::CleanupGuard;
+use errors::*;
+use std::io;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+/// A forward from a host port to a device port, removed when dropped.
+pub struct PortForward {
+    pub local: u16,
+    pub remote: u16,
+    stop: Arc<Fn() + Send + Sync>,
+    _interrupt: CleanupGuard,
+}
+
+impl PortForward {
+    pub fn new<F: Fn() + Send + Sync + 'static>(local: u16, remote: u16, stop: F) -> PortForward {
+        let stop: Arc<Fn() + Send + Sync> = Arc::new(stop);
+        let interrupt_stop = stop.clone();
+        PortForward {
+            local,
+            remote,
+            stop,
+            _interrupt: cleanup::on_interrupt(move || interrupt_stop()),
+        }
+    }
+
+    /// A forward that needs no teardown, e.g. on the host itself.
+    pub fn noop(local: u16, remote: u16) -> PortForward {
+        PortForward::new(local, remote, || {})
+    }
+}
+
+impl Drop for PortForward {
+    fn drop(&mut self) {
+        debug!("Removing port forward {}:{}", self.local, self.remote);
+        (self.stop)()
+    }
+}
+
+/// Parse a `LOCAL:REMOTE` port pair. A single port is used on both sides.
+pub fn parse_port_pair(spec: &str) -> Result<(u16, u16)> {
+    let invalid = || format!("Invalid port forward '{}', expected LOCAL:REMOTE", spec);
+    let mut ports = spec.splitn(2, ':');
+    let local = ports.next().unwrap_or("").trim().parse::<u16>().map_err(|_| invalid())?;
+    let remote = match ports.next() {
+        Some(remote) => remote.trim().parse::<u16>().map_err(|_| invalid())?,
+        None => local,
+    };
+    Ok((local, remote))
+}
+
+/// Forward between two ports of the host, for devices sharing its network.
+pub fn local_forward(local: u16, remote: u16) -> Result<PortForward> {
+    if local == remote {
+        return Ok(PortForward::noop(local, remote));
+    }
+    relay_listener(local, remote, move || Ok(TcpStream::connect(("127.0.0.1", remote))?))
+}
+
+/// Accept connections on `127.0.0.1:local` and relay each of them to a stream
+/// opened by `connect`, until the returned forward is dropped.
+pub fn relay_listener<F>(local: u16, remote: u16, connect: F) -> Result<PortForward>
+    where F: Fn() -> Result<TcpStream> + Send + 'static {
+    let listener = TcpListener::bind(("127.0.0.1", local))
+        .chain_err(|| format!("Couldn't listen on local port {}", local))?;
+    let stopped = Arc::new(AtomicBool::new(false));
+    let thread_stopped = stopped.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if thread_stopped.load(Ordering::SeqCst) {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!("Failure while accepting connection on port {}: {}", local, e);
+                    break;
+                }
+            };
+            match connect() {
+                Ok(device) => {
+                    thread::spawn(move || if let Err(e) = relay(stream, device) {
+                        debug!("Port forward connection {}:{} failed: {}", local, remote, e);
+                    });
+                }
+                Err(e) => error!("Couldn't connect to device port {}: {}", remote, e),
+            }
+        }
+    });
+    Ok(PortForward::new(local, remote, move || {
+        stopped.store(true, Ordering::SeqCst);
+        // Wake up the accepting thread so that it notices it has to stop.
+        let _ = TcpStream::connect(("127.0.0.1", local));
+    }))
+}
+
+/// Copy both directions between two streams until both reach EOF.
+pub fn relay(stream: TcpStream, device: TcpStream) -> Result<()> {
+    // Copy one direction until EOF, then propagate the EOF to the other side.
+    fn forward(mut from: TcpStream, mut to: TcpStream) -> Result<()> {
+        let result = io::copy(&mut from, &mut to);
+        let _ = to.shutdown(Shutdown::Write);
+        result?;
+        Ok(())
+    }
+
+    let (device_reader, stream_writer) = (device.try_clone()?, stream.try_clone()?);
+    let downstream = thread::spawn(move || forward(device_reader, stream_writer));
+    if let Err(e) = forward(stream, device) {
+        debug!("Error forwarding to device: {}", e);
+    }
+    match downstream.join() {
+        Ok(Err(e)) => debug!("Error forwarding from device: {}", e),
+        Err(_) => Err("forwarding thread panicked")?,
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_port_pairs() {
+        assert_eq!(parse_port_pair("8080:80").unwrap(), (8080, 80));
+        assert_eq!(parse_port_pair("8080").unwrap(), (8080, 8080));
+        assert!(parse_port_pair("8080:").is_err());
+        assert!(parse_port_pair("http:80").is_err());
+    }
+}
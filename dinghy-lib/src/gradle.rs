@@ -0,0 +1,117 @@
+//! Integration with the Gradle project of an Android app: the cdylibs are
+//! built for the ABIs the app asks for (`abiFilters`), copied to its
+//! `jniLibs`, and its instrumented tests run on the device.
+
+use errors::*;
+use jnilibs;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+use Dinghy;
+use Platform;
+
+pub struct GradleProject {
+    root: PathBuf,
+    module: String,
+}
+
+impl GradleProject {
+    pub fn new<P: AsRef<Path>>(root: P, module: &str) -> GradleProject {
+        GradleProject {
+            root: root.as_ref().to_path_buf(),
+            module: module.to_string(),
+        }
+    }
+
+    /// The ABIs listed by the `abiFilters` of the module build script, empty
+    /// if it has none.
+    pub fn abi_filters(&self) -> Result<Vec<String>> {
+        let module_dir = self.root.join(&self.module);
+        let script = ["build.gradle", "build.gradle.kts"].iter()
+            .map(|it| module_dir.join(it))
+            .find(|it| it.is_file())
+            .ok_or(format!("No build.gradle(.kts) found in {}", module_dir.display()))?;
+        let mut content = String::new();
+        fs::File::open(&script)
+            .and_then(|mut file| file.read_to_string(&mut content))
+            .chain_err(|| format!("Couldn't read {}", script.display()))?;
+        Ok(parse_abi_filters(&content))
+    }
+
+    pub fn jni_libs_dir(&self) -> PathBuf {
+        self.root.join(&self.module).join("src").join("main").join("jniLibs")
+    }
+
+    /// Run `./gradlew :<module>:connectedAndroidTest`, on the device of
+    /// serial number `serial` if given.
+    pub fn connected_android_test(&self, serial: Option<&str>) -> Result<()> {
+        let mut command = Command::new(self.root.join("gradlew"));
+        command.arg(format!(":{}:connectedAndroidTest", self.module)).current_dir(&self.root);
+        if let Some(serial) = serial {
+            command.env("ANDROID_SERIAL", serial);
+        }
+        debug!("Running {:?}", command);
+        if !command.status().chain_err(|| format!("Couldn't run gradlew in {}", self.root.display()))?.success() {
+            bail!("connectedAndroidTest failed")
+        }
+        Ok(())
+    }
+}
+
+/// One platform per ABI to build for: the `abis` asked, or all the ABIs of the
+/// Android platforms known if none is.
+pub fn platforms_for(dinghy: &Dinghy, abis: &[String]) -> Result<Vec<(&'static str, Arc<Box<Platform>>)>> {
+    let mut platforms: Vec<(&'static str, Arc<Box<Platform>>)> = vec![];
+    for platform in dinghy.platforms() {
+        if let Ok(abi) = jnilibs::android_abi(&**platform) {
+            let wanted = abis.is_empty() || abis.iter().any(|it| it == abi);
+            if wanted && !platforms.iter().any(|&(known, _)| known == abi) {
+                platforms.push((abi, platform));
+            }
+        }
+    }
+    for abi in abis {
+        if !platforms.iter().any(|&(known, _)| known == abi) {
+            bail!("No Android platform found for ABI {}, declare one in the [platforms] configuration", abi)
+        }
+    }
+    if platforms.is_empty() {
+        bail!("No Android platform found")
+    }
+    Ok(platforms)
+}
+
+/// The quoted values of the `abiFilters` lines, in Groovy (`abiFilters 'x86',
+/// 'arm64-v8a'`) or Kotlin (`abiFilters += listOf("x86")`) scripts.
+fn parse_abi_filters(script: &str) -> Vec<String> {
+    let mut abis: Vec<String> = vec![];
+    for line in script.lines().map(str::trim).filter(|it| it.starts_with("abiFilters")) {
+        let quoted = line.split(|c| c == '"' || c == '\'')
+            .enumerate()
+            .filter(|&(index, _)| index % 2 == 1)
+            .map(|(_, it)| it);
+        for abi in quoted {
+            if !abis.iter().any(|it| it == abi) {
+                abis.push(abi.to_string());
+            }
+        }
+    }
+    abis
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abi_filters() {
+        let groovy = "ndk {\n    abiFilters 'arm64-v8a', 'x86_64'\n}\n";
+        assert_eq!(parse_abi_filters(groovy), vec!["arm64-v8a".to_string(), "x86_64".to_string()]);
+        let kotlin = "ndk {\n    abiFilters += listOf(\"armeabi-v7a\")\n    abiFilters.add(\"x86_64\")\n}\n";
+        assert_eq!(parse_abi_filters(kotlin), vec!["armeabi-v7a".to_string(), "x86_64".to_string()]);
+        assert!(parse_abi_filters("android {}\n").is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+//! Battery and thermal state of devices, checked before benchmarks: a hot or
+//! nearly discharged phone throttles, and gives meaningless numbers.
+
+use config::BenchConfiguration;
+use errors::*;
+use std::fmt;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use Device;
+
+/// Battery level under which benchmarks are not trusted, in percent.
+static DEFAULT_MIN_BATTERY: u8 = 20;
+/// Thermal state over which benchmarks are not trusted.
+static DEFAULT_MAX_THERMAL: ThermalState = ThermalState::Light;
+/// Delay between two checks while waiting for a device to cool or charge.
+static POLL_INTERVAL: u64 = 30;
+
+/// What a device tells about its health, `None` when unknown.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceHealth {
+    pub battery_level: Option<u8>,
+    pub charging: Option<bool>,
+    pub thermal: Option<ThermalState>,
+}
+
+/// Thermal throttling levels, as defined by Android's thermal service.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum ThermalState {
+    None,
+    Light,
+    Moderate,
+    Severe,
+    Critical,
+    Emergency,
+    Shutdown,
+}
+
+impl ThermalState {
+    pub fn from_level(level: u8) -> Option<ThermalState> {
+        Some(match level {
+            0 => ThermalState::None,
+            1 => ThermalState::Light,
+            2 => ThermalState::Moderate,
+            3 => ThermalState::Severe,
+            4 => ThermalState::Critical,
+            5 => ThermalState::Emergency,
+            6 => ThermalState::Shutdown,
+            _ => return None,
+        })
+    }
+}
+
+impl FromStr for ThermalState {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ThermalState> {
+        Ok(match s {
+            "none" => ThermalState::None,
+            "light" => ThermalState::Light,
+            "moderate" => ThermalState::Moderate,
+            "severe" => ThermalState::Severe,
+            "critical" => ThermalState::Critical,
+            "emergency" => ThermalState::Emergency,
+            "shutdown" => ThermalState::Shutdown,
+            _ => bail!("Unknown thermal state '{}'", s),
+        })
+    }
+}
+
+impl fmt::Display for ThermalState {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+impl DeviceHealth {
+    /// Why benchmarks shouldn't be trusted on the device, empty if they can.
+    pub fn problems(&self, conf: &BenchConfiguration) -> Result<Vec<String>> {
+        let min_battery = conf.min_battery.unwrap_or(DEFAULT_MIN_BATTERY);
+        let max_thermal = match conf.max_thermal {
+            Some(ref state) => state.parse()?,
+            None => DEFAULT_MAX_THERMAL,
+        };
+        let mut problems = vec![];
+        if let Some(level) = self.battery_level {
+            if level < min_battery && self.charging != Some(true) {
+                problems.push(format!("battery at {}% (minimum {}%)", level, min_battery));
+            }
+        }
+        if let Some(state) = self.thermal {
+            if state > max_thermal {
+                problems.push(format!("thermal state {} (maximum {})", state, max_thermal));
+            }
+        }
+        Ok(problems)
+    }
+}
+
+/// Check that `device` is fit for benchmarks, waiting for it to cool down or
+/// charge if configured to. Unfit devices are reported, and refused in strict
+/// mode.
+pub fn check_for_bench(device: &Device, conf: &BenchConfiguration) -> Result<()> {
+    let start = Instant::now();
+    let wait = Duration::from_secs(conf.wait.unwrap_or(0));
+    loop {
+        let health = device.health()?;
+        debug!("Health of {}: {:?}", device.id(), health);
+        let problems = health.problems(conf)?;
+        if problems.is_empty() {
+            return Ok(());
+        }
+        if start.elapsed() >= wait {
+            let message = format!("Benchmarks on {} are unreliable: {}", device.id(), problems.join(", "));
+            if conf.strict.unwrap_or(false) {
+                bail!(message);
+            }
+            warn!("{}", message);
+            return Ok(());
+        }
+        info!("Waiting for {} to get ready for benchmarks: {}", device.id(), problems.join(", "));
+        thread::sleep(Duration::from_secs(POLL_INTERVAL));
+    }
+}
+
+/// Battery level and charging status from `dumpsys battery`.
+pub fn parse_dumpsys_battery(output: &str) -> DeviceHealth {
+    let mut health = DeviceHealth::default();
+    for line in output.lines() {
+        let mut split = line.splitn(2, ':');
+        match (split.next().map(str::trim), split.next().map(str::trim)) {
+            (Some("level"), Some(level)) => health.battery_level = level.parse().ok(),
+            // 2 is charging, 5 is full (and plugged).
+            (Some("status"), Some(status)) => health.charging = Some(status == "2" || status == "5"),
+            _ => {}
+        }
+    }
+    health
+}
+
+/// Thermal state from `dumpsys thermalservice`.
+pub fn parse_dumpsys_thermal(output: &str) -> Option<ThermalState> {
+    output.lines()
+        .filter_map(|line| {
+            let mut split = line.splitn(2, ':');
+            match (split.next().map(str::trim), split.next().map(str::trim)) {
+                (Some("Thermal Status"), Some(level)) => level.parse().ok().and_then(ThermalState::from_level),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_android_health() {
+        let battery = "Current Battery Service state:\n  AC powered: false\n  USB powered: true\n  status: 3\n  level: 14\n  scale: 100\n";
+        let health = DeviceHealth {
+            thermal: parse_dumpsys_thermal("IsStatusOverride: false\nThermal Status: 2\n"),
+            ..parse_dumpsys_battery(battery)
+        };
+        assert_eq!(health.battery_level, Some(14));
+        assert_eq!(health.charging, Some(false));
+        assert_eq!(health.thermal, Some(ThermalState::Moderate));
+        assert_eq!(health.problems(&BenchConfiguration::default()).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,78 @@
+//! Commands run around the runs on a device, e.g. to seed a backend database
+//! before the tests, or to notify a dashboard after them.
+//!
+//! Host hooks run with `sh -c` from the project directory, the device and the
+//! artifacts of the run being described by `DINGHY_*` environment variables.
+//! Device hooks run in a shell on the device itself, e.g. to clear caches.
+
+use errors::*;
+use project::Project;
+use std::env;
+use std::process::Command;
+use Build;
+use Device;
+
+/// When a hook runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    BeforeInstall,
+    AfterRun,
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Stage::BeforeInstall => "before_install",
+            Stage::AfterRun => "after_run",
+        }
+    }
+}
+
+/// Runs `commands` in turn, stopping at the first failing one.
+///
+/// `envs` are set on top of the description of the device and of the build.
+pub fn run(stage: Stage,
+           commands: &[String],
+           project: &Project,
+           device: &Device,
+           build: &Build,
+           envs: &[(&str, String)]) -> Result<()> {
+    if commands.is_empty() {
+        return Ok(());
+    }
+    let project_dir = project.project_dir().or_else(|_| env::current_dir().map_err(Error::from))?;
+    let exes = env::join_paths(build.runnables.iter().map(|it| &it.exe))
+        .chain_err(|| "Couldn't list the executables of the build")?;
+    for command in commands {
+        info!("Running {} hook `{}' for {}", stage.as_str(), command, device.id());
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .current_dir(&project_dir)
+            .env("DINGHY_HOOK", stage.as_str())
+            .env("DINGHY_DEVICE_ID", device.id())
+            .env("DINGHY_DEVICE_NAME", device.name())
+            .env("DINGHY_RUNNABLES", build.runnables.iter().map(|it| it.id.as_str()).collect::<Vec<_>>().join(" "))
+            .env("DINGHY_EXES", &exes)
+            .env("DINGHY_TARGET_PATH", &build.target_path)
+            .env("DINGHY_ARTIFACTS", build.artifacts(device.id()).root())
+            .envs(envs.iter().map(|&(key, ref value)| (key, value)))
+            .status()
+            .chain_err(|| format!("Couldn't run {} hook `{}'", stage.as_str(), command))?;
+        if !status.success() {
+            bail!("The {} hook `{}' failed on {} ({})", stage.as_str(), command, device.id(), status)
+        }
+    }
+    Ok(())
+}
+
+/// Runs the `what` (e.g. `device_setup`) `commands` on `device` in turn,
+/// stopping at the first failing one.
+pub fn run_on_device(what: &str, commands: &[String], device: &Device) -> Result<()> {
+    for command in commands {
+        info!("Running {} `{}' on {}", what, command, device.id());
+        device.run_shell(command)
+            .chain_err(|| format!("The {} command `{}' failed on {}", what, command, device.id()))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+//! Registry of what dinghy installed on each device, so that the bundles and
+//! apps no run used for a while can be removed (`cargo dinghy gc`).
+//!
+//! Each device has a JSON file, keyed on its id, mapping the locations of the
+//! installs (remote bundle directory, or bundle identifier on iOS) to the last
+//! time they were installed or reused. The file is locked while updated, as
+//! several dinghy invocations may install on the same device at once.
+
+use errors::*;
+use json;
+use json::JsonValue;
+use libc;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Directory of the registries, shared by all the projects of the host.
+pub fn registry_dir() -> PathBuf {
+    env::home_dir().unwrap_or(env::temp_dir()).join(".dinghy").join("installs")
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|it| it.as_secs()).unwrap_or(0)
+}
+
+/// Runs `update` on the registry of `device_id`, with the registry locked.
+fn update_registry<T, F>(device_id: &str, update: F) -> Result<T>
+    where F: FnOnce(&mut BTreeMap<String, u64>) -> T {
+    let path = ::artifacts::ensure_dir(registry_dir())?.join(format!("{}.json", ::artifacts::sanitize(device_id)));
+    let mut file = fs::OpenOptions::new().create(true).read(true).write(true).open(&path)
+        .chain_err(|| format!("Couldn't open install registry {}", path.display()))?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        bail!("Couldn't lock install registry {}", path.display())
+    }
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    let mut installs = parse(&content);
+    let result = update(&mut installs);
+    file.seek(SeekFrom::Start(0))?;
+    file.set_len(0)?;
+    file.write_all(to_json(&installs).dump().as_bytes())?;
+    // The lock goes with the file.
+    Ok(result)
+}
+
+fn parse(content: &str) -> BTreeMap<String, u64> {
+    match json::parse(content) {
+        Ok(JsonValue::Object(object)) => object.iter()
+            .filter_map(|(location, time)| time.as_u64().map(|time| (location.to_string(), time)))
+            .collect(),
+        _ => BTreeMap::new(),
+    }
+}
+
+fn to_json(installs: &BTreeMap<String, u64>) -> JsonValue {
+    let mut json = JsonValue::new_object();
+    for (location, time) in installs {
+        json[location.as_str()] = (*time).into();
+    }
+    json
+}
+
+/// Records that `location` was installed, or reused, on `device_id` now. The
+/// install itself went fine, so failing to record it only warns.
+pub fn record(device_id: &str, location: &str) {
+    let recorded = update_registry(device_id, |installs| {
+        installs.insert(location.to_string(), now());
+    });
+    if let Err(e) = recorded {
+        warn!("Couldn't record the install of {} on {}: {}", location, device_id, e)
+    }
+}
+
+/// The locations of the installs on `device_id` not used for `max_age`.
+pub fn stale(device_id: &str, max_age: Duration) -> Result<Vec<String>> {
+    update_registry(device_id, |installs| older_than(installs, now(), max_age))
+}
+
+/// Forgets `location`, removed from `device_id`.
+pub fn forget(device_id: &str, location: &str) -> Result<()> {
+    update_registry(device_id, |installs| {
+        installs.remove(location);
+    })
+}
+
+fn older_than(installs: &BTreeMap<String, u64>, now: u64, max_age: Duration) -> Vec<String> {
+    installs.iter()
+        .filter(|&(_, &time)| time + max_age.as_secs() < now)
+        .map(|(location, _)| location.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_installs() {
+        let installs = parse(r#"{"/data/local/tmp/dinghy/old": 1000, "com.example.Dinghy": 90000, "broken": "x"}"#);
+        assert_eq!(installs.len(), 2);
+        let day = Duration::from_secs(86_400);
+        assert_eq!(older_than(&installs, 100_000, day), vec!["/data/local/tmp/dinghy/old".to_string()]);
+        assert!(older_than(&installs, 100_000, day * 2).is_empty());
+        assert_eq!(parse(&to_json(&installs).dump()), installs);
+    }
+}
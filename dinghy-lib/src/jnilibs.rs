@@ -0,0 +1,56 @@
+//! Packaging of the C dynamic libraries (`crate-type = ["cdylib"]`) for
+//! Android apps, in the `jniLibs/<abi>/` layout of Android projects, or in the
+//! `lib/<abi>/` entries of an APK (the same layout, under `lib`).
+
+use dinghy_build::triple::Triple;
+use errors::*;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use utils::copy_and_sync_file;
+use Build;
+use Platform;
+
+/// The Android ABI a platform builds for.
+pub fn android_abi(platform: &Platform) -> Result<&'static str> {
+    platform.rustc_triple()
+        .and_then(Triple::parse)
+        .and_then(|it| it.to_android_abi())
+        .ok_or(format!("'{}' is not an Android platform", platform.id()).into())
+}
+
+/// Copy the cdylibs of `build`, and the shared libraries they need, into
+/// `<dir>/<abi>/`. Returns the copied libraries.
+pub fn copy_to_layout(build: &Build, abi: &str, dir: &Path) -> Result<Vec<PathBuf>> {
+    let abi_dir = dir.join(abi);
+    fs::create_dir_all(&abi_dir).chain_err(|| format!("Couldn't create {}", abi_dir.display()))?;
+    let shared_libraries = build.dynamic_libraries.iter()
+        .filter(|it| it.extension().and_then(|it| it.to_str()) == Some("so"));
+    let mut copied = vec![];
+    for lib in build.cdylibs.iter().chain(shared_libraries) {
+        let target = abi_dir.join(lib.file_name().ok_or(format!("Invalid library path {}", lib.display()))?);
+        debug!("Copying {} to {}", lib.display(), target.display());
+        copy_and_sync_file(lib, &target)
+            .chain_err(|| format!("Couldn't copy {} to {}", lib.display(), target.display()))?;
+        copied.push(target);
+    }
+    Ok(copied)
+}
+
+/// Write to `apk` the `template` APK with the libraries of `<staging>/lib`
+/// added. The signature of the template doesn't cover them: the APK has to
+/// be signed again (e.g. with `apksigner`) before it can be installed.
+pub fn package_apk(template: &Path, staging: &Path, apk: &Path) -> Result<()> {
+    let apk = env::current_dir()?.join(apk);
+    fs::copy(template, &apk)
+        .chain_err(|| format!("Couldn't copy {} to {}", template.display(), apk.display()))?;
+    // Stored uncompressed, so that apps not extracting their native libraries
+    // can load them in place.
+    let status = Command::new("zip").arg("-qr0").arg(&apk).arg("lib").current_dir(staging).status()?;
+    if !status.success() {
+        bail!("Couldn't add the libraries of {} to {}", staging.display(), apk.display())
+    }
+    Ok(())
+}
@@ -0,0 +1,161 @@
+//! Compiler launchers (sccache, ccache) wrapping the C compilers and linkers
+//! dinghy sets up for the builds, so that repeated cross builds hit the cache.
+//!
+//! The launcher is `compiler_launcher` under `[build]`, or the
+//! `DINGHY_COMPILER_LAUNCHER` variable: a command, or `auto` to use sccache or
+//! ccache when one is in the `PATH`. The cache hits and misses of the command
+//! are shown with the timings.
+
+use config::Configuration;
+use dinghy_build::build_env::set_env;
+use errors::*;
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+
+pub const LAUNCHER_ENV: &str = "DINGHY_COMPILER_LAUNCHER";
+
+/// The variables of the tools the launcher wraps: the compilers.
+const WRAPPED_TOOLS: &[&str] = &["CC", "CXX"];
+
+lazy_static! {
+    /// The statistics of the launcher when set up, to count the hits of the
+    /// command only.
+    static ref BASELINE: Mutex<Option<CacheStats>> = Mutex::new(None);
+}
+
+/// Hits and misses of the cache of a launcher.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub launcher: String,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{} {} hits, {} misses", self.launcher, self.hits, self.misses)
+    }
+}
+
+/// Resolves the launcher of the configuration, and exports it for the
+/// toolchains to wrap their compilers with.
+pub fn setup(conf: &Configuration) -> Result<()> {
+    let configured = env::var(LAUNCHER_ENV).ok().or(conf.build.compiler_launcher.clone());
+    let launcher = match configured.as_ref().map(|it| it.as_str()) {
+        None | Some("") | Some("none") => {
+            env::remove_var(LAUNCHER_ENV);
+            return Ok(());
+        }
+        Some("auto") => match ::which::which("sccache").or_else(|_| ::which::which("ccache")) {
+            Ok(launcher) => launcher,
+            Err(_) => {
+                debug!("No sccache nor ccache in the PATH, building without a compiler launcher");
+                env::remove_var(LAUNCHER_ENV);
+                return Ok(());
+            }
+        },
+        Some(launcher) => ::which::which(launcher)
+            .map_err(|_| format!("Compiler launcher {} not found in the PATH", launcher))?,
+    };
+    debug!("Wrapping the compilers with {}", launcher.display());
+    set_env(LAUNCHER_ENV, &launcher);
+    if let Ok(mut baseline) = BASELINE.lock() {
+        if baseline.is_none() {
+            *baseline = stats_of(&launcher);
+        }
+    }
+    Ok(())
+}
+
+/// The launcher set up for the build, if any.
+pub fn launcher() -> Option<String> {
+    match env::var(LAUNCHER_ENV) {
+        Ok(ref launcher) if launcher.is_empty() => None,
+        Ok(launcher) => Some(launcher),
+        Err(_) => None,
+    }
+}
+
+/// `command`, run through the launcher if there is one.
+pub fn wrap(command: &str) -> String {
+    match launcher() {
+        Some(launcher) => format!("{} {}", launcher, command),
+        None => command.to_string(),
+    }
+}
+
+/// `command` for the tool `var` (`CC`, `AR`...), run through the launcher if
+/// there is one and the tool is a compiler.
+pub fn wrap_tool(var: &str, command: &str) -> String {
+    if WRAPPED_TOOLS.contains(&var) { wrap(command) } else { command.to_string() }
+}
+
+/// Whether the executable `name` of a toolchain is a compiler.
+pub fn is_compiler(name: &str) -> bool {
+    ["gcc", "g++", "clang", "clang++", "cc", "c++"].iter()
+        .any(|it| name == *it || name.ends_with(&format!("-{}", it)))
+}
+
+/// The hits and misses of the launcher since it was set up, if any.
+pub fn stats() -> Option<CacheStats> {
+    let baseline = BASELINE.lock().ok().and_then(|it| it.clone())?;
+    let launcher = launcher()?;
+    let current = stats_of(Path::new(&launcher))?;
+    Some(CacheStats {
+        launcher: current.launcher,
+        hits: current.hits.saturating_sub(baseline.hits),
+        misses: current.misses.saturating_sub(baseline.misses),
+    })
+}
+
+fn stats_of(launcher: &Path) -> Option<CacheStats> {
+    let name = launcher.file_name()?.to_string_lossy().to_string();
+    let args: &[&str] = if name.contains("ccache") { &["--print-stats"] } else { &["--show-stats"] };
+    let output = Command::new(launcher).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let (hits, misses) = parse_stats(&String::from_utf8_lossy(&output.stdout));
+    Some(CacheStats { launcher: name, hits, misses })
+}
+
+/// The hits and misses in the statistics of sccache (`Cache hits  12`) or
+/// ccache (`direct_cache_hit	12`).
+fn parse_stats(stats: &str) -> (u64, u64) {
+    let (mut hits, mut misses) = (0, 0);
+    for line in stats.lines() {
+        let mut words = line.split_whitespace().collect::<Vec<_>>();
+        let count = match words.pop().and_then(|it| it.parse::<u64>().ok()) {
+            Some(count) => count,
+            None => continue,
+        };
+        match words.join(" ").to_lowercase().as_str() {
+            "cache hits" | "direct_cache_hit" | "preprocessed_cache_hit" => hits += count,
+            "cache misses" | "cache_miss" => misses += count,
+            _ => (),
+        }
+    }
+    (hits, misses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn launcher_stats() {
+        let sccache = "Compile requests                     12\nCache hits                            8\n\
+                       Cache hits (C/C++)                    8\nCache misses                          3\n\
+                       Cache hits rate                   72.73 %\n";
+        assert_eq!(parse_stats(sccache), (8, 3));
+        let ccache = "stats_updated_timestamp\t1600000000\ndirect_cache_hit\t5\npreprocessed_cache_hit\t2\ncache_miss\t4\n";
+        assert_eq!(parse_stats(ccache), (7, 4));
+        assert!(is_compiler("aarch64-linux-android-clang++"));
+        assert!(is_compiler("arm-linux-gnueabihf-gcc"));
+        assert!(!is_compiler("arm-linux-gnueabihf-gcc-ar"));
+        assert!(!is_compiler("aarch64-linux-android-ld"));
+    }
+}
@@ -4,7 +4,9 @@ extern crate clap;
 extern crate core_foundation;
 #[cfg(target_os = "macos")]
 extern crate core_foundation_sys;
+extern crate ctrlc;
 extern crate dinghy_build;
+extern crate env_logger;
 #[macro_use]
 extern crate error_chain;
 extern crate filetime;
@@ -12,46 +14,104 @@ extern crate ignore;
 extern crate isatty;
 extern crate itertools;
 extern crate json;
-#[cfg(target_os = "macos")]
+#[macro_use]
+extern crate lazy_static;
 extern crate libc;
 #[macro_use]
 extern crate log;
+extern crate notify;
 extern crate plist;
 extern crate regex;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate shell_escape;
-#[cfg(target_os = "macos")]
-extern crate tempdir;
 extern crate toml;
 extern crate walkdir;
 extern crate which;
 
+pub mod artifacts;
+pub mod baseline;
+pub mod cdylib;
+pub mod cleanup;
 pub mod compiler;
 pub mod config;
+pub mod console;
+pub mod coverage;
+pub mod criterion;
+pub mod crossbuild;
 pub mod device;
+pub mod doctor;
 pub mod errors;
+pub mod forward;
+pub mod gradle;
+pub mod health;
+pub mod hooks;
+pub mod installs;
+pub mod jnilibs;
+pub mod launcher;
+pub mod lock;
+pub mod logging;
+pub mod network;
+pub mod ndk;
+pub mod output;
 pub mod overlay;
 pub mod platform;
+pub mod plugin;
+pub mod processes;
 pub mod project;
+pub mod recording;
+pub mod region;
+pub mod repetition;
+pub mod report;
+pub mod runner;
+pub mod rustup;
+pub mod serve;
+pub mod setup;
+pub mod shard;
+pub mod symbolicate;
+pub mod sync;
+pub mod timings;
+pub mod trace;
 pub mod utils;
+pub mod watch;
+pub mod xcframework;
 mod toolchain;
 
+use compiler::CheckCommand;
 use compiler::Compiler;
 use compiler::CompileMode;
 use config::Configuration;
 use config::PlatformConfiguration;
+use console::ConsoleOptions;
+use device::agent::AgentManager;
 use device::android::AndroidManager;
+use device::docker::DockerDeviceManager;
 use device::host::HostManager;
+use device::qemu::QemuManager;
+use device::replay::ReplayManager;
 #[cfg(target_os = "macos")]
 use device::ios::IosManager;
 use device::ssh::SshDeviceManager;
+use device::wasi::WasiManager;
+use platform::custom_platform::CustomPlatform;
 use platform::host::HostPlatform;
 #[cfg(target_os = "macos")]
 use platform::ios::IosPlatform;
 use platform::regular_platform::RegularPlatform;
+use platform::PlatformKind;
+use platform::wasi::WasiPlatform;
 use project::Project;
+use artifacts::ArtifactStore;
+use forward::PortForward;
+use health::DeviceHealth;
+use network::NetworkCondition;
+use network::NetworkProfile;
+use processes::DeviceProcess;
+use processes::KillTarget;
+use recording::ScreenRecording;
+use region::Region;
+use report::RunResult;
 use std::env::home_dir;
 use std::fmt::Display;
 use std::path::Path;
@@ -69,10 +129,20 @@ pub struct Dinghy {
 
 impl Dinghy {
     pub fn probe(conf: &Arc<Configuration>, compiler: &Arc<Compiler>) -> Result<Dinghy> {
+        let _span = logging::span("discover");
+        Dinghy::with_managers(conf, compiler, Dinghy::probe_managers(conf, compiler)?)
+    }
+
+    /// The devices connected or configured, without the platforms.
+    pub fn probe_devices(conf: &Arc<Configuration>, compiler: &Arc<Compiler>) -> Result<Vec<Arc<Box<Device>>>> {
+        Dinghy::discover_devices(&Dinghy::probe_managers(conf, compiler)?)
+    }
+
+    fn probe_managers(conf: &Arc<Configuration>, compiler: &Arc<Compiler>) -> Result<Vec<Box<PlatformManager>>> {
         let host = HostManager::probe(compiler).ok_or("Host platform couldn't be determined.")?;
         let mut managers: Vec<Box<PlatformManager>> = vec![Box::new(host)];
 
-        if let Some(android) = AndroidManager::probe() {
+        if let Some(android) = AndroidManager::probe(conf.clone()) {
             debug!("register AndroidManager");
             managers.push(Box::new(android))
         }
@@ -80,12 +150,49 @@ impl Dinghy {
             debug!("register SshDeviceManager");
             managers.push(Box::new(ssh))
         }
+        if let Some(docker) = DockerDeviceManager::probe(conf.clone()) {
+            debug!("register DockerDeviceManager");
+            managers.push(Box::new(docker))
+        }
+        if let Some(qemu) = QemuManager::probe(conf.clone()) {
+            debug!("register QemuManager");
+            managers.push(Box::new(qemu))
+        }
+        if let Some(wasi) = WasiManager::probe(conf.clone()) {
+            debug!("register WasiManager");
+            managers.push(Box::new(wasi))
+        }
+        if let Some(agents) = AgentManager::probe(conf.clone()) {
+            debug!("register AgentManager");
+            managers.push(Box::new(agents))
+        }
         #[cfg(target_os = "macos")] {
-            if let Some(m) = IosManager::new()? {
+            if let Some(m) = IosManager::new(conf.clone())? {
                 debug!("register IosDeviceManager");
                 managers.push(Box::new(m) as _)
             }
         }
+        for plugin in plugin::plugins() {
+            let plugin_managers = plugin.managers(conf)
+                .chain_err(|| format!("Couldn't probe the devices of plugin {}", plugin.id()))?;
+            debug!("register {} managers of plugin {}", plugin_managers.len(), plugin.id());
+            managers.extend(plugin_managers);
+        }
+        Ok(managers)
+    }
+
+    /// Like `probe`, but with the devices of a recorded trace instead of the
+    /// connected ones (the host excepted).
+    pub fn replay(conf: &Arc<Configuration>, compiler: &Arc<Compiler>, trace: &Path) -> Result<Dinghy> {
+        let host = HostManager::probe(compiler).ok_or("Host platform couldn't be determined.")?;
+        Dinghy::with_managers(conf, compiler, vec![Box::new(host), Box::new(ReplayManager::load(trace)?)])
+    }
+
+    /// The devices of the given managers, e.g. mock ones in tests (the first
+    /// device is expected to be the host).
+    pub fn with_managers(conf: &Arc<Configuration>,
+                         compiler: &Arc<Compiler>,
+                         managers: Vec<Box<PlatformManager>>) -> Result<Dinghy> {
         Ok(Dinghy {
             devices: Dinghy::discover_devices(&managers)?,
             platforms: Dinghy::discover_platforms(compiler, &conf)?,
@@ -93,38 +200,94 @@ impl Dinghy {
     }
 
     pub fn discover_platforms(compiler: &Arc<Compiler>, conf: &Configuration) -> Result<Vec<(String, Arc<Box<Platform>>)>> {
+        let mut platforms = vec!();
+        for (platform_name, platform) in Dinghy::try_discover_platforms(compiler, conf) {
+            if let Some(pf) = platform? {
+                platforms.push((platform_name, Arc::new(pf)));
+            }
+        }
+        Ok(platforms)
+    }
+
+    /// Every platform known, host first, or the error making it unusable.
+    /// `None` for the platforms of another host (iOS ones off macOS).
+    pub fn try_discover_platforms(compiler: &Arc<Compiler>, conf: &Configuration) -> Vec<(String, Result<Option<Box<Platform>>>)> {
         let mut platforms = vec!();
         let host_conf = conf.platforms.get("host")
             .map(|it| (*it).clone())
             .unwrap_or(PlatformConfiguration::empty());
-        platforms.push(("host".to_string(), Arc::new(HostPlatform::new(compiler, host_conf.clone())?)));
+        platforms.push(("host".to_string(), HostPlatform::new(compiler, host_conf.clone()).map(Some)));
         for (platform_name, platform_conf) in &conf.platforms {
             if platform_name == "host" {
                 continue;
             }
-            if let Some(rustc_triple) = platform_conf.rustc_triple.as_ref() {
-                let pf = if rustc_triple.ends_with("-ios") {
-                    Dinghy::discover_ios_platform(platform_name.to_owned(), rustc_triple, compiler, &platform_conf)?
-                } else {
-                    Some(RegularPlatform::new(
-                        compiler,
-                        platform_conf.clone(),
-                        platform_name.to_string(),
-                        rustc_triple.clone(),
-                        platform_conf.toolchain.clone()
-                            .map(|it| PathBuf::from(it))
-                            .or(home_dir().map(|it| it.join(".dinghy").join("toolchain").join(platform_name)))
-                            .ok_or(format!("Toolchain missing for platform {}", platform_name))?)?)
-                };
-                if let Some(pf) = pf {
-                    platforms.push((platform_name.clone(), Arc::new(pf)))
-                }
-            } else {
-                bail!("Platform configuration for '{}' requires a rustc_triple.", platform_name)
+            platforms.push((platform_name.clone(), Dinghy::discover_platform(compiler, conf, platform_name, platform_conf)));
+        }
+
+        // Toolchains not referenced by any platform get an implicit one named after their triple
+        for (rustc_triple, toolchain_conf) in &conf.toolchains {
+            let referenced = platforms.iter().any(|&(_, ref pf)| match *pf {
+                Ok(Some(ref pf)) => pf.rustc_triple() == Some(rustc_triple.as_str()),
+                _ => false,
+            });
+            if referenced {
+                continue;
             }
+            platforms.push((rustc_triple.clone(), CustomPlatform::new(
+                compiler,
+                PlatformConfiguration::empty(),
+                rustc_triple.clone(),
+                rustc_triple.clone(),
+                toolchain_conf.clone()).map(Some)));
         }
+        platforms
+    }
 
-        Ok(platforms)
+    fn discover_platform(compiler: &Arc<Compiler>,
+                         conf: &Configuration,
+                         platform_name: &str,
+                         platform_conf: &PlatformConfiguration) -> Result<Option<Box<Platform>>> {
+        if let Some(pf) = Dinghy::discover_plugin_platform(platform_name, platform_conf, compiler)? {
+            return Ok(Some(pf));
+        }
+        let rustc_triple = platform_conf.rustc_triple.as_ref()
+            .ok_or(format!("Platform configuration for '{}' requires a rustc_triple.", platform_name))?;
+        let custom_toolchain = if platform_conf.toolchain.is_none() {
+            conf.toolchains.get(rustc_triple)
+        } else {
+            None
+        };
+        match (PlatformKind::of(rustc_triple), custom_toolchain) {
+            (PlatformKind::Apple, _) =>
+                Dinghy::discover_ios_platform(platform_name.to_owned(), rustc_triple, compiler, &platform_conf),
+            (PlatformKind::Wasi, _) =>
+                Ok(Some(WasiPlatform::new(compiler, platform_conf.clone(), platform_name.to_string(), rustc_triple.clone())?)),
+            (PlatformKind::Toolchain, Some(toolchain_conf)) => Ok(Some(CustomPlatform::new(
+                compiler,
+                platform_conf.clone(),
+                platform_name.to_string(),
+                rustc_triple.clone(),
+                toolchain_conf.clone())?)),
+            (PlatformKind::Toolchain, None) => Ok(Some(RegularPlatform::new(
+                compiler,
+                platform_conf.clone(),
+                platform_name.to_string(),
+                rustc_triple.clone(),
+                platform_conf.toolchain.clone()
+                    .map(|it| PathBuf::from(it))
+                    .or(home_dir().map(|it| it.join(".dinghy").join("toolchain").join(platform_name)))
+                    .ok_or(format!("Toolchain missing for platform {}", platform_name))?)?)),
+        }
+    }
+
+    fn discover_plugin_platform(id: &str, config: &PlatformConfiguration, compiler: &Arc<Compiler>) -> Result<Option<Box<Platform>>> {
+        for plugin in plugin::plugins() {
+            if let Some(pf) = plugin.platform(id, config, compiler)? {
+                debug!("platform {} provided by plugin {}", id, plugin.id());
+                return Ok(Some(pf));
+            }
+        }
+        Ok(None)
     }
 
     #[cfg(target_os = "macos")]
@@ -142,7 +305,11 @@ impl Dinghy {
         sleep(Duration::from_millis(100));
         let mut v = vec![];
         for m in managers {
-            v.extend(m.devices()?.into_iter().map(|it| Arc::new(it)));
+            v.extend(m.devices()?.into_iter().map(|it| if trace::is_recording() {
+                Arc::new(Box::new(trace::RecordingDevice::new(it)) as Box<Device>)
+            } else {
+                Arc::new(it)
+            }));
         }
         Ok(v)
     }
@@ -173,21 +340,142 @@ impl Dinghy {
     }
 }
 
-pub trait Device: Display + DeviceCompatibility {
+pub trait Device: Display + DeviceCompatibility + Send + Sync {
+    /// Package the runnables of `build` the way they are installed on the
+    /// device (e.g. signed IPAs), in the archives directory of the device, and
+    /// return the packages.
+    fn archive_app(&self, _project: &Project, _build: &Build) -> Result<Vec<PathBuf>> {
+        bail!("Archiving is not supported on {}", self.id())
+    }
+
     fn clean_app(&self, build_bundle: &BuildBundle) -> Result<()>;
 
-    fn debug_app(&self, project: &Project, build: &Build, args: &[&str], envs: &[&str]) -> Result<BuildBundle>;
+    fn debug_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<BuildBundle>;
+
+    /// Describe how `run_app` would bundle and run the runnables of `build`
+    /// (bundles, transfers and commands), without touching the device.
+    fn dry_run_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<Vec<String>> {
+        bail!("Dry runs are not supported on {}", self.id())
+    }
+
+    /// Why the device can't be used, with the steps to fix it, if it was
+    /// found in an unusable state.
+    fn failure(&self) -> Option<&str> {
+        None
+    }
+
+    /// Forward connections to `local` on the host to `remote` on the device,
+    /// for as long as the returned forward lives.
+    fn forward_port(&self, _local: u16, _remote: u16) -> Result<PortForward> {
+        bail!("Port forwarding is not supported on {}", self.id())
+    }
+
+    /// Battery and thermal state of the device, as far as it tells.
+    fn health(&self) -> Result<DeviceHealth> {
+        Ok(DeviceHealth::default())
+    }
 
     fn id(&self) -> &str;
 
+    /// Kill the processes of `target`, returning the ones killed.
+    fn kill(&self, _target: &KillTarget) -> Result<Vec<DeviceProcess>> {
+        bail!("Killing processes is not supported on {}", self.id())
+    }
+
+    /// Entries of `path` in the data of the app `app` (bundle identifier on
+    /// iOS, package on Android), the directories ending with `/`.
+    fn list_app_files(&self, app: &str, _path: &str) -> Result<Vec<String>> {
+        bail!("Can't browse the data of {}, app data is not supported on {}", app, self.id())
+    }
+
     fn name(&self) -> &str;
 
-    fn run_app(&self, project: &Project, build: &Build, args: &[&str], envs: &[&str]) -> Result<Vec<BuildBundle>>;
+    /// Simulate the network conditions of `profile` on the device, for as
+    /// long as the returned condition lives.
+    fn network_condition(&self, profile: &NetworkProfile) -> Result<NetworkCondition> {
+        bail!("Network profile {} can't be applied, network conditions are not supported on {}", profile.name, self.id())
+    }
+
+    /// The processes running on the device.
+    fn processes(&self) -> Result<Vec<DeviceProcess>> {
+        bail!("Listing the processes is not supported on {}", self.id())
+    }
+
+    /// Run the first runnable under the platform profiler, and return the
+    /// bundle along with the path of the trace pulled back on the host.
+    fn profile_app(&self, _project: &Project, _build: &Build, _run_args: &RunArgs) -> Result<(BuildBundle, PathBuf)> {
+        bail!("Profiling is not supported on {}", self.id())
+    }
+
+    /// Copy `path` (a file or a directory) of the data of the app `app` to the
+    /// `to_dir` directory of the host.
+    fn pull_app_files(&self, app: &str, _path: &str, _to_dir: &Path) -> Result<()> {
+        bail!("Can't pull the data of {}, app data is not supported on {}", app, self.id())
+    }
+
+    /// Copy the file `from` of the host to `path` in the data of the app `app`.
+    fn push_app_file(&self, app: &str, _from: &Path, _path: &str) -> Result<()> {
+        bail!("Can't push to the data of {}, app data is not supported on {}", app, self.id())
+    }
+
+    /// Restart the device.
+    fn reboot(&self) -> Result<()> {
+        bail!("Rebooting is not supported on {}", self.id())
+    }
+
+    /// Start recording the screen of the device, the video being saved to
+    /// `path` when the recording is finished.
+    fn record_screen(&self, _path: &Path) -> Result<ScreenRecording> {
+        bail!("Screen recording is not supported on {}", self.id())
+    }
+
+    /// Make the `local` port of the host reachable from the device, for as
+    /// long as the returned forward lives. Also returns the `address:port` the
+    /// device has to connect to.
+    fn reverse_port(&self, _local: u16, _remote: u16) -> Result<(PortForward, String)> {
+        bail!("Reverse port forwarding is not supported on {}", self.id())
+    }
+
+    /// Triples of the binaries the device runs, preferred first. Empty if the
+    /// device doesn't tell.
+    fn rustc_triples(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Installs and runs the runnables of `build`, with how each of them
+    /// ended. A runnable failing is a result, not an error.
+    fn run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<RunResult>>;
+
+    /// Run a shell command on the device, e.g. a setup script.
+    fn run_shell(&self, _command: &str) -> Result<()> {
+        bail!("Running shell commands is not supported on {}", self.id())
+    }
+
+    /// Save a screenshot of the device to `path` (PNG, or TIFF on old iOS
+    /// versions).
+    fn screenshot(&self, _path: &Path) -> Result<()> {
+        bail!("Screenshots are not supported on {}", self.id())
+    }
 
     fn start_remote_lldb(&self) -> Result<String>;
+
+    /// Stream the console of the device to stdout, independently of any run.
+    fn stream_console(&self, _options: &ConsoleOptions) -> Result<()> {
+        bail!("Streaming the console is not supported on {}", self.id())
+    }
+
+    /// Remove an install recorded in the `installs` registry: a remote bundle
+    /// directory, or the bundle identifier of an app on iOS.
+    fn uninstall(&self, location: &str) -> Result<()> {
+        bail!("Can't remove {}, uninstalling is not supported on {}", location, self.id())
+    }
 }
 
 pub trait DeviceCompatibility {
+    fn is_compatible_with_custom_platform(&self, _platform: &CustomPlatform) -> bool {
+        false
+    }
+
     fn is_compatible_with_regular_platform(&self, _platform: &RegularPlatform) -> bool {
         false
     }
@@ -200,9 +488,23 @@ pub trait DeviceCompatibility {
     fn is_compatible_with_ios_platform(&self, _platform: &IosPlatform) -> bool {
         false
     }
+
+    fn is_compatible_with_wasi_platform(&self, _platform: &WasiPlatform) -> bool {
+        false
+    }
+
+    /// Compatibility with the platforms brought by plugins.
+    fn is_compatible_with_plugin_platform(&self, _platform: &Platform) -> bool {
+        false
+    }
 }
 
 pub trait Platform {
+    /// Build `project` for the platform, from the setup of the environment
+    /// (`platform::setup_build_env`) and of the toolchain and its shims to the
+    /// compilation. Bundling the runnables is left to the devices, as the
+    /// layout of a bundle depends on the device (an app on iOS, a directory
+    /// pushed with adb on Android...).
     fn build(&self, project: &Project, build_args: &BuildArgs) -> Result<Build>;
 
     fn id(&self) -> String;
@@ -211,7 +513,8 @@ pub trait Platform {
 
     fn rustc_triple(&self) -> Option<&str>;
 
-    fn strip(&self, build: &Build) -> Result<()>;
+    /// Strip the runnables of `build`, setting their `stripped_exe`.
+    fn strip(&self, build: &mut Build) -> Result<()>;
 }
 
 pub trait PlatformManager {
@@ -222,19 +525,44 @@ pub trait PlatformManager {
 #[derive(Clone, Debug)]
 pub struct Build {
     pub build_args: BuildArgs,
+    /// The C dynamic libraries built, e.g. to load from an Android app.
+    pub cdylibs: Vec<PathBuf>,
+    /// Packages whose doctests are left out of a test build, as rustdoc can
+    /// only run doctests on the host.
+    pub doctests: Vec<String>,
     pub dynamic_libraries: Vec<PathBuf>,
     pub runnables: Vec<Runnable>,
+    /// The triple built for, `None` for the host.
+    pub rustc_triple: Option<String>,
+    /// The C static libraries built, e.g. to link in an iOS app.
+    pub staticlibs: Vec<PathBuf>,
     pub target_path: PathBuf,
 }
 
+impl Build {
+    /// Host-side staging area of the artifacts of `device_id` for this build.
+    pub fn artifacts(&self, device_id: &str) -> ArtifactStore {
+        ArtifactStore::new(&self.target_path, device_id)
+    }
+}
+
 
 #[derive(Clone, Debug)]
 pub struct BuildArgs {
+    /// Only check the code, with `cargo check` or `cargo clippy`, instead of
+    /// building it: nothing is linked, and the build has no runnables.
+    pub check: Option<CheckCommand>,
     pub compile_mode: CompileMode,
+    pub coverage: bool,
+    /// Only plan the build: nothing is compiled, and the runnables are the
+    /// ones the build would produce.
+    pub dry_run: bool,
     pub verbose: bool,
     pub forced_overlays: Vec<String>,
 }
 
+/// A runnable made ready for a device: its executable, libraries, test data
+/// and resources, in a bundle the device installs and runs.
 #[derive(Clone, Debug, Default)]
 pub struct BuildBundle {
     pub id: String,
@@ -246,23 +574,71 @@ pub struct BuildBundle {
 
 impl BuildBundle {
     fn replace_prefix_with<P: AsRef<Path>>(&self, path: P) -> Result<Self> {
+        let prefixed = |it: &Path| -> Result<PathBuf> { Ok(path.as_ref().join(it.strip_prefix(&self.root_dir)?)) };
         Ok(BuildBundle {
-            id: self.id.clone(),
-            bundle_dir: path.as_ref().to_path_buf()
-                .join(self.bundle_dir.strip_prefix(&self.root_dir)?),
-            bundle_exe: path.as_ref().to_path_buf()
-                .join(self.bundle_exe.strip_prefix(&self.root_dir)?),
-            lib_dir: path.as_ref().to_path_buf()
-                .join(self.lib_dir.strip_prefix(&self.root_dir)?),
+            bundle_dir: prefixed(&self.bundle_dir)?,
+            bundle_exe: prefixed(&self.bundle_exe)?,
+            lib_dir: prefixed(&self.lib_dir)?,
             root_dir: path.as_ref().to_path_buf(),
+            ..self.clone()
         })
     }
 }
 
 
+#[derive(Clone, Debug, Default)]
+pub struct RunArgs {
+    pub args: Vec<String>,
+    /// Send the bundles compressed, for slow links to the devices.
+    pub compress: bool,
+    pub coverage: bool,
+    /// Also set the locale and timezone of the device itself, where possible
+    /// (Android only).
+    pub device_region: bool,
+    pub envs: Vec<String>,
+    /// Start from a clean state: remove the app and its data from the device
+    /// before installing it again.
+    pub fresh: bool,
+    /// Keep the screen of the device on during the run.
+    pub keep_awake: bool,
+    /// Extra lldb commands, run before launching the app (iOS only).
+    pub lldb_commands: Vec<String>,
+    /// Extra Python modules imported in lldb (iOS only).
+    pub lldb_helpers: Vec<PathBuf>,
+    /// Network condition simulated during the run.
+    pub network_profile: Option<NetworkProfile>,
+    /// Record the screen of the device during the run.
+    pub record: bool,
+    /// Locale and timezone of the run, also set in `envs`.
+    pub region: Region,
+    /// Take a screenshot of the device when runnables fail.
+    pub screenshot_on_failure: bool,
+    pub timeout: Option<Duration>,
+}
+
+impl RunArgs {
+    pub fn args(&self) -> Vec<&str> {
+        self.args.iter().map(|it| it.as_str()).collect()
+    }
+
+    pub fn envs(&self) -> Vec<&str> {
+        self.envs.iter().map(|it| it.as_str()).collect()
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Runnable {
     pub id: String,
     pub exe: PathBuf,
     pub source: PathBuf,
+    /// Stripped copy of `exe`, deployed instead of it. `exe` stays on the
+    /// host for symbolication.
+    pub stripped_exe: Option<PathBuf>,
+}
+
+impl Runnable {
+    /// The executable to send to the devices.
+    pub fn deployed_exe(&self) -> &Path {
+        self.stripped_exe.as_ref().unwrap_or(&self.exe)
+    }
 }
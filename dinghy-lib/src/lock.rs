@@ -0,0 +1,85 @@
+//! Advisory locks serializing the runs of concurrent dinghy invocations (or CI
+//! jobs) on the same device.
+//!
+//! Each device has a lock file, keyed on its id, in a directory shared by all
+//! the projects of the host. The lock is an `flock` on that file, so it is
+//! released by the system even if the holder is killed.
+
+use errors::*;
+use libc;
+use Device;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Holds the lock of a device until dropped.
+pub struct DeviceLock {
+    _file: File,
+}
+
+/// How long to wait for a device used by another run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LockWait {
+    Forever,
+    For(Duration),
+    NoWait,
+}
+
+/// Directory of the device lock files.
+pub fn locks_dir() -> PathBuf {
+    env::temp_dir().join("dinghy-locks")
+}
+
+/// Take the lock of `device_id`, waiting for other runs as told by `wait`.
+pub fn lock_device(device_id: &str, wait: LockWait) -> Result<DeviceLock> {
+    let path = ::artifacts::ensure_dir(locks_dir())?.join(format!("{}.lock", ::artifacts::sanitize(device_id)));
+    let file = fs::OpenOptions::new().create(true).write(true).open(&path)
+        .chain_err(|| format!("Couldn't open lock file {}", path.display()))?;
+    let start = Instant::now();
+    let mut warned = false;
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            debug!("Locked device {} ({})", device_id, path.display());
+            return Ok(DeviceLock { _file: file });
+        }
+        let elapsed = start.elapsed();
+        match wait {
+            LockWait::NoWait => Err(ErrorKind::DeviceBusy(device_id.to_string()))?,
+            LockWait::For(timeout) if elapsed >= timeout => Err(ErrorKind::DeviceBusy(device_id.to_string()))?,
+            _ => {}
+        }
+        if !warned {
+            info!("Device {} is used by another dinghy run, waiting for it", device_id);
+            warned = true;
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+/// Serialize the runs on `device` with the other dinghy invocations. The host
+/// runs everything concurrently anyway.
+pub fn lock_for_run(device: &Device, wait: LockWait) -> Result<Option<DeviceLock>> {
+    if device.id() == "HOST" {
+        return Ok(None);
+    }
+    Ok(Some(lock_device(device.id(), wait)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_is_exclusive() {
+        let device = format!("test-device-{}", ::std::process::id());
+        let lock = lock_device(&device, LockWait::NoWait).unwrap();
+        assert!(lock_device(&device, LockWait::For(Duration::from_millis(100))).is_err());
+        drop(lock);
+        assert!(lock_device(&device, LockWait::NoWait).is_ok());
+    }
+}
@@ -0,0 +1,193 @@
+//! The logger of dinghy.
+//!
+//! Messages are filtered for the console the same way `RUST_LOG` filters them
+//! for `env_logger`, and every debug message of dinghy is also written to a
+//! log file that can be attached to a bug report, whatever the verbosity.
+//!
+//! The operations (discover, build, bundle, install, run) are wrapped in
+//! spans: the messages logged while a span is open are prefixed with its
+//! path, and its duration is logged when it ends.
+
+use env_logger::filter::Builder as FilterBuilder;
+use env_logger::filter::Filter;
+use errors::*;
+use log;
+use log::Level;
+use log::LevelFilter;
+use log::Log;
+use log::Metadata;
+use log::Record;
+use std::cell::RefCell;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Mutex;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// The number of log files kept in the log directory.
+const KEPT_LOG_FILES: usize = 10;
+
+thread_local! {
+    static SPANS: RefCell<Vec<String>> = RefCell::new(vec![]);
+}
+
+/// An operation, ended when dropped.
+pub struct Span {
+    name: String,
+    start: Instant,
+}
+
+/// Starts the operation `name`, nested in the ones already started on this thread.
+pub fn span<S: Into<String>>(name: S) -> Span {
+    let name = name.into();
+    SPANS.with(|spans| spans.borrow_mut().push(name.clone()));
+    debug!("started");
+    Span { name, start: Instant::now() }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        debug!("done in {}.{:03}s", elapsed.as_secs(), elapsed.subsec_nanos() / 1_000_000);
+        SPANS.with(|spans| {
+            let mut spans = spans.borrow_mut();
+            if let Some(ix) = spans.iter().rposition(|it| it == &self.name) {
+                spans.remove(ix);
+            }
+        });
+    }
+}
+
+fn span_path() -> String {
+    SPANS.with(|spans| spans.borrow().join("/"))
+}
+
+struct DinghyLogger {
+    console: Filter,
+    colored: bool,
+    file: Option<Mutex<File>>,
+    start: Instant,
+}
+
+impl DinghyLogger {
+    fn in_file(&self, metadata: &Metadata) -> bool {
+        self.file.is_some() && (metadata.level() <= Level::Warn
+            || (metadata.level() <= Level::Debug
+            && (metadata.target().starts_with("dinghy") || metadata.target().starts_with("cargo_dinghy"))))
+    }
+
+    fn colored_level(&self, level: Level) -> String {
+        if !self.colored {
+            return format!("{:5}", level);
+        }
+        let color = match level {
+            Level::Error => "31",
+            Level::Warn => "33",
+            Level::Info => "32",
+            Level::Debug => "34",
+            Level::Trace => "35",
+        };
+        format!("\x1b[{}m{:5}\x1b[0m", color, level)
+    }
+}
+
+impl Log for DinghyLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata) || self.in_file(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let spans = span_path();
+        let message = if spans.is_empty() {
+            format!("{}", record.args())
+        } else {
+            format!("[{}] {}", spans, record.args())
+        };
+        if self.console.matches(record) {
+            eprintln!(" {} {} > {}", self.colored_level(record.level()), record.target(), message);
+        }
+        if self.in_file(record.metadata()) {
+            if let Some(ref file) = self.file {
+                let elapsed = self.start.elapsed();
+                if let Ok(mut file) = file.lock() {
+                    let _ = writeln!(file, "{:>4}.{:03} {:5} {} > {}",
+                                     elapsed.as_secs(), elapsed.subsec_nanos() / 1_000_000,
+                                     record.level(), record.target(), message);
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(ref file) = self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+/// Sets up the logger, filtering the console with `filter` (in the `RUST_LOG`
+/// syntax) and writing a debug log file in `log_dir` if any.
+///
+/// Returns the path of the log file.
+pub fn init(filter: &str, log_dir: Option<&Path>) -> Result<Option<PathBuf>> {
+    let console = FilterBuilder::new().parse(filter).build();
+    let log_file = match log_dir {
+        Some(log_dir) => match open_log_file(log_dir) {
+            Ok(log_file) => Some(log_file),
+            Err(e) => {
+                eprintln!("Couldn't create a log file in {}: {}", log_dir.display(), e);
+                None
+            }
+        },
+        None => None,
+    };
+    let max_level = if log_file.is_some() {
+        ::std::cmp::max(console.filter(), LevelFilter::Debug)
+    } else {
+        console.filter()
+    };
+    let (path, file) = match log_file {
+        Some((path, file)) => (Some(path), Some(Mutex::new(file))),
+        None => (None, None),
+    };
+    log::set_boxed_logger(Box::new(DinghyLogger {
+        console,
+        colored: ::isatty::stderr_isatty(),
+        file,
+        start: Instant::now(),
+    })).map_err(|_| "A logger is already set")?;
+    log::set_max_level(max_level);
+    Ok(path)
+}
+
+fn open_log_file(log_dir: &Path) -> Result<(PathBuf, File)> {
+    fs::create_dir_all(log_dir)?;
+    prune_log_files(log_dir, KEPT_LOG_FILES - 1)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|it| it.as_secs()).unwrap_or(0);
+    let path = log_dir.join(format!("dinghy-{}-{}.log", now, process::id()));
+    let file = File::create(&path)?;
+    Ok((path, file))
+}
+
+/// Removes the oldest log files of `log_dir`, keeping `kept` of them.
+fn prune_log_files(log_dir: &Path, kept: usize) -> Result<()> {
+    let mut log_files = fs::read_dir(log_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map(|it| it == "log").unwrap_or(false))
+        .filter_map(|path| fs::metadata(&path).and_then(|it| it.modified()).ok().map(|it| (it, path)))
+        .collect::<Vec<_>>();
+    log_files.sort();
+    let count = log_files.len();
+    for (_, path) in log_files.into_iter().take(count.saturating_sub(kept)) {
+        let _ = fs::remove_file(path);
+    }
+    Ok(())
+}
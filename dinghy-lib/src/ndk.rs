@@ -0,0 +1,135 @@
+//! `cargo dinghy setup android`: download of a pinned Android NDK into
+//! `~/.dinghy/ndk`, and `[toolchains]` entries using its clang for the Android
+//! triples, so that CI hosts need nothing but dinghy and rustup.
+
+use errors::*;
+use std::env::home_dir;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// The NDK installed when no version is asked for.
+pub static NDK_VERSION: &str = "r26d";
+/// The Android API level the toolchains target when none is asked for, the
+/// oldest one NDK_VERSION supports.
+pub static MIN_API: u32 = 21;
+
+/// The Rust triples of Android, and the prefix of the matching clang of the NDK.
+static TRIPLES: &[(&str, &str)] = &[
+    ("aarch64-linux-android", "aarch64-linux-android"),
+    ("armv7-linux-androideabi", "armv7a-linux-androideabi"),
+    ("i686-linux-android", "i686-linux-android"),
+    ("x86_64-linux-android", "x86_64-linux-android"),
+];
+
+pub fn ndk_dir(version: &str) -> Result<PathBuf> {
+    Ok(home_dir().ok_or("No home directory")?.join(".dinghy").join("ndk").join(format!("android-ndk-{}", version)))
+}
+
+/// Download and unpack the NDK `version`, unless already there.
+pub fn install(version: &str) -> Result<PathBuf> {
+    let ndk = ndk_dir(version)?;
+    if ndk.join("source.properties").is_file() {
+        info!("NDK {} already installed in {}", version, ndk.display());
+        return Ok(ndk);
+    }
+    let host = if cfg!(target_os = "macos") {
+        "darwin"
+    } else if cfg!(target_os = "linux") {
+        "linux"
+    } else {
+        bail!("The NDK can only be installed automatically on Linux and macOS hosts")
+    };
+    let parent = ndk.parent().ok_or("Invalid NDK directory")?;
+    fs::create_dir_all(parent).chain_err(|| format!("Couldn't create {}", parent.display()))?;
+    let url = format!("https://dl.google.com/android/repository/android-ndk-{}-{}.zip", version, host);
+    let archive = parent.join(format!("android-ndk-{}.zip", version));
+    info!("Downloading {}", url);
+    let status = Command::new("curl").arg("-fL").arg("-o").arg(&archive).arg(&url).status()
+        .chain_err(|| "Couldn't run curl")?;
+    if !status.success() {
+        bail!("Couldn't download {}", url)
+    }
+    info!("Unpacking {}", archive.display());
+    let status = Command::new("unzip").arg("-q").arg("-o").arg(&archive).arg("-d").arg(parent).status()
+        .chain_err(|| "Couldn't run unzip")?;
+    let _ = fs::remove_file(&archive);
+    if !status.success() || !ndk.is_dir() {
+        bail!("Couldn't unpack the NDK {} into {}", version, parent.display())
+    }
+    Ok(ndk)
+}
+
+/// The directory of the prebuilt LLVM toolchain of the NDK for this host.
+pub fn prebuilt_dir(ndk: &Path) -> Result<PathBuf> {
+    let prebuilt = ndk.join("toolchains").join("llvm").join("prebuilt");
+    // Only x86_64 builds exist, run through Rosetta on Apple silicon.
+    let host = if cfg!(target_os = "macos") { "darwin-x86_64" } else { "linux-x86_64" };
+    if !prebuilt.join(host).is_dir() {
+        bail!("No LLVM toolchain for {} in {}, NDK r19 or later is needed", host, ndk.display())
+    }
+    Ok(prebuilt.join(host))
+}
+
+/// The `[toolchains]` entries of the Android triples, using the clang of
+/// `prebuilt` for `api`.
+pub fn toolchain_entries(prebuilt: &Path, api: u32) -> Vec<(String, String)> {
+    let bin = prebuilt.join("bin");
+    TRIPLES.iter()
+        .map(|&(triple, clang)| {
+            let entry = format!(
+                "[toolchains.\"{triple}\"]\n\
+                 linker = \"{clang}\"\n\
+                 cc = \"{clang}\"\n\
+                 cxx = \"{clang}++\"\n\
+                 ar = \"{ar}\"\n\
+                 sysroot = \"{sysroot}\"\n",
+                triple = triple,
+                clang = bin.join(format!("{}{}-clang", clang, api)).display(),
+                ar = bin.join("llvm-ar").display(),
+                sysroot = prebuilt.join("sysroot").display());
+            (triple.to_string(), entry)
+        })
+        .collect()
+}
+
+/// Append the `entries` to the `config` file, except the ones of triples it
+/// already has a toolchain for. Returns the triples added.
+pub fn configure(config: &Path, entries: &[(String, String)]) -> Result<Vec<String>> {
+    let mut content = String::new();
+    if config.exists() {
+        fs::File::open(config)
+            .and_then(|mut file| file.read_to_string(&mut content))
+            .chain_err(|| format!("Couldn't read {}", config.display()))?;
+    }
+    let mut added = vec![];
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(config)
+        .chain_err(|| format!("Couldn't open {}", config.display()))?;
+    for &(ref triple, ref entry) in entries {
+        if content.contains(&format!("[toolchains.\"{}\"]", triple)) {
+            warn!("{} already has a toolchain for {}, left as is", config.display(), triple);
+            continue;
+        }
+        write!(file, "\n{}", entry)?;
+        added.push(triple.clone());
+    }
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn armv7_entry() {
+        let entries = toolchain_entries(Path::new("/ndk/prebuilt"), 24);
+        let &(ref triple, ref entry) = entries.iter().find(|it| it.0 == "armv7-linux-androideabi").unwrap();
+        assert_eq!(triple, "armv7-linux-androideabi");
+        assert!(entry.starts_with("[toolchains.\"armv7-linux-androideabi\"]\n"));
+        assert!(entry.contains("linker = \"/ndk/prebuilt/bin/armv7a-linux-androideabi24-clang\"\n"));
+        assert!(entry.contains("sysroot = \"/ndk/prebuilt/sysroot\"\n"));
+    }
+}
@@ -0,0 +1,119 @@
+//! Simulated network conditions (latency, loss, bandwidth) for the runs.
+//!
+//! Profiles are built in (`edge`, `3g`, `lte`, `very-bad`, `loss`) or defined
+//! in `[network_profiles.<name>]`. Android devices apply them with `tc netem`
+//! (rooted devices only), iOS devices with the condition inducer of their
+//! developer services, through `pymobiledevice3`.
+
+use cleanup;
+use cleanup::CleanupGuard;
+use config::Configuration;
+use errors::*;
+use std::sync::Arc;
+
+/// Built in profiles: name, delay (ms), loss (%), rate (kbit/s) and iOS
+/// condition, after the ones of the Network Link Conditioner.
+const BUILT_IN_PROFILES: &[(&str, u32, f32, Option<u32>, &str)] = &[
+    ("edge", 400, 0.0, Some(240), "SlowNetworkConditionEdgeAverage"),
+    ("3g", 100, 0.0, Some(780), "SlowNetworkCondition3GAverage"),
+    ("lte", 50, 0.0, Some(10_000), "SlowNetworkConditionLTE"),
+    ("very-bad", 500, 10.0, Some(1_000), "SlowNetworkConditionVeryBadNetwork"),
+    ("loss", 0, 100.0, None, "SlowNetworkCondition100PctLoss"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetworkProfile {
+    pub name: String,
+    /// Added latency, in milliseconds.
+    pub delay_ms: u32,
+    /// Packets dropped, in percents.
+    pub loss_percent: f32,
+    /// Bandwidth, in kbit/s, if limited.
+    pub rate_kbps: Option<u32>,
+    /// Condition profile of the iOS condition inducer (e.g.
+    /// `SlowNetworkCondition3GAverage`).
+    pub ios_condition: Option<String>,
+}
+
+impl NetworkProfile {
+    /// The profile `name`, from the configuration or built in.
+    pub fn named(name: &str, conf: &Configuration) -> Result<NetworkProfile> {
+        if let Some(profile) = conf.network_profiles.get(name) {
+            return Ok(NetworkProfile {
+                name: name.to_string(),
+                delay_ms: profile.delay.unwrap_or(0),
+                loss_percent: profile.loss.unwrap_or(0.0),
+                rate_kbps: profile.rate,
+                ios_condition: profile.ios_condition.clone(),
+            });
+        }
+        BUILT_IN_PROFILES.iter()
+            .find(|it| it.0 == name)
+            .map(|&(name, delay_ms, loss_percent, rate_kbps, ios_condition)| NetworkProfile {
+                name: name.to_string(),
+                delay_ms,
+                loss_percent,
+                rate_kbps,
+                ios_condition: Some(ios_condition.to_string()),
+            })
+            .ok_or_else(|| format!("Unknown network profile '{}', expected one of the configuration or {}",
+                                   name, BUILT_IN_PROFILES.iter().map(|it| it.0).collect::<Vec<_>>().join(", ")).into())
+    }
+
+    /// The `tc qdisc ... netem` parameters of the profile.
+    pub fn netem(&self) -> String {
+        let mut netem = format!("delay {}ms loss {}%", self.delay_ms, self.loss_percent);
+        if let Some(rate) = self.rate_kbps {
+            netem.push_str(&format!(" rate {}kbit", rate));
+        }
+        netem
+    }
+
+    /// The profile, as a condition of the run reports.
+    pub fn description(&self) -> String {
+        format!("network profile {} ({})", self.name, self.netem())
+    }
+}
+
+/// A network condition applied to a device, lifted when dropped or when
+/// dinghy is interrupted.
+pub struct NetworkCondition {
+    /// How the condition was applied, for the run reports.
+    pub applied: String,
+    stop: Arc<Fn() + Send + Sync>,
+    _interrupt: CleanupGuard,
+}
+
+impl NetworkCondition {
+    pub fn new<F: Fn() + Send + Sync + 'static>(applied: String, stop: F) -> NetworkCondition {
+        let stop: Arc<Fn() + Send + Sync> = Arc::new(stop);
+        let interrupt_stop = stop.clone();
+        NetworkCondition {
+            applied,
+            stop,
+            _interrupt: cleanup::on_interrupt(move || interrupt_stop()),
+        }
+    }
+}
+
+impl Drop for NetworkCondition {
+    fn drop(&mut self) {
+        debug!("Lifting {}", self.applied);
+        (self.stop)()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_profiles() {
+        let conf = Configuration::default();
+        let profile = NetworkProfile::named("3g", &conf).unwrap();
+        assert_eq!(profile.netem(), "delay 100ms loss 0% rate 780kbit");
+        assert_eq!(profile.ios_condition, Some("SlowNetworkCondition3GAverage".to_string()));
+        assert_eq!(NetworkProfile::named("very-bad", &conf).unwrap().netem(), "delay 500ms loss 10% rate 1000kbit");
+        assert!(NetworkProfile::named("dial-up", &conf).is_err());
+    }
+}
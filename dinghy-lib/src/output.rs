@@ -0,0 +1,254 @@
+//! Output of the runnables, as it is streamed back from the devices.
+//!
+//! Every device writes what its runnables print, stdout and stderr, to an
+//! `OutputSink` as it comes. The sink shows it live on the terminal, feeds the
+//! stdout lines to the report parser, and writes each stream to its own file,
+//! along with a log of both, each line timestamped and tagged with its stream.
+//! The log is written a line at a time, when the line is complete: a partial
+//! line of one stream shows up after the lines the other stream completed in
+//! the meantime. Nothing is kept in memory: the
+//! runner moves the files to the reports of the run once it is over.
+//!
+//! When the compiler messages are consumed as JSON, the standard output is
+//! reserved for them (`reserve_stdout`): what the runnables print to their
+//! stdout is shown on stderr instead.
+//!
+//! When several devices run at once, their output is shown line by line, each
+//! line prefixed with the id of its device (`prefix_lines`).
+
+use artifacts;
+use errors::*;
+use report::TestReportParser;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::io::stderr;
+use std::io::stdout;
+use std::mem;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+use timings;
+
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+static PREFIX_LINES: AtomicBool = AtomicBool::new(false);
+
+// Number of sinks created so far, to name their files apart: a runnable can
+// run several times in a session (repetitions, retries).
+static SINKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Keep the standard output for the compiler messages, from now on.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::SeqCst);
+}
+
+/// Whether the standard output is kept for the compiler messages, anything
+/// else going to stderr.
+pub fn stdout_reserved() -> bool {
+    STDOUT_RESERVED.load(Ordering::SeqCst)
+}
+
+/// Show the output of the sinks created from now on by complete lines, each
+/// prefixed with the id of the device, for the output of several devices to
+/// be told apart.
+pub fn prefix_lines() {
+    PREFIX_LINES.store(true, Ordering::SeqCst);
+}
+
+/// Show `bytes` of `stream` on the terminal.
+fn show(stream: Stream, bytes: &[u8]) {
+    let _ = match stream {
+        Stream::Stdout if !stdout_reserved() => stdout().write_all(bytes).and_then(|_| stdout().flush()),
+        Stream::Stdout => stderr().write_all(bytes).and_then(|_| stderr().flush()),
+        Stream::Stderr => stderr().write_all(bytes).and_then(|_| stderr().flush()),
+    };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Stream::Stdout => "out",
+            Stream::Stderr => "err",
+        }
+    }
+}
+
+/// Where the output of a runnable goes. Clones write to the same sink, so
+/// that the readers of the stdout and the stderr of a process can share it.
+#[derive(Clone)]
+pub struct OutputSink {
+    state: Arc<Mutex<SinkState>>,
+}
+
+struct SinkState {
+    parser: Option<TestReportParser>,
+    started: Instant,
+    // Files of stdout, stderr and the log, with their paths.
+    files: [(PathBuf, fs::File); 3],
+    // First error writing the files, reported when the sink is finished.
+    error: Option<io::Error>,
+    last_line: Option<String>,
+    // Incomplete last line of stdout, then of stderr.
+    pending: [Vec<u8>; 2],
+    // Prefix of the lines shown, when shown by line.
+    prefix: Option<String>,
+}
+
+impl SinkState {
+    fn save(&mut self, index: usize, bytes: &[u8]) {
+        if let Err(e) = self.files[index].1.write_all(bytes) {
+            self.error.get_or_insert(e);
+        }
+    }
+
+    fn line(&mut self, stream: Stream, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_right_matches(|c| c == '\r' || c == '\n');
+        if let Some(ref prefix) = self.prefix {
+            show(stream, format!("{}{}\n", prefix, line).as_bytes());
+        }
+        let logged = format!("{:>9.3} {} {}\n", timings::secs(self.started.elapsed()), stream.as_str(), line);
+        self.save(2, logged.as_bytes());
+        if stream == Stream::Stdout {
+            if let Some(ref mut parser) = self.parser {
+                parser.parse_line(line);
+            }
+            self.last_line = Some(line.to_string());
+        }
+    }
+}
+
+impl OutputSink {
+    /// A sink writing its files to the output directory of the device of
+    /// `parser` (see `artifacts::output_dir`).
+    pub fn new(parser: TestReportParser) -> Result<OutputSink> {
+        let dir = artifacts::ensure_dir(artifacts::output_dir(parser.device())?)?;
+        OutputSink::in_dir(&dir, parser)
+    }
+
+    /// A sink writing its files to `dir`.
+    pub fn in_dir(dir: &Path, parser: TestReportParser) -> Result<OutputSink> {
+        let name = format!("{}-{}", artifacts::sanitize(parser.runnable()), SINKS.fetch_add(1, Ordering::SeqCst));
+        let create = |extension: &str| -> Result<(PathBuf, fs::File)> {
+            let path = dir.join(format!("{}.{}", name, extension));
+            let file = fs::File::create(&path).chain_err(|| format!("Couldn't create {}", path.display()))?;
+            Ok((path, file))
+        };
+        let prefix = if PREFIX_LINES.load(Ordering::SeqCst) { Some(format!("[{}] ", parser.device())) } else { None };
+        Ok(OutputSink {
+            state: Arc::new(Mutex::new(SinkState {
+                parser: Some(parser),
+                started: Instant::now(),
+                files: [create("stdout")?, create("stderr")?, create("log")?],
+                error: None,
+                last_line: None,
+                pending: [vec![], vec![]],
+                prefix,
+            })),
+        })
+    }
+
+    /// Write a `chunk` of the `stream` of the runnable.
+    pub fn write(&self, stream: Stream, chunk: &[u8]) {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        // Shown with the lock held, for the terminal to show the chunks of both
+        // streams in the order they came.
+        if state.prefix.is_none() {
+            show(stream, chunk);
+        }
+        let index = stream as usize;
+        state.save(index, chunk);
+        state.pending[index].extend_from_slice(chunk);
+        while let Some(eol) = state.pending[index].iter().position(|&it| it == b'\n') {
+            let line = state.pending[index].drain(..eol + 1).collect::<Vec<_>>();
+            state.line(stream, &line);
+        }
+    }
+
+    /// The last complete line written to stdout, if any.
+    pub fn last_line(&self) -> Option<String> {
+        self.state.lock().ok().and_then(|it| it.last_line.clone())
+    }
+
+    /// Flush the incomplete lines, and hand the parser back with the files of
+    /// the output, for the report of the run.
+    pub fn finish(self) -> TestReportParser {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        for &stream in &[Stream::Stdout, Stream::Stderr] {
+            let line = mem::replace(&mut state.pending[stream as usize], vec![]);
+            if !line.is_empty() {
+                state.line(stream, &line);
+            }
+        }
+        let mut parser = state.parser.take().expect("Output sink finished twice");
+        if let Some(ref e) = state.error {
+            warn!("Couldn't save the whole output of {}: {}", parser.runnable(), e);
+        }
+        let path = |index: usize| state.files[index].0.clone();
+        parser.set_output(path(0), path(1), path(2));
+        parser
+    }
+}
+
+/// Write what is read from `reader` as the `stream` of `sink`, in the
+/// background, until the end of `reader`.
+pub fn relay<R: Read + Send + 'static>(mut reader: R, stream: Stream, sink: OutputSink) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => sink.write(stream, &buffer[..read]),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn interleaved_streams() {
+        let read = |path: &Option<PathBuf>| {
+            let mut content = String::new();
+            fs::File::open(path.as_ref().unwrap()).unwrap().read_to_string(&mut content).unwrap();
+            content
+        };
+        let dir = artifacts::ensure_dir(env::temp_dir().join(format!("dinghy-output-{}", process::id()))).unwrap();
+        let sink = OutputSink::in_dir(&dir, TestReportParser::new("device", "runnable")).unwrap();
+        sink.write(Stream::Stdout, b"test tests::passes ... ");
+        sink.write(Stream::Stderr, b"warning\n");
+        sink.write(Stream::Stdout, b"ok\nno newline");
+        assert_eq!(sink.last_line(), Some("test tests::passes ... ok".to_string()));
+        let report = sink.finish().finish(true);
+        assert_eq!(read(&report.stdout), "test tests::passes ... ok\nno newline");
+        assert_eq!(read(&report.stderr), "warning\n");
+        let log = read(&report.log).lines().map(|it| it.split_whitespace().skip(1).collect::<Vec<_>>().join(" ")).collect::<Vec<_>>();
+        assert_eq!(log, vec!["err warning", "out test tests::passes ... ok", "out no newline"]);
+        assert_eq!(report.tests.len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,95 @@
+use compiler::Compiler;
+use config::PlatformConfiguration;
+use config::ToolchainConfiguration;
+use dinghy_build::build_env::set_all_env;
+use overlay::Overlayer;
+use platform;
+use project::Project;
+use std::fmt::Display;
+use std::sync::Arc;
+use toolchain::Toolchain;
+use Build;
+use BuildArgs;
+use Device;
+use Platform;
+use Result;
+
+/// A platform described entirely by a `[toolchains."<triple>"]` section of the
+/// configuration, for targets dinghy has no built-in knowledge of.
+pub struct CustomPlatform {
+    compiler: Arc<Compiler>,
+    pub configuration: PlatformConfiguration,
+    pub id: String,
+    pub toolchain: Toolchain,
+    pub toolchain_configuration: ToolchainConfiguration,
+}
+
+impl CustomPlatform {
+    pub fn new(compiler: &Arc<Compiler>,
+               configuration: PlatformConfiguration,
+               id: String,
+               rustc_triple: String,
+               toolchain_configuration: ToolchainConfiguration) -> Result<Box<Platform>> {
+        Ok(Box::new(CustomPlatform {
+            compiler: compiler.clone(),
+            configuration,
+            id,
+            toolchain: Toolchain { rustc_triple },
+            toolchain_configuration,
+        }))
+    }
+}
+
+impl Display for CustomPlatform {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
+        write!(f, "Custom toolchain for {}", self.toolchain.rustc_triple)
+    }
+}
+
+impl Platform for CustomPlatform {
+    fn build(&self, project: &Project, build_args: &BuildArgs) -> Result<Build> {
+        platform::setup_build_env(project, self.rustc_triple())?;
+        // Set custom env variables specific to the toolchain and the platform
+        set_all_env(&self.toolchain_configuration.env());
+        set_all_env(&self.configuration.env());
+
+        let sysroot = self.toolchain_configuration.sysroot.clone().unwrap_or("/".to_string());
+        Overlayer::overlay(&self.configuration, self, project, &sysroot)?;
+
+        if self.toolchain_configuration.sysroot.is_some() {
+            self.toolchain.setup_sysroot(&sysroot);
+        }
+        if let Some(cc) = self.toolchain_configuration.cc.as_ref() {
+            self.toolchain.setup_shimmed_tool(&self.id, "CC", cc)?;
+        }
+        if let Some(cxx) = self.toolchain_configuration.cxx.as_ref() {
+            self.toolchain.setup_shimmed_tool(&self.id, "CXX", cxx)?;
+        }
+        if let Some(ar) = self.toolchain_configuration.ar.as_ref() {
+            self.toolchain.setup_shimmed_tool(&self.id, "AR", ar)?;
+        }
+        if let Some(linker) = self.toolchain_configuration.linker.as_ref() {
+            self.toolchain.setup_linker(&self.id, linker)?;
+        }
+        self.toolchain.setup_pkg_config()?;
+
+        self.compiler.build(self.rustc_triple(), build_args)
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn is_compatible_with(&self, device: &Device) -> bool {
+        device.is_compatible_with_custom_platform(self)
+    }
+
+    fn rustc_triple(&self) -> Option<&str> {
+        Some(&self.toolchain.rustc_triple)
+    }
+
+    fn strip(&self, _build: &mut Build) -> Result<()> {
+        warn!("Stripping is not supported for custom toolchain {}", self.toolchain.rustc_triple);
+        Ok(())
+    }
+}
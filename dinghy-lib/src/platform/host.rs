@@ -31,6 +31,7 @@ impl HostPlatform {
 
 impl Platform for HostPlatform {
     fn build(&self, project: &Project, build_args: &BuildArgs) -> Result<Build> {
+        platform::setup_build_env(project, None)?;
         // Set custom env variables specific to the platform
         set_all_env(&self.configuration.env());
 
@@ -51,8 +52,8 @@ impl Platform for HostPlatform {
         None
     }
 
-    fn strip(&self, build: &Build) -> Result<()> {
-        for runnable in &build.runnables {
+    fn strip(&self, build: &mut Build) -> Result<()> {
+        for runnable in build.runnables.iter_mut() {
             info!("Stripping {}", runnable.exe.display());
             platform::strip_runnable(runnable, Command::new("strip"))?;
         }
@@ -1,10 +1,12 @@
 use compiler::Compiler;
 use config::PlatformConfiguration;
-use dinghy_build::build_env::set_env;
+use dinghy_build::triple::Triple;
 use errors::*;
 use overlay::Overlayer;
+use platform;
 use project::Project;
 use std::fmt::Display;
+use std::fs;
 use std::process;
 use std::sync::Arc;
 use toolchain::Toolchain;
@@ -18,6 +20,8 @@ pub struct IosPlatform {
     id: String,
     pub sim: bool,
     pub toolchain: Toolchain,
+    /// Toolchains of the other slices of universal binaries.
+    pub fat_toolchains: Vec<Toolchain>,
     pub configuration: PlatformConfiguration,
     compiler: Arc<Compiler>,
 }
@@ -26,39 +30,82 @@ impl IosPlatform {
     pub fn new(id: String, rustc_triple: &str, compiler: &Arc<Compiler>, configuration: &PlatformConfiguration) -> Result<Box<Platform>> {
         Ok(Box::new(IosPlatform {
             id,
-            sim: rustc_triple.starts_with("x86"),
+            sim: Triple::parse(rustc_triple).map(|it| it.is_simulator()).unwrap_or(false),
             toolchain: Toolchain {
                 rustc_triple: rustc_triple.to_string()
             },
+            fat_toolchains: configuration.fat_rustc_triples.iter()
+                .flat_map(|it| it.iter())
+                .map(|it| Toolchain { rustc_triple: it.to_string() })
+                .collect(),
             compiler: Arc::clone(compiler),
             configuration: configuration.clone(),
         }))
     }
 
+    /// Targets of the binaries built by this platform, universal binaries
+    /// having several of them.
+    pub fn rustc_triples(&self) -> Vec<&str> {
+        ::std::iter::once(&self.toolchain)
+            .chain(self.fat_toolchains.iter())
+            .map(|it| it.rustc_triple.as_str())
+            .collect()
+    }
+
+    fn sdk_name(&self) -> &'static str {
+        let os = self.toolchain.rustc_triple.split('-').nth(2).unwrap_or("ios");
+        match (os, self.sim) {
+            ("watchos", true) => "watchsimulator",
+            ("watchos", false) => "watchos",
+            ("tvos", true) => "appletvsimulator",
+            ("tvos", false) => "appletvos",
+            ("visionos", true) => "xrsimulator",
+            ("visionos", false) => "xros",
+            (_, true) => "iphonesimulator",
+            (_, false) => "iphoneos",
+        }
+    }
+
     fn sysroot_path(&self) -> Result<String> {
-        let sdk_name = if self.sim {
-            "iphonesimulator"
-        } else {
-            "iphoneos"
-        };
         let xcrun = process::Command::new("xcrun")
-            .args(&["--sdk", sdk_name, "--show-sdk-path"])
+            .args(&["--sdk", self.sdk_name(), "--show-sdk-path"])
             .output()?;
         Ok(String::from_utf8(xcrun.stdout)?.trim_right().to_string())
     }
+
+    fn setup_toolchain(&self, toolchain: &Toolchain, sysroot: &str) -> Result<()> {
+        let xcrun = format!("xcrun --sdk {}", self.sdk_name());
+        toolchain.setup_shimmed_tool(&self.id(), "CC",
+                                     &format!("{} clang -isysroot {}", xcrun, sysroot))?;
+        toolchain.setup_shimmed_tool(&self.id(), "CXX",
+                                     &format!("{} clang++ -isysroot {}", xcrun, sysroot))?;
+        toolchain.setup_shimmed_tool(&self.id(), "AR", &format!("{} ar", xcrun))?;
+        toolchain.setup_sysroot(&sysroot);
+        toolchain.setup_linker(&self.id(),
+                               &format!("cc -isysroot {}", sysroot))?;
+        toolchain.setup_pkg_config()
+    }
 }
 
 impl Platform for IosPlatform {
     fn build(&self, project: &Project, build_args: &BuildArgs) -> Result<Build> {
+        platform::setup_build_env(project, self.rustc_triple())?;
         let sysroot = self.sysroot_path()?;
         Overlayer::overlay(&self.configuration, self, project, &self.sysroot_path()?)?;
-        self.toolchain.setup_cc(self.id().as_str(), "gcc")?;
-        set_env("TARGET_SYSROOT", &sysroot);
-        self.toolchain.setup_linker(&self.id(),
-                                    &format!("cc -isysroot {}", sysroot))?;
-        self.toolchain.setup_pkg_config()?;
+        self.setup_toolchain(&self.toolchain, &sysroot)?;
+        let mut build = self.compiler.build(self.rustc_triple(), build_args)?;
+        if self.fat_toolchains.is_empty() || build_args.dry_run {
+            return Ok(build);
+        }
 
-        self.compiler.build(self.rustc_triple(), build_args)
+        let mut slices = vec![];
+        for toolchain in &self.fat_toolchains {
+            info!("Building {} slice of the universal binaries", toolchain.rustc_triple);
+            self.setup_toolchain(toolchain, &sysroot)?;
+            slices.push((toolchain.rustc_triple.as_str(), self.compiler.build(Some(&toolchain.rustc_triple), build_args)?));
+        }
+        lipo(&mut build, &slices)?;
+        Ok(build)
     }
 
     fn id(&self) -> String {
@@ -73,8 +120,8 @@ impl Platform for IosPlatform {
         Some(&self.toolchain.rustc_triple)
     }
 
-    fn strip(&self, build: &Build) -> Result<()> {
-        for runnable in &build.runnables {
+    fn strip(&self, build: &mut Build) -> Result<()> {
+        for runnable in build.runnables.iter_mut() {
             let mut command = ::std::process::Command::new("xcrun");
             command.arg("strip");
             super::strip_runnable(runnable, command)?;
@@ -83,6 +130,31 @@ impl Platform for IosPlatform {
     }
 }
 
+/// Merge each runnable of `build` with the runnables of the same name built
+/// for the other slices into a universal binary, which replaces it.
+fn lipo(build: &mut Build, slices: &[(&str, Build)]) -> Result<()> {
+    let universal_dir = build.target_path.join("universal");
+    fs::create_dir_all(&universal_dir)?;
+    for runnable in build.runnables.iter_mut() {
+        let universal = universal_dir.join(&runnable.id);
+        let mut command = process::Command::new("xcrun");
+        command.arg("lipo").arg("-create").arg(&runnable.exe);
+        for &(rustc_triple, ref slice) in slices {
+            let slice_runnable = slice.runnables.iter()
+                .find(|it| it.id == runnable.id)
+                .ok_or(format!("{} was not built for {}", runnable.id, rustc_triple))?;
+            command.arg(&slice_runnable.exe);
+        }
+        command.arg("-output").arg(&universal);
+        debug!("Running {:?}", command);
+        if !command.status()?.success() {
+            bail!("Couldn't merge {} into a universal binary", runnable.id)
+        }
+        runnable.exe = universal;
+    }
+    Ok(())
+}
+
 impl Display for IosPlatform {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
         if self.sim {
@@ -1,15 +1,55 @@
+use dinghy_build::triple::Triple;
+use project::Project;
 use Result;
 use Runnable;
 use std::fs;
 use std::process::Command;
 use utils::file_name_as_str;
 
+pub mod custom_platform;
 pub mod regular_platform;
 pub mod host;
 #[cfg(target_os = "macos")]
 pub mod ios;
+pub mod wasi;
 
-fn strip_runnable(runnable: &Runnable, mut command: Command) -> Result<()> {
+/// The kinds of platforms, each owning the builds of the triples it knows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlatformKind {
+    /// Apple devices and simulators (iOS, tvOS, watchOS, visionOS), built with
+    /// the SDKs of Xcode.
+    Apple,
+    /// WebAssembly with WASI, run by a runtime on the host.
+    Wasi,
+    /// Anything else, built with a toolchain: a custom one if configured for
+    /// the triple, a regular one otherwise.
+    Toolchain,
+}
+
+impl PlatformKind {
+    /// The kind of platform building for `rustc_triple`, from the parts of the
+    /// triple.
+    pub fn of(rustc_triple: &str) -> PlatformKind {
+        if rustc_triple.split('-').nth(1).map(|it| it.starts_with("wasi")).unwrap_or(false) {
+            PlatformKind::Wasi
+        } else if Triple::parse(rustc_triple).map(|it| it.is_apple_mobile()).unwrap_or(false) {
+            PlatformKind::Apple
+        } else {
+            PlatformKind::Toolchain
+        }
+    }
+}
+
+/// What the builds of all the platforms start with: the `[target_env]`
+/// variables of `rustc_triple` and the compiler launcher, exported for the
+/// toolchain and the build scripts.
+pub fn setup_build_env(project: &Project, rustc_triple: Option<&str>) -> Result<()> {
+    ::crossbuild::export_target_env(&project.conf, rustc_triple);
+    ::launcher::setup(&project.conf)
+}
+
+/// Strip a copy of the executable of `runnable`, next to it.
+fn strip_runnable(runnable: &mut Runnable, mut command: Command) -> Result<()> {
     let exe_stripped_name = file_name_as_str(&runnable.exe)?;
 
     let mut stripped_runnable = runnable.clone();
@@ -32,5 +72,24 @@ fn strip_runnable(runnable: &Runnable, mut command: Command) -> Result<()> {
            runnable.exe.display(),
            fs::metadata(&runnable.exe)?.len(),
            fs::metadata(&stripped_runnable.exe)?.len());
+    runnable.stripped_exe = Some(stripped_runnable.exe);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn platform_kinds() {
+        for triple in &["aarch64-apple-ios", "aarch64-apple-ios-sim", "x86_64-apple-ios", "arm64_32-apple-watchos", "aarch64-apple-tvos"] {
+            assert_eq!(PlatformKind::of(triple), PlatformKind::Apple, "{}", triple);
+        }
+        for triple in &["wasm32-wasi", "wasm32-wasip1", "wasm32-wasip1-threads"] {
+            assert_eq!(PlatformKind::of(triple), PlatformKind::Wasi, "{}", triple);
+        }
+        for triple in &["aarch64-linux-android", "aarch64-apple-darwin", "aarch64-apple-ios-macabi", "wasm32-unknown-unknown"] {
+            assert_eq!(PlatformKind::of(triple), PlatformKind::Toolchain, "{}", triple);
+        }
+    }
+}
@@ -75,6 +75,7 @@ impl Display for RegularPlatform {
 
 impl Platform for RegularPlatform {
     fn build(&self, project: &Project, build_args: &BuildArgs) -> Result<Build> {
+        platform::setup_build_env(project, self.rustc_triple())?;
         // Cleanup environment
         set_all_env(&[
             ("LIBRARY_PATH", ""),
@@ -133,15 +134,16 @@ impl Platform for RegularPlatform {
         Some(&self.toolchain.rustc_triple)
     }
 
-    fn strip(&self, build: &Build) -> Result<()> {
-        for runnable in &build.runnables {
+    fn strip(&self, build: &mut Build) -> Result<()> {
+        for runnable in build.runnables.iter_mut() {
             platform::strip_runnable(runnable, Command::new(self.toolchain.executable("strip")))?;
         }
         Ok(())
     }
 }
 
-fn find_sysroot<P: AsRef<Path>>(toolchain_path: P) -> Result<PathBuf> {
+/// The `sysroot` directory of a toolchain, directly in it or one level below.
+pub fn find_sysroot<P: AsRef<Path>>(toolchain_path: P) -> Result<PathBuf> {
     let toolchain = toolchain_path.as_ref();
     let immediate = toolchain.join("sysroot");
     if immediate.is_dir() {
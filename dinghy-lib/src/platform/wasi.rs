@@ -0,0 +1,62 @@
+use compiler::Compiler;
+use config::PlatformConfiguration;
+use dinghy_build::build_env::set_all_env;
+use platform;
+use project::Project;
+use std::sync::Arc;
+use Build;
+use BuildArgs;
+use Device;
+use Platform;
+use Result;
+
+/// WebAssembly executables using WASI (`wasm32-wasi`), run by a WebAssembly
+/// runtime on the host. No C toolchain is involved.
+pub struct WasiPlatform {
+    compiler: Arc<Compiler>,
+    pub configuration: PlatformConfiguration,
+    pub id: String,
+    pub rustc_triple: String,
+}
+
+impl WasiPlatform {
+    pub fn new(compiler: &Arc<Compiler>,
+               configuration: PlatformConfiguration,
+               id: String,
+               rustc_triple: String) -> Result<Box<Platform>> {
+        Ok(Box::new(WasiPlatform {
+            compiler: compiler.clone(),
+            configuration,
+            id,
+            rustc_triple,
+        }))
+    }
+}
+
+impl Platform for WasiPlatform {
+    fn build(&self, project: &Project, build_args: &BuildArgs) -> Result<Build> {
+        platform::setup_build_env(project, self.rustc_triple())?;
+        set_all_env(&self.configuration.env());
+        if self.configuration.overlays.is_some() {
+            warn!("Overlays are not supported on platform {}, and are ignored", self.id);
+        }
+        self.compiler.build(self.rustc_triple(), build_args)
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn is_compatible_with(&self, device: &Device) -> bool {
+        device.is_compatible_with_wasi_platform(self)
+    }
+
+    fn rustc_triple(&self) -> Option<&str> {
+        Some(&self.rustc_triple)
+    }
+
+    fn strip(&self, _build: &mut Build) -> Result<()> {
+        debug!("No stripping of the WebAssembly executables of {}", self.id);
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+//! Registration of third-party backends.
+//!
+//! A plugin brings its own platforms and devices (e.g. a board farm, or an
+//! operating system dinghy knows nothing about) without patching dinghy: the
+//! program embedding dinghy registers it before calling `Dinghy::probe`, which
+//! then lists its devices and platforms along the built-in ones. There is no
+//! discovery: the stock `cargo dinghy` only knows the built-in backends.
+//!
+//! Devices of a plugin declare the plugin platforms they can run through
+//! `DeviceCompatibility::is_compatible_with_plugin_platform`.
+
+use compiler::Compiler;
+use config::Configuration;
+use config::PlatformConfiguration;
+use errors::*;
+use std::sync::Arc;
+use std::sync::Mutex;
+use Platform;
+use PlatformManager;
+
+lazy_static! {
+    static ref PLUGINS: Mutex<Vec<Arc<Plugin>>> = Mutex::new(vec![]);
+}
+
+pub trait Plugin: Send + Sync {
+    fn id(&self) -> &str;
+
+    /// Device managers of the plugin, empty if its devices can't be reached
+    /// from this host.
+    fn managers(&self, conf: &Arc<Configuration>) -> Result<Vec<Box<PlatformManager>>>;
+
+    /// The platform described by the `[platforms.<id>]` configuration, if it
+    /// belongs to this plugin.
+    fn platform(&self,
+                id: &str,
+                conf: &PlatformConfiguration,
+                compiler: &Arc<Compiler>) -> Result<Option<Box<Platform>>>;
+}
+
+/// Make a plugin known to the next `Dinghy::probe`. Plugins are consulted in
+/// registration order, before the built-in platforms.
+pub fn register<P: Plugin + 'static>(plugin: P) {
+    let plugin: Arc<Plugin> = Arc::new(plugin);
+    PLUGINS.lock()
+        .map(|mut plugins| {
+            debug!("register plugin {}", plugin.id());
+            plugins.push(plugin)
+        })
+        .unwrap_or_else(|_| error!("Couldn't register a plugin, the registry is poisoned"));
+}
+
+/// The registered plugins.
+pub fn plugins() -> Vec<Arc<Plugin>> {
+    PLUGINS.lock()
+        .map(|plugins| plugins.clone())
+        .unwrap_or(vec![])
+}
@@ -0,0 +1,116 @@
+//! The processes running on the devices, listed by `cargo dinghy ps` and
+//! killed by `cargo dinghy kill`, to get rid of the test runs a previous
+//! invocation left stuck on a device without grabbing it.
+
+use json::JsonValue;
+use std::fmt;
+
+/// A process running on a device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceProcess {
+    pub pid: u32,
+    /// The user name, or the uid where names aren't known.
+    pub user: Option<String>,
+    pub name: String,
+}
+
+impl DeviceProcess {
+    pub fn to_json(&self) -> JsonValue {
+        let mut process = JsonValue::new_object();
+        process["pid"] = self.pid.into();
+        process["user"] = self.user.as_ref().map(|it| it.as_str()).into();
+        process["name"] = self.name.as_str().into();
+        process
+    }
+}
+
+impl fmt::Display for DeviceProcess {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{:>7} {:<12} {}", self.pid, self.user.as_ref().map(|it| it.as_str()).unwrap_or("-"), self.name)
+    }
+}
+
+pub fn to_json(processes: &[DeviceProcess]) -> JsonValue {
+    JsonValue::Array(processes.iter().map(|it| it.to_json()).collect())
+}
+
+/// The processes to kill: a pid, or all the processes of a name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KillTarget {
+    Pid(u32),
+    Name(String),
+}
+
+impl KillTarget {
+    pub fn parse(target: &str) -> KillTarget {
+        match target.parse() {
+            Ok(pid) => KillTarget::Pid(pid),
+            Err(_) => KillTarget::Name(target.to_string()),
+        }
+    }
+
+    /// Whether `process` is one of the target, by its name or the file name
+    /// of its path.
+    pub fn matches(&self, process: &DeviceProcess) -> bool {
+        match *self {
+            KillTarget::Pid(pid) => process.pid == pid,
+            KillTarget::Name(ref name) => process.name == *name || process.name.rsplit('/').next() == Some(name.as_str()),
+        }
+    }
+}
+
+impl fmt::Display for KillTarget {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KillTarget::Pid(pid) => write!(fmt, "pid {}", pid),
+            KillTarget::Name(ref name) => write!(fmt, "{}", name),
+        }
+    }
+}
+
+/// Parse the output of `ps`, after its header: the columns of toybox
+/// (`PID USER NAME`, Android 8 and later) as well as the ones of the toolbox
+/// of older versions (`USER PID PPID VSIZE RSS WCHAN PC NAME`).
+pub fn parse_ps(output: &str) -> Vec<DeviceProcess> {
+    let mut lines = output.lines();
+    let header = match lines.next() {
+        Some(header) => header.split_whitespace().collect::<Vec<_>>(),
+        None => return vec![],
+    };
+    let column = |names: &[&str]| header.iter().position(|it| names.contains(it));
+    let (pid, user, name) = match (column(&["PID"]), column(&["USER", "UID"]), column(&["NAME", "CMD", "ARGS"])) {
+        (Some(pid), user, Some(name)) => (pid, user, name),
+        _ => return vec![],
+    };
+    lines
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            // The name is the last field: toolbox adds a state column its
+            // header doesn't name, and leaves WCHAN empty at times.
+            let name = fields.get(name).map(|_| fields[fields.len() - 1])?;
+            Some(DeviceProcess {
+                pid: fields.get(pid)?.parse().ok()?,
+                user: user.and_then(|it| fields.get(it)).map(|it| it.to_string()),
+                name: name.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ps_outputs() {
+        let toybox = parse_ps("  PID USER         NAME\n    1 root         init\n 4242 shell        my_crate-1e8a3f1a2b3c4d5e\n");
+        assert_eq!(toybox[1], DeviceProcess { pid: 4242, user: Some("shell".to_string()), name: "my_crate-1e8a3f1a2b3c4d5e".to_string() });
+        let toolbox = parse_ps("USER     PID   PPID  VSIZE  RSS     WCHAN    PC         NAME\n\
+                                root      1     0     8904   776   ffffffff 00000000 S /init\n\
+                                shell     4242  3101  20544  4096           00000000 R /data/local/tmp/dinghy/my_crate/my_crate\n");
+        assert_eq!(toolbox.iter().map(|it| it.pid).collect::<Vec<_>>(), vec![1, 4242]);
+        assert!(KillTarget::parse("my_crate").matches(&toolbox[1]));
+        assert!(KillTarget::parse("4242").matches(&toolbox[1]));
+        assert!(!KillTarget::parse("init").matches(&toybox[1]));
+    }
+}
@@ -9,19 +9,31 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
 use utils::copy_and_sync_file;
+use walkdir::WalkDir;
 use Platform;
 use Result;
 use Runnable;
 
 #[derive(Debug)]
 pub struct Project {
-    pub conf: Arc<Configuration>
+    pub conf: Arc<Configuration>,
+    /// Platform and device ids the configuration is for, if any.
+    target: Option<(String, String)>,
 }
 
 impl Project {
     pub fn new(conf: &Arc<Configuration>) -> Project {
         Project {
             conf: conf.clone(),
+            target: None,
+        }
+    }
+
+    /// The project, with the run settings of the platform and device.
+    pub fn for_device(&self, platform_id: &str, device_id: &str) -> Project {
+        Project {
+            conf: Arc::new(self.conf.for_device(platform_id, device_id)),
+            target: Some((platform_id.to_string(), device_id.to_string())),
         }
     }
 
@@ -47,8 +59,13 @@ impl Project {
     }
 
     pub fn for_runnable(&self, runnable: &Runnable) -> Result<Self> {
+        let conf = dinghy_config(&runnable.source)?;
         Ok(Project {
-            conf: Arc::new(dinghy_config(&runnable.source)?),
+            conf: Arc::new(match self.target {
+                Some((ref platform_id, ref device_id)) => conf.for_device(platform_id, device_id),
+                None => conf,
+            }),
+            target: self.target.clone(),
         })
     }
 
@@ -73,25 +90,83 @@ impl Project {
         Ok(())
     }
 
+    /// Copy the test data to `app_path/test_data`. Files already there with the
+    /// same size and modification time are left alone, and the ones that are
+    /// no longer in the test data sources are removed, so that large test data
+    /// sets are only copied once.
     pub fn copy_test_data<T: AsRef<Path>>(&self, app_path: T) -> Result<()> {
         let app_path = app_path.as_ref();
         let test_data_path = app_path.join("test_data");
         fs::create_dir_all(&test_data_path)?;
 
+        let mut copied = vec![];
         for td in self.conf.test_data.iter() {
             let file = td.base.parent().unwrap_or(&PathBuf::from("/")).join(&td.source);
             if Path::new(&file).exists() {
                 let metadata = file.metadata()?;
                 let dst = test_data_path.join(&td.id);
                 if metadata.is_dir() {
-                    self.rec_copy(file, dst, td.copy_git_ignored)?;
+                    self.rec_copy(&file, &dst, td.copy_git_ignored)?;
                 } else {
-                    fs::copy(file, dst)?;
+                    if dst.is_dir() {
+                        fs::remove_dir_all(&dst)?;
+                    }
+                    if !dst.is_file()
+                        || dst.metadata()?.len() != metadata.len()
+                        || dst.metadata()?.modified()? != metadata.modified()? {
+                        copy_and_sync_file(&file, &dst)?;
+                    }
                 }
+                copied.push((dst, file));
             } else {
                 warn!("configuration required test_data `{:?}` but it could not be found", td);
             }
         }
+
+        // Remove what the previous copies left behind.
+        let entries = WalkDir::new(&test_data_path).min_depth(1).into_iter()
+            .filter_map(|it| it.ok())
+            .map(|it| it.path().to_path_buf())
+            .collect::<Vec<_>>();
+        for entry in entries {
+            let source = copied.iter()
+                .find(|&&(ref dst, _)| entry.starts_with(dst))
+                .and_then(|&(ref dst, ref src)| entry.strip_prefix(dst).ok().map(|it| src.join(it)));
+            let stale = match source {
+                Some(source) => !source.exists() || source.is_dir() != entry.is_dir(),
+                None => true,
+            };
+            if stale && entry.exists() {
+                debug!("Removing stale test data {}", entry.display());
+                if entry.is_dir() {
+                    fs::remove_dir_all(&entry)?;
+                } else {
+                    fs::remove_file(&entry)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn copy_bundle_resources<T: AsRef<Path>>(&self, app_path: T) -> Result<()> {
+        let app_path = app_path.as_ref();
+        for resource in self.conf.bundle_resources.iter() {
+            let file = resource.base.parent().unwrap_or(&PathBuf::from("/")).join(&resource.source);
+            if Path::new(&file).exists() {
+                let dst = app_path.join(&resource.id);
+                debug!("Copying bundle resource {} to {}", file.display(), dst.display());
+                if file.metadata()?.is_dir() {
+                    self.rec_copy(file, dst, true)?;
+                } else {
+                    if let Some(parent) = dst.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    copy_and_sync_file(file, dst)?;
+                }
+            } else {
+                warn!("configuration required bundle resource `{:?}` but it could not be found", resource);
+            }
+        }
         Ok(())
     }
 
@@ -0,0 +1,51 @@
+//! Screen recordings of the devices during the runs, for the failures that
+//! depend on what is displayed or on timing.
+
+use cleanup;
+use cleanup::CleanupGuard;
+use errors::*;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A screen recording in progress, saved to `path` once stopped.
+pub struct ScreenRecording {
+    pub path: PathBuf,
+    stop: Arc<Fn() -> Result<()> + Send + Sync>,
+    stopped: bool,
+    _interrupt: CleanupGuard,
+}
+
+impl ScreenRecording {
+    /// `stop` ends the recording and brings it back to `path`. It also runs
+    /// if dinghy is interrupted, so that the recording isn't lost.
+    pub fn new<F: Fn() -> Result<()> + Send + Sync + 'static>(path: PathBuf, stop: F) -> ScreenRecording {
+        let stop: Arc<Fn() -> Result<()> + Send + Sync> = Arc::new(stop);
+        let interrupt_stop = stop.clone();
+        ScreenRecording {
+            path,
+            stop,
+            stopped: false,
+            _interrupt: cleanup::on_interrupt(move || {
+                let _ = interrupt_stop();
+            }),
+        }
+    }
+
+    /// Stops the recording, returning where it was saved.
+    pub fn finish(mut self) -> Result<PathBuf> {
+        self.stopped = true;
+        (self.stop)()?;
+        Ok(self.path.clone())
+    }
+}
+
+impl Drop for ScreenRecording {
+    fn drop(&mut self) {
+        if self.stopped {
+            return;
+        }
+        if let Err(e) = (self.stop)() {
+            warn!("Couldn't save the screen recording {}: {}", self.path.display(), e)
+        }
+    }
+}
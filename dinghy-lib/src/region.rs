@@ -0,0 +1,89 @@
+//! Locale and timezone of the runs, for the tests of date and number
+//! formatting.
+
+use errors::*;
+
+/// The locale (e.g. `fr_FR`) and timezone (e.g. `Europe/Paris`) the runnables
+/// see, if set.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Region {
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+}
+
+impl Region {
+    /// Checks `locale` (`fr_FR`, `fr-FR` or `fr`) and `timezone`, the locale
+    /// being kept in its POSIX form.
+    pub fn new(locale: Option<&str>, timezone: Option<&str>) -> Result<Region> {
+        let locale = match locale {
+            Some(locale) => {
+                let locale = locale.replace('-', "_");
+                let valid = {
+                    let mut parts = locale.split('_');
+                    let language = parts.next().unwrap_or("");
+                    language.len() >= 2 && language.chars().all(|it| it.is_ascii_alphabetic())
+                        && parts.all(|it| !it.is_empty() && it.chars().all(|it| it.is_ascii_alphanumeric()))
+                };
+                if !valid {
+                    bail!("Invalid locale '{}', expected e.g. fr_FR", locale)
+                }
+                Some(locale)
+            }
+            None => None,
+        };
+        if let Some(timezone) = timezone {
+            if timezone.is_empty() || timezone.contains(char::is_whitespace) {
+                bail!("Invalid timezone '{}', expected e.g. Europe/Paris", timezone)
+            }
+        }
+        Ok(Region { locale, timezone: timezone.map(|it| it.to_string()) })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locale.is_none() && self.timezone.is_none()
+    }
+
+    /// The locale as a BCP 47 language tag (`fr-FR`), the way Android takes it.
+    pub fn language_tag(&self) -> Option<String> {
+        self.locale.as_ref().map(|it| it.replace('_', "-"))
+    }
+
+    /// The variables setting the region of the runnables: `TZ`, `LANG` and
+    /// `LC_ALL`.
+    pub fn envs(&self) -> Vec<String> {
+        let mut envs = vec![];
+        if let Some(ref timezone) = self.timezone {
+            envs.push(format!("TZ={}", timezone));
+        }
+        if let Some(ref locale) = self.locale {
+            envs.push(format!("LANG={}.UTF-8", locale));
+            envs.push(format!("LC_ALL={}.UTF-8", locale));
+        }
+        envs
+    }
+
+    /// The region, as conditions of the run reports.
+    pub fn conditions(&self) -> Vec<String> {
+        self.locale.iter().map(|it| format!("locale {}", it))
+            .chain(self.timezone.iter().map(|it| format!("timezone {}", it)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regions() {
+        let region = Region::new(Some("fr-FR"), Some("Europe/Paris")).unwrap();
+        assert_eq!(region.locale, Some("fr_FR".to_string()));
+        assert_eq!(region.language_tag(), Some("fr-FR".to_string()));
+        assert_eq!(region.envs(), vec!["TZ=Europe/Paris", "LANG=fr_FR.UTF-8", "LC_ALL=fr_FR.UTF-8"]);
+        assert_eq!(region.conditions(), vec!["locale fr_FR", "timezone Europe/Paris"]);
+        assert!(Region::new(Some("zh_Hans_CN"), None).is_ok());
+        assert!(Region::new(Some("fr FR"), None).is_err());
+        assert!(Region::new(None, Some("Europe/ Paris")).is_err());
+        assert!(Region::new(None, None).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,192 @@
+//! Repeated benchmark runs.
+//!
+//! Phone SoCs throttle as they heat up, so a single run of a benchmark tells
+//! little. With `--repeat`, each benchmark executable runs several times, with
+//! a cool-down pause before each repetition, and the measures of each
+//! benchmark are summed up as their mean within a 95% confidence interval. The
+//! benchmarks whose measures vary more than `max_variation` across the
+//! repetitions are flagged as unstable.
+
+use baseline;
+use errors::*;
+use json::JsonValue;
+use report::RunResult;
+use report::TestOutcome;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Variation, in percent, over which a benchmark is unstable by default.
+pub const DEFAULT_MAX_VARIATION: f64 = 5.0;
+
+/// Student's t for a two-sided 95% interval, by degrees of freedom.
+const T_95: &[f64] = &[
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228,
+    2.201, 2.179, 2.160, 2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086,
+    2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042,
+];
+
+/// The measures of a benchmark across the repetitions, in nanoseconds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchStats {
+    pub runnable: String,
+    pub name: String,
+    pub samples: Vec<f64>,
+}
+
+impl BenchStats {
+    pub fn mean(&self) -> f64 {
+        if self.samples.is_empty() { 0.0 } else { self.samples.iter().sum::<f64>() / self.samples.len() as f64 }
+    }
+
+    /// Sample standard deviation.
+    pub fn std_dev(&self) -> f64 {
+        if self.samples.len() < 2 {
+            return 0.0;
+        }
+        let mean = self.mean();
+        let squares = self.samples.iter().map(|it| (it - mean) * (it - mean)).sum::<f64>();
+        (squares / (self.samples.len() - 1) as f64).sqrt()
+    }
+
+    /// Coefficient of variation, in percent.
+    pub fn variation(&self) -> f64 {
+        let mean = self.mean();
+        if mean == 0.0 { 0.0 } else { self.std_dev() / mean * 100.0 }
+    }
+
+    /// 95% confidence interval of the mean.
+    pub fn interval(&self) -> (f64, f64) {
+        let mean = self.mean();
+        if self.samples.len() < 2 {
+            return (mean, mean);
+        }
+        let t = T_95.get(self.samples.len() - 2).cloned().unwrap_or(1.96);
+        let margin = t * self.std_dev() / (self.samples.len() as f64).sqrt();
+        (mean - margin, mean + margin)
+    }
+
+    pub fn is_unstable(&self, max_variation: f64) -> bool {
+        self.variation() > max_variation
+    }
+
+    pub fn to_json(&self, max_variation: f64) -> JsonValue {
+        let (low, high) = self.interval();
+        let mut stats = JsonValue::new_object();
+        stats["runnable"] = self.runnable.as_str().into();
+        stats["name"] = self.name.as_str().into();
+        stats["samples_ns"] = self.samples.clone().into();
+        stats["mean_ns"] = self.mean().into();
+        stats["std_dev_ns"] = self.std_dev().into();
+        stats["variation_percent"] = self.variation().into();
+        stats["interval_ns"] = vec![low, high].into();
+        stats["unstable"] = self.is_unstable(max_variation).into();
+        stats
+    }
+}
+
+/// Merges the results of the repetitions of a runnable. Its benchmarks
+/// measure the mean of the repetitions within its confidence interval
+/// (`low mean high`, the way criterion shows its estimates); its other tests
+/// are the ones of the last repetition.
+pub fn merge(runs: Vec<RunResult>) -> Result<(RunResult, Vec<BenchStats>)> {
+    let count = runs.len();
+    let mut runs = runs.into_iter();
+    let mut merged = runs.next().ok_or("No repetition to merge")?;
+    let mut stats = vec![];
+    collect_samples(&merged, &mut stats);
+    let mut outputs = vec![(merged.report.stdout.take(), merged.report.stderr.take(), merged.report.log.take())];
+    for run in runs {
+        collect_samples(&run, &mut stats);
+        outputs.push((run.report.stdout.clone(), run.report.stderr.clone(), run.report.log.clone()));
+        let report = &mut merged.report;
+        if report.exit_success {
+            report.exit_success = run.report.exit_success;
+            report.exit_code = run.report.exit_code;
+            report.signal = run.report.signal;
+        }
+        report.duration = match (report.duration, run.report.duration) {
+            (Some(total), Some(duration)) => Some(total + duration),
+            (total, duration) => total.or(duration),
+        };
+        report.tests = run.report.tests;
+        report.summary = run.report.summary;
+        for condition in run.report.conditions {
+            if !report.conditions.contains(&condition) {
+                report.conditions.push(condition);
+            }
+        }
+    }
+    merged.report.stdout = concat(outputs.iter().map(|it| it.0.as_ref()).collect())?;
+    merged.report.stderr = concat(outputs.iter().map(|it| it.1.as_ref()).collect())?;
+    merged.report.log = concat(outputs.iter().map(|it| it.2.as_ref()).collect())?;
+    merged.report.conditions.push(format!("repeated {} times", count));
+    for test in merged.report.tests.iter_mut().filter(|it| it.outcome == TestOutcome::Bench) {
+        if let Some(stats) = stats.iter().find(|it| it.name == test.name) {
+            let (low, high) = stats.interval();
+            test.details = Some(format!("{:.3} ns {:.3} ns {:.3} ns", low, stats.mean(), high));
+        }
+    }
+    Ok((merged, stats))
+}
+
+/// Concatenate the output `files` of the repetitions, each under a header,
+/// into a new file next to the first one. The files are removed once copied.
+fn concat(files: Vec<Option<&PathBuf>>) -> Result<Option<PathBuf>> {
+    let first = match files.iter().filter_map(|it| *it).next() {
+        Some(first) => first,
+        None => return Ok(None),
+    };
+    let name = first.file_name().ok_or("Invalid output file")?.to_string_lossy().to_string();
+    let path = first.with_file_name(format!("repeated-{}", name));
+    let mut merged = fs::File::create(&path)?;
+    for (index, file) in files.iter().enumerate() {
+        writeln!(merged, "==== repetition {}/{} ====", index + 1, files.len())?;
+        if let Some(file) = *file {
+            io::copy(&mut fs::File::open(file)?, &mut merged)?;
+            fs::remove_file(file)?;
+        }
+    }
+    Ok(Some(path))
+}
+
+fn collect_samples(run: &RunResult, stats: &mut Vec<BenchStats>) {
+    for test in run.report.tests.iter().filter(|it| it.outcome == TestOutcome::Bench) {
+        let median = match test.details.as_ref().and_then(|it| baseline::median_ns(it)) {
+            Some(median) => median,
+            None => continue,
+        };
+        match stats.iter().position(|it| it.name == test.name) {
+            Some(index) => stats[index].samples.push(median),
+            None => stats.push(BenchStats {
+                runnable: run.report.runnable.clone(),
+                name: test.name.clone(),
+                samples: vec![median],
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bench_stats() {
+        let stats = BenchStats {
+            runnable: "bench".to_string(),
+            name: "fib".to_string(),
+            samples: vec![100.0, 110.0, 90.0, 100.0],
+        };
+        assert_eq!(stats.mean(), 100.0);
+        assert!((stats.std_dev() - 8.165).abs() < 0.001);
+        let (low, high) = stats.interval();
+        assert!((high - 100.0 - 3.182 * 8.165 / 2.0).abs() < 0.01);
+        assert!((100.0 - low - (high - 100.0)).abs() < 1e-9);
+        assert!(stats.is_unstable(5.0));
+        assert!(!stats.is_unstable(10.0));
+        let single = BenchStats { samples: vec![42.0], ..stats };
+        assert_eq!((single.std_dev(), single.interval()), (0.0, (42.0, 42.0)));
+    }
+}
@@ -0,0 +1,653 @@
+//! Structured results of a run, parsed from the libtest output of the runnables.
+
+use errors::*;
+use json::JsonValue;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Duration;
+use std::time::Instant;
+use timings;
+use utils::xml_escape;
+use BuildBundle;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestOutcome {
+    Ok,
+    Failed,
+    /// Failed, then passed when retried.
+    Flaky,
+    Ignored,
+    Bench,
+}
+
+impl TestOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            TestOutcome::Ok => "ok",
+            TestOutcome::Failed => "failed",
+            TestOutcome::Flaky => "flaky",
+            TestOutcome::Ignored => "ignored",
+            TestOutcome::Bench => "bench",
+        }
+    }
+
+    pub fn parse(outcome: &str) -> Option<TestOutcome> {
+        match outcome {
+            "ok" => Some(TestOutcome::Ok),
+            "failed" => Some(TestOutcome::Failed),
+            "flaky" => Some(TestOutcome::Flaky),
+            "ignored" => Some(TestOutcome::Ignored),
+            "bench" => Some(TestOutcome::Bench),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TestCase {
+    pub name: String,
+    pub outcome: TestOutcome,
+    /// Bench measure, or captured output of a failed test.
+    pub details: Option<String>,
+    /// Panic message of a failed test.
+    pub panic: Option<String>,
+    /// How many times the test was run again after failing.
+    pub retries: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TestSummary {
+    pub passed: usize,
+    pub failed: usize,
+    pub ignored: usize,
+    pub measured: usize,
+    pub filtered_out: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct TestReport {
+    pub device: String,
+    pub runnable: String,
+    /// Whether the runnable process itself exited successfully.
+    pub exit_success: bool,
+    /// Exit code of the runnable, when the device tells it.
+    pub exit_code: Option<i32>,
+    /// Signal the runnable was killed by, if any.
+    pub signal: Option<i32>,
+    /// From the start of the runnable to its end, as seen from the host.
+    pub duration: Option<Duration>,
+    pub tests: Vec<TestCase>,
+    /// Sum of the `test result:` lines, if any was seen.
+    pub summary: Option<TestSummary>,
+    /// Device settings changed for the run (e.g. pinned CPU governors).
+    pub conditions: Vec<String>,
+    /// File of what the runnable printed to its stdout.
+    pub stdout: Option<PathBuf>,
+    /// File of what the runnable printed to its stderr.
+    pub stderr: Option<PathBuf>,
+    /// File of both, line by line in the order the lines were completed, each
+    /// line timestamped and tagged with its stream (see `output::OutputSink`).
+    pub log: Option<PathBuf>,
+}
+
+impl TestReport {
+    /// Report for a run whose output couldn't be observed by dinghy.
+    pub fn unparsed(device: &str, runnable: &str, exit_success: bool) -> TestReport {
+        TestReport {
+            device: device.to_string(),
+            runnable: runnable.to_string(),
+            exit_success,
+            exit_code: None,
+            signal: None,
+            duration: None,
+            tests: vec![],
+            summary: None,
+            conditions: vec![],
+            stdout: None,
+            stderr: None,
+            log: None,
+        }
+    }
+
+    pub fn count(&self, outcome: TestOutcome) -> usize {
+        self.tests.iter().filter(|it| it.outcome == outcome).count()
+    }
+
+    /// Record a run of the failed test `name` alone, as reported by `rerun`:
+    /// if it passed, the test is flaky and no longer failed. Returns whether
+    /// it passed.
+    pub fn merge_retry(&mut self, name: &str, rerun: &TestReport) -> bool {
+        let passed = rerun.exit_success
+            && rerun.tests.iter().any(|it| it.name == name && it.outcome == TestOutcome::Ok);
+        let was_failed = {
+            let test = match self.tests.iter_mut().find(|it| it.name == name) {
+                Some(test) => test,
+                None => return false,
+            };
+            test.retries += 1;
+            let was_failed = test.outcome == TestOutcome::Failed;
+            if passed && was_failed {
+                test.outcome = TestOutcome::Flaky;
+            }
+            was_failed
+        };
+        if passed && was_failed {
+            if let Some(ref mut summary) = self.summary {
+                summary.failed = summary.failed.saturating_sub(1);
+                summary.passed += 1;
+            }
+            // The runnable only failed because of its failed tests when it
+            // ran them all to the summary.
+            if self.count(TestOutcome::Failed) == 0 && self.summary.as_ref().map(|it| it.failed == 0).unwrap_or(false) {
+                self.exit_success = true;
+            }
+        }
+        passed
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.exit_success
+            && self.count(TestOutcome::Failed) == 0
+            && self.summary.as_ref().map(|it| it.failed == 0).unwrap_or(true)
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut report = JsonValue::new_object();
+        report["device"] = self.device.as_str().into();
+        report["runnable"] = self.runnable.as_str().into();
+        report["success"] = self.is_success().into();
+        report["exit_success"] = self.exit_success.into();
+        if let Some(exit_code) = self.exit_code {
+            report["exit_code"] = exit_code.into();
+        }
+        if let Some(signal) = self.signal {
+            report["signal"] = signal.into();
+        }
+        if let Some(duration) = self.duration {
+            report["duration"] = timings::secs(duration).into();
+        }
+        if let Some(ref stdout) = self.stdout {
+            report["stdout"] = stdout.to_string_lossy().to_string().into();
+        }
+        if let Some(ref stderr) = self.stderr {
+            report["stderr"] = stderr.to_string_lossy().to_string().into();
+        }
+        if let Some(ref log) = self.log {
+            report["log"] = log.to_string_lossy().to_string().into();
+        }
+        if let Some(ref summary) = self.summary {
+            let mut json_summary = JsonValue::new_object();
+            json_summary["passed"] = summary.passed.into();
+            json_summary["failed"] = summary.failed.into();
+            json_summary["ignored"] = summary.ignored.into();
+            json_summary["measured"] = summary.measured.into();
+            json_summary["filtered_out"] = summary.filtered_out.into();
+            report["summary"] = json_summary;
+        }
+        let mut tests = JsonValue::new_array();
+        for test in &self.tests {
+            let mut json_test = JsonValue::new_object();
+            json_test["name"] = test.name.as_str().into();
+            json_test["outcome"] = test.outcome.as_str().into();
+            if test.retries > 0 {
+                json_test["retries"] = test.retries.into();
+            }
+            if let Some(ref details) = test.details {
+                json_test["details"] = details.as_str().into();
+            }
+            if let Some(ref panic) = test.panic {
+                json_test["panic"] = panic.as_str().into();
+            }
+            let _ = tests.push(json_test);
+        }
+        report["tests"] = tests;
+        report["flaky"] = self.count(TestOutcome::Flaky).into();
+        if !self.conditions.is_empty() {
+            report["conditions"] = self.conditions.iter().map(|it| it.as_str()).collect::<Vec<_>>().into();
+        }
+        report
+    }
+
+    /// Read back a report written by `to_json`.
+    pub fn from_json(json: &JsonValue) -> TestReport {
+        let string = |json: &JsonValue| json.as_str().map(|it| it.to_string());
+        let summary = &json["summary"];
+        TestReport {
+            device: string(&json["device"]).unwrap_or(String::new()),
+            runnable: string(&json["runnable"]).unwrap_or(String::new()),
+            exit_success: json["exit_success"].as_bool().unwrap_or(false),
+            exit_code: json["exit_code"].as_i32(),
+            signal: json["signal"].as_i32(),
+            duration: json["duration"].as_f64()
+                .map(|it| Duration::new(it as u64, ((it - it.trunc()) * 1e9) as u32)),
+            tests: json["tests"].members()
+                .filter_map(|test| Some(TestCase {
+                    name: string(&test["name"])?,
+                    outcome: test["outcome"].as_str().and_then(TestOutcome::parse)?,
+                    details: string(&test["details"]),
+                    panic: string(&test["panic"]),
+                    retries: test["retries"].as_usize().unwrap_or(0),
+                }))
+                .collect(),
+            summary: if summary.is_object() {
+                let count = |key: &str| summary[key].as_usize().unwrap_or(0);
+                Some(TestSummary {
+                    passed: count("passed"),
+                    failed: count("failed"),
+                    ignored: count("ignored"),
+                    measured: count("measured"),
+                    filtered_out: count("filtered_out"),
+                })
+            } else {
+                None
+            },
+            conditions: json["conditions"].members().filter_map(|it| string(it)).collect(),
+            stdout: string(&json["stdout"]).map(PathBuf::from),
+            stderr: string(&json["stderr"]).map(PathBuf::from),
+            log: string(&json["log"]).map(PathBuf::from),
+        }
+    }
+}
+
+/// What running a runnable on a device gave: the bundle it ran from, and its
+/// report, which tells how its process ended.
+#[derive(Clone, Debug)]
+pub struct RunResult {
+    pub build_bundle: BuildBundle,
+    pub report: TestReport,
+}
+
+impl RunResult {
+    pub fn new(build_bundle: BuildBundle, report: TestReport) -> RunResult {
+        RunResult { build_bundle, report }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.report.is_success()
+    }
+}
+
+/// Write the reports as a JSON array to `path`.
+pub fn write_json<P: AsRef<Path>>(path: P, reports: &[TestReport]) -> Result<()> {
+    let path = path.as_ref();
+    let mut json_reports = JsonValue::new_array();
+    for report in reports {
+        let _ = json_reports.push(report.to_json());
+    }
+    fs::File::create(path)
+        .and_then(|mut file| file.write_all(::json::stringify_pretty(json_reports, 2).as_bytes()))
+        .chain_err(|| format!("Couldn't write JSON report to {}", path.display()))?;
+    Ok(())
+}
+
+/// Read back the reports written by `write_json`.
+pub fn read_json<P: AsRef<Path>>(path: P) -> Result<Vec<TestReport>> {
+    let path = path.as_ref();
+    let mut content = String::new();
+    fs::File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut content))
+        .chain_err(|| format!("Couldn't read JSON report {}", path.display()))?;
+    let json = ::json::parse(&content).chain_err(|| format!("Invalid JSON report {}", path.display()))?;
+    Ok(json.members().map(TestReport::from_json).collect())
+}
+
+/// Write the reports as a JUnit XML file to `path`, one test suite per device
+/// and runnable, with the device id as the class name prefix.
+pub fn write_junit<P: AsRef<Path>>(path: P, reports: &[TestReport]) -> Result<()> {
+    let path = path.as_ref();
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<testsuites>\n");
+    for report in reports {
+        let classname = xml_escape(&format!("{}.{}", report.device, report.runnable));
+        let failures = report.count(TestOutcome::Failed)
+            + if report.tests.is_empty() && !report.is_success() { 1 } else { 0 };
+        xml.push_str(&format!("  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" flaky=\"{}\">\n",
+                              classname,
+                              report.tests.len().max(1),
+                              failures,
+                              report.count(TestOutcome::Ignored),
+                              report.count(TestOutcome::Flaky)));
+        if report.tests.is_empty() {
+            // No parseable test output: the runnable itself is the test case.
+            xml.push_str(&format!("    <testcase classname=\"{}\" name=\"{}\">", classname, xml_escape(&report.runnable)));
+            if !report.is_success() {
+                xml.push_str("<failure message=\"runnable failed\"/>");
+            }
+            xml.push_str("</testcase>\n");
+        }
+        for test in &report.tests {
+            xml.push_str(&format!("    <testcase classname=\"{}\" name=\"{}\">", classname, xml_escape(&test.name)));
+            match test.outcome {
+                TestOutcome::Failed => xml.push_str(&format!(
+                    "<failure message=\"{}\">{}</failure>",
+                    xml_escape(test.panic.as_ref().map(|it| it.as_str()).unwrap_or("test failed")),
+                    xml_escape(test.details.as_ref().map(|it| it.as_str()).unwrap_or("")))),
+                // As reported by the Maven Surefire rerun option.
+                TestOutcome::Flaky => xml.push_str(&format!(
+                    "<flakyFailure message=\"{}\">{}</flakyFailure>",
+                    xml_escape(test.panic.as_ref().map(|it| it.as_str()).unwrap_or("test failed")),
+                    xml_escape(test.details.as_ref().map(|it| it.as_str()).unwrap_or("")))),
+                TestOutcome::Ignored => xml.push_str("<skipped/>"),
+                TestOutcome::Bench => if let Some(ref details) = test.details {
+                    xml.push_str(&format!("<system-out>{}</system-out>", xml_escape(details)));
+                },
+                TestOutcome::Ok => {}
+            }
+            xml.push_str("</testcase>\n");
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    fs::File::create(path)
+        .and_then(|mut file| file.write_all(xml.as_bytes()))
+        .chain_err(|| format!("Couldn't write JUnit report to {}", path.display()))?;
+    Ok(())
+}
+
+/// Incremental parser of libtest output, fed line by line as the output of the
+/// runnable is streamed back from the device.
+pub struct TestReportParser {
+    device: String,
+    runnable: String,
+    tests: Vec<TestCase>,
+    summary: Option<TestSummary>,
+    // Name and lines of the `---- <name> stdout ----` section being read.
+    failure_section: Option<(String, Vec<String>)>,
+    // Last non-empty line, the name of a criterion benchmark too long to be
+    // on the line of its measure.
+    previous_line: Option<String>,
+    started: Instant,
+    // Files of the stdout, the stderr and the log of the runnable.
+    output: Option<(PathBuf, PathBuf, PathBuf)>,
+}
+
+impl TestReportParser {
+    pub fn new(device: &str, runnable: &str) -> TestReportParser {
+        TestReportParser {
+            device: device.to_string(),
+            runnable: runnable.to_string(),
+            tests: vec![],
+            summary: None,
+            failure_section: None,
+            previous_line: None,
+            started: Instant::now(),
+            output: None,
+        }
+    }
+
+    pub fn parse_all(&mut self, output: &str) {
+        for line in output.lines() {
+            self.parse_line(line);
+        }
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    pub fn runnable(&self) -> &str {
+        &self.runnable
+    }
+
+    /// Keep the files of what the runnable printed with its report.
+    pub fn set_output(&mut self, stdout: PathBuf, stderr: PathBuf, log: PathBuf) {
+        self.output = Some((stdout, stderr, log));
+    }
+
+    pub fn parse_line(&mut self, line: &str) {
+        let line = strip_ansi(line);
+        let line = line.trim_right_matches(|c| c == '\r' || c == '\n');
+
+        if line.starts_with("---- ") && line.ends_with(" stdout ----") {
+            self.close_failure_section();
+            let name = &line["---- ".len()..line.len() - " stdout ----".len()];
+            self.failure_section = Some((name.to_string(), vec![]));
+            return;
+        }
+        if self.failure_section.is_some() {
+            if line == "failures:" || line.starts_with("test result: ") {
+                self.close_failure_section();
+            } else {
+                if let Some((_, ref mut lines)) = self.failure_section {
+                    lines.push(line.to_string());
+                }
+                return;
+            }
+        }
+
+        if line.starts_with("test result: ") {
+            self.parse_summary(&line["test result: ".len()..]);
+        } else if line.starts_with("test ") {
+            self.parse_test_line(&line["test ".len()..]);
+        } else if line.contains("time:") {
+            self.parse_criterion_line(line);
+        }
+        if !line.trim().is_empty() {
+            self.previous_line = Some(line.to_string());
+        }
+    }
+
+    /// The report of a runnable that ended, successfully or not, without the
+    /// device telling more.
+    pub fn finish(self, exit_success: bool) -> TestReport {
+        self.finish_exit(exit_success, None, None)
+    }
+
+    /// The report of a runnable whose process ended with `status`.
+    pub fn finish_status(self, status: ExitStatus) -> TestReport {
+        self.finish_exit(status.success(), status.code(), status.signal())
+    }
+
+    /// The report of a runnable that exited with `code`, as reported by the
+    /// device.
+    pub fn finish_code(self, code: i32) -> TestReport {
+        self.finish_exit(code == 0, Some(code), None)
+    }
+
+    /// The report of a runnable killed by `signal`.
+    pub fn finish_signal(self, signal: i32) -> TestReport {
+        self.finish_exit(false, None, Some(signal))
+    }
+
+    fn finish_exit(mut self, exit_success: bool, exit_code: Option<i32>, signal: Option<i32>) -> TestReport {
+        self.close_failure_section();
+        let (stdout, stderr, log) = match self.output {
+            Some((stdout, stderr, log)) => (Some(stdout), Some(stderr), Some(log)),
+            None => (None, None, None),
+        };
+        TestReport {
+            device: self.device,
+            runnable: self.runnable,
+            exit_success,
+            exit_code,
+            signal,
+            duration: Some(self.started.elapsed()),
+            tests: self.tests,
+            summary: self.summary,
+            conditions: vec![],
+            stdout,
+            stderr,
+            log,
+        }
+    }
+
+    fn parse_test_line(&mut self, line: &str) {
+        let (name, result) = match line.find(" ... ") {
+            Some(index) => (&line[..index], line[index + " ... ".len()..].trim()),
+            None => return,
+        };
+        let (outcome, details) = if result == "ok" {
+            (TestOutcome::Ok, None)
+        } else if result == "FAILED" {
+            (TestOutcome::Failed, None)
+        } else if result.starts_with("ignored") {
+            (TestOutcome::Ignored, None)
+        } else if result.starts_with("bench:") {
+            (TestOutcome::Bench, Some(result["bench:".len()..].trim().to_string()))
+        } else {
+            return;
+        };
+        self.tests.push(TestCase { name: name.to_string(), outcome, details, panic: None, retries: 0 });
+    }
+
+    /// A measure of criterion: `fib 20    time:   [26.0 us 26.2 us 26.5 us]`,
+    /// the name being on the line before for long ones.
+    fn parse_criterion_line(&mut self, line: &str) {
+        let index = match line.find("time:") {
+            Some(index) => index,
+            None => return,
+        };
+        let measure = line[index + "time:".len()..].trim();
+        if !measure.starts_with('[') || !measure.ends_with(']') {
+            return;
+        }
+        let name = match line[..index].trim() {
+            "" => match self.previous_line {
+                Some(ref previous) => previous.trim().to_string(),
+                None => return,
+            },
+            name => name.to_string(),
+        };
+        let details = Some(measure[1..measure.len() - 1].to_string());
+        self.tests.push(TestCase { name, outcome: TestOutcome::Bench, details, panic: None, retries: 0 });
+    }
+
+    fn parse_summary(&mut self, line: &str) {
+        // ok. 3 passed; 1 failed; 0 ignored; 0 measured; 0 filtered out
+        let summary = self.summary.get_or_insert(TestSummary::default());
+        for part in line.split(|c| c == '.' || c == ';') {
+            let mut tokens = part.trim().splitn(2, ' ');
+            let count = match tokens.next().and_then(|it| it.parse::<usize>().ok()) {
+                Some(count) => count,
+                None => continue,
+            };
+            match tokens.next().unwrap_or("") {
+                "passed" => summary.passed += count,
+                "failed" => summary.failed += count,
+                "ignored" => summary.ignored += count,
+                "measured" => summary.measured += count,
+                "filtered out" => summary.filtered_out += count,
+                _ => {}
+            }
+        }
+    }
+
+    fn close_failure_section(&mut self) {
+        if let Some((name, mut lines)) = self.failure_section.take() {
+            while lines.last().map(|it| it.is_empty()).unwrap_or(false) {
+                lines.pop();
+            }
+            let panic = lines.iter()
+                .map(|it| it.trim())
+                .find(|it| it.starts_with("thread '") && it.contains("panicked at"))
+                .map(|it| it.to_string());
+            if let Some(test) = self.tests.iter_mut().rev().find(|it| it.name == name) {
+                test.details = Some(lines.join("\n"));
+                test.panic = panic;
+            }
+        }
+    }
+}
+
+/// Remove the ANSI escape sequences (colors) of a line of output.
+fn strip_ansi(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+        } else if chars.next() == Some('[') {
+            // Parameters and intermediate bytes, up to the final byte.
+            while let Some(c) = chars.next() {
+                if c >= '@' && c <= '~' {
+                    break;
+                }
+            }
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_libtest_output() {
+        let mut parser = TestReportParser::new("device", "runnable");
+        parser.parse_all("
+running 3 tests
+test tests::fails ... FAILED
+test tests::ignored ... ignored
+test tests::passes ... ok
+
+failures:
+
+---- tests::fails stdout ----
+\tthread 'tests::fails' panicked at 'boom', src/lib.rs:10:9
+note: Run with `RUST_BACKTRACE=1` for a backtrace.
+
+
+failures:
+    tests::fails
+
+test result: FAILED. 1 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out
+");
+        let mut report = parser.finish(false);
+        assert_eq!(report.tests.len(), 3);
+        assert_eq!(report.count(TestOutcome::Failed), 1);
+        assert_eq!(report.tests[0].panic.as_ref().map(|it| it.as_str()),
+                   Some("thread 'tests::fails' panicked at 'boom', src/lib.rs:10:9"));
+        let summary = report.summary.as_ref().unwrap();
+        assert_eq!((summary.passed, summary.failed, summary.ignored), (1, 1, 1));
+        assert!(!report.is_success());
+
+        let mut rerun = TestReportParser::new("device", "runnable");
+        rerun.parse_all("test tests::fails ... ok\n\ntest result: ok. 1 passed; 0 failed; 0 ignored; 0 measured; 2 filtered out\n");
+        assert!(report.merge_retry("tests::fails", &rerun.finish(true)));
+        assert_eq!(report.count(TestOutcome::Flaky), 1);
+        assert_eq!(report.tests[0].retries, 1);
+        assert!(report.is_success());
+    }
+
+    #[test]
+    fn parse_criterion_output() {
+        let mut parser = TestReportParser::new("device", "runnable");
+        parser.parse_all("
+Benchmarking fib 20: Analyzing
+fib 20                  time:   [26.029 us 26.251 us 26.505 us]
+                        change: [-1.2011% +0.1592% +1.5523%] (p = 0.82 > 0.05)
+Benchmarking parsing/a rather long benchmark name: Analyzing
+parsing/a rather long benchmark name
+                        time:   [1.2054 ms 1.2187 ms 1.2339 ms]
+");
+        let report = parser.finish(true);
+        assert_eq!(report.count(TestOutcome::Bench), 2);
+        assert_eq!(report.tests[0].name, "fib 20");
+        assert_eq!(report.tests[0].details.as_ref().map(|it| it.as_str()), Some("26.029 us 26.251 us 26.505 us"));
+        assert_eq!(report.tests[1].name, "parsing/a rather long benchmark name");
+    }
+
+    #[test]
+    fn parse_colored_output() {
+        let mut parser = TestReportParser::new("device", "runnable");
+        parser.parse_line("test tests::passes ... \x1b[32mok\x1b[0m\n");
+        assert_eq!(parser.finish(true).count(TestOutcome::Ok), 1);
+    }
+
+    #[test]
+    fn exit_of_the_runnable() {
+        let report = TestReportParser::new("device", "runnable").finish_code(101);
+        assert!(!report.is_success());
+        assert_eq!(report.exit_code, Some(101));
+        assert!(report.duration.is_some());
+        let killed = TestReport::from_json(&TestReportParser::new("device", "runnable").finish_signal(11).to_json());
+        assert_eq!((killed.exit_success, killed.exit_code, killed.signal), (false, None, Some(11)));
+    }
+}
@@ -0,0 +1,566 @@
+//! Entry points for the tools embedding dinghy (IDE plugins, CI
+//! orchestrators...): picking a device, then running a build on it the way
+//! `cargo dinghy run/test/bench` does, without going through the command line.
+//!
+//! ```ignore
+//! let compiler = Arc::new(Compiler::new(BuildOptions { packages: vec!["app".to_string()], ..BuildOptions::default() }));
+//! let dinghy = Dinghy::probe(&conf, &compiler)?;
+//! let (platform, device) = DeviceQuery::new().device("pixel").select(&dinghy)?;
+//! let build = platform.build(&Project::new(&conf), &build_args)?;
+//! let run = Runner::new(device.ok_or("No device found")?).forward(8080, 8080).run(&project, &build, &run_args)?;
+//! ```
+
+use artifacts;
+use artifacts::ArtifactStore;
+use compiler::CompileMode;
+use coverage;
+use criterion;
+use errors::*;
+use forward::PortForward;
+use health;
+use hooks;
+use hooks::Stage;
+use itertools::Itertools;
+use json::JsonValue;
+use lock;
+use lock::DeviceLock;
+use lock::LockWait;
+use logging;
+use network::NetworkCondition;
+use project::Project;
+use repetition;
+use repetition::BenchStats;
+use report;
+use report::RunResult;
+use report::TestOutcome;
+use report::TestReport;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use timings;
+use timings::Phase;
+use Build;
+use BuildBundle;
+use Device;
+use Dinghy;
+use Platform;
+use RunArgs;
+
+/// Which platform and device to target, as `--platform` and `-d` tell.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceQuery {
+    pub platform: Option<String>,
+    /// Part of the description of the device, case insensitive.
+    pub device: Option<String>,
+}
+
+impl DeviceQuery {
+    pub fn new() -> DeviceQuery {
+        DeviceQuery::default()
+    }
+
+    pub fn platform(mut self, platform: &str) -> DeviceQuery {
+        self.platform = Some(platform.to_string());
+        self
+    }
+
+    pub fn device(mut self, device: &str) -> DeviceQuery {
+        self.device = Some(device.to_string());
+        self
+    }
+
+    /// Whether `device` matches the device hint, if any.
+    pub fn matches(&self, device: &Device) -> bool {
+        self.device.as_ref()
+            .map(|filter| format!("{}", device).to_lowercase().contains(&filter.to_lowercase()))
+            .unwrap_or(true)
+    }
+
+    /// The devices the device hint designates: the one whose id it is, else
+    /// the only one whose id starts with it, else the ones matching it. Fails
+    /// when it is the name of several devices, rather than picking one.
+    fn matching(&self, devices: Vec<Arc<Box<Device>>>) -> Result<Vec<Arc<Box<Device>>>> {
+        let (hint, lowercase_hint) = match self.device {
+            Some(ref hint) => (hint, hint.to_lowercase()),
+            None => return Ok(devices),
+        };
+        if let Some(device) = devices.iter().find(|it| it.id().to_lowercase() == lowercase_hint) {
+            return Ok(vec![device.clone()]);
+        }
+        let by_id_prefix = devices.iter().filter(|it| it.id().to_lowercase().starts_with(&lowercase_hint)).cloned().collect_vec();
+        if by_id_prefix.len() == 1 {
+            return Ok(by_id_prefix);
+        }
+        let matching = devices.into_iter().filter(|it| self.matches(&***it)).collect_vec();
+        let homonyms = matching.iter().filter(|it| it.name().to_lowercase() == lowercase_hint).collect_vec();
+        if homonyms.len() > 1 {
+            let ids = homonyms.iter().map(|it| it.id()).collect_vec();
+            bail!("Several devices are named `{}', select one with (a prefix of) its id:\n{}",
+                  hint,
+                  homonyms.iter().map(|it| format!("  -d {}  {}", unique_id_prefix(it.id(), &ids), it)).join("\n"))
+        }
+        Ok(matching)
+    }
+
+    /// The platform, and the first matching device it can run on. Without a
+    /// platform, the platform of the first usable matching device; without
+    /// either, the host.
+    pub fn select(&self, dinghy: &Dinghy) -> Result<(Arc<Box<Platform>>, Option<Arc<Box<Device>>>)> {
+        if let Some(ref platform_name) = self.platform {
+            let platform = dinghy
+                .platform_by_name(platform_name)
+                .ok_or(format!("No '{}' platform found", platform_name))?;
+
+            let compatible = dinghy.devices()
+                .into_iter()
+                .filter(|it| platform.is_compatible_with(&**it.as_ref()))
+                .collect_vec();
+            let device = self.matching(compatible)?.into_iter().next();
+
+            Ok((platform, device))
+        } else if let Some(ref device_filter) = self.device {
+            let devices = self.matching(dinghy.devices())?;
+            if devices.len() == 0 {
+                Err(format!("No devices found for name hint `{}'", device_filter))?;
+            }
+            let failures = devices.iter()
+                .filter_map(|d| d.failure().map(|failure| format!("{} is unavailable: {}", d.id(), failure)))
+                .collect_vec();
+            if failures.len() == devices.len() {
+                Err(failures.join("\n"))?;
+            }
+            devices.into_iter().filter_map(|d| {
+                let pf = dinghy.platforms().iter().find(|pf| pf.is_compatible_with(&**d)).cloned();
+                debug!("Looking for platform for {}: found {:?}", d.id(), pf.as_ref().map(|p| p.id()));
+                pf.map(|it| (it, Some(d)))
+            })
+            .next()
+            .ok_or(format!("No device and platform combination found for device hint `{}'", device_filter).into())
+        } else {
+            Ok((dinghy.host_platform(), Some(dinghy.host_device())))
+        }
+    }
+}
+
+/// The shortest prefix of `id`, of at least 8 characters, that none of the
+/// other `ids` starts with: enough to select the device with `-d`.
+pub fn unique_id_prefix<'a>(id: &'a str, ids: &[&str]) -> &'a str {
+    let mut len = ::std::cmp::min(8, id.len());
+    while len < id.len()
+        && (!id.is_char_boundary(len) || ids.iter().any(|other| *other != id && other.starts_with(&id[..len]))) {
+        len += 1;
+    }
+    &id[..len]
+}
+
+/// What a run left behind.
+pub struct RunOutcome {
+    pub build_bundles: Vec<BuildBundle>,
+    pub reports: Vec<TestReport>,
+    /// The merged coverage data, if coverage was collected and could be merged.
+    pub profdata: Option<PathBuf>,
+    /// The measures of the benchmarks across the repetitions, if repeated.
+    pub bench_stats: Vec<BenchStats>,
+}
+
+impl RunOutcome {
+    pub fn is_success(&self) -> bool {
+        self.reports.iter().all(|it| it.is_success())
+    }
+}
+
+/// Runs builds on a device: takes its lock, checks its health before
+/// benchmarks, and sets up the port forwards for the duration of the run.
+pub struct Runner {
+    cool_down: Duration,
+    device: Arc<Box<Device>>,
+    forwards: Vec<(u16, u16)>,
+    lock_wait: LockWait,
+    repetitions: usize,
+    retries: usize,
+    reverses: Vec<(u16, u16)>,
+}
+
+impl Runner {
+    pub fn new(device: Arc<Box<Device>>) -> Runner {
+        Runner {
+            cool_down: Duration::from_secs(0),
+            device,
+            forwards: vec![],
+            lock_wait: LockWait::Forever,
+            repetitions: 1,
+            retries: 0,
+            reverses: vec![],
+        }
+    }
+
+    /// How long to wait for the device if another run is using it.
+    pub fn lock_wait(mut self, lock_wait: LockWait) -> Runner {
+        self.lock_wait = lock_wait;
+        self
+    }
+
+    /// Run each benchmark executable `repetitions` times, pausing for
+    /// `cool_down` and checking the health of the device before each
+    /// repetition.
+    pub fn repeat(mut self, repetitions: usize, cool_down: Duration) -> Runner {
+        self.repetitions = ::std::cmp::max(repetitions, 1);
+        self.cool_down = cool_down;
+        self
+    }
+
+    /// Run the failed tests again, alone, up to `retries` times, reporting
+    /// the ones passing then as flaky rather than failed.
+    pub fn retries(mut self, retries: usize) -> Runner {
+        self.retries = retries;
+        self
+    }
+
+    /// Forward the `local` port of the host to the `remote` one of the device.
+    pub fn forward(mut self, local: u16, remote: u16) -> Runner {
+        self.forwards.push((local, remote));
+        self
+    }
+
+    /// Make the `local` port of the host reachable from the device, the
+    /// runnables finding its address in `DINGHY_HOST_PORT_<local>`.
+    pub fn reverse(mut self, local: u16, remote: u16) -> Runner {
+        self.reverses.push((local, remote));
+        self
+    }
+
+    pub fn device(&self) -> &Arc<Box<Device>> {
+        &self.device
+    }
+
+    /// What `run` would do, without touching the device.
+    pub fn dry_run(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<String>> {
+        let mut plan = vec![format!("Would run on device {}", self.device)];
+        for &(local, remote) in &self.forwards {
+            plan.push(format!("  * forward host port {} to device port {}", local, remote));
+        }
+        for &(local, remote) in &self.reverses {
+            plan.push(format!("  * make host port {} reachable from device port {} (DINGHY_HOST_PORT_{})",
+                              local, remote, local));
+        }
+        if let Some(ref profile) = run_args.network_profile {
+            plan.push(format!("  * simulate {}", profile.description()));
+        }
+        for line in self.device.dry_run_app(project, build, run_args)? {
+            plan.push(format!("  {}", line));
+        }
+        Ok(plan)
+    }
+
+    /// Run the runnables of `build`, and keep their reports (and coverage)
+    /// with the artifacts of the device.
+    pub fn run(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<RunOutcome> {
+        let _span = logging::span(format!("run on {}", self.device.id()));
+        let _timer = timings::start(Phase::Run);
+        self.session(project, build, run_args, false)
+    }
+
+    /// Start the first runnable of `build` under the debugger, set up and
+    /// torn down as `run` does. The outcome has no report.
+    pub fn debug(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<RunOutcome> {
+        let _span = logging::span(format!("debug on {}", self.device.id()));
+        self.session(project, build, run_args, true)
+    }
+
+    /// Set the device up, run `build` on it (or debug it), then tear it down
+    /// and keep the results.
+    fn session(&self, project: &Project, build: &Build, run_args: &RunArgs, debug: bool) -> Result<RunOutcome> {
+        let (_lock, _forwards, network, run_args) = self.prepare(project, build, run_args)?;
+        let artifacts = build.artifacts(self.device.id());
+        artifacts::reset_dir(artifacts::output_dir(self.device.id())?)?;
+        if run_args.coverage {
+            artifacts::reset_dir(artifacts.coverage_dir())?;
+        }
+        let bench = build.build_args.compile_mode == CompileMode::Bench;
+        if bench {
+            artifacts::reset_dir(artifacts.criterion_dir())?;
+        }
+        let run_conf = &project.conf.run;
+        let commands = |it: &Option<Vec<String>>| it.clone().unwrap_or(vec![]);
+        let (after_run, device_teardown) = (commands(&run_conf.after_run), commands(&run_conf.device_teardown));
+        hooks::run(Stage::BeforeInstall, &commands(&run_conf.before_install), project, &**self.device, build, &[])?;
+        hooks::run_on_device("device_setup", &commands(&run_conf.device_setup), &**self.device)?;
+        let recording = if run_args.record {
+            let dir = artifacts::ensure_dir(artifacts.recordings_dir())?;
+            Some(self.device.record_screen(&dir.join("last-run.mp4"))?)
+        } else {
+            None
+        };
+        let ran = if debug {
+            self.device.debug_app(project, build, &run_args).map(|it| (vec![it], vec![], vec![]))
+        } else {
+            self.run_on_device(project, build, &run_args, &artifacts).map(|(ran, bench_stats)| {
+                let (build_bundles, reports) = ran.into_iter().map(|it| (it.build_bundle, it.report)).unzip();
+                (build_bundles, reports, bench_stats)
+            })
+        };
+        if let Some(recording) = recording {
+            match recording.finish() {
+                Ok(path) => info!("Screen recording of {}: {}", self.device.id(), path.display()),
+                Err(e) => warn!("Couldn't save the screen recording of {}: {}", self.device.id(), e),
+            }
+        }
+        // The hooks restoring the state of the device or of a backend have to
+        // run whatever happened, each whatever the other did, and the report
+        // is written before the after_run hooks read it.
+        let teardown = hooks::run_on_device("device_teardown", &device_teardown, &**self.device);
+        let (build_bundles, mut reports, bench_stats): (Vec<_>, Vec<_>, _) = match ran {
+            Ok(ran) => ran,
+            Err(e) => {
+                let after_run = hooks::run(Stage::AfterRun, &after_run, project, &**self.device, build,
+                                           &[("DINGHY_RUN_SUCCESS", "false".to_string())]);
+                for hook_error in teardown.err().into_iter().chain(after_run.err()) {
+                    warn!("{}", hook_error);
+                }
+                return Err(e);
+            }
+        };
+        let profdata = self.keep_results(project, build, &artifacts, network.as_ref(), &run_args,
+                                         &mut reports, &bench_stats);
+        let mut hook_envs = vec![("DINGHY_RUN_SUCCESS", reports.iter().all(|it| it.is_success()).to_string())];
+        let written = if reports.is_empty() {
+            Ok(())
+        } else {
+            let last_run = artifacts.reports_dir().join("last-run.json");
+            hook_envs.push(("DINGHY_REPORT", last_run.to_string_lossy().to_string()));
+            artifacts::ensure_dir(artifacts.reports_dir()).and_then(|_| report::write_json(&last_run, &reports))
+        };
+        let after_run = hooks::run(Stage::AfterRun, &after_run, project, &**self.device, build, &hook_envs);
+        // Everything ran, the first error is the one to report.
+        teardown?;
+        let profdata = profdata?;
+        written?;
+        after_run?;
+        Ok(RunOutcome { build_bundles, reports, profdata, bench_stats })
+    }
+
+    /// What `run` does on the device: run the runnables (repeatedly for
+    /// benchmarks), then retry the failed tests.
+    fn run_on_device(&self, project: &Project, build: &Build, run_args: &RunArgs, artifacts: &ArtifactStore)
+                     -> Result<(Vec<RunResult>, Vec<BenchStats>)> {
+        let bench = build.build_args.compile_mode == CompileMode::Bench;
+        let (mut ran, bench_stats) = if bench && self.repetitions > 1 {
+            self.run_repeated(project, build, run_args, artifacts)?
+        } else if run_args.screenshot_on_failure {
+            let mut ran = vec![];
+            for runnable in &build.runnables {
+                let single = Build { runnables: vec![runnable.clone()], ..build.clone() };
+                ran.extend(self.run_single(project, &single, run_args, artifacts)?);
+            }
+            (ran, vec![])
+        } else {
+            (self.device.run_app(project, build, run_args)?, vec![])
+        };
+        if self.retries > 0 {
+            for result in &mut ran {
+                self.retry_failed_tests(project, build, run_args, &mut result.report)?;
+            }
+        }
+        Ok((ran, bench_stats))
+    }
+
+    /// Complete the reports of a run and save their output, merge the
+    /// coverage and criterion data pulled back from the device, and save the
+    /// statistics of the repeated benchmarks. Returns the merged coverage data.
+    fn keep_results(&self,
+                    project: &Project,
+                    build: &Build,
+                    artifacts: &ArtifactStore,
+                    network: Option<&NetworkCondition>,
+                    run_args: &RunArgs,
+                    reports: &mut [TestReport],
+                    bench_stats: &[BenchStats]) -> Result<Option<PathBuf>> {
+        for report in reports.iter_mut() {
+            report.conditions.extend(run_args.region.conditions());
+            report.conditions.extend(network.iter().map(|it| it.applied.clone()));
+            let runnable = &report.runnable;
+            for (file, extension) in vec![(&mut report.stdout, "stdout"), (&mut report.stderr, "stderr"), (&mut report.log, "log")] {
+                if let Some(written) = file.take() {
+                    let path = artifacts::ensure_dir(artifacts.reports_dir())?
+                        .join(format!("{}.{}", artifacts::sanitize(runnable), extension));
+                    fs::rename(&written, &path)
+                        .or_else(|_| fs::copy(&written, &path).and_then(|_| fs::remove_file(&written)))
+                        .chain_err(|| format!("Couldn't save the output of {} to {}", runnable, path.display()))?;
+                    *file = Some(path);
+                }
+            }
+        }
+        let profdata = if run_args.coverage {
+            coverage::merge_profiles(&artifacts.coverage_dir())?
+        } else {
+            None
+        };
+        if build.build_args.compile_mode == CompileMode::Bench {
+            self.merge_criterion_data(artifacts)?;
+        }
+        if !bench_stats.is_empty() {
+            let max_variation = project.conf.bench.max_variation.unwrap_or(repetition::DEFAULT_MAX_VARIATION);
+            for stats in bench_stats.iter().filter(|it| it.is_unstable(max_variation)) {
+                warn!("{} is unstable on {}: its measures vary by {:.1}% across {} repetitions",
+                      stats.name, self.device.id(), stats.variation(), stats.samples.len());
+            }
+            let path = artifacts::ensure_dir(artifacts.reports_dir())?.join("bench-stats.json");
+            let json = bench_stats.iter().map(|it| it.to_json(max_variation)).collect::<Vec<_>>();
+            fs::File::create(&path)
+                .and_then(|mut file| file.write_all(JsonValue::from(json).pretty(2).as_bytes()))
+                .chain_err(|| format!("Couldn't write the benchmark statistics to {}", path.display()))?;
+        }
+        Ok(profdata)
+    }
+
+    /// Run each runnable of `build` the number of repetitions, with a pause
+    /// before each but the first, merging the results of its repetitions.
+    fn run_repeated(&self, project: &Project, build: &Build, run_args: &RunArgs, artifacts: &ArtifactStore)
+                    -> Result<(Vec<RunResult>, Vec<BenchStats>)> {
+        let mut results = vec![];
+        let mut bench_stats = vec![];
+        for (index, runnable) in build.runnables.iter().enumerate() {
+            let single = Build { runnables: vec![runnable.clone()], ..build.clone() };
+            let mut runs = vec![];
+            for repetition in 0..self.repetitions {
+                if index > 0 || repetition > 0 {
+                    if self.cool_down > Duration::from_secs(0) {
+                        info!("Cooling {} down for {}s", self.device.id(), self.cool_down.as_secs());
+                        thread::sleep(self.cool_down);
+                    }
+                    health::check_for_bench(&**self.device, &project.conf.bench)?;
+                }
+                info!("Repetition {}/{} of {} on {}", repetition + 1, self.repetitions, runnable.id, self.device.id());
+                runs.extend(self.run_single(project, &single, run_args, artifacts)?);
+            }
+            let (result, stats) = repetition::merge(runs)?;
+            results.push(result);
+            bench_stats.extend(stats);
+        }
+        Ok((results, bench_stats))
+    }
+
+    /// Run the only runnable of `build`, taking a screenshot of the device
+    /// right after it if it failed and screenshots were asked for, while the
+    /// screen still shows what it left.
+    fn run_single(&self, project: &Project, build: &Build, run_args: &RunArgs, artifacts: &ArtifactStore)
+                  -> Result<Vec<RunResult>> {
+        let ran = self.device.run_app(project, build, run_args)?;
+        if run_args.screenshot_on_failure {
+            for result in ran.iter().filter(|it| !it.is_success()) {
+                self.screenshot_failure(artifacts, &result.report.runnable);
+            }
+        }
+        Ok(ran)
+    }
+
+    /// Merge the criterion data pulled back from the device, if any, into the
+    /// data of the host.
+    fn merge_criterion_data(&self, artifacts: &ArtifactStore) -> Result<()> {
+        let host_dir = criterion::host_dir()?;
+        let benchmarks = criterion::merge(&artifacts.criterion_dir(), &host_dir)?;
+        if !benchmarks.is_empty() {
+            info!("Criterion data of {} benchmark(s) of {} merged into {}",
+                  benchmarks.len(), self.device.id(), host_dir.display());
+        }
+        Ok(())
+    }
+
+    /// Keep a screenshot of the device after `runnable` failed, the failure
+    /// being the one to report if it can't be taken.
+    fn screenshot_failure(&self, artifacts: &ArtifactStore, runnable: &str) {
+        let taken = artifacts::ensure_dir(artifacts.screenshots_dir())
+            .map(|dir| dir.join(format!("{}.png", artifacts::sanitize(runnable))))
+            .and_then(|path| self.device.screenshot(&path).map(|_| path));
+        match taken {
+            Ok(path) => info!("Screenshot of {} after {} failed: {}", self.device.id(), runnable, path.display()),
+            Err(e) => warn!("Couldn't take a screenshot of {} after {} failed: {}", self.device.id(), runnable, e),
+        }
+    }
+
+    /// Run each failed test of `report` alone, with the libtest filter, until
+    /// it passes or the retries are exhausted.
+    fn retry_failed_tests(&self, project: &Project, build: &Build, run_args: &RunArgs, report: &mut TestReport) -> Result<()> {
+        let runnable = match build.runnables.iter().find(|it| it.id == report.runnable) {
+            Some(runnable) => runnable.clone(),
+            None => return Ok(()),
+        };
+        let failed = report.tests.iter()
+            .filter(|it| it.outcome == TestOutcome::Failed)
+            .map(|it| it.name.clone())
+            .collect_vec();
+        let mut single_build = build.clone();
+        single_build.runnables = vec![runnable];
+        for name in failed {
+            // Only the flags of the original arguments apply, their filters would
+            // deselect the test.
+            let mut retry_args = run_args.clone();
+            retry_args.args = vec![name.clone(), "--exact".to_string()];
+            retry_args.args.extend(run_args.args.iter()
+                .filter(|it| ["--ignored", "--include-ignored", "--nocapture"].contains(&it.as_str())
+                    || (it.starts_with("--") && it.contains('=')))
+                .cloned());
+            for attempt in 1..self.retries + 1 {
+                info!("Retrying {} of {} ({}/{})", name, report.runnable, attempt, self.retries);
+                let rerun = self.device.run_app(project, &single_build, &retry_args)?.into_iter().next();
+                let passed = match rerun {
+                    Some(rerun) => report.merge_retry(&name, &rerun.report),
+                    None => false,
+                };
+                if passed {
+                    warn!("{} of {} is flaky: it passed on retry {}", name, report.runnable, attempt);
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the bundles of a run from the device.
+    pub fn clean(&self, outcome: &RunOutcome) -> Result<()> {
+        for build_bundle in &outcome.build_bundles {
+            self.device.clean_app(build_bundle)?;
+        }
+        Ok(())
+    }
+
+    fn prepare(&self, project: &Project, build: &Build, run_args: &RunArgs)
+               -> Result<(Option<DeviceLock>, Vec<PortForward>, Option<NetworkCondition>, RunArgs)> {
+        let lock = lock::lock_for_run(&**self.device, self.lock_wait)?;
+        if build.build_args.compile_mode == CompileMode::Bench {
+            health::check_for_bench(&**self.device, &project.conf.bench)?;
+        }
+        let mut run_args = run_args.clone();
+        let mut forwards = self.forwards.iter()
+            .map(|&(local, remote)| self.device.forward_port(local, remote))
+            .collect::<Result<Vec<_>>>()?;
+        for &(local, remote) in &self.reverses {
+            let (forward, address) = self.device.reverse_port(local, remote)?;
+            // Tell the runnables where to reach the host service from the device.
+            run_args.envs.push(format!("DINGHY_HOST_PORT_{}={}", local, address));
+            forwards.push(forward);
+        }
+        let network = match run_args.network_profile {
+            Some(ref profile) => Some(self.device.network_condition(profile)?),
+            None => None,
+        };
+        Ok((lock, forwards, network, run_args))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id_prefixes() {
+        let ids = ["00008030-001A2B3C4D5E602E", "00008030-00112233445566AA", "emulator-5554"];
+        assert_eq!(unique_id_prefix(ids[0], &ids), "00008030-001A");
+        assert_eq!(unique_id_prefix(ids[1], &ids), "00008030-0011");
+        assert_eq!(unique_id_prefix(ids[2], &ids), "emulator");
+        assert_eq!(unique_id_prefix("abc", &ids), "abc");
+    }
+}
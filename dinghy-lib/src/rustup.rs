@@ -0,0 +1,57 @@
+//! Detection (and installation) of the standard libraries missing from the
+//! rustup toolchain, which would otherwise fail the build deep inside cargo.
+
+use errors::*;
+use std::io;
+use std::io::Write;
+use std::process::Command;
+
+/// Make sure the standard library of `rustc_triple` is installed, installing
+/// it if `install` is set, or if the user agrees to when asked. Toolchains not
+/// managed by rustup are left alone.
+pub fn ensure_target(rustc_triple: &str, install: bool) -> Result<()> {
+    match installed_targets()? {
+        Some(ref installed) if installed.iter().any(|it| it == rustc_triple) => return Ok(()),
+        Some(_) => {}
+        None => {
+            debug!("No rustup targets known, not checking the {} standard library", rustc_triple);
+            return Ok(());
+        }
+    }
+    let command = format!("rustup target add {}", rustc_triple);
+    if !install && !confirm(&format!("The Rust standard library for {} is not installed. Run `{}` now?", rustc_triple, command))? {
+        bail!("The Rust standard library for {} is not installed. Install it with `{}`, or pass --install-targets.",
+              rustc_triple, command)
+    }
+    info!("Installing the Rust standard library for {}", rustc_triple);
+    if !Command::new("rustup").args(&["target", "add", rustc_triple]).status()?.success() {
+        bail!("`{}` failed", command)
+    }
+    Ok(())
+}
+
+/// The targets whose standard library rustup installed, `None` if rustup is
+/// not there or can't tell.
+pub fn installed_targets() -> Result<Option<Vec<String>>> {
+    let rustup = match ::which::which("rustup") {
+        Ok(rustup) => rustup,
+        Err(_) => return Ok(None),
+    };
+    let installed = Command::new(&rustup).args(&["target", "list", "--installed"]).output()?;
+    if !installed.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8(installed.stdout)?.lines().map(|it| it.trim().to_string()).collect()))
+}
+
+/// Ask a yes/no question, assuming no when nobody is there to answer.
+fn confirm(question: &str) -> Result<bool> {
+    if !::isatty::stdin_isatty() || !::isatty::stderr_isatty() {
+        return Ok(false);
+    }
+    write!(io::stderr(), "{} [y/N] ", question)?;
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().eq_ignore_ascii_case("y") || answer.trim().eq_ignore_ascii_case("yes"))
+}
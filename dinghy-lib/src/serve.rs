@@ -0,0 +1,511 @@
+//! `cargo dinghy serve`: the devices of this host, served over TCP to remote
+//! agent clients.
+//!
+//! The protocol is line based. Clients authenticate with `AUTH <token>`, then
+//! send requests, answered by `OK`, by `ERR <message>` or as described below:
+//!
+//! * `DEVICES`: one `cargo dinghy agent-devices` line per device, then `END`,
+//! * `MANIFEST <dir>`: the sync manifest of a directory, then `END`,
+//! * `PUT <size> <secs> <nanos> <path>`, followed by the content of the file,
+//! * `RM <path>`: remove a file or a directory,
+//! * `PULL <dir>`: `FILE <size> <path>` followed by the content of each file of
+//!   the directory, then `END`,
+//! * `RUN <mode> <device>`, followed by `BUNDLE <dir>`, `LIBS <dir>`,
+//!   `ENV <value>` and `ARG <value>` lines and `GO`: runs the bundle with
+//!   `cargo dinghy agent-run`, streams its standard output and error as
+//!   `OUT <size>` and `LOG <size>` chunks, then ends with `EXIT <code>`. The
+//!   run is killed if the client goes away.
+//!
+//! Paths are relative to the work directory of the server.
+
+use errors::*;
+use filetime::FileTime;
+use filetime::set_file_times;
+use output::Stream;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
+use std::process::Command;
+use std::process::ExitStatus;
+use std::str::FromStr;
+use std::process::Stdio;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::UNIX_EPOCH;
+use sync::Manifest;
+use walkdir::WalkDir;
+
+/// One side of a connection between a server and an agent client.
+pub struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Result<Connection> {
+        Ok(Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+
+    /// Connect and authenticate to the server at `address`.
+    pub fn connect(address: &str, token: &str) -> Result<Connection> {
+        let stream = TcpStream::connect(address).chain_err(|| format!("Couldn't connect to {}", address))?;
+        let mut connection = Connection::new(stream)?;
+        connection.send(&format!("AUTH {}", token))?;
+        connection.expect_ok().chain_err(|| format!("Couldn't authenticate to {}", address))?;
+        Ok(connection)
+    }
+
+    /// The `agent-devices` lines of the server.
+    pub fn devices(&mut self) -> Result<Vec<String>> {
+        self.send("DEVICES")?;
+        self.receive_until_end()
+    }
+
+    /// Bring `remote_dir` up to date with `local_dir`, sending only the files
+    /// that changed since the last sync.
+    pub fn sync(&mut self, local_dir: &Path, remote_dir: &str) -> Result<()> {
+        self.send(&format!("MANIFEST {}", remote_dir))?;
+        let installed = Manifest::parse(&self.receive_until_end()?.join("\n"));
+        let plan = Manifest::scan(local_dir)?.plan_from(&installed);
+        for removed in &plan.removed {
+            self.remove(&format!("{}/{}", remote_dir, removed))?;
+        }
+        for changed in &plan.changed {
+            let file = local_dir.join(changed);
+            let metadata = file.metadata()?;
+            let mtime = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+            debug!("Sending {}", file.display());
+            self.send(&format!("PUT {} {} {} {}/{}",
+                               metadata.len(), mtime.as_secs(), mtime.subsec_nanos(), remote_dir, changed))?;
+            io::copy(&mut fs::File::open(&file)?.take(metadata.len()), &mut self.writer)?;
+            self.expect_ok()?;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, path: &str) -> Result<()> {
+        self.send(&format!("RM {}", path))?;
+        self.expect_ok()
+    }
+
+    /// Run a synced bundle on `device`. The output of the run is passed to
+    /// `output` as it comes, with the stream it was written to. Returns the
+    /// exit code of the run, or `None` if it timed out.
+    pub fn run<F: FnMut(Stream, &[u8])>(&mut self,
+                                        device: &str,
+                                        mode: &str,
+                                        bundle_dir: &str,
+                                        lib_dir: &str,
+                                        envs: &[String],
+                                        args: &[String],
+                                        timeout: Option<Duration>,
+                                        mut output: F) -> Result<Option<i32>> {
+        if envs.iter().chain(args.iter()).any(|it| it.contains('\n')) {
+            bail!("Arguments and environment variables of agent runs can't span several lines")
+        }
+        self.send(&format!("RUN {} {}", mode, device))?;
+        self.send(&format!("BUNDLE {}", bundle_dir))?;
+        self.send(&format!("LIBS {}", lib_dir))?;
+        for env in envs {
+            self.send(&format!("ENV {}", env))?;
+        }
+        for arg in args {
+            self.send(&format!("ARG {}", arg))?;
+        }
+        self.send("GO")?;
+
+        let deadline = timeout.map(|it| Instant::now() + it);
+        loop {
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Ok(None);
+                }
+                self.reader.get_ref().set_read_timeout(Some(deadline - now))?;
+            }
+            let frame = match self.receive() {
+                Ok(frame) => frame,
+                Err(_) if deadline.map(|it| Instant::now() >= it).unwrap_or(false) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            let (kind, value) = split_request(&frame);
+            match kind {
+                "OUT" | "LOG" => {
+                    let mut chunk = vec![];
+                    (&mut self.reader).take(parse_number(value)?).read_to_end(&mut chunk)?;
+                    output(if kind == "OUT" { Stream::Stdout } else { Stream::Stderr }, &chunk);
+                }
+                "EXIT" => return Ok(Some(parse_number(value)?)),
+                "ERR" => bail!("{}", value),
+                _ => bail!("Unexpected reply from server: {}", frame),
+            }
+        }
+    }
+
+    /// Copy the files of `remote_dir` to `local_dir`.
+    pub fn pull(&mut self, remote_dir: &str, local_dir: &Path) -> Result<()> {
+        self.send(&format!("PULL {}", remote_dir))?;
+        loop {
+            let frame = self.receive()?;
+            let (kind, value) = split_request(&frame);
+            match kind {
+                "FILE" => {
+                    let (size, path) = split_request(value);
+                    let file = resolve(local_dir, path)?;
+                    if let Some(parent) = file.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    let size: u64 = parse_number(size)?;
+                    io::copy(&mut (&mut self.reader).take(size), &mut fs::File::create(&file)?)?;
+                }
+                "END" => return Ok(()),
+                "ERR" => bail!("{}", value),
+                _ => bail!("Unexpected reply from server: {}", frame),
+            }
+        }
+    }
+
+    fn send(&mut self, line: &str) -> Result<()> {
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            bail!("Connection closed")
+        }
+        Ok(line.trim_right_matches('\n').to_string())
+    }
+
+    fn expect_ok(&mut self) -> Result<()> {
+        let reply = self.receive()?;
+        match split_request(&reply) {
+            ("OK", _) => Ok(()),
+            ("ERR", message) => bail!("{}", message),
+            _ => bail!("Unexpected reply from server: {}", reply),
+        }
+    }
+
+    fn receive_until_end(&mut self) -> Result<Vec<String>> {
+        let mut lines = vec![];
+        loop {
+            let line = self.receive()?;
+            if line == "END" {
+                return Ok(lines);
+            }
+            if line.starts_with("ERR ") {
+                bail!("{}", &line[4..])
+            }
+            lines.push(line);
+        }
+    }
+}
+
+/// Serve the devices of this host on `listen` to the clients knowing `token`,
+/// keeping their files in `work_dir`.
+pub fn serve(listen: &str, token: &str, work_dir: &Path) -> Result<()> {
+    if token.is_empty() {
+        bail!("A token is required to serve devices")
+    }
+    ::artifacts::ensure_dir(work_dir)?;
+    let listener = TcpListener::bind(listen).chain_err(|| format!("Couldn't listen on {}", listen))?;
+    info!("Serving the devices of this host on {}, from {}", listen, work_dir.display());
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Failure while accepting connection: {}", e);
+                continue;
+            }
+        };
+        let (token, work_dir) = (token.to_string(), work_dir.to_path_buf());
+        thread::spawn(move || {
+            let peer = stream.peer_addr().map(|it| it.to_string()).unwrap_or("unknown peer".into());
+            debug!("Connection from {}", peer);
+            if let Err(e) = handle(stream, &token, &work_dir) {
+                warn!("Connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle(stream: TcpStream, token: &str, work_dir: &Path) -> Result<()> {
+    let mut connection = Connection::new(stream)?;
+    let auth = connection.receive()?;
+    match split_request(&auth) {
+        ("AUTH", given) if same_token(given, token) => connection.send("OK")?,
+        _ => {
+            connection.send("ERR authentication failed")?;
+            bail!("Authentication failed")
+        }
+    }
+    loop {
+        let request = match connection.receive() {
+            Ok(request) => request,
+            Err(_) => return Ok(()),
+        };
+        let (verb, value) = split_request(&request);
+        let result = match verb {
+            "DEVICES" => list_devices(&mut connection),
+            "MANIFEST" => send_manifest(&mut connection, work_dir, value),
+            "PUT" => receive_file(&mut connection, work_dir, value),
+            "RM" => remove(&mut connection, work_dir, value),
+            "PULL" => send_files(&mut connection, work_dir, value),
+            "RUN" => run(&mut connection, work_dir, value),
+            _ => Err(format!("Unknown request {}", verb).into()),
+        };
+        if let Err(e) = result {
+            debug!("Request {} failed: {}", verb, e);
+            connection.send(&format!("ERR {}", e.to_string().replace('\n', " ")))?;
+        }
+    }
+}
+
+fn list_devices(connection: &mut Connection) -> Result<()> {
+    let output = Command::new(env::current_exe()?).arg("agent-devices").output()?;
+    if !output.status.success() {
+        bail!("Couldn't list the devices: {}", String::from_utf8_lossy(&output.stderr).trim())
+    }
+    for line in String::from_utf8(output.stdout)?.lines() {
+        connection.send(line)?;
+    }
+    connection.send("END")
+}
+
+fn send_manifest(connection: &mut Connection, work_dir: &Path, dir: &str) -> Result<()> {
+    let dir = resolve(work_dir, dir)?;
+    let manifest = if dir.exists() { Manifest::scan(&dir)? } else { Manifest::default() };
+    for line in manifest.to_string().lines() {
+        connection.send(line)?;
+    }
+    connection.send("END")
+}
+
+fn receive_file(connection: &mut Connection, work_dir: &Path, header: &str) -> Result<()> {
+    let mut fields = header.splitn(4, ' ');
+    let size: u64 = parse_number(fields.next().unwrap_or(""))?;
+    let secs: u64 = parse_number(fields.next().unwrap_or(""))?;
+    let nanos: u32 = parse_number(fields.next().unwrap_or(""))?;
+    let path = fields.next().unwrap_or("");
+    // The content is read even if the file can't be written, to stay in sync
+    // with the client.
+    let mut content = (&mut connection.reader).take(size);
+    let written = resolve(work_dir, path).and_then(|file| {
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut content, &mut fs::File::create(&file)?)?;
+        let mtime = FileTime::from_seconds_since_1970(secs, nanos);
+        set_file_times(&file, mtime, mtime)?;
+        Ok(())
+    });
+    io::copy(&mut content, &mut io::sink())?;
+    written?;
+    connection.send("OK")
+}
+
+fn remove(connection: &mut Connection, work_dir: &Path, path: &str) -> Result<()> {
+    let path = resolve(work_dir, path)?;
+    if path.is_dir() {
+        fs::remove_dir_all(&path)?;
+    } else if path.exists() {
+        fs::remove_file(&path)?;
+    }
+    connection.send("OK")
+}
+
+fn send_files(connection: &mut Connection, work_dir: &Path, dir: &str) -> Result<()> {
+    let dir = resolve(work_dir, dir)?;
+    for entry in WalkDir::new(&dir) {
+        let entry = entry.chain_err(|| format!("Couldn't walk {}", dir.display()))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry.path().strip_prefix(&dir)?.to_string_lossy().into_owned();
+        let mut file = fs::File::open(entry.path())?;
+        let size = file.metadata()?.len();
+        connection.send(&format!("FILE {} {}", size, path))?;
+        io::copy(&mut (&mut file).take(size), &mut connection.writer)?;
+    }
+    connection.send("END")
+}
+
+fn run(connection: &mut Connection, work_dir: &Path, header: &str) -> Result<()> {
+    let (mode, device) = split_request(header);
+    let mut command = Command::new(env::current_exe()?);
+    command.arg("-d").arg(device).arg("agent-run").arg("--mode").arg(mode);
+    let mut args = vec![];
+    loop {
+        let line = connection.receive()?;
+        match split_request(&line) {
+            ("BUNDLE", dir) => { command.arg("--bundle").arg(resolve(work_dir, dir)?); }
+            ("LIBS", dir) => { command.arg("--libs").arg(resolve(work_dir, dir)?); }
+            ("ENV", env) => { command.arg("--env").arg(env); }
+            ("ARG", arg) => args.push(arg.to_string()),
+            ("GO", _) => break,
+            _ => bail!("Unexpected run parameter {}", line),
+        }
+    }
+    command.arg("--").args(&args);
+
+    debug!("Running {:?}", command);
+    let mut child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let writer = Arc::new(Mutex::new(connection.writer.try_clone()?));
+    let failed = Arc::new(AtomicBool::new(false));
+    let forwarders = vec![
+        child.stdout.take().map(|it| forward_in_background(it, &writer, "OUT", &failed)),
+        child.stderr.take().map(|it| forward_in_background(it, &writer, "LOG", &failed)),
+    ];
+    let status = match supervise(&mut child, &connection.writer, &failed)? {
+        Some(status) => status,
+        // The forwarders are left behind: the processes the run started may
+        // still hold its output open.
+        None => bail!("Client went away, run killed"),
+    };
+    for forwarder in forwarders.into_iter().filter_map(|it| it) {
+        let _ = forwarder.join();
+    }
+    connection.send(&format!("EXIT {}", status.code().unwrap_or(-1)))
+}
+
+/// Wait for the `child` running for the client of `socket`, killing and
+/// reaping it if the client goes away, or if its output couldn't be sent
+/// (`failed`). Returns `None` if it was killed.
+///
+/// The client sends nothing while the run lasts: the socket is watched for its
+/// end, so that a silent run gets killed too.
+fn supervise(child: &mut Child, socket: &TcpStream, failed: &AtomicBool) -> Result<Option<ExitStatus>> {
+    socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+    let mut byte = [0u8];
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        let gone = match socket.peek(&mut byte) {
+            Ok(0) => true,
+            Ok(_) => {
+                // Unexpected data, left for after the run.
+                thread::sleep(Duration::from_millis(100));
+                false
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => false,
+            Err(_) => true,
+        };
+        if gone || failed.load(Ordering::SeqCst) {
+            debug!("Client went away, killing the run");
+            let _ = child.kill();
+            child.wait()?;
+            break None;
+        }
+    };
+    socket.set_read_timeout(None)?;
+    Ok(status)
+}
+
+/// Forward what `from` produces as `<kind> <size>` chunks from a background
+/// thread, setting `failed` if the client can't be written to.
+fn forward_in_background<R: Read + Send + 'static>(from: R,
+                                                   writer: &Arc<Mutex<TcpStream>>,
+                                                   kind: &'static str,
+                                                   failed: &Arc<AtomicBool>) -> thread::JoinHandle<()> {
+    let (writer, failed) = (writer.clone(), failed.clone());
+    thread::spawn(move || if forward_chunks(from, &writer, kind).is_err() {
+        failed.store(true, Ordering::SeqCst);
+    })
+}
+
+/// Forward what `from` produces as `<kind> <size>` chunks.
+fn forward_chunks<R: Read>(mut from: R, writer: &Mutex<TcpStream>, kind: &str) -> Result<()> {
+    let mut buffer = [0u8; 8192];
+    loop {
+        let len = from.read(&mut buffer)?;
+        if len == 0 {
+            return Ok(());
+        }
+        let mut writer = writer.lock().map_err(|_| "Connection lock poisoned")?;
+        writer.write_all(format!("{} {}\n", kind, len).as_bytes())?;
+        writer.write_all(&buffer[..len])?;
+    }
+}
+
+/// Split a request (or reply) line in its verb and the rest of the line.
+fn split_request(line: &str) -> (&str, &str) {
+    let mut parts = line.splitn(2, ' ');
+    (parts.next().unwrap_or(""), parts.next().unwrap_or(""))
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T> {
+    value.parse().map_err(|_| format!("Invalid number '{}'", value).into())
+}
+
+/// Resolve a path sent by the peer, which has to stay inside `dir`.
+fn resolve(dir: &Path, path: &str) -> Result<PathBuf> {
+    let relative = Path::new(path);
+    let escapes = relative.components().any(|it| match it {
+        Component::Normal(_) | Component::CurDir => false,
+        _ => true,
+    });
+    if path.is_empty() || escapes {
+        bail!("Invalid path '{}'", path)
+    }
+    Ok(dir.join(relative))
+}
+
+/// Compare tokens without leaking the length of their common prefix.
+fn same_token(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given.bytes().zip(expected.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_stays_in_work_dir() {
+        assert_eq!(resolve(Path::new("/srv"), "dev/bundle/test_data").unwrap(),
+                   PathBuf::from("/srv/dev/bundle/test_data"));
+        assert!(resolve(Path::new("/srv"), "dev/../../etc/passwd").is_err());
+        assert!(resolve(Path::new("/srv"), "/etc/passwd").is_err());
+        assert!(resolve(Path::new("/srv"), "").is_err());
+    }
+
+    #[test]
+    fn silent_run_killed_when_client_goes_away() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let mut child = Command::new("sleep").arg("30").stdout(Stdio::piped()).spawn().unwrap();
+        drop(client);
+        let started = Instant::now();
+        assert!(supervise(&mut child, &server, &AtomicBool::new(false)).unwrap().is_none());
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(child.try_wait().unwrap().is_some());
+    }
+
+    #[test]
+    fn compare_tokens() {
+        assert!(same_token("s3cret", "s3cret"));
+        assert!(!same_token("s3cret", "s3cre"));
+        assert!(!same_token("s3creT", "s3cret"));
+    }
+}
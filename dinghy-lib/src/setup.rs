@@ -0,0 +1,171 @@
+//! Diagnosis of the setup of the host: the tools the platforms and devices
+//! need, and for each platform whether it can be used, the standard library of
+//! its target installed, and the devices it can run on.
+
+use compiler::Compiler;
+use config::Configuration;
+use device::android;
+use device::avd;
+use errors::*;
+use json::JsonValue;
+use ndk;
+use rustup;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+use Dinghy;
+
+pub struct HostTool {
+    pub name: &'static str,
+    /// What the tool is needed for.
+    pub purpose: &'static str,
+    pub path: Option<PathBuf>,
+}
+
+impl HostTool {
+    pub fn to_json(&self) -> JsonValue {
+        let mut tool = JsonValue::new_object();
+        tool["name"] = self.name.into();
+        tool["purpose"] = self.purpose.into();
+        tool["path"] = self.path.as_ref().map(|it| it.to_string_lossy().to_string()).into();
+        tool
+    }
+}
+
+pub struct PlatformSetup {
+    pub id: String,
+    pub rustc_triple: Option<String>,
+    /// Why the platform can't be used on this host, if it can't.
+    pub unavailable: Option<String>,
+    /// Whether the standard library of the target is installed, `None` when
+    /// unknown (no rustup) or irrelevant (host).
+    pub target_installed: Option<bool>,
+    /// The devices the platform can run on.
+    pub devices: Vec<String>,
+}
+
+impl PlatformSetup {
+    pub fn to_json(&self) -> JsonValue {
+        let mut platform = JsonValue::new_object();
+        platform["id"] = self.id.as_str().into();
+        platform["rustc_triple"] = self.rustc_triple.clone().into();
+        platform["available"] = self.unavailable.is_none().into();
+        platform["unavailable"] = self.unavailable.clone().into();
+        platform["target_installed"] = self.target_installed.into();
+        platform["devices"] = self.devices.clone().into();
+        platform
+    }
+}
+
+/// The external tools dinghy may use, and where they were found.
+pub fn host_tools() -> Vec<HostTool> {
+    vec![
+        HostTool { name: "rustup", purpose: "installing the standard library of the targets", path: ::which::which("rustup").ok() },
+        HostTool { name: "adb", purpose: "Android devices", path: android::adb().ok() },
+        HostTool { name: "Android NDK", purpose: "Android standalone toolchains", path: android_ndk() },
+        HostTool { name: "emulator", purpose: "Android emulators (--avd)", path: avd::emulator().ok() },
+        HostTool { name: "Xcode", purpose: "iOS devices and simulators", path: xcode() },
+        HostTool { name: "docker", purpose: "Docker devices", path: ::which::which("docker").ok() },
+        HostTool {
+            name: "wasmtime/wasmer",
+            purpose: "WebAssembly (WASI) executables",
+            path: ::which::which("wasmtime").or_else(|_| ::which::which("wasmer")).ok(),
+        },
+    ]
+}
+
+/// Every platform known, with what it needs on this host.
+pub fn platforms(conf: &Arc<Configuration>, compiler: &Arc<Compiler>) -> Result<Vec<PlatformSetup>> {
+    let devices = Dinghy::probe_devices(conf, compiler)?;
+    let installed_targets = rustup::installed_targets()?;
+    let mut setups = vec![];
+    for (id, platform) in Dinghy::try_discover_platforms(compiler, conf) {
+        let configured_triple = conf.platforms.get(&id).and_then(|it| it.rustc_triple.clone());
+        let setup = match platform {
+            Ok(Some(platform)) => {
+                let rustc_triple = platform.rustc_triple().map(|it| it.to_string());
+                PlatformSetup {
+                    id,
+                    target_installed: match (rustc_triple.as_ref(), installed_targets.as_ref()) {
+                        (Some(triple), Some(installed)) => Some(installed.iter().any(|it| it == triple)),
+                        _ => None,
+                    },
+                    rustc_triple,
+                    unavailable: None,
+                    devices: devices.iter()
+                        .filter(|device| platform.is_compatible_with(&***device))
+                        .map(|device| device.id().to_string())
+                        .collect(),
+                }
+            }
+            Ok(None) => PlatformSetup {
+                id,
+                rustc_triple: configured_triple,
+                unavailable: Some("needs a macOS host".to_string()),
+                target_installed: None,
+                devices: vec![],
+            },
+            Err(e) => PlatformSetup {
+                id,
+                rustc_triple: configured_triple,
+                unavailable: Some(e.to_string()),
+                target_installed: None,
+                devices: vec![],
+            },
+        };
+        setups.push(setup);
+    }
+    Ok(setups)
+}
+
+pub fn to_json(tools: &[HostTool], platforms: &[PlatformSetup]) -> JsonValue {
+    let mut json = JsonValue::new_object();
+    json["tools"] = tools.iter().map(|it| it.to_json()).collect::<Vec<_>>().into();
+    json["platforms"] = platforms.iter().map(|it| it.to_json()).collect::<Vec<_>>().into();
+    json
+}
+
+/// The NDK pointed to by the environment, installed in the Android SDK, or
+/// by `cargo dinghy setup android`.
+pub fn android_ndk() -> Option<PathBuf> {
+    if let Some(ndk) = ["ANDROID_NDK_HOME", "ANDROID_NDK_ROOT", "NDK_HOME"].iter()
+        .filter_map(|it| env::var(it).ok())
+        .map(PathBuf::from)
+        .find(|it| it.is_dir()) {
+        return Some(ndk);
+    }
+    for sdk in ["ANDROID_HOME", "ANDROID_SDK_ROOT"].iter().filter_map(|it| env::var(it).ok()) {
+        let sdk = Path::new(&sdk);
+        if sdk.join("ndk-bundle").is_dir() {
+            return Some(sdk.join("ndk-bundle"));
+        }
+        // Side by side NDKs, one directory per version: the last one.
+        let versions = fs::read_dir(sdk.join("ndk")).into_iter()
+            .flat_map(|it| it)
+            .filter_map(|it| it.ok())
+            .map(|it| it.path())
+            .filter(|it| it.is_dir())
+            .max();
+        if versions.is_some() {
+            return versions;
+        }
+    }
+    ndk::ndk_dir(ndk::NDK_VERSION).ok().and_then(|it| it.parent().map(|it| it.to_path_buf()))
+        .and_then(|dir| fs::read_dir(dir).ok())
+        .into_iter()
+        .flat_map(|it| it)
+        .filter_map(|it| it.ok())
+        .map(|it| it.path())
+        .filter(|it| it.join("source.properties").is_file())
+        .max()
+}
+
+/// The developer directory of the selected Xcode.
+pub fn xcode() -> Option<PathBuf> {
+    Command::new("xcode-select").arg("-p").output().ok()
+        .and_then(|output| if output.status.success() { String::from_utf8(output.stdout).ok() } else { None })
+        .map(|it| PathBuf::from(it.trim()))
+}
@@ -0,0 +1,59 @@
+//! Splitting of the runnables of a build across several devices, each of
+//! them running one shard, so that a big suite finishes sooner on a rack of
+//! devices.
+
+use errors::*;
+use Runnable;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shard {
+    /// Starting at 1.
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Shard {
+    /// Parse an `INDEX/COUNT` specification, e.g. `2/4`.
+    pub fn parse(spec: &str) -> Result<Shard> {
+        let mut parts = spec.splitn(2, '/');
+        let index = parts.next().and_then(|it| it.trim().parse().ok());
+        let count = parts.next().and_then(|it| it.trim().parse().ok());
+        match (index, count) {
+            (Some(index), Some(count)) if index >= 1 && index <= count => Ok(Shard { index, count }),
+            _ => bail!("Invalid shard '{}', expected INDEX/COUNT with INDEX from 1 to COUNT", spec),
+        }
+    }
+
+    /// Keep the runnables of this shard: one in `count`, in id order, so that
+    /// the shards of a build are disjoint and cover all of it.
+    pub fn select(&self, runnables: &mut Vec<Runnable>) {
+        runnables.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut position = 0;
+        runnables.retain(|_| {
+            position += 1;
+            (position - 1) % self.count == self.index - 1
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn shards_cover_the_runnables() {
+        let runnables = ["d", "a", "c", "b", "e"].iter()
+            .map(|id| Runnable { id: id.to_string(), exe: PathBuf::new(), source: PathBuf::new(), stripped_exe: None })
+            .collect::<Vec<_>>();
+        let ids = |spec: &str| {
+            let mut shard = runnables.clone();
+            Shard::parse(spec).unwrap().select(&mut shard);
+            shard.into_iter().map(|it| it.id).collect::<Vec<_>>()
+        };
+        assert_eq!(ids("1/2"), vec!["a", "c", "e"]);
+        assert_eq!(ids("2/2"), vec!["b", "d"]);
+        assert!(Shard::parse("3/2").is_err());
+        assert!(Shard::parse("auto").is_err());
+    }
+}
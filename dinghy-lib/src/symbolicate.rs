@@ -0,0 +1,384 @@
+//! Host-side symbolication of the crash reports collected on devices.
+//!
+//! Frames of the runnable are resolved with the freshly built binary, system
+//! frames of iOS reports with the symbols Xcode caches in its DeviceSupport
+//! directory. Reports are rewritten in place, unresolved frames are left as is.
+
+use errors::*;
+use json;
+use json::JsonValue;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Symbolicate an iOS crash report of the Dinghy app built from `binary`, in
+/// either the text format or the JSON `.ips` format of iOS 15 and later.
+/// Returns whether any frame was resolved.
+pub fn symbolicate_ios_report(report: &Path, binary: &Path) -> Result<bool> {
+    let content = read_report(report)?;
+    if let Some((header, body)) = parse_ips_report(&content) {
+        return symbolicate_ips_report(report, &content, header, body, binary);
+    }
+    let images = parse_ios_images(&content)?;
+    let symbols_dir = parse_ios_os_version(&content).and_then(|(version, build)| ios_symbols_dir(&version, &build));
+
+    // Group the frame addresses per binary image, to run atos once per image.
+    let frame_regex = ios_frame_regex()?;
+    let mut addresses: HashMap<String, Vec<String>> = HashMap::new();
+    for line in content.lines() {
+        if let Some(captures) = frame_regex.captures(line) {
+            addresses.entry(captures[2].to_string()).or_insert(vec![]).push(captures[3].to_string());
+        }
+    }
+
+    let mut symbols: HashMap<String, String> = HashMap::new();
+    for (name, image_addresses) in addresses {
+        let image = match images.get(&name) {
+            Some(image) => image,
+            None => continue,
+        };
+        let file = match ios_image_file(&name, image, binary, symbols_dir.as_ref()) {
+            Some(file) => file,
+            None => continue,
+        };
+        symbols.extend(atos(image, &file, &image_addresses)?);
+    }
+
+    let rewritten = rewrite_lines(&content, |line| {
+        frame_regex.captures(line).and_then(|captures| {
+            symbols.get(&captures[3]).map(|symbol| format!("{}{}", &captures[1], symbol))
+        })
+    });
+    write_if_changed(report, &content, rewritten)
+}
+
+/// Symbolicate a JSON `.ips` report: a one line JSON header followed by the
+/// JSON body, whose frames refer to `usedImages` by index. Resolved frames get
+/// a `symbol` entry, the ones already carrying one are left as is.
+fn symbolicate_ips_report(report: &Path, content: &str, header: JsonValue, mut body: JsonValue, binary: &Path) -> Result<bool> {
+    let images = parse_ips_images(&body);
+    let symbols_dir = parse_ips_os_version(&body).and_then(|(version, build)| ios_symbols_dir(&version, &build));
+
+    let frames = parse_ips_frames(&body, &images);
+    let mut addresses: HashMap<usize, Vec<String>> = HashMap::new();
+    for &(_, _, image_index, ref address) in &frames {
+        addresses.entry(image_index).or_insert(vec![]).push(address.clone());
+    }
+
+    let mut symbols: HashMap<String, String> = HashMap::new();
+    for (image_index, image_addresses) in addresses {
+        let (ref name, ref image) = images[image_index];
+        let file = match ios_image_file(name, image, binary, symbols_dir.as_ref()) {
+            Some(file) => file,
+            None => continue,
+        };
+        symbols.extend(atos(image, &file, &image_addresses)?);
+    }
+
+    if !set_ips_symbols(&mut body, &frames, &symbols) {
+        return Ok(false);
+    }
+    write_if_changed(report, content, Some(format!("{}\n{}\n", header.dump(), body.pretty(2))))
+}
+
+/// Symbolicate the native backtraces of an Android tombstone or crash log,
+/// resolving the frames of the given local `binaries` by file name. Returns
+/// whether any frame was resolved.
+pub fn symbolicate_android_report(report: &Path, binaries: &[PathBuf]) -> Result<bool> {
+    let addr2line = match ::which::which("llvm-addr2line").or_else(|_| ::which::which("addr2line")) {
+        Ok(addr2line) => addr2line,
+        Err(_) => {
+            debug!("Neither llvm-addr2line nor addr2line found, {} left as is", report.display());
+            return Ok(false);
+        }
+    };
+    let content = read_report(report)?;
+
+    let frame_regex = android_frame_regex()?;
+    let mut addresses: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    for line in content.lines() {
+        if let Some(captures) = frame_regex.captures(line) {
+            let remote_name = Path::new(&captures[3]).file_name().map(|it| it.to_os_string());
+            if let Some(binary) = binaries.iter().find(|it| it.file_name().map(|it| it.to_os_string()) == remote_name) {
+                addresses.entry(binary.clone()).or_insert(vec![]).push(format!("0x{}", &captures[2]));
+            }
+        }
+    }
+
+    let mut symbols: HashMap<(PathBuf, String), String> = HashMap::new();
+    for (binary, binary_addresses) in addresses {
+        let output = Command::new(&addr2line)
+            .arg("-C")
+            .arg("-f")
+            .arg("-e").arg(&binary)
+            .args(&binary_addresses)
+            .output()?;
+        if !output.status.success() {
+            debug!("addr2line failed on {}: {}", binary.display(), String::from_utf8_lossy(&output.stderr));
+            continue;
+        }
+        let output = String::from_utf8(output.stdout)?;
+        let lines = output.lines().collect::<Vec<_>>();
+        // Two lines per address: the function, then its location.
+        for (address, resolved) in binary_addresses.iter().zip(lines.chunks(2)) {
+            if resolved.len() == 2 && resolved[0] != "??" {
+                symbols.insert((binary.clone(), address.clone()),
+                               format!("{} at {}", resolved[0], resolved[1]));
+            }
+        }
+    }
+
+    let rewritten = rewrite_lines(&content, |line| {
+        frame_regex.captures(line).and_then(|captures| {
+            let remote_name = Path::new(&captures[3]).file_name().map(|it| it.to_os_string());
+            binaries.iter()
+                .find(|it| it.file_name().map(|it| it.to_os_string()) == remote_name)
+                .and_then(|binary| symbols.get(&(binary.clone(), format!("0x{}", &captures[2]))))
+                .map(|symbol| format!("{}{} {} ({})", &captures[1], &captures[2], &captures[3], symbol))
+        })
+    });
+    write_if_changed(report, &content, rewritten)
+}
+
+struct IosImage {
+    load_address: String,
+    arch: String,
+    path: String,
+}
+
+/// The local file holding the symbols of an image: the freshly built binary
+/// for the Dinghy app, the DeviceSupport cache for system images.
+fn ios_image_file(name: &str, image: &IosImage, binary: &Path, symbols_dir: Option<&PathBuf>) -> Option<PathBuf> {
+    let file = if name == "Dinghy" {
+        binary.to_path_buf()
+    } else {
+        symbols_dir?.join(image.path.trim_left_matches('/'))
+    };
+    if file.exists() { Some(file) } else { None }
+}
+
+/// Resolve `addresses` of `image` with atos, by address.
+fn atos(image: &IosImage, file: &Path, addresses: &[String]) -> Result<HashMap<String, String>> {
+    let mut symbols = HashMap::new();
+    let output = Command::new("atos")
+        .arg("-arch").arg(&image.arch)
+        .arg("-o").arg(file)
+        .arg("-l").arg(&image.load_address)
+        .args(addresses)
+        .output()?;
+    if !output.status.success() {
+        debug!("atos failed on {}: {}", file.display(), String::from_utf8_lossy(&output.stderr));
+        return Ok(symbols);
+    }
+    for (address, symbol) in addresses.iter().zip(String::from_utf8(output.stdout)?.lines()) {
+        // atos echoes back the addresses it couldn't resolve.
+        if !symbol.starts_with("0x") {
+            symbols.insert(address.clone(), symbol.to_string());
+        }
+    }
+    Ok(symbols)
+}
+
+/// Split a JSON `.ips` report in its header and body, or `None` for a text report.
+fn parse_ips_report(content: &str) -> Option<(JsonValue, JsonValue)> {
+    let split = content.find('\n')?;
+    let header = json::parse(&content[..split]).ok()?;
+    let body = json::parse(&content[split + 1..]).ok()?;
+    if header.is_object() && body.is_object() { Some((header, body)) } else { None }
+}
+
+/// Images of a `.ips` body, by index, named after their file.
+fn parse_ips_images(body: &JsonValue) -> Vec<(String, IosImage)> {
+    body["usedImages"].members().map(|image| {
+        let path = image["path"].as_str().unwrap_or("").to_string();
+        let name = image["name"].as_str()
+            .map(|it| it.to_string())
+            .unwrap_or_else(|| path.rsplit('/').next().unwrap_or("").to_string());
+        (name, IosImage {
+            load_address: format!("0x{:x}", image["base"].as_u64().unwrap_or(0)),
+            arch: image["arch"].as_str().unwrap_or("arm64").to_string(),
+            path: path,
+        })
+    }).collect()
+}
+
+/// The unresolved frames of a `.ips` body, as thread index, frame index, image
+/// index and address.
+fn parse_ips_frames(body: &JsonValue, images: &[(String, IosImage)]) -> Vec<(usize, usize, usize, String)> {
+    let mut frames = vec![];
+    for (thread_index, thread) in body["threads"].members().enumerate() {
+        for (frame_index, frame) in thread["frames"].members().enumerate() {
+            if !frame["symbol"].is_null() {
+                continue;
+            }
+            let (image_index, offset) = match (frame["imageIndex"].as_usize(), frame["imageOffset"].as_u64()) {
+                (Some(image_index), Some(offset)) if image_index < images.len() => (image_index, offset),
+                _ => continue,
+            };
+            let base = u64::from_str_radix(images[image_index].1.load_address.trim_left_matches("0x"), 16).unwrap_or(0);
+            frames.push((thread_index, frame_index, image_index, format!("0x{:x}", base + offset)));
+        }
+    }
+    frames
+}
+
+/// Set the `symbol` of the resolved `frames`. Returns whether any frame was resolved.
+fn set_ips_symbols(body: &mut JsonValue, frames: &[(usize, usize, usize, String)], symbols: &HashMap<String, String>) -> bool {
+    let mut changed = false;
+    for &(thread_index, frame_index, _, ref address) in frames {
+        if let Some(symbol) = symbols.get(address) {
+            body["threads"][thread_index]["frames"][frame_index]["symbol"] = symbol.as_str().into();
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Version and build of the OS, from the `osVersion` of a `.ips` body.
+fn parse_ips_os_version(body: &JsonValue) -> Option<(String, String)> {
+    let version = body["osVersion"]["train"].as_str()?.split(' ').last()?;
+    let build = body["osVersion"]["build"].as_str()?;
+    Some((version.to_string(), build.to_string()))
+}
+
+fn ios_frame_regex() -> Result<Regex> {
+    // 3   Dinghy    0x0000000100a1b2c3 0x100a14000 + 29379
+    Ok(Regex::new(r"^(\d+\s+(\S+)\s+(0x[0-9a-fA-F]+)\s+)0x[0-9a-fA-F]+ \+ \d+\s*$")?)
+}
+
+fn android_frame_regex() -> Result<Regex> {
+    //     #00 pc 000000000001c3b8  /data/local/tmp/dinghy/foo/foo (...)
+    Ok(Regex::new(r"^(.*#\d+\s+pc\s+)([0-9a-fA-F]+)\s+(/\S+)")?)
+}
+
+fn parse_ios_images(content: &str) -> Result<HashMap<String, IosImage>> {
+    // 0x100a14000 - 0x100a1ffff Dinghy arm64  <0123abcd...> /var/containers/Bundle/.../Dinghy.app/Dinghy
+    let image_regex = Regex::new(
+        r"^\s*(0x[0-9a-fA-F]+)\s+-\s+0x[0-9a-fA-F]+\s+\+?(\S+)\s+(\S+)\s+<[0-9a-fA-F-]+>\s+(.+)$",
+    )?;
+    let mut images = HashMap::new();
+    for line in content.lines().skip_while(|it| !it.starts_with("Binary Images:")) {
+        if let Some(captures) = image_regex.captures(line) {
+            images.insert(captures[2].to_string(), IosImage {
+                load_address: captures[1].to_string(),
+                arch: captures[3].to_string(),
+                path: captures[4].trim().to_string(),
+            });
+        }
+    }
+    Ok(images)
+}
+
+/// Version and build of the OS, from the `OS Version: iPhone OS 11.2 (15C114)` header.
+fn parse_ios_os_version(content: &str) -> Option<(String, String)> {
+    let header = content.lines().find(|it| it.starts_with("OS Version:"))?;
+    let open = header.rfind('(')?;
+    let close = header.rfind(')')?;
+    let version = header[..open].trim().split(' ').last()?;
+    Some((version.to_string(), header[open + 1..close].to_string()))
+}
+
+/// The symbol cache Xcode fills when a device is first used for development.
+fn ios_symbols_dir(version: &str, build: &str) -> Option<PathBuf> {
+    let device_support = ::std::env::home_dir()?.join("Library/Developer/Xcode/iOS DeviceSupport");
+    let suffix = format!("{} ({})", version, build);
+    fs::read_dir(device_support).ok()?
+        .filter_map(|it| it.ok())
+        .find(|it| it.file_name().to_str().map(|it| it.ends_with(&suffix)).unwrap_or(false))
+        .map(|it| it.path().join("Symbols"))
+}
+
+fn read_report(report: &Path) -> Result<String> {
+    let mut content = vec![];
+    fs::File::open(report)?.read_to_end(&mut content)?;
+    Ok(String::from_utf8_lossy(&content).into_owned())
+}
+
+fn rewrite_lines<F: Fn(&str) -> Option<String>>(content: &str, rewrite: F) -> Option<String> {
+    let mut changed = false;
+    let mut rewritten = String::with_capacity(content.len());
+    for line in content.lines() {
+        match rewrite(line) {
+            Some(line) => {
+                rewritten.push_str(&line);
+                changed = true;
+            }
+            None => rewritten.push_str(line),
+        }
+        rewritten.push('\n');
+    }
+    if changed { Some(rewritten) } else { None }
+}
+
+fn write_if_changed(report: &Path, original: &str, rewritten: Option<String>) -> Result<bool> {
+    match rewritten {
+        Some(ref rewritten) if rewritten != original => {
+            fs::File::create(report)?.write_all(rewritten.as_bytes())?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ios_report_headers() {
+        let report = "\
+OS Version:          iPhone OS 11.2.1 (15C153)
+
+Binary Images:
+0x100a14000 - 0x100a1ffff Dinghy arm64  <0123456789abcdef0123456789abcdef> /var/containers/Bundle/Application/X/Dinghy.app/Dinghy
+0x1817d0000 - 0x1817f3fff libsystem_kernel.dylib arm64  <fedcba9876543210fedcba9876543210> /usr/lib/system/libsystem_kernel.dylib
+";
+        assert_eq!(parse_ios_os_version(report), Some(("11.2.1".to_string(), "15C153".to_string())));
+        let images = parse_ios_images(report).unwrap();
+        assert_eq!(images["Dinghy"].load_address, "0x100a14000");
+        assert_eq!(images["libsystem_kernel.dylib"].path, "/usr/lib/system/libsystem_kernel.dylib");
+
+        let frame = ios_frame_regex().unwrap();
+        let captures = frame.captures("3   Dinghy     \t0x0000000100a1b2c3 0x100a14000 + 29379").unwrap();
+        assert_eq!(&captures[2], "Dinghy");
+        assert_eq!(&captures[3], "0x0000000100a1b2c3");
+    }
+
+    #[test]
+    fn parse_ips_report_frames() {
+        let report = r#"{"app_name":"Dinghy","bug_type":"309","os_version":"iPhone OS 15.4 (19E241)"}
+{
+  "osVersion" : {"train" : "iPhone OS 15.4", "build" : "19E241"},
+  "usedImages" : [
+    {"base" : 4305534976, "arch" : "arm64", "path" : "/private/var/containers/Bundle/Application/X/Dinghy.app/Dinghy", "name" : "Dinghy"},
+    {"base" : 6465339392, "arch" : "arm64e", "path" : "/usr/lib/system/libsystem_kernel.dylib", "name" : "libsystem_kernel.dylib"}
+  ],
+  "threads" : [{"triggered" : true, "frames" : [
+    {"imageOffset" : 29379, "imageIndex" : 0},
+    {"imageOffset" : 4116, "symbol" : "__pthread_kill", "imageIndex" : 1}
+  ]}]
+}
+"#;
+        let (header, mut body) = parse_ips_report(report).unwrap();
+        assert_eq!(header["bug_type"], "309");
+        assert_eq!(parse_ips_os_version(&body), Some(("15.4".to_string(), "19E241".to_string())));
+        let images = parse_ips_images(&body);
+        assert_eq!(images[0].0, "Dinghy");
+        assert_eq!(images[0].1.load_address, "0x100a14000");
+        assert_eq!(images[1].1.arch, "arm64e");
+
+        let frames = parse_ips_frames(&body, &images);
+        assert_eq!(frames, vec![(0, 0, 0, "0x100a1b2c3".to_string())]);
+        let mut symbols = HashMap::new();
+        symbols.insert("0x100a1b2c3".to_string(), "main (in Dinghy) (main.rs:3)".to_string());
+        assert!(set_ips_symbols(&mut body, &frames, &symbols));
+        assert_eq!(body["threads"][0]["frames"][0]["symbol"], "main (in Dinghy) (main.rs:3)");
+
+        assert!(parse_ips_report("OS Version:          iPhone OS 11.2.1 (15C153)\n").is_none());
+    }
+}
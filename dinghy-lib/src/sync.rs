@@ -0,0 +1,148 @@
+//! Incremental transfer of bundles to devices.
+//!
+//! A manifest lists the size and modification time of each file of a bundle.
+//! The manifest of the last transfer is kept on the device, next to the
+//! bundle: comparing it with the manifest of the new bundle tells which files
+//! have to be pushed and which ones have to be removed. Bundling preserves the
+//! modification times of the copied files, so unchanged test data keeps the
+//! same manifest entries from one run to the next.
+
+use errors::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::time::UNIX_EPOCH;
+use walkdir::WalkDir;
+
+/// Name of the manifest file, at the root of the synced directory.
+pub static MANIFEST_FILE: &str = ".dinghy-manifest";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Manifest {
+    /// Size, modification seconds and nanoseconds, by `/` separated relative path.
+    files: BTreeMap<String, (u64, u64, u32)>,
+}
+
+/// What has to be done on the device to turn one manifest into another.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SyncPlan {
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl Manifest {
+    /// Manifest of the files of `dir`, the manifest file itself excluded.
+    pub fn scan<P: AsRef<Path>>(dir: P) -> Result<Manifest> {
+        let dir = dir.as_ref();
+        let mut files = BTreeMap::new();
+        for entry in WalkDir::new(dir) {
+            let entry = entry.chain_err(|| format!("Couldn't walk {}", dir.display()))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let path = entry.path().strip_prefix(dir)?;
+            let path = path.components()
+                .map(|it| it.as_os_str().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join("/");
+            if path == MANIFEST_FILE {
+                continue;
+            }
+            let metadata = entry.metadata().chain_err(|| format!("Couldn't stat {}", entry.path().display()))?;
+            let mtime = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+            files.insert(path, (metadata.len(), mtime.as_secs(), mtime.subsec_nanos()));
+        }
+        Ok(Manifest { files })
+    }
+
+    /// Parse a manifest, as written by `to_string`. Malformed lines are ignored,
+    /// the corresponding files are then simply pushed again.
+    pub fn parse(content: &str) -> Manifest {
+        let mut files = BTreeMap::new();
+        for line in content.lines() {
+            let mut fields = line.splitn(4, ' ');
+            let entry = (fields.next().and_then(|it| it.parse().ok()),
+                         fields.next().and_then(|it| it.parse().ok()),
+                         fields.next().and_then(|it| it.parse().ok()),
+                         fields.next());
+            if let (Some(size), Some(secs), Some(nanos), Some(path)) = entry {
+                files.insert(path.to_string(), (size, secs, nanos));
+            }
+        }
+        Manifest { files }
+    }
+
+    pub fn to_string(&self) -> String {
+        self.files.iter()
+            .map(|(path, &(size, secs, nanos))| format!("{} {} {} {}\n", size, secs, nanos, path))
+            .collect()
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::File::create(path.as_ref())?.write_all(self.to_string().as_bytes())?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Files to push and to remove to go from the `installed` manifest to this one.
+    pub fn plan_from(&self, installed: &Manifest) -> SyncPlan {
+        SyncPlan {
+            changed: self.files.iter()
+                .filter(|&(path, entry)| installed.files.get(path) != Some(entry))
+                .map(|(path, _)| path.clone())
+                .collect(),
+            removed: installed.files.keys()
+                .filter(|path| !self.files.contains_key(*path))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+impl SyncPlan {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Pack the `files` of `dir` (relative paths, directories recursively) into
+/// the gzipped tarball `archive`, to send them to a device in one go.
+pub fn tarball<P: AsRef<Path>>(dir: &Path, files: &[String], archive: P) -> Result<()> {
+    let archive = archive.as_ref();
+    let list = archive.with_extension("list");
+    let mut list_file = fs::File::create(&list)?;
+    for file in files {
+        writeln!(list_file, "{}", file)?;
+    }
+    let status = Command::new("tar")
+        .arg("czf").arg(archive)
+        .arg("-C").arg(dir)
+        .arg("-T").arg(&list)
+        .status()?;
+    if !status.success() {
+        bail!("Couldn't pack {} into {}", dir.display(), archive.display())
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plan_between_manifests() {
+        let installed = Manifest::parse("3 10 0 a\n4 10 0 data/b c\n5 10 0 stale\nnot a manifest line\n");
+        let bundle = Manifest::parse("3 10 0 a\n4 12 0 data/b c\n6 10 0 new\n");
+        assert_eq!(Manifest::parse(&bundle.to_string()), bundle);
+        assert_eq!(bundle.plan_from(&installed), SyncPlan {
+            changed: vec!["data/b c".to_string(), "new".to_string()],
+            removed: vec!["stale".to_string()],
+        });
+        assert!(bundle.plan_from(&bundle).is_empty());
+    }
+}
@@ -0,0 +1,173 @@
+//! Where the time of a command goes: compiling, bundling, transferring to the
+//! device and running there.
+//!
+//! Phases nest (the transfer of a bundle starts with bundling it, a run with
+//! installing): the time spent in a nested phase only counts for it, not for
+//! the enclosing one.
+
+use json::JsonValue;
+use launcher::CacheStats;
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Compile,
+    Bundle,
+    Transfer,
+    Run,
+}
+
+/// The time spent in each phase, and the size of the bundles transferred.
+#[derive(Clone, Debug, Default)]
+pub struct Timings {
+    pub compile: Duration,
+    pub bundle: Duration,
+    pub transfer: Duration,
+    pub transferred_bytes: u64,
+    pub run: Duration,
+    /// Hits and misses of the compiler launcher during the command, if any.
+    pub compiler_cache: Option<CacheStats>,
+}
+
+lazy_static! {
+    static ref TOTALS: Mutex<Timings> = Mutex::new(Timings::default());
+}
+
+thread_local! {
+    static PHASES: RefCell<Vec<(Phase, Instant)>> = RefCell::new(vec![]);
+}
+
+/// A phase, ended when dropped.
+pub struct PhaseTimer(());
+
+/// Starts timing `phase`, pausing the phase it is nested in.
+pub fn start(phase: Phase) -> PhaseTimer {
+    PHASES.with(|phases| {
+        let mut phases = phases.borrow_mut();
+        let now = Instant::now();
+        if let Some(&mut (outer, ref mut since)) = phases.last_mut() {
+            add(outer, now.duration_since(*since));
+        }
+        phases.push((phase, now));
+    });
+    PhaseTimer(())
+}
+
+impl Drop for PhaseTimer {
+    fn drop(&mut self) {
+        PHASES.with(|phases| {
+            let mut phases = phases.borrow_mut();
+            let now = Instant::now();
+            if let Some((phase, since)) = phases.pop() {
+                add(phase, now.duration_since(since));
+            }
+            if let Some(&mut (_, ref mut since)) = phases.last_mut() {
+                *since = now;
+            }
+        });
+    }
+}
+
+fn add(phase: Phase, duration: Duration) {
+    if let Ok(mut totals) = TOTALS.lock() {
+        match phase {
+            Phase::Compile => totals.compile += duration,
+            Phase::Bundle => totals.bundle += duration,
+            Phase::Transfer => totals.transfer += duration,
+            Phase::Run => totals.run += duration,
+        }
+    }
+}
+
+/// Counts `bytes` sent to a device.
+pub fn transferred(bytes: u64) {
+    if let Ok(mut totals) = TOTALS.lock() {
+        totals.transferred_bytes += bytes;
+    }
+}
+
+/// The timings so far, starting over from zero.
+pub fn take() -> Timings {
+    TOTALS.lock().map(|mut totals| ::std::mem::replace(&mut *totals, Timings::default())).unwrap_or_default()
+}
+
+/// `duration` in seconds, as shown and reported.
+pub fn secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+impl Timings {
+    pub fn is_empty(&self) -> bool {
+        self.compile + self.bundle + self.transfer + self.run == Duration::from_secs(0)
+    }
+
+    /// The transfer rate, in MB/s, if anything was transferred.
+    pub fn transfer_rate(&self) -> Option<f64> {
+        if self.transferred_bytes == 0 || self.transfer == Duration::from_secs(0) {
+            None
+        } else {
+            Some(self.transferred_bytes as f64 / 1e6 / secs(self.transfer))
+        }
+    }
+
+    /// The timings as a message like the JSON messages of cargo, durations
+    /// being in seconds.
+    pub fn to_json(&self) -> JsonValue {
+        let mut json = JsonValue::new_object();
+        json["reason"] = "dinghy-timings".into();
+        json["compile"] = secs(self.compile).into();
+        json["bundle"] = secs(self.bundle).into();
+        json["transfer"] = secs(self.transfer).into();
+        json["transferred_bytes"] = self.transferred_bytes.into();
+        json["run"] = secs(self.run).into();
+        if let Some(ref cache) = self.compiler_cache {
+            json["compiler_cache"]["launcher"] = cache.launcher.as_str().into();
+            json["compiler_cache"]["hits"] = cache.hits.into();
+            json["compiler_cache"]["misses"] = cache.misses.into();
+        }
+        json
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "compile {:.1}s, bundle {:.1}s, transfer {:.1}s", secs(self.compile), secs(self.bundle), secs(self.transfer))?;
+        if let Some(rate) = self.transfer_rate() {
+            write!(fmt, " at {:.1}MB/s", rate)?;
+        }
+        write!(fmt, ", run {:.1}s", secs(self.run))?;
+        if let Some(ref cache) = self.compiler_cache {
+            write!(fmt, ", {}", cache)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let timings = Timings {
+            compile: Duration::from_millis(12_340),
+            bundle: Duration::from_millis(400),
+            transfer: Duration::from_secs(2),
+            transferred_bytes: 5_000_000,
+            run: Duration::from_millis(8_000),
+            compiler_cache: None,
+        };
+        assert_eq!(timings.to_string(), "compile 12.3s, bundle 0.4s, transfer 2.0s at 2.5MB/s, run 8.0s");
+        assert_eq!(timings.to_json()["transferred_bytes"], 5_000_000);
+        let idle = Timings { transferred_bytes: 0, ..timings };
+        assert_eq!(idle.to_string(), "compile 12.3s, bundle 0.4s, transfer 2.0s, run 8.0s");
+        let cached = Timings { compiler_cache: Some(CacheStats { launcher: "sccache".into(), hits: 8, misses: 3 }), ..idle.clone() };
+        assert_eq!(cached.to_string(), "compile 12.3s, bundle 0.4s, transfer 2.0s, run 8.0s, sccache 8 hits, 3 misses");
+        assert_eq!(cached.to_json()["compiler_cache"]["hits"], 8);
+        assert!(Timings::default().is_empty());
+    }
+}
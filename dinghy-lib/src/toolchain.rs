@@ -6,6 +6,7 @@ use dinghy_build::build_env::set_env;
 use dinghy_build::build_env::set_target_env;
 use errors::*;
 use itertools::Itertools;
+use launcher;
 use std::{env, fs, path};
 use std::io::Write;
 #[cfg(unix)]
@@ -24,20 +25,38 @@ pub struct Toolchain {
 }
 
 impl Toolchain {
+    /// Export a tool the way cc-rs looks it up: `TARGET_<VAR>`, `<VAR>_<triple>`
+    /// and `<VAR>_<triple_with_underscores>`. Compilers go through the compiler
+    /// launcher, if any.
     pub fn setup_tool(&self, var: &str, exe: &str) -> Result<()> {
-        set_env(format!("TARGET_{}", var), exe);
-        set_env(format!("{}_{}", var, self.rustc_triple), exe);
+        self.export_tool(var, &launcher::wrap_tool(var, exe));
         Ok(())
     }
 
-    pub fn setup_cc(&self, _id: &str, compiler_command: &str) -> Result<()> {
-        set_env("TARGET_CC", compiler_command);
-        set_env(format!("CC_{}", self.rustc_triple), compiler_command);
+    fn export_tool(&self, var: &str, command: &str) {
+        set_env(format!("TARGET_{}", var), command);
+        set_env(format!("{}_{}", var, self.rustc_triple), command);
+        set_env(format!("{}_{}", var, self.rustc_triple.replace("-", "_")), command);
+    }
+
+    /// Same as `setup_tool`, but for commands with arguments, that build
+    /// scripts would not be able to run as is: they are wrapped in a shim.
+    pub fn setup_shimmed_tool(&self, id: &str, var: &str, command: &str) -> Result<()> {
+        let shim = create_shim(project_root()?,
+                               &self.rustc_triple,
+                               id,
+                               &var.to_lowercase(),
+                               format!("{} {}", launcher::wrap_tool(var, command), GLOB_ARGS).as_str())?;
+        self.export_tool(var, &shim.to_string_lossy());
         Ok(())
     }
 
+    pub fn setup_cc(&self, _id: &str, compiler_command: &str) -> Result<()> {
+        self.setup_tool("CC", compiler_command)
+    }
+
     pub fn setup_linker(&self, id: &str, linker_command: &str) -> Result<()> {
-        let shim = create_shim(project_root()?, &self.rustc_triple, id, "linker", format!("{} {}", linker_command, GLOB_ARGS).as_str())?;
+        let shim = create_shim(project_root()?, &self.rustc_triple, id, "linker", format!("{} {}", launcher::wrap(linker_command), GLOB_ARGS).as_str())?;
         set_env(format!("CARGO_TARGET_{}_LINKER", envify(self.rustc_triple.as_str())).as_str(), shim);
         Ok(())
     }
@@ -47,6 +66,11 @@ impl Toolchain {
         set_target_env("PKG_CONFIG_LIBPATH", Some(&self.rustc_triple), "");
         Ok(())
     }
+
+    pub fn setup_sysroot<P: AsRef<path::Path>>(&self, sysroot: P) {
+        set_env("TARGET_SYSROOT", sysroot.as_ref());
+        set_target_env("PKG_CONFIG_SYSROOT_DIR", Some(&self.rustc_triple), sysroot.as_ref());
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -108,15 +132,22 @@ impl ToolchainConfig {
             let exe_file_name = exe.file_name();
             let exe_path = exe.path();
             let exe_path = exe_path.to_string_lossy(); // Rust and paths = 💩💩💩
+            if !exe_file_name.to_string_lossy().contains(self.toolchain_triple.as_str()) {
+                // An unprefixed `cc` or `ar` in the PATH would be picked by the build scripts,
+                // which run on the host.
+                debug!("Not shimming {}, it would shadow the tool of the host", exe_path);
+                continue;
+            }
 
             let rustified_exe = &exe_file_name.to_string_lossy().replace(self.toolchain_triple.as_str(),
                                                                          self.rustc_triple.as_str());
-            trace!("Shim {} -> {}", exe_path, rustified_exe);
+            let command = if launcher::is_compiler(rustified_exe) { launcher::wrap(&exe_path) } else { exe_path.to_string() };
+            trace!("Shim {} -> {}", command, rustified_exe);
             create_shim(root,
                         self.rustc_triple.as_str(),
                         id,
                         rustified_exe,
-                        &format!("{} {}", exe_path, GLOB_ARGS))?;
+                        &format!("{} {}", command, GLOB_ARGS))?;
         }
         append_path_to_env("PATH", shims_path.to_string_lossy().as_ref());
         Ok(())
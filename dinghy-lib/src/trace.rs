@@ -0,0 +1,301 @@
+//! Recording of the operations dinghy makes on devices, to debug dinghy itself
+//! and to replay them without hardware (see `device::replay`).
+//!
+//! While recording (`--record <path>`), every call made on a device is appended
+//! to the trace as a JSON line, along with the lower level operations the
+//! backends report: properties read, services started, files pushed, commands
+//! run.
+
+use errors::*;
+use forward::PortForward;
+use health::DeviceHealth;
+use json;
+use json::JsonValue;
+use project::Project;
+use report::RunResult;
+use std::fmt;
+use std::fs;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::result;
+use std::sync::Mutex;
+use Build;
+use BuildBundle;
+use Device;
+use DeviceCompatibility;
+use RunArgs;
+use platform::custom_platform::CustomPlatform;
+use platform::host::HostPlatform;
+#[cfg(target_os = "macos")]
+use platform::ios::IosPlatform;
+use platform::regular_platform::RegularPlatform;
+use platform::wasi::WasiPlatform;
+use Platform;
+
+lazy_static! {
+    static ref RECORDER: Mutex<Option<fs::File>> = Mutex::new(None);
+}
+
+/// Operation recorded when a device is found, with its description.
+pub static DISCOVER: &str = "discover";
+
+/// One operation on a device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub device: String,
+    pub operation: String,
+    /// What the operation was about (property key, pushed path, command...).
+    pub detail: String,
+    /// `{"ok": <value>}` or `{"error": <message>}`.
+    pub result: JsonValue,
+}
+
+impl Event {
+    /// The recorded value, or the recorded error.
+    pub fn value(&self) -> Result<&JsonValue> {
+        if let Some(error) = self.result["error"].as_str() {
+            bail!("{}", error)
+        }
+        Ok(&self.result["ok"])
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut event = JsonValue::new_object();
+        event["device"] = self.device.as_str().into();
+        event["operation"] = self.operation.as_str().into();
+        event["detail"] = self.detail.as_str().into();
+        event["result"] = self.result.clone();
+        event
+    }
+
+    pub fn from_json(json: &JsonValue) -> Option<Event> {
+        Some(Event {
+            device: json["device"].as_str()?.to_string(),
+            operation: json["operation"].as_str()?.to_string(),
+            detail: json["detail"].as_str().unwrap_or("").to_string(),
+            result: json["result"].clone(),
+        })
+    }
+}
+
+/// Append the operations of this run to the trace at `path`.
+pub fn start_recording<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    let file = fs::OpenOptions::new().create(true).append(true).open(path)
+        .chain_err(|| format!("Couldn't open trace {}", path.display()))?;
+    *RECORDER.lock().map_err(|_| "Trace recorder poisoned")? = Some(file);
+    Ok(())
+}
+
+pub fn is_recording() -> bool {
+    RECORDER.lock().map(|it| it.is_some()).unwrap_or(false)
+}
+
+/// Record an operation, if recording.
+pub fn record<T, E, F>(device: &str, operation: &str, detail: &str, result: &result::Result<T, E>, to_json: F)
+    where E: fmt::Display, F: FnOnce(&T) -> JsonValue {
+    let mut recorder = match RECORDER.lock() {
+        Ok(recorder) => recorder,
+        Err(_) => return,
+    };
+    if let Some(ref mut file) = *recorder {
+        let mut json_result = JsonValue::new_object();
+        match *result {
+            Ok(ref value) => json_result["ok"] = to_json(value),
+            Err(ref e) => json_result["error"] = e.to_string().into(),
+        }
+        let event = Event {
+            device: device.to_string(),
+            operation: operation.to_string(),
+            detail: detail.to_string(),
+            result: json_result,
+        };
+        if let Err(e) = writeln!(file, "{}", event.to_json().dump()) {
+            warn!("Couldn't record {} on {}: {}", operation, device, e);
+        }
+    }
+}
+
+/// Read the events of a trace.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Event>> {
+    let path = path.as_ref();
+    let file = fs::File::open(path).chain_err(|| format!("Couldn't open trace {}", path.display()))?;
+    let mut events = vec![];
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        events.push(Event::from_json(&json::parse(&line)?)
+            .ok_or(format!("Invalid event in trace {}: {}", path.display(), line))?);
+    }
+    Ok(events)
+}
+
+pub fn health_to_json(health: &DeviceHealth) -> JsonValue {
+    let mut json = JsonValue::new_object();
+    if let Some(level) = health.battery_level {
+        json["battery_level"] = level.into();
+    }
+    if let Some(charging) = health.charging {
+        json["charging"] = charging.into();
+    }
+    if let Some(thermal) = health.thermal {
+        json["thermal"] = thermal.to_string().into();
+    }
+    json
+}
+
+pub fn health_from_json(json: &JsonValue) -> DeviceHealth {
+    DeviceHealth {
+        battery_level: json["battery_level"].as_u8(),
+        charging: json["charging"].as_bool(),
+        thermal: json["thermal"].as_str().and_then(|it| it.parse().ok()),
+    }
+}
+
+/// A device whose operations are recorded.
+pub struct RecordingDevice {
+    inner: Box<Device>,
+}
+
+impl RecordingDevice {
+    pub fn new(inner: Box<Device>) -> RecordingDevice {
+        let description: Result<()> = Ok(());
+        record(inner.id(), DISCOVER, &inner.to_string(), &description, |_| {
+            let mut json = JsonValue::new_object();
+            json["name"] = inner.name().into();
+            if let Some(failure) = inner.failure() {
+                json["failure"] = failure.into();
+            }
+            json["rustc_triples"] = inner.rustc_triples().into();
+            json
+        });
+        RecordingDevice { inner }
+    }
+
+    fn record<T, F>(&self, operation: &str, detail: &str, result: Result<T>, to_json: F) -> Result<T>
+        where F: FnOnce(&T) -> JsonValue {
+        record(self.inner.id(), operation, detail, &result, to_json);
+        result
+    }
+}
+
+impl fmt::Display for RecordingDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.inner)
+    }
+}
+
+impl DeviceCompatibility for RecordingDevice {
+    fn is_compatible_with_custom_platform(&self, platform: &CustomPlatform) -> bool {
+        self.inner.is_compatible_with_custom_platform(platform)
+    }
+
+    fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
+        self.inner.is_compatible_with_regular_platform(platform)
+    }
+
+    fn is_compatible_with_host_platform(&self, platform: &HostPlatform) -> bool {
+        self.inner.is_compatible_with_host_platform(platform)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn is_compatible_with_ios_platform(&self, platform: &IosPlatform) -> bool {
+        self.inner.is_compatible_with_ios_platform(platform)
+    }
+
+    fn is_compatible_with_wasi_platform(&self, platform: &WasiPlatform) -> bool {
+        self.inner.is_compatible_with_wasi_platform(platform)
+    }
+
+    fn is_compatible_with_plugin_platform(&self, platform: &Platform) -> bool {
+        self.inner.is_compatible_with_plugin_platform(platform)
+    }
+}
+
+impl Device for RecordingDevice {
+    fn archive_app(&self, project: &Project, build: &Build) -> Result<Vec<PathBuf>> {
+        self.record("archive_app", "", self.inner.archive_app(project, build),
+                    |archives| archives.iter().map(|it| it.display().to_string()).collect::<Vec<_>>().into())
+    }
+
+    fn clean_app(&self, build_bundle: &BuildBundle) -> Result<()> {
+        self.record("clean_app", &build_bundle.id, self.inner.clean_app(build_bundle), |_| JsonValue::Null)
+    }
+
+    fn debug_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<BuildBundle> {
+        self.record("debug_app", "", self.inner.debug_app(project, build, run_args), |it| it.id.as_str().into())
+    }
+
+    fn dry_run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<String>> {
+        self.inner.dry_run_app(project, build, run_args)
+    }
+
+    fn failure(&self) -> Option<&str> {
+        self.inner.failure()
+    }
+
+    fn forward_port(&self, local: u16, remote: u16) -> Result<PortForward> {
+        self.record("forward_port", &format!("{}:{}", local, remote), self.inner.forward_port(local, remote),
+                    |_| JsonValue::Null)
+    }
+
+    fn health(&self) -> Result<DeviceHealth> {
+        self.record("health", "", self.inner.health(), health_to_json)
+    }
+
+    fn id(&self) -> &str {
+        self.inner.id()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn profile_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<(BuildBundle, PathBuf)> {
+        self.record("profile_app", "", self.inner.profile_app(project, build, run_args),
+                    |&(_, ref trace)| trace.display().to_string().into())
+    }
+
+    fn reverse_port(&self, local: u16, remote: u16) -> Result<(PortForward, String)> {
+        self.record("reverse_port", &format!("{}:{}", local, remote), self.inner.reverse_port(local, remote),
+                    |&(_, ref address)| address.as_str().into())
+    }
+
+    fn rustc_triples(&self) -> Vec<String> {
+        self.inner.rustc_triples()
+    }
+
+    fn run_app(&self, project: &Project, build: &Build, run_args: &RunArgs) -> Result<Vec<RunResult>> {
+        self.record("run_app", &run_args.args.join(" "), self.inner.run_app(project, build, run_args),
+                    |results| results.iter().map(|it| it.report.to_json()).collect::<Vec<_>>().into())
+    }
+
+    fn start_remote_lldb(&self) -> Result<String> {
+        self.record("start_remote_lldb", "", self.inner.start_remote_lldb(), |it| it.as_str().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_round_trip() {
+        let mut result = JsonValue::new_object();
+        result["ok"] = health_to_json(&DeviceHealth { battery_level: Some(80), ..DeviceHealth::default() });
+        let event = Event {
+            device: "emulator-5554".to_string(),
+            operation: "health".to_string(),
+            detail: String::new(),
+            result,
+        };
+        let parsed = Event::from_json(&json::parse(&event.to_json().dump()).unwrap()).unwrap();
+        assert_eq!(parsed, event);
+        assert_eq!(health_from_json(parsed.value().unwrap()).battery_level, Some(80));
+    }
+}
@@ -2,9 +2,26 @@ use clap::ArgMatches;
 use errors::Result;
 use filetime::FileTime;
 use filetime::set_file_times;
+use output;
+use output::OutputSink;
+use output::Stream;
+use report::TestReportParser;
 use std::fs;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
 use std::path::Path;
 use std::path::PathBuf;
+use std::process::Child;
+use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 
 pub fn arg_as_string_vec(matches: &ArgMatches, option: &str) -> Vec<String> {
     matches.values_of(option)
@@ -99,3 +116,149 @@ pub fn file_name_as_str(file_path: &Path) -> Result<&str> {
         .and_then(|it| it.to_str())
         .ok_or(format!("'{}' is not a valid file name", file_path.display()))?)
 }
+
+pub fn xml_escape(text: &str) -> String {
+    text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\"", "&quot;")
+}
+
+/// Wait for a child process, killing it if it is still running after `timeout`.
+///
+/// Returns `None` if the process had to be killed.
+pub fn wait_with_timeout(child: &mut Child, timeout: Option<Duration>) -> Result<Option<ExitStatus>> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Ok(Some(child.wait()?)),
+    };
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if start.elapsed() >= timeout {
+            debug!("Killing process {} after {:?}", child.id(), timeout);
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Like `Command::output()`, but gives up after `timeout`, still returning
+/// the output collected so far.
+pub fn output_with_timeout(command: &mut Command, timeout: Option<Duration>) -> Result<(Option<ExitStatus>, Vec<u8>, Vec<u8>)> {
+    fn read_in_background<R: Read + Send + 'static>(stream: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let mut buffer = vec![];
+            if let Some(mut stream) = stream {
+                let _ = stream.read_to_end(&mut buffer);
+            }
+            buffer
+        })
+    }
+
+    debug!("Running {:?}", command);
+    let mut child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+    let status = wait_with_timeout(&mut child, timeout)?;
+    Ok((status,
+        stdout.join().map_err(|_| "Couldn't read process stdout")?,
+        stderr.join().map_err(|_| "Couldn't read process stderr")?))
+}
+
+/// A child process whose output is forwarded to ours, through an
+/// `OutputSink` feeding a report parser.
+pub struct ReportedChild {
+    pub child: Child,
+    sink: OutputSink,
+    forwarders: Vec<thread::JoinHandle<()>>,
+    /// Set once the child is gone, for the forwarders following a file.
+    done: Arc<AtomicBool>,
+}
+
+impl ReportedChild {
+    pub fn spawn(command: &mut Command, parser: TestReportParser) -> Result<ReportedChild> {
+        let sink = OutputSink::new(parser)?;
+        debug!("Running {:?}", command);
+        let mut child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        let forwarders = relay_child(&mut child, &sink);
+        Ok(ReportedChild { child, sink, forwarders, done: Arc::new(AtomicBool::new(false)) })
+    }
+
+    /// Like `spawn`, but forwards what the child writes to the `output` file
+    /// (e.g. the output of an app launched by lldb) instead of its stdout,
+    /// which goes to the `log` file.
+    pub fn spawn_following(command: &mut Command,
+                           output: &Path,
+                           log: &Path,
+                           parser: TestReportParser) -> Result<ReportedChild> {
+        fs::File::create(output)?;
+        let mut reader = BufReader::new(fs::File::open(output)?);
+        let sink = OutputSink::new(parser)?;
+        debug!("Running {:?}", command);
+        let child = command.stdout(fs::File::create(log)?).spawn()?;
+        let done = Arc::new(AtomicBool::new(false));
+        let finished = done.clone();
+        let output_sink = sink.clone();
+        let forwarder = thread::spawn(move || {
+            let mut line = vec![];
+            loop {
+                let exited = finished.load(Ordering::SeqCst);
+                let read = reader.read_until(b'\n', &mut line).unwrap_or(0);
+                if line.ends_with(b"\n") || (exited && read == 0 && !line.is_empty()) {
+                    output_sink.write(Stream::Stdout, &line);
+                    line.clear();
+                } else if read == 0 {
+                    if exited {
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis(50));
+                }
+            }
+        });
+        Ok(ReportedChild { child, sink, forwarders: vec![forwarder], done })
+    }
+
+    /// Wait for the process (see `wait_with_timeout`) and the end of its output.
+    pub fn wait_with_timeout(mut self, timeout: Option<Duration>) -> Result<(Option<ExitStatus>, TestReportParser)> {
+        let status = wait_with_timeout(&mut self.child, timeout)?;
+        self.done.store(true, Ordering::SeqCst);
+        for forwarder in self.forwarders {
+            forwarder.join().map_err(|_| "Couldn't read process output")?;
+        }
+        Ok((status, self.sink.finish()))
+    }
+}
+
+/// Relay the stdout and stderr of `child`, if piped, to `sink`.
+fn relay_child(child: &mut Child, sink: &OutputSink) -> Vec<thread::JoinHandle<()>> {
+    let mut forwarders = vec![];
+    if let Some(child_stdout) = child.stdout.take() {
+        forwarders.push(output::relay(child_stdout, Stream::Stdout, sink.clone()));
+    }
+    if let Some(child_stderr) = child.stderr.take() {
+        forwarders.push(output::relay(child_stderr, Stream::Stderr, sink.clone()));
+    }
+    forwarders
+}
+
+/// Run a command through a `ReportedChild`, killing it after `timeout`.
+pub fn run_with_report(command: &mut Command,
+                       parser: TestReportParser,
+                       timeout: Option<Duration>) -> Result<(Option<ExitStatus>, TestReportParser)> {
+    ReportedChild::spawn(command, parser)?.wait_with_timeout(timeout)
+}
+
+/// Run a command, writing its output to `sink` and killing it after
+/// `timeout`. Returns `None` if the process had to be killed.
+pub fn run_with_sink(command: &mut Command, sink: &OutputSink, timeout: Option<Duration>) -> Result<Option<ExitStatus>> {
+    debug!("Running {:?}", command);
+    let mut child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let forwarders = relay_child(&mut child, sink);
+    let status = wait_with_timeout(&mut child, timeout)?;
+    for forwarder in forwarders {
+        forwarder.join().map_err(|_| "Couldn't read process output")?;
+    }
+    Ok(status)
+}
@@ -0,0 +1,95 @@
+//! `cargo dinghy watch`: waiting for the sources of the project to change,
+//! so that the runnables are built and run again on the device.
+
+use errors::*;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+/// How long the changes are gathered before being reported, so that saving
+/// several files triggers a single rebuild.
+static DEBOUNCE_MILLIS: u64 = 300;
+
+pub struct SourceWatcher {
+    events: Receiver<DebouncedEvent>,
+    root: PathBuf,
+    ignored: Vec<PathBuf>,
+    // Stops watching when dropped.
+    _watcher: RecommendedWatcher,
+}
+
+impl SourceWatcher {
+    /// Watch the `root` directory, except the `ignored` ones (the target
+    /// directories).
+    pub fn new(root: &Path, ignored: Vec<PathBuf>) -> Result<SourceWatcher> {
+        let (sender, events) = channel();
+        let mut watcher = ::notify::watcher(sender, Duration::from_millis(DEBOUNCE_MILLIS))
+            .chain_err(|| "Couldn't start watching the sources")?;
+        watcher.watch(root, RecursiveMode::Recursive)
+            .chain_err(|| format!("Couldn't watch {}", root.display()))?;
+        Ok(SourceWatcher { events, root: root.to_path_buf(), ignored, _watcher: watcher })
+    }
+
+    /// Wait until files of the project change, and return them.
+    pub fn wait_for_change(&self) -> Result<Vec<PathBuf>> {
+        loop {
+            let event = self.events.recv().chain_err(|| "The source watcher stopped")?;
+            let mut changed = self.changed_paths(event);
+            while let Ok(event) = self.events.try_recv() {
+                changed.extend(self.changed_paths(event));
+            }
+            if !changed.is_empty() {
+                return Ok(changed);
+            }
+        }
+    }
+
+    fn changed_paths(&self, event: DebouncedEvent) -> Vec<PathBuf> {
+        let paths = match event {
+            DebouncedEvent::Create(path)
+            | DebouncedEvent::Write(path)
+            | DebouncedEvent::Remove(path) => vec![path],
+            DebouncedEvent::Rename(from, to) => vec![from, to],
+            DebouncedEvent::Error(e, path) => {
+                warn!("Error while watching {:?}: {}", path, e);
+                vec![]
+            }
+            _ => vec![],
+        };
+        paths.into_iter().filter(|it| is_source(it, &self.root, &self.ignored)).collect()
+    }
+}
+
+/// Whether a change of `path` is worth a rebuild: outside of the `ignored`
+/// directories, the hidden ones of `root` (`.git`...), and not an editor
+/// temporary file.
+fn is_source(path: &Path, root: &Path, ignored: &[PathBuf]) -> bool {
+    if ignored.iter().any(|it| path.starts_with(it)) {
+        return false;
+    }
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    if relative.components().any(|it| it.as_os_str().to_string_lossy().starts_with('.')) {
+        return false;
+    }
+    let name = path.file_name().map(|it| it.to_string_lossy().to_string()).unwrap_or(String::new());
+    !(name.ends_with('~') || name.ends_with(".swp") || name.ends_with(".tmp") || name.starts_with("#"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_changes() {
+        let root = Path::new("/home/me/.work/ws");
+        let ignored = vec![root.join("target")];
+        assert!(is_source(&root.join("src/lib.rs"), root, &ignored));
+        assert!(is_source(&root.join("tests/data/input.txt"), root, &ignored));
+        assert!(!is_source(&root.join("target/debug/app"), root, &ignored));
+        assert!(!is_source(&root.join(".git/index"), root, &ignored));
+        assert!(!is_source(&root.join("src/.lib.rs.swp"), root, &ignored));
+        assert!(!is_source(&root.join("src/lib.rs~"), root, &ignored));
+    }
+}
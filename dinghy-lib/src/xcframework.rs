@@ -0,0 +1,111 @@
+//! Packaging of the C static libraries (`crate-type = ["staticlib"]`) for iOS
+//! apps: an `.xcframework` with a library per platform variant (devices,
+//! simulators), or universal libraries, along with the C header cbindgen
+//! makes for them.
+
+use config::XcodeConfiguration;
+use dinghy_build::triple::Triple;
+use errors::*;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Whether `rustc_triple` builds for the iOS simulator rather than devices.
+pub fn is_simulator(rustc_triple: &str) -> bool {
+    Triple::parse(rustc_triple).map(|it| it.is_simulator()).unwrap_or(false)
+}
+
+/// Generate `<name>.h` and its `module.modulemap` (for Swift) in `dir`, with
+/// cbindgen run on the crate of the current directory.
+pub fn generate_header(cbindgen_conf: &Path, name: &str, dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(dir).chain_err(|| format!("Couldn't create {}", dir.display()))?;
+    let header = dir.join(format!("{}.h", name));
+    let status = Command::new("cbindgen")
+        .arg("--config").arg(cbindgen_conf)
+        .arg("--output").arg(&header)
+        .status()
+        .chain_err(|| "Couldn't run cbindgen, is it installed (cargo install cbindgen)?")?;
+    if !status.success() {
+        bail!("cbindgen failed to generate {}", header.display())
+    }
+    let mut modulemap = fs::File::create(dir.join("module.modulemap"))?;
+    writeln!(modulemap, "module {} {{\n    header \"{}.h\"\n    export *\n}}", name, name)?;
+    Ok(header)
+}
+
+/// Merge `libs` (one per architecture) into `output` with lipo.
+pub fn lipo(libs: &[PathBuf], output: &Path) -> Result<()> {
+    if libs.len() == 1 {
+        fs::copy(&libs[0], output)
+            .chain_err(|| format!("Couldn't copy {} to {}", libs[0].display(), output.display()))?;
+        return Ok(());
+    }
+    let status = Command::new("lipo").arg("-create").args(libs).arg("-output").arg(output).status()?;
+    if !status.success() {
+        bail!("lipo failed to create {}", output.display())
+    }
+    Ok(())
+}
+
+/// Create the `output` xcframework from the `libs` of each triple, merged per
+/// platform variant in `scratch`.
+pub fn create_xcframework(libs: &[(String, PathBuf)], headers: Option<&Path>, scratch: &Path, output: &Path) -> Result<()> {
+    let _ = fs::remove_dir_all(output);
+    let mut command = Command::new("xcodebuild");
+    command.arg("-create-xcframework");
+    for &(variant, simulator) in &[("ios", false), ("ios-simulator", true)] {
+        let variant_libs = libs.iter()
+            .filter(|&&(ref triple, _)| is_simulator(triple) == simulator)
+            .map(|&(_, ref lib)| lib.clone())
+            .collect::<Vec<_>>();
+        let name = match variant_libs.first().and_then(|it| it.file_name()) {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+        let dir = scratch.join(variant);
+        fs::create_dir_all(&dir)?;
+        let merged = dir.join(name);
+        lipo(&variant_libs, &merged)?;
+        command.arg("-library").arg(&merged);
+        if let Some(headers) = headers {
+            command.arg("-headers").arg(headers);
+        }
+    }
+    command.arg("-output").arg(output);
+    debug!("Running {:?}", command);
+    if !command.status()?.success() {
+        bail!("xcodebuild failed to create {}", output.display())
+    }
+    Ok(())
+}
+
+/// Run the XCTest suites of the Xcode project on `destination`.
+pub fn xcodebuild_test(xcode: &XcodeConfiguration, destination: &str) -> Result<()> {
+    let mut command = Command::new("xcodebuild");
+    command.arg("test");
+    if xcode.project.ends_with(".xcworkspace") {
+        command.arg("-workspace").arg(&xcode.project);
+    } else {
+        command.arg("-project").arg(&xcode.project);
+    }
+    command.arg("-scheme").arg(&xcode.scheme).arg("-destination").arg(destination);
+    debug!("Running {:?}", command);
+    if !command.status().chain_err(|| "Couldn't run xcodebuild")?.success() {
+        bail!("xcodebuild test failed")
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulator_triples() {
+        assert!(is_simulator("x86_64-apple-ios"));
+        assert!(is_simulator("aarch64-apple-ios-sim"));
+        assert!(!is_simulator("aarch64-apple-ios"));
+    }
+}
@@ -58,3 +58,61 @@ pub fn try_test_file_path(test_data_id: &str) -> Option<PathBuf> {
             .map(PathBuf::from)
     }
 }
+
+/// Exports the `dinghy_test_main` entry point dinghy runs the tests of a
+/// `cdylib` crate through: each function is called, and reported the way the
+/// test harness does. Arguments not starting with `-` filter the tests by name.
+///
+/// ```ignore
+/// #[macro_use]
+/// extern crate dinghy_test;
+///
+/// cdylib_tests!(decodes_frames, rejects_garbage);
+/// ```
+#[macro_export]
+macro_rules! cdylib_tests {
+    ($($test:ident),* $(,)*) => {
+        #[no_mangle]
+        pub extern "C" fn dinghy_test_main(argc: ::std::os::raw::c_int,
+                                           argv: *const *const ::std::os::raw::c_char) -> ::std::os::raw::c_int {
+            let args = (1..argc as isize)
+                .map(|i| unsafe { ::std::ffi::CStr::from_ptr(*argv.offset(i)) }.to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            $crate::run_cdylib_tests(&args, &[$((stringify!($test), $test as fn())),*])
+        }
+    };
+}
+
+/// Runs the `tests` whose name contains one of the filters of `args`, if any,
+/// returning the exit code of the run. Used by `cdylib_tests!`.
+pub fn run_cdylib_tests(args: &[String], tests: &[(&str, fn())]) -> ::std::os::raw::c_int {
+    let filters = args.iter().filter(|it| !it.starts_with('-')).collect::<Vec<_>>();
+    let selected = tests.iter()
+        .filter(|&&(name, _)| filters.is_empty() || filters.iter().any(|it| name.contains(it.as_str())))
+        .collect::<Vec<_>>();
+    println!();
+    println!("running {} tests", selected.len());
+    let mut failed = vec![];
+    for &&(name, test) in &selected {
+        let passed = ::std::panic::catch_unwind(test).is_ok();
+        println!("test {} ... {}", name, if passed { "ok" } else { "FAILED" });
+        if !passed {
+            failed.push(name);
+        }
+    }
+    if !failed.is_empty() {
+        println!();
+        println!("failures:");
+        for name in &failed {
+            println!("    {}", name);
+        }
+    }
+    println!();
+    println!("test result: {}. {} passed; {} failed; 0 ignored; 0 measured; {} filtered out",
+             if failed.is_empty() { "ok" } else { "FAILED" },
+             selected.len() - failed.len(),
+             failed.len(),
+             tests.len() - selected.len());
+    println!();
+    if failed.is_empty() { 0 } else { 101 }
+}